@@ -1,5 +1,6 @@
 use yew::prelude::*;
 use wasm_bindgen::prelude::*;
+use crate::time::{local_time, SharedTimeSource};
 
 pub struct Clock {
     time: String,
@@ -11,6 +12,12 @@ pub enum ClockMsg {
     Tick,
 }
 
+#[derive(Properties, Clone, PartialEq)]
+pub struct ClockProps {
+    #[prop_or_default]
+    pub time: SharedTimeSource,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Interval {
     #[allow(dead_code)]
@@ -26,7 +33,7 @@ impl Drop for Interval {
 
 impl Component for Clock {
     type Message = ClockMsg;
-    type Properties = ();
+    type Properties = ClockProps;
 
     fn create(ctx: &Context<Self>) -> Self {
         // Set up interval for automatic ticks
@@ -55,15 +62,15 @@ impl Component for Clock {
         };
         
         // Initialize the time
-        instance.update_time();
+        instance.update_time(&ctx.props().time);
         
         instance
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             ClockMsg::Tick => {
-                self.update_time();
+                self.update_time(&ctx.props().time);
                 true
             }
         }
@@ -84,15 +91,15 @@ impl Component for Clock {
 }
 
 impl Clock {
-    fn update_time(&mut self) {
-        let date = js_sys::Date::new_0();
+    fn update_time(&mut self, time: &SharedTimeSource) {
+        let date = local_time(time.now());
         
         // Format time
-        let hours = date.get_hours();
-        let minutes = date.get_minutes();
-        let seconds = date.get_seconds();
+        let hours = date.hours;
+        let minutes = date.minutes;
+        let seconds = date.seconds;
         let period = if hours >= 12 { "PM" } else { "AM" };
-        let display_hours = if hours % 12 == 0 { 12 } else { hours % 12 };
+        let display_hours = match hours % 12 { 0 => 12, hours => hours };
         
         self.time = format!(
             "{:02}:{:02}:{:02} {}",
@@ -106,10 +113,10 @@ impl Clock {
             "July", "August", "September", "October", "November", "December"
         ];
         
-        let day = days[date.get_day() as usize];
-        let month = months[date.get_month() as usize];
-        let date_num = date.get_date();
-        let year = date.get_full_year();
+        let day = days[date.weekday as usize];
+        let month = months[date.month as usize];
+        let date_num = date.day;
+        let year = date.year;
         
         self.date = format!("{}, {} {}, {}", day, month, date_num, year);
     }
@@ -1,15 +1,26 @@
 use yew::prelude::*;
-use web_sys::{self, MouseEvent};
+use web_sys::{self, MouseEvent, StorageEvent};
+use wasm_bindgen::prelude::*;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::path::Path;
-// Use WebWindow alias to avoid confusion with your component
-use web_sys::Window as WebWindow;
 
 use crate::components::window::{Window, WindowState, WindowContentType};
 use crate::components::taskbar::Taskbar;
-use crate::filesystem::FileSystem;
+use crate::filesystem::{FileSystem, FsckMode};
+use crate::storage::local_storage::is_metadata_key;
+use crate::storage::{MemoryStorage, SharedStorage};
+use crate::time::SharedTimeSource;
+
+// Encrypted directories lock themselves after this long without being used
+const ENCRYPTION_IDLE_TIMEOUT_MS: u64 = 15 * 60 * 1000;
+
+// File system changes are saved once they've stopped for SAVE_IDLE_MS, but
+// never held back for longer than SAVE_MAX_WAIT_MS
+const SAVE_CHECK_INTERVAL_MS: i32 = 250;
+const SAVE_IDLE_MS: u64 = 500;
+const SAVE_MAX_WAIT_MS: u64 = 5000;
 
 pub struct Desktop {
     fs: Rc<RefCell<FileSystem>>,
@@ -18,6 +29,14 @@ pub struct Desktop {
     window_counter: u32,
     context_menu: Option<(i32, i32)>,
     background_color: String,
+    // Counts the changes other tabs made to the file system, and snapshot
+    // restores in this one, for the windows showing it to notice
+    fs_revision: u32,
+    // Why the file system is only kept in memory, if it is, so that the
+    // user knows nothing they do will be saved
+    storage_error: Option<String>,
+    _storage_listener: Option<Closure<dyn FnMut(StorageEvent)>>,
+    _pagehide_listener: Option<Closure<dyn FnMut()>>,
 }
 
 pub enum DesktopMsg {
@@ -29,44 +48,107 @@ pub enum DesktopMsg {
     ContextMenu(i32, i32),
     OpenFile(String, String), // (path, file_type)
     ChangeBackgroundColor(String),
+    FileSystemChanged, // Another tab saved the file system
+    FileSystemRestored, // A snapshot was restored in this tab
+    LockEncryptedDirectories,
+    LockIdleDirectories,
+    SaveFileSystem,
+    CheckFileSystem,
+    DismissStorageError,
 }
 
 impl Component for Desktop {
     type Message = DesktopMsg;
     type Properties = ();
 
-    fn create(_ctx: &Context<Self>) -> Self {
-        // Initialize file system
-        let fs = match FileSystem::new() {
-            Ok(fs) => Rc::new(RefCell::new(fs)),
+    fn create(ctx: &Context<Self>) -> Self {
+        // Load the file system, or if its storage can't be used, keep one in
+        // memory for this session rather than failing to start at all
+        let (fs, storage_error) = match FileSystem::new() {
+            Ok(fs) => (fs, None),
             Err(e) => {
-                log::error!("Failed to initialize file system: {}", e);
-                // Rc::new(RefCell::new(FileSystem { // replaced with below - ensure this part is commented out
-                //     files: HashMap::new(),
-                // }))
-                // In case of error, still try to create a FileSystem, even if it might be empty or in a bad state
-                match FileSystem::new() {
-                    Ok(fs_err) => Rc::new(RefCell::new(fs_err)),
-                    Err(e_err) => {
-                        log::error!("Failed to initialize file system AGAIN: {}", e_err);
-                        // Rc::new(RefCell::new(FileSystem { files: HashMap::new() })) // REMOVE this line
-                        // Fallback to creating a default FileSystem - if FileSystem implements Default
-                        match FileSystem::new() { // Try one more time
-                            Ok(fs_err_again) => Rc::new(RefCell::new(fs_err_again)),
-                            Err(e_err_again) => {
-                                log::error!("Failed to initialize file system AGAIN, AGAIN: {}", e_err_again);
-                                // If even the second attempt fails, return an error or handle it appropriately.
-                                // For now, let's panic to indicate a serious initialization failure.
-                                panic!("Failed to initialize FileSystem after multiple attempts: {}", e_err_again);
-                                // Alternatively, you could use a default FileSystem if you implement Default for FileSystem
-                                // or return a Result<Desktop, String> from Desktop::create and handle the error in main.rs
-                                // For a very basic fallback (if FileSystem implements Default):
-                                // Rc::new(RefCell::new(FileSystem::default()))
-                            }
-                        }
+                log::error!("Failed to load the file system, keeping it in memory instead: {}", e);
+                let fs = FileSystem::with_storage(SharedStorage::new(MemoryStorage::default()), SharedTimeSource::default())
+                    .expect("a new file system in memory can always be created");
+                (fs, Some(e))
+            }
+        };
+        let fs = Rc::new(RefCell::new(fs));
+
+        // Storage events only fire for changes made by other tabs, so use them
+        // to keep this tab's file system in sync
+        let on_fs_changed = ctx.link().callback(|_| DesktopMsg::FileSystemChanged);
+        let storage_listener = Closure::wrap(Box::new(move |e: StorageEvent| {
+            // A missing key means the whole storage was cleared
+            if e.key().is_none_or(|key| is_metadata_key(&key)) {
+                on_fs_changed.emit(());
+            }
+        }) as Box<dyn FnMut(StorageEvent)>);
+
+        let storage_listener = match web_sys::window() {
+            Some(window) => match window.add_event_listener_with_callback("storage", storage_listener.as_ref().unchecked_ref()) {
+                Ok(_) => Some(storage_listener),
+                Err(e) => {
+                    log::warn!("Failed to listen for storage changes: {:?}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        // Periodically lock encrypted directories that are no longer in use
+        let on_idle_check = ctx.link().callback(|_| DesktopMsg::LockIdleDirectories);
+        let idle_check = Closure::wrap(Box::new(move || {
+            on_idle_check.emit(());
+        }) as Box<dyn FnMut()>);
+        if let Some(window) = web_sys::window() {
+            if let Err(e) = window.set_interval_with_callback_and_timeout_and_arguments_0(
+                idle_check.as_ref().unchecked_ref(),
+                60 * 1000,
+            ) {
+                log::warn!("Failed to schedule locking of idle directories: {:?}", e);
+            }
+        }
+        idle_check.forget(); // The desktop lives as long as the page
+
+        // Changes are written in batches rather than one at a time
+        fs.borrow_mut().set_deferred_saves(true);
+        let on_save_check = ctx.link().callback(|_| DesktopMsg::SaveFileSystem);
+        let save_check = Closure::wrap(Box::new(move || {
+            on_save_check.emit(());
+        }) as Box<dyn FnMut()>);
+        if let Some(window) = web_sys::window() {
+            if let Err(e) = window.set_interval_with_callback_and_timeout_and_arguments_0(
+                save_check.as_ref().unchecked_ref(),
+                SAVE_CHECK_INTERVAL_MS,
+            ) {
+                log::warn!("Failed to schedule saving of the file system: {:?}", e);
+            }
+        }
+        save_check.forget();
+
+        // Whatever hasn't been saved yet must be before the page goes away
+        let fs_to_save = fs.clone();
+        let pagehide_listener = Closure::wrap(Box::new(move || {
+            match fs_to_save.try_borrow_mut() {
+                Ok(mut fs) => {
+                    if let Err(e) = fs.save() {
+                        log::error!("Failed to save file system: {}", e);
                     }
                 }
+                Err(_) => log::error!("Failed to save file system: it is in use"),
             }
+        }) as Box<dyn FnMut()>);
+
+        let pagehide_listener = match web_sys::window() {
+            Some(window) => match window.add_event_listener_with_callback("pagehide", pagehide_listener.as_ref().unchecked_ref()) {
+                Ok(_) => Some(pagehide_listener),
+                Err(e) => {
+                    log::warn!("Failed to listen for the page being closed: {:?}", e);
+                    None
+                }
+            },
+            None => None,
         };
 
         Self {
@@ -76,6 +158,10 @@ impl Component for Desktop {
             window_counter: 0,
             context_menu: None,
             background_color: "#2a6496".to_string(),
+            fs_revision: 0,
+            storage_error,
+            _storage_listener: storage_listener,
+            _pagehide_listener: pagehide_listener,
         }
     }
 
@@ -102,7 +188,7 @@ impl Component for Desktop {
                 }));
                 
                 // Unfocus all other windows
-                for (_, other_window) in &self.windows {
+                for other_window in self.windows.values() {
                     other_window.borrow_mut().is_focused = false;
                 }
                 
@@ -188,6 +274,65 @@ impl Component for Desktop {
                 self.background_color = color;
                 true
             }
+            DesktopMsg::FileSystemChanged => {
+                match self.fs.borrow_mut().sync_from_storage() {
+                    Ok(conflicts) => {
+                        for conflict in conflicts {
+                            log::warn!("Conflicting change to {} from another tab", conflict.path);
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Failed to sync file system: {}", e);
+                    }
+                }
+                self.fs_revision = self.fs_revision.wrapping_add(1);
+                true
+            }
+            DesktopMsg::FileSystemRestored => {
+                self.fs_revision = self.fs_revision.wrapping_add(1);
+                true
+            }
+            DesktopMsg::LockEncryptedDirectories => {
+                self.fs.borrow_mut().lock_all();
+                self.context_menu = None;
+                true
+            }
+            DesktopMsg::LockIdleDirectories => {
+                let locked = self.fs.borrow_mut().lock_idle(ENCRYPTION_IDLE_TIMEOUT_MS);
+                for path in &locked {
+                    log::info!("Locked idle encrypted directory {}", path);
+                }
+                !locked.is_empty()
+            }
+            DesktopMsg::SaveFileSystem => {
+                if let Err(e) = self.fs.borrow_mut().save_if_idle(SAVE_IDLE_MS, SAVE_MAX_WAIT_MS) {
+                    log::error!("Failed to save file system: {}", e);
+                }
+                false
+            }
+            DesktopMsg::CheckFileSystem => {
+                // Only report: another tab may have written contents it hasn't
+                // saved the entries for yet, and repairing would delete them.
+                // Repairs are left to `fsck -r` in a Terminal.
+                match self.fs.borrow_mut().fsck(FsckMode::Quick, false) {
+                    Ok(report) => {
+                        for issue in &report.issues {
+                            log::warn!("fsck: {}", issue);
+                        }
+                        if !report.issues.is_empty() {
+                            log::warn!("fsck: run 'fsck -f -r' in a Terminal to repair");
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Failed to check file system: {}", e);
+                    }
+                }
+                false
+            }
+            DesktopMsg::DismissStorageError => {
+                self.storage_error = None;
+                true
+            }
         }
     }
 
@@ -222,45 +367,20 @@ impl Component for Desktop {
             DesktopMsg::CreateWindow("Clock".to_string(), WindowContentType::Clock)
         });
 
-// Create context menu callbacks that accept MouseEvent parameters
-        let create_file_explorer_clone = create_file_explorer.clone();
-        let create_file_explorer_ctx = Callback::from(move |e: MouseEvent| {
-            e.stop_propagation();
-            create_file_explorer_clone.emit(())
-       });
-
-        let create_terminal_clone = create_terminal.clone();
-        let create_terminal_ctx = Callback::from(move |e: MouseEvent| {
-            e.stop_propagation();
-            create_terminal_clone.emit(())
-        });
-
-        let create_text_editor_clone = create_text_editor.clone();
-        let create_text_editor_ctx = Callback::from(move |e: MouseEvent| {
-            e.stop_propagation();
-            create_text_editor_clone.emit(())
-        });
-
-        let create_clock_clone = create_clock.clone();
-        let create_clock_ctx = Callback::from(move |e: MouseEvent| {
-            e.stop_propagation();
-            create_clock_clone.emit(())
-        });
-
-        let fs_clone = Rc::clone(&self.fs);
+        let on_fs_restored = ctx.link().callback(|_| DesktopMsg::FileSystemRestored);
         let on_open_file = ctx.link().callback(move |(path, file_type)| {
             DesktopMsg::OpenFile(path, file_type)
         });
         
         // Context menu click handlers
         let hide_context_menu = ctx.link().callback(|_| DesktopMsg::ContextMenu(0, 0));
-        let create_file_explorer_ctx = create_file_explorer.clone();
-        let create_terminal_ctx = create_terminal.clone();
-        let create_text_editor_ctx = create_text_editor.clone();
-        let create_clock_ctx = create_clock.clone();
+        let lock_encrypted_directories = ctx.link().callback(|_| DesktopMsg::LockEncryptedDirectories);
         let create_file_compressor = ctx.link().callback(|_| {
             DesktopMsg::CreateWindow("File Compressor".to_string(), WindowContentType::FileCompressor)
         });
+        let create_settings = ctx.link().callback(|_| {
+            DesktopMsg::CreateWindow("Settings".to_string(), WindowContentType::Settings)
+        });
         
         // Background color callbacks
         let blue_bg = ctx.link().callback(|_| DesktopMsg::ChangeBackgroundColor("#2a6496".to_string()));
@@ -274,17 +394,39 @@ impl Component for Desktop {
                      style={format!("width: 100%; height: 100vh; background-color: {}; position: relative; overflow: hidden;", self.background_color)}
                      oncontextmenu={on_context_menu}>
                     
+                    // Shown until dismissed when nothing will be saved
+                    {
+                        if let Some(error) = &self.storage_error {
+                            html! {
+                                <div class="storage-error"
+                                     style="position: absolute; top: 0; left: 0; right: 0; z-index: 1000; padding: 8px; color: red; background-color: #fff0f0; border-bottom: 1px solid #ffdddd;">
+                                    { format!("The file system couldn't be loaded ({}), so changes will not be saved.", error) }
+                                    <button
+                                        style="margin-left: 8px;"
+                                        onclick={ctx.link().callback(|_| DesktopMsg::DismissStorageError)}
+                                    >
+                                        { "×" }
+                                    </button>
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    
                     /* Windows */
                     {
-                        self.windows.iter().map(|(_, window)| {
+                        self.windows.values().map(|window| {
                             html! {
                                 <Window 
                                     window={Rc::clone(window)}
                                     fs={Rc::clone(&self.fs)}
+                                    fs_revision={self.fs_revision}
                                     on_close={on_close.clone()}
                                     on_minimize={on_minimize.clone()}
                                     on_focus={on_focus.clone()}
                                     on_open_file={on_open_file.clone()}
+                                    on_fs_replaced={on_fs_restored.clone()}
                                 />
                             }
                         }).collect::<Html>()
@@ -302,6 +444,7 @@ impl Component for Desktop {
                         on_create_terminal={create_terminal}
                         on_create_text_editor={create_text_editor}
                         on_create_clock={create_clock}
+                        time={self.fs.borrow().time_source()}
                     />
                     
                     // Context Menu (conditionally rendered)
@@ -318,8 +461,6 @@ impl Component for Desktop {
                                 "padding: 8px 16px; cursor: pointer; white-space: nowrap; 
                                  user-select: none; display: flex; align-items: center;";
                             
-                            let hover_style = "hover:background-color: #f0f0f0;";
-                            
                             html! {
                                 <>
                                     <div class="context-menu-overlay" 
@@ -353,6 +494,18 @@ impl Component for Desktop {
                                             <span style="margin-right: 8px;">{"🗜️"}</span>
                                             {"File Compressor"}
                                         </div>
+                                        <div class="context-menu-item"
+                                             style={menu_item_style}
+                                             onclick={lock_encrypted_directories}>
+                                            <span style="margin-right: 8px;">{"🔒"}</span>
+                                            {"Lock Encrypted Folders"}
+                                        </div>
+                                        <div class="context-menu-item"
+                                             style={menu_item_style}
+                                             onclick={create_settings}>
+                                            <span style="margin-right: 8px;">{"⚙️"}</span>
+                                            {"Settings"}
+                                        </div>
                                         <hr style="margin: 4px 0; border-top: 1px solid #eee;" />
                                         <div class="context-menu-item"
                                              style={menu_item_style}>
@@ -394,4 +547,10 @@ impl Component for Desktop {
             </>
         }   
     }
-}
\ No newline at end of file
+    fn rendered(&mut self, ctx: &Context<Self>, first_render: bool) {
+        // Check the file system once the desktop is up, rather than holding it back
+        if first_render {
+            ctx.link().send_message(DesktopMsg::CheckFileSystem);
+        }
+    }
+}
@@ -1,9 +1,23 @@
 use yew::prelude::*;
 use std::rc::Rc;
 use std::cell::RefCell;
-use crate::filesystem::{FileSystem, FileType};
+use crate::filesystem::{FileSystem, FileTimes, FileType, ListOptions};
+use serde::{Serialize, Deserialize};
 use std::path::Path;
 
+// First line of archives created by the compressor; the rest is the JSON list
+// of members
+const ARCHIVE_HEADER: &str = "KERNELOS ARCHIVE 1";
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveEntry {
+    name: String,
+    contents: String,
+    created: u64,
+    modified: u64,
+    accessed: u64,
+}
+
 pub struct FileCompressor {
     fs: Rc<RefCell<FileSystem>>,
     current_directory: String,
@@ -26,6 +40,9 @@ pub enum FileCompressorMsg {
 #[derive(Properties, Clone, PartialEq)]
 pub struct FileCompressorProps {
     pub fs: Rc<RefCell<FileSystem>>,
+    // Changes when another tab changes the file system
+    #[prop_or_default]
+    pub fs_revision: u32,
 }
 
 impl Component for FileCompressor {
@@ -47,7 +64,7 @@ impl Component for FileCompressor {
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             FileCompressorMsg::NavigateTo(path) => {
-                match self.fs.borrow().list_directory(&path) {
+                match self.fs.borrow().list(&path, &ListOptions::default()) {
                     Ok(_) => {
                         self.current_directory = path;
                         self.selected_files.clear();
@@ -90,24 +107,38 @@ impl Component for FileCompressor {
                     return true;
                 }
                 
-                // Basic implementation: In a real implementation, we would use a compression library
-                // Here, we'll simulate compression by creating a new file with a list of files
                 let archive_path = if self.archive_name.ends_with(".zip") {
                     format!("{}/{}", self.current_directory, self.archive_name)
                 } else {
                     format!("{}/{}.zip", self.current_directory, self.archive_name)
                 };
                 
-                // Simple text representation of the archive
-                let archive_content = format!(
-                    "SIMULATED ZIP ARCHIVE\n\
-                     Created: {}\n\
-                     Files:\n{}", 
-                    js_sys::Date::new_0().to_string(),
-                    self.selected_files.iter().map(|f| format!(" - {}\n", f)).collect::<String>()
-                );
+                // Store each file with its timestamps so extraction can restore them
+                let archive_content = self.selected_files.iter()
+                    .map(|file_path| {
+                        let fs = self.fs.borrow();
+                        let metadata = fs.metadata(file_path)?;
+                        Ok(ArchiveEntry {
+                            name: metadata.name,
+                            contents: fs.read_file(file_path)?,
+                            created: metadata.created,
+                            modified: metadata.modified,
+                            accessed: metadata.accessed,
+                        })
+                    })
+                    .collect::<Result<Vec<ArchiveEntry>, String>>()
+                    .and_then(|entries| serde_json::to_string(&entries).map_err(|e| e.to_string()))
+                    .map(|json| format!("{}\n{}", ARCHIVE_HEADER, json));
+                let archive_content = match archive_content {
+                    Ok(content) => content,
+                    Err(e) => {
+                        self.status_message = Some((format!("Failed to create archive: {}", e), true));
+                        return true;
+                    }
+                };
                 
-                match self.fs.borrow_mut().write_file(&archive_path, &archive_content) {
+                let result = self.fs.borrow_mut().write_file(&archive_path, &archive_content);
+                match result {
                     Ok(_) => {
                         self.status_message = Some((format!("Successfully created archive: {}", archive_path), false));
                         self.selected_files.clear();
@@ -120,10 +151,25 @@ impl Component for FileCompressor {
                 true
             },
             FileCompressorMsg::ExtractArchive(path) => {
-                // Simple extraction simulation
-                match self.fs.borrow().read_file(&path) {
+                let archive = self.fs.borrow().read_file(&path);
+                match archive {
                     Ok(content) => {
-                        if content.starts_with("SIMULATED ZIP ARCHIVE") {
+                        if let Some(json) = content.strip_prefix(ARCHIVE_HEADER) {
+                            let archive_name = Path::new(&path)
+                                .file_stem()
+                                .and_then(|s| s.to_str())
+                                .unwrap_or("extracted");
+                            let extract_dir = format!("{}/{}_extracted", self.current_directory, archive_name);
+
+                            let result = serde_json::from_str::<Vec<ArchiveEntry>>(json.trim_start())
+                                .map_err(|e| format!("Corrupted archive: {}", e))
+                                .and_then(|entries| self.extract_entries(&extract_dir, entries));
+                            self.status_message = match result {
+                                Ok(_) => Some((format!("Extracted to: {}", extract_dir), false)),
+                                Err(e) => Some((format!("Failed to extract archive: {}", e), true)),
+                            };
+                        } else if content.starts_with("SIMULATED ZIP ARCHIVE") {
+                            // Archives from before files were actually stored
                             // Extract archive name without extension
                             let archive_name = Path::new(&path)
                                 .file_stem()
@@ -133,11 +179,13 @@ impl Component for FileCompressor {
                             // Create extract directory
                             let extract_dir = format!("{}/{}_extracted", self.current_directory, archive_name);
                             
-                            match self.fs.borrow_mut().create_directory(&extract_dir, true) {
+                            let result = self.fs.borrow_mut().create_directory(&extract_dir, true);
+                            match result {
                                 Ok(_) => {
                                     // Create a sample extracted file
                                     let sample_file = format!("{}/README.txt", extract_dir);
-                                    match self.fs.borrow_mut().write_file(&sample_file, "This is a simulated extracted file.\nIn a real implementation, the actual files would be extracted here.") {
+                                    let result = self.fs.borrow_mut().write_file(&sample_file, "This is a simulated extracted file.\nIn a real implementation, the actual files would be extracted here.");
+                                    match result {
                                         Ok(_) => {
                                             self.status_message = Some((format!("Extracted to: {}", extract_dir), false));
                                         },
@@ -168,11 +216,18 @@ impl Component for FileCompressor {
         }
     }
 
+    fn changed(&mut self, _ctx: &Context<Self>, _old_props: &Self::Properties) -> bool {
+        // Files another tab removed can't be compressed any more
+        let fs = self.fs.borrow();
+        self.selected_files.retain(|path| fs.metadata(path).is_ok());
+        true
+    }
+
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let files = match self.fs.borrow().list_directory(&self.current_directory) {
-            Ok(files) => files,
-            Err(_) => Vec::new(),
-        };
+        let options = ListOptions { directories_first: true, ..ListOptions::default() };
+        let files = self.fs.borrow().list(&self.current_directory, &options)
+            .map(|listing| listing.entries)
+            .unwrap_or_default();
         
         let path_parts: Vec<String> = self.current_directory
             .split('/')
@@ -354,3 +409,22 @@ impl Component for FileCompressor {
         }
     }
 }
+
+impl FileCompressor {
+    fn extract_entries(&self, extract_dir: &str, entries: Vec<ArchiveEntry>) -> Result<(), String> {
+        let mut fs = self.fs.borrow_mut();
+        fs.create_directory(extract_dir, true)?;
+
+        for entry in entries {
+            let file_path = format!("{}/{}", extract_dir, entry.name);
+            fs.write_file(&file_path, &entry.contents)?;
+            fs.set_times(&file_path, FileTimes {
+                created: Some(entry.created),
+                modified: Some(entry.modified),
+                accessed: Some(entry.accessed),
+            })?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,15 +1,45 @@
 use yew::prelude::*;
 use std::rc::Rc;
 use std::cell::RefCell;
-use crate::filesystem::{FileSystem, FileType, FileMetadata};
+use crate::filesystem::{FileSystem, FileType, FileMetadata, ListOptions, Listing, SortDirection, SortKey};
 use wasm_bindgen::JsValue;
 
+// Entries shown per page of a directory
+const PAGE_SIZE: usize = 100;
+
 pub struct FileExplorer {
     fs: Rc<RefCell<FileSystem>>,
     current_path: String,
     files: Vec<FileMetadata>,
+    // Entries in the directory matching the filter, across all pages
+    total_files: usize,
     selected_file: Option<String>,
     error_message: Option<String>,
+    list_options: ListOptions,
+    passphrase_dialog: Option<PassphraseDialog>,
+}
+
+// Asks for a passphrase in password fields, so that it isn't shown
+struct PassphraseDialog {
+    purpose: PassphrasePurpose,
+    passphrase: String,
+    // Typed again when choosing a new passphrase
+    repeated: String,
+    encrypt_names: bool,
+    error: Option<String>,
+}
+
+enum PassphrasePurpose {
+    // Unlock `directory`, then go to `path` in it
+    Unlock { directory: String, path: String },
+    // Encrypt the current directory
+    Encrypt,
+}
+
+impl PassphraseDialog {
+    fn new(purpose: PassphrasePurpose) -> Self {
+        Self { purpose, passphrase: String::new(), repeated: String::new(), encrypt_names: false, error: None }
+    }
 }
 
 pub enum FileExplorerMsg {
@@ -21,7 +51,18 @@ pub enum FileExplorerMsg {
     DeleteFile(String),
     CreateNewFile,
     CreateNewDirectory,
-    Error(String),
+    EncryptDirectory,
+    PassphraseChanged(String),
+    RepeatedPassphraseChanged(String),
+    ToggleEncryptNames,
+    SubmitPassphrase,
+    CancelPassphrase,
+    LockDirectory,
+    ToggleHidden,
+    SortBy(SortKey),
+    Filter(String),
+    NextPage,
+    PreviousPage,
     ClearError,
 }
 
@@ -29,6 +70,9 @@ pub enum FileExplorerMsg {
 pub struct FileExplorerProps {
     pub fs: Rc<RefCell<FileSystem>>,
     pub on_open_file: Callback<(String, String)>, // (path, file_type)
+    // Changes when another tab changes the file system
+    #[prop_or_default]
+    pub fs_revision: u32,
 }
 
 impl Component for FileExplorer {
@@ -38,34 +82,50 @@ impl Component for FileExplorer {
     fn create(ctx: &Context<Self>) -> Self {
         let fs = Rc::clone(&ctx.props().fs);
         let current_path = "/home".to_string();
+        let list_options = ListOptions {
+            directories_first: true,
+            limit: Some(PAGE_SIZE),
+            ..ListOptions::default()
+        };
         
         // Load initial directory
-        let files = match fs.borrow().list_directory(&current_path) {
-            Ok(files) => files,
-            Err(_) => Vec::new(),
-        };
+        let listing = fs.borrow().list(&current_path, &list_options).unwrap_or_default();
 
         Self {
             fs,
             current_path,
-            files,
+            files: listing.entries,
+            total_files: listing.total,
             selected_file: None,
             error_message: None,
+            list_options,
+            passphrase_dialog: None,
         }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             FileExplorerMsg::NavigateTo(path) => {
-                match self.fs.borrow().list_directory(&path) {
-                    Ok(files) => {
+                // Ask for the passphrase before entering a locked directory
+                let locked_directory = self.fs.borrow().locked_directory(&path);
+                if let Some(directory) = locked_directory {
+                    self.passphrase_dialog = Some(PassphraseDialog::new(PassphrasePurpose::Unlock { directory, path }));
+                    return true;
+                }
+
+                let options = ListOptions { offset: 0, ..self.list_options.clone() };
+                let result = self.fs.borrow().list(&path, &options);
+                match result {
+                    Ok(listing) => {
+                        self.list_options = options;
                         self.current_path = path;
-                        self.files = files;
+                        self.files = listing.entries;
+                        self.total_files = listing.total;
                         self.selected_file = None;
                         true
                     },
                     Err(e) => {
-                        self.error_message = Some(e);
+                        self.error_message = Some(e.to_string());
                         true
                     }
                 }
@@ -80,13 +140,21 @@ impl Component for FileExplorer {
                 false
             },
             FileExplorerMsg::Refresh => {
-                match self.fs.borrow().list_directory(&self.current_path) {
-                    Ok(files) => {
-                        self.files = files;
+                let result = self.fs.borrow().list(&self.current_path, &self.list_options);
+                match result {
+                    Ok(Listing { entries, total }) if entries.is_empty() && total > 0 => {
+                        // The last page emptied out; show the one before it
+                        self.list_options.offset = (total - 1) / PAGE_SIZE * PAGE_SIZE;
+                        ctx.link().send_message(FileExplorerMsg::Refresh);
+                        false
+                    },
+                    Ok(listing) => {
+                        self.files = listing.entries;
+                        self.total_files = listing.total;
                         true
                     },
                     Err(e) => {
-                        self.error_message = Some(e);
+                        self.error_message = Some(e.to_string());
                         true
                     }
                 }
@@ -125,7 +193,7 @@ impl Component for FileExplorer {
                         false
                     },
                     Err(e) => {
-                        self.error_message = Some(e);
+                        self.error_message = Some(e.to_string());
                         true
                     }
                 }
@@ -140,7 +208,7 @@ impl Component for FileExplorer {
                         false
                     },
                     Err(e) => {
-                        self.error_message = Some(e);
+                        self.error_message = Some(e.to_string());
                         true
                     }
                 }
@@ -155,15 +223,102 @@ impl Component for FileExplorer {
                         false
                     },
                     Err(e) => {
-                        self.error_message = Some(e);
+                        self.error_message = Some(e.to_string());
                         true
                     }
                 }
             },
-            FileExplorerMsg::Error(message) => {
-                self.error_message = Some(message);
+            FileExplorerMsg::EncryptDirectory => {
+                self.passphrase_dialog = Some(PassphraseDialog::new(PassphrasePurpose::Encrypt));
                 true
             },
+            FileExplorerMsg::PassphraseChanged(passphrase) => {
+                if let Some(dialog) = &mut self.passphrase_dialog {
+                    dialog.passphrase = passphrase;
+                }
+                false
+            },
+            FileExplorerMsg::RepeatedPassphraseChanged(repeated) => {
+                if let Some(dialog) = &mut self.passphrase_dialog {
+                    dialog.repeated = repeated;
+                }
+                false
+            },
+            FileExplorerMsg::ToggleEncryptNames => {
+                if let Some(dialog) = &mut self.passphrase_dialog {
+                    dialog.encrypt_names = !dialog.encrypt_names;
+                }
+                true
+            },
+            FileExplorerMsg::SubmitPassphrase => {
+                let Some(dialog) = &mut self.passphrase_dialog else {
+                    return false;
+                };
+                let result = match &dialog.purpose {
+                    PassphrasePurpose::Unlock { directory, path } => {
+                        self.fs.borrow_mut().unlock_directory(directory, &dialog.passphrase)
+                            .map(|_| ctx.link().send_message(FileExplorerMsg::NavigateTo(path.clone())))
+                    }
+                    PassphrasePurpose::Encrypt if dialog.passphrase != dialog.repeated => {
+                        Err("Passphrases do not match".to_string())
+                    }
+                    PassphrasePurpose::Encrypt => {
+                        self.fs.borrow_mut().encrypt_directory(&self.current_path, &dialog.passphrase, dialog.encrypt_names)
+                            .map(|_| ctx.link().send_message(FileExplorerMsg::Refresh))
+                    }
+                };
+                match result {
+                    Ok(()) => self.passphrase_dialog = None,
+                    Err(e) => dialog.error = Some(e),
+                }
+                true
+            },
+            FileExplorerMsg::CancelPassphrase => {
+                self.passphrase_dialog = None;
+                true
+            },
+            FileExplorerMsg::LockDirectory => {
+                self.fs.borrow_mut().lock_directory(&self.current_path);
+                ctx.link().send_message(FileExplorerMsg::NavigateUp);
+                false
+            },
+            FileExplorerMsg::ToggleHidden => {
+                self.list_options.include_hidden = !self.list_options.include_hidden;
+                ctx.link().send_message(FileExplorerMsg::Refresh);
+                false
+            },
+            FileExplorerMsg::SortBy(key) => {
+                // Clicking the column sorted by again reverses the order
+                if self.list_options.sort_by == key {
+                    self.list_options.direction = self.list_options.direction.reversed();
+                } else {
+                    self.list_options.sort_by = key;
+                    self.list_options.direction = SortDirection::Ascending;
+                }
+                self.list_options.offset = 0;
+                ctx.link().send_message(FileExplorerMsg::Refresh);
+                false
+            },
+            FileExplorerMsg::Filter(filter) => {
+                self.list_options.filter = Some(filter);
+                self.list_options.offset = 0;
+                ctx.link().send_message(FileExplorerMsg::Refresh);
+                false
+            },
+            FileExplorerMsg::NextPage => {
+                if self.list_options.offset + PAGE_SIZE < self.total_files {
+                    self.list_options.offset += PAGE_SIZE;
+                    ctx.link().send_message(FileExplorerMsg::Refresh);
+                }
+                false
+            },
+            FileExplorerMsg::PreviousPage => {
+                if self.list_options.offset > 0 {
+                    self.list_options.offset = self.list_options.offset.saturating_sub(PAGE_SIZE);
+                    ctx.link().send_message(FileExplorerMsg::Refresh);
+                }
+                false
+            },
             FileExplorerMsg::ClearError => {
                 self.error_message = None;
                 true
@@ -171,6 +326,14 @@ impl Component for FileExplorer {
         }
     }
 
+    fn changed(&mut self, ctx: &Context<Self>, old_props: &Self::Properties) -> bool {
+        // The listing shown may be out of date
+        if ctx.props().fs_revision != old_props.fs_revision {
+            ctx.link().send_message(FileExplorerMsg::Refresh);
+        }
+        true
+    }
+
     fn view(&self, ctx: &Context<Self>) -> Html {
         let path_parts: Vec<String> = self.current_path
             .split('/')
@@ -179,7 +342,9 @@ impl Component for FileExplorer {
             .collect();
         
         html! {
-            <div class="file-explorer" style="display: flex; flex-direction: column; height: 100%;">
+            <div class="file-explorer" style="display: flex; flex-direction: column; height: 100%; position: relative;">
+                { self.passphrase_view(ctx) }
+
                 // Path navigation
                 <div class="path-bar" style="padding: 8px; background-color: #f0f0f0; border-bottom: 1px solid #ddd;">
                     <button onclick={ctx.link().callback(|_| FileExplorerMsg::NavigateUp)}>
@@ -216,6 +381,39 @@ impl Component for FileExplorer {
                     <button onclick={ctx.link().callback(|_| FileExplorerMsg::CreateNewDirectory)}>
                         { "New Directory" }
                     </button>
+                    {
+                        if self.fs.borrow().is_encrypted(&self.current_path) {
+                            html! {
+                                <button onclick={ctx.link().callback(|_| FileExplorerMsg::LockDirectory)}>
+                                    { "🔒 Lock" }
+                                </button>
+                            }
+                        } else {
+                            html! {
+                                <button onclick={ctx.link().callback(|_| FileExplorerMsg::EncryptDirectory)}>
+                                    { "Encrypt" }
+                                </button>
+                            }
+                        }
+                    }
+                    <label style="margin-left: 8px;">
+                        <input
+                            type="checkbox"
+                            checked={self.list_options.include_hidden}
+                            onclick={ctx.link().callback(|_| FileExplorerMsg::ToggleHidden)}
+                        />
+                        { " Show hidden files" }
+                    </label>
+                    <input
+                        type="text"
+                        placeholder="Filter"
+                        style="margin-left: 8px;"
+                        value={self.list_options.filter.clone().unwrap_or_default()}
+                        oninput={ctx.link().callback(|e: InputEvent| {
+                            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                            FileExplorerMsg::Filter(input.value())
+                        })}
+                    />
                 </div>
                 
                 // Error messages
@@ -239,10 +437,10 @@ impl Component for FileExplorer {
                     <table style="width: 100%; border-collapse: collapse;">
                         <thead>
                             <tr style="background-color: #f5f5f5;">
-                                <th style="text-align: left; padding: 8px; border-bottom: 1px solid #ddd;">{ "Name" }</th>
-                                <th style="text-align: left; padding: 8px; border-bottom: 1px solid #ddd;">{ "Type" }</th>
-                                <th style="text-align: right; padding: 8px; border-bottom: 1px solid #ddd;">{ "Size" }</th>
-                                <th style="text-align: right; padding: 8px; border-bottom: 1px solid #ddd;">{ "Modified" }</th>
+                                { self.sort_header(ctx, "Name", SortKey::Name, "left") }
+                                { self.sort_header(ctx, "Type", SortKey::Type, "left") }
+                                { self.sort_header(ctx, "Size", SortKey::Size, "right") }
+                                { self.sort_header(ctx, "Modified", SortKey::Modified, "right") }
                                 <th style="padding: 8px; border-bottom: 1px solid #ddd;">{ "Actions" }</th>
                             </tr>
                         </thead>
@@ -257,6 +455,7 @@ impl Component for FileExplorer {
                                     };
                                     
                                     let type_icon = match file.file_type {
+                                        FileType::Directory if file.encryption.is_some() => "🔐",
                                         FileType::Directory => "📁",
                                         FileType::File => "📄",
                                     };
@@ -308,7 +507,122 @@ impl Component for FileExplorer {
                         </tbody>
                     </table>
                 </div>
+
+                // Pages, for directories with more entries than fit on one
+                {
+                    if self.total_files > PAGE_SIZE {
+                        let first = self.list_options.offset + 1;
+                        let last = (self.list_options.offset + PAGE_SIZE).min(self.total_files);
+                        html! {
+                            <div class="pager" style="padding: 8px; background-color: #f8f8f8; border-top: 1px solid #ddd;">
+                                <button
+                                    disabled={self.list_options.offset == 0}
+                                    onclick={ctx.link().callback(|_| FileExplorerMsg::PreviousPage)}>
+                                    { "← Previous" }
+                                </button>
+                                <span style="margin: 0 8px;">
+                                    { format!("{}–{} of {}", first, last, self.total_files) }
+                                </span>
+                                <button
+                                    disabled={last == self.total_files}
+                                    onclick={ctx.link().callback(|_| FileExplorerMsg::NextPage)}>
+                                    { "Next →" }
+                                </button>
+                            </div>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
             </div>
         }
     }
-} 
\ No newline at end of file
+} 
+
+impl FileExplorer {
+    // The passphrase dialog, over the rest of the explorer, if it is open
+    fn passphrase_view(&self, ctx: &Context<Self>) -> Html {
+        let Some(dialog) = &self.passphrase_dialog else {
+            return html! {};
+        };
+        let (title, choosing) = match &dialog.purpose {
+            PassphrasePurpose::Unlock { directory, .. } => (format!("Passphrase for {}", directory), false),
+            PassphrasePurpose::Encrypt => (format!("New passphrase for {}", self.current_path), true),
+        };
+        let onsubmit = ctx.link().callback(|e: SubmitEvent| {
+            e.prevent_default();
+            FileExplorerMsg::SubmitPassphrase
+        });
+        let oninput = ctx.link().callback(|e: InputEvent| {
+            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+            FileExplorerMsg::PassphraseChanged(input.value())
+        });
+        let oninput_repeated = ctx.link().callback(|e: InputEvent| {
+            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+            FileExplorerMsg::RepeatedPassphraseChanged(input.value())
+        });
+
+        html! {
+            <div class="passphrase-dialog" style="position: absolute; inset: 0; background-color: rgba(0, 0, 0, 0.3); display: flex; align-items: center; justify-content: center; z-index: 10;">
+                <form {onsubmit} style="background-color: white; padding: 16px; border-radius: 4px; box-shadow: 0 2px 10px rgba(0, 0, 0, 0.3); display: flex; flex-direction: column; gap: 8px; min-width: 260px;">
+                    <div>{ title }</div>
+                    <input type="password" autocomplete="off" autofocus=true value={dialog.passphrase.clone()} {oninput} />
+                    {
+                        if choosing {
+                            html! {
+                                <>
+                                    <input
+                                        type="password"
+                                        placeholder="Repeat the passphrase"
+                                        autocomplete="off"
+                                        value={dialog.repeated.clone()}
+                                        oninput={oninput_repeated}
+                                    />
+                                    <label>
+                                        <input
+                                            type="checkbox"
+                                            checked={dialog.encrypt_names}
+                                            onclick={ctx.link().callback(|_| FileExplorerMsg::ToggleEncryptNames)}
+                                        />
+                                        { " Encrypt file names as well" }
+                                    </label>
+                                </>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    {
+                        match &dialog.error {
+                            Some(error) => html! { <div style="color: red;">{ error }</div> },
+                            None => html! {},
+                        }
+                    }
+                    <div style="display: flex; justify-content: flex-end; gap: 8px;">
+                        <button type="button" onclick={ctx.link().callback(|_| FileExplorerMsg::CancelPassphrase)}>
+                            { "Cancel" }
+                        </button>
+                        <button type="submit">{ if choosing { "Encrypt" } else { "Unlock" } }</button>
+                    </div>
+                </form>
+            </div>
+        }
+    }
+
+    // Column header that sorts the listing by `key` when clicked
+    fn sort_header(&self, ctx: &Context<Self>, title: &str, key: SortKey, align: &str) -> Html {
+        let indicator = match (self.list_options.sort_by == key, self.list_options.direction) {
+            (false, _) => "",
+            (true, SortDirection::Ascending) => " ▲",
+            (true, SortDirection::Descending) => " ▼",
+        };
+        let style = format!("text-align: {}; padding: 8px; border-bottom: 1px solid #ddd; cursor: pointer;", align);
+
+        html! {
+            <th style={style} onclick={ctx.link().callback(move |_| FileExplorerMsg::SortBy(key))}>
+                { title }{ indicator }
+            </th>
+        }
+    }
+}
+
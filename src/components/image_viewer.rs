@@ -4,18 +4,15 @@ use std::cell::RefCell;
 use crate::filesystem::FileSystem;
 
 pub struct ImageViewer {
-    fs: Rc<RefCell<FileSystem>>,
     file_path: String,
     error_message: Option<String>,
     zoom_level: f64,
-    image_data: Option<String>,
 }
 
 pub enum ImageViewerMsg {
     ZoomIn,
     ZoomOut,
     ResetZoom,
-    SetError(String),
     ClearError,
 }
 
@@ -30,19 +27,18 @@ impl Component for ImageViewer {
     type Properties = ImageViewerProps;
 
     fn create(ctx: &Context<Self>) -> Self {
-        let fs = Rc::clone(&ctx.props().fs);
         let file_path = ctx.props().file_path.clone();
         
         // In a real implementation, we would load the actual image data
         // For this simplified version, we'll just simulate an image viewer
-        // by showing a placeholder and the file path
+        // by showing a placeholder and the file path, and only reading it to
+        // report a file that can't be read
+        let error_message = ctx.props().fs.borrow().read_file(&file_path).err().map(|e| e.to_string());
         
         Self {
-            fs,
             file_path,
-            error_message: None,
+            error_message,
             zoom_level: 1.0,
-            image_data: None,
         }
     }
 
@@ -60,10 +56,6 @@ impl Component for ImageViewer {
                 self.zoom_level = 1.0;
                 true
             }
-            ImageViewerMsg::SetError(message) => {
-                self.error_message = Some(message);
-                true
-            }
             ImageViewerMsg::ClearError => {
                 self.error_message = None;
                 true
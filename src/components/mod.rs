@@ -8,4 +8,5 @@ pub mod text_editor;
 pub mod clock;
 pub mod image_viewer;
 pub mod file_compressor;
+pub mod settings;
 pub use desktop::Desktop;
\ No newline at end of file
@@ -0,0 +1,243 @@
+use yew::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use crate::filesystem::{FileSystem, SnapshotChange};
+use crate::time::local_time;
+
+pub struct Settings {
+    fs: Rc<RefCell<FileSystem>>,
+    snapshot_name: String,
+    // Changes since a snapshot, as (snapshot name, changes)
+    diff: Option<(String, Vec<SnapshotChange>)>,
+    status_message: Option<(String, bool)>, // (message, is_error)
+}
+
+pub enum SettingsMsg {
+    UpdateSnapshotName(String),
+    CreateSnapshot,
+    RestoreSnapshot(String),
+    DeleteSnapshot(String),
+    DiffSnapshot(String),
+    ClearMessage,
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct SettingsProps {
+    pub fs: Rc<RefCell<FileSystem>>,
+    // Called after a snapshot is restored
+    #[prop_or_default]
+    pub on_restored: Callback<()>,
+}
+
+impl Component for Settings {
+    type Message = SettingsMsg;
+    type Properties = SettingsProps;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        Self {
+            fs: Rc::clone(&ctx.props().fs),
+            snapshot_name: String::new(),
+            diff: None,
+            status_message: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            SettingsMsg::UpdateSnapshotName(name) => {
+                self.snapshot_name = name;
+                false
+            }
+            SettingsMsg::CreateSnapshot => {
+                let name = self.snapshot_name.trim().to_string();
+                let result = self.fs.borrow_mut().create_snapshot(&name);
+                self.status_message = match result {
+                    Ok(info) => {
+                        self.snapshot_name.clear();
+                        Some((format!("Created snapshot {} ({} files)", info.name, info.files), false))
+                    }
+                    Err(e) => Some((format!("Failed to create snapshot: {}", e), true)),
+                };
+                true
+            }
+            SettingsMsg::RestoreSnapshot(name) => {
+                let confirmed = web_sys::window()
+                    .and_then(|window| window.confirm_with_message(
+                        &format!("Restore snapshot {}? Changes made since it was taken will be lost.", name)
+                    ).ok())
+                    .unwrap_or(false);
+                if !confirmed {
+                    return false;
+                }
+
+                let result = self.fs.borrow_mut().restore_snapshot(&name);
+                self.status_message = match result {
+                    Ok(_) => {
+                        ctx.props().on_restored.emit(());
+                        Some((format!("Restored snapshot {}", name), false))
+                    }
+                    Err(e) => Some((format!("Failed to restore snapshot: {}", e), true)),
+                };
+                self.diff = None;
+                true
+            }
+            SettingsMsg::DeleteSnapshot(name) => {
+                let confirmed = web_sys::window()
+                    .and_then(|window| window.confirm_with_message(
+                        &format!("Delete snapshot {}? This can't be undone.", name)
+                    ).ok())
+                    .unwrap_or(false);
+                if !confirmed {
+                    return false;
+                }
+
+                let result = self.fs.borrow_mut().delete_snapshot(&name);
+                self.status_message = match result {
+                    Ok(_) => Some((format!("Deleted snapshot {}", name), false)),
+                    Err(e) => Some((format!("Failed to delete snapshot: {}", e), true)),
+                };
+                if self.diff.as_ref().is_some_and(|(diffed, _)| *diffed == name) {
+                    self.diff = None;
+                }
+                true
+            }
+            SettingsMsg::DiffSnapshot(name) => {
+                let result = self.fs.borrow().diff_snapshot(&name);
+                match result {
+                    Ok(changes) => self.diff = Some((name, changes)),
+                    Err(e) => self.status_message = Some((format!("Failed to compare with snapshot: {}", e), true)),
+                }
+                true
+            }
+            SettingsMsg::ClearMessage => {
+                self.status_message = None;
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let snapshots = self.fs.borrow().list_snapshots();
+
+        html! {
+            <div class="settings" style="display: flex; flex-direction: column; height: 100%; overflow-y: auto; padding: 8px;">
+                <h3 style="margin: 0 0 8px 0;">{ "Snapshots" }</h3>
+                <div style="margin-bottom: 8px; color: #666;">
+                    { "A snapshot records the whole file system, so it can be restored after risky changes." }
+                </div>
+
+                {
+                    if let Some((message, is_error)) = &self.status_message {
+                        let style = if *is_error {
+                            "padding: 8px; background-color: #ffebee; color: #d32f2f; margin-bottom: 8px;"
+                        } else {
+                            "padding: 8px; background-color: #e8f5e9; color: #388e3c; margin-bottom: 8px;"
+                        };
+
+                        html! {
+                            <div style={style}>
+                                { message }
+                                <button
+                                    style="margin-left: 8px; background: none; border: none; cursor: pointer;"
+                                    onclick={ctx.link().callback(|_| SettingsMsg::ClearMessage)}
+                                >
+                                    { "×" }
+                                </button>
+                            </div>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+
+                <div style="margin-bottom: 8px;">
+                    <input
+                        type="text"
+                        placeholder="Snapshot name"
+                        value={self.snapshot_name.clone()}
+                        oninput={ctx.link().callback(|e: InputEvent| {
+                            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                            SettingsMsg::UpdateSnapshotName(input.value())
+                        })}
+                    />
+                    <button style="margin-left: 8px;" onclick={ctx.link().callback(|_| SettingsMsg::CreateSnapshot)}>
+                        { "Create Snapshot" }
+                    </button>
+                </div>
+
+                {
+                    match snapshots {
+                        Ok(snapshots) if snapshots.is_empty() => html! {
+                            <div style="color: #666;">{ "No snapshots yet" }</div>
+                        },
+                        Ok(snapshots) => html! {
+                            <table style="width: 100%; border-collapse: collapse;">
+                                <thead>
+                                    <tr style="background-color: #f0f0f0; text-align: left;">
+                                        <th style="padding: 8px; border-bottom: 1px solid #ddd;">{ "Name" }</th>
+                                        <th style="padding: 8px; border-bottom: 1px solid #ddd;">{ "Created" }</th>
+                                        <th style="padding: 8px; border-bottom: 1px solid #ddd; text-align: right;">{ "Files" }</th>
+                                        <th style="padding: 8px; border-bottom: 1px solid #ddd;">{ "Actions" }</th>
+                                    </tr>
+                                </thead>
+                                <tbody>
+                                    {
+                                        snapshots.into_iter().map(|info| {
+                                            let restore_name = info.name.clone();
+                                            let diff_name = info.name.clone();
+                                            let delete_name = info.name.clone();
+                                            html! {
+                                                <tr style="border-bottom: 1px solid #f0f0f0;">
+                                                    <td style="padding: 8px;">{ &info.name }</td>
+                                                    <td style="padding: 8px;">{ local_time(info.created).to_string() }</td>
+                                                    <td style="padding: 8px; text-align: right;">{ info.files }</td>
+                                                    <td style="padding: 8px;">
+                                                        <button onclick={ctx.link().callback(move |_| SettingsMsg::RestoreSnapshot(restore_name.clone()))}>
+                                                            { "Restore" }
+                                                        </button>
+                                                        <button style="margin-left: 4px;" onclick={ctx.link().callback(move |_| SettingsMsg::DiffSnapshot(diff_name.clone()))}>
+                                                            { "Changes" }
+                                                        </button>
+                                                        <button style="margin-left: 4px;" onclick={ctx.link().callback(move |_| SettingsMsg::DeleteSnapshot(delete_name.clone()))}>
+                                                            { "Delete" }
+                                                        </button>
+                                                    </td>
+                                                </tr>
+                                            }
+                                        }).collect::<Html>()
+                                    }
+                                </tbody>
+                            </table>
+                        },
+                        Err(e) => html! {
+                            <div style="color: #d32f2f;">{ format!("Failed to list snapshots: {}", e) }</div>
+                        },
+                    }
+                }
+
+                {
+                    if let Some((name, changes)) = &self.diff {
+                        html! {
+                            <div style="margin-top: 8px;">
+                                <h4 style="margin: 8px 0;">{ format!("Changes since {}", name) }</h4>
+                                {
+                                    if changes.is_empty() {
+                                        html! { <div style="color: #666;">{ "No changes" }</div> }
+                                    } else {
+                                        html! {
+                                            <pre style="margin: 0; font-family: monospace;">
+                                                { changes.iter().map(|change| change.to_string()).collect::<Vec<String>>().join("\n") }
+                                            </pre>
+                                        }
+                                    }
+                                }
+                            </div>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+            </div>
+        }
+    }
+}
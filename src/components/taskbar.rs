@@ -1,4 +1,5 @@
 use yew::prelude::*;
+use crate::time::{local_time, SharedTimeSource};
 
 #[derive(Properties, Clone, PartialEq)]
 pub struct TaskbarProps {
@@ -8,6 +9,8 @@ pub struct TaskbarProps {
     pub on_create_terminal: Callback<()>,
     pub on_create_text_editor: Callback<()>,
     pub on_create_clock: Callback<()>,
+    #[prop_or_default]
+    pub time: SharedTimeSource,
 }
 
 pub struct Taskbar;
@@ -60,12 +63,12 @@ impl Component for Taskbar {
         ";
 
         // Get current time for clock
-        let now = js_sys::Date::new_0();
-        let hours = now.get_hours();
-        let minutes = now.get_minutes();
+        let now = local_time(ctx.props().time.now());
+        let hours = now.hours;
+        let minutes = now.minutes;
         let time_string = format!(
             "{:02}:{:02} {}",
-            if hours % 12 == 0 { 12 } else { hours % 12 },
+            match hours % 12 { 0 => 12, hours => hours },
             minutes,
             if hours >= 12 { "PM" } else { "AM" }
         );
@@ -1,18 +1,30 @@
 use yew::prelude::*;
 use std::rc::Rc;
 use std::cell::RefCell;
-use web_sys::{HtmlInputElement, KeyboardEvent};
-use crate::filesystem::{FileSystem, FileType, FileMetadata};
+use web_sys::{HtmlElement, HtmlInputElement, KeyboardEvent};
+use crate::filesystem::{FileSystem, FileType, FileMetadata, ListOptions};
+use crate::shell::parser::{self, Incomplete};
+use crate::shell::{ansi, Output, Shell, EXIT_SUCCESS};
 use std::path::Path;
 
 pub struct Terminal {
     fs: Rc<RefCell<FileSystem>>,
-    current_directory: String,
-    command_history: Vec<String>,
+    shell: Shell,
     history_index: Option<usize>,
+    search: Option<ReverseSearch>,
     output_history: Vec<TerminalOutput>,
     current_input: String,
+    // The lines of a command still being typed, as after an open quote
+    continuation: String,
+    // What Ctrl+U, Ctrl+K or Ctrl+W last cut, for Ctrl+Y to put back
+    kill_buffer: String,
+    // Where to put the caret once the input shows what it was set to, as a
+    // byte offset into current_input
+    caret: Option<usize>,
     input_ref: NodeRef,
+    output_ref: NodeRef,
+    // Ten characters, hidden, to tell how wide one is
+    measure_ref: NodeRef,
 }
 
 pub enum TerminalMsg {
@@ -20,16 +32,42 @@ pub enum TerminalMsg {
     ExecuteCommand,
     KeyDown(KeyboardEvent),
     ScrollToBottom,
+    // A link in the output was clicked
+    OpenLink(String),
 }
 
 #[derive(Properties, Clone, PartialEq)]
 pub struct TerminalProps {
     pub fs: Rc<RefCell<FileSystem>>,
+    // Called with (path, file_type) to open a file linked from the output
+    #[prop_or_default]
+    pub on_open_file: Callback<(String, String)>,
+    // Changes when another tab changes the file system, which may have
+    // changed what the prompt shows, like the working directory
+    #[prop_or_default]
+    pub fs_revision: u32,
+}
+
+// The 16 colours of ANSI escape sequences: black, red, green, yellow, blue,
+// magenta, cyan and white, then their bright versions
+const ANSI_COLORS: [&str; 16] = [
+    "#1e1e1e", "#ff6b6b", "#7ec699", "#e6c07b", "#61afef", "#c678dd", "#56b6c2", "#d0d0d0",
+    "#808080", "#ff8787", "#98e0b0", "#f0d399", "#82c0ff", "#dc9cf0", "#7fd7e0", "#ffffff",
+];
+
+// An incremental search back through the history, started with Ctrl+R
+struct ReverseSearch {
+    query: String,
+    // The line of the history found, if any
+    found: Option<usize>,
+    // What was typed before, to go back to if the search is cancelled
+    original: String,
 }
 
 #[derive(Clone, PartialEq)]
 enum TerminalOutput {
-    Command(String),
+    // A command line as typed, with the exit status it ended with once it has run
+    Command(String, Option<i32>),
     StandardOutput(String),
     ErrorOutput(String),
 }
@@ -39,17 +77,33 @@ impl Component for Terminal {
     type Properties = TerminalProps;
 
     fn create(ctx: &Context<Self>) -> Self {
+        let mut shell = Shell::new(Rc::clone(&ctx.props().fs));
+        shell.set_terminal(true);
+        let mut output_history = vec![
+            TerminalOutput::StandardOutput("WasmOS Terminal v0.1.0".to_string()),
+            TerminalOutput::StandardOutput("Type 'help' for available commands.".to_string()),
+        ];
+        // ~/.kshrc runs before anything is typed
+        for output in shell.run_startup_file() {
+            match output {
+                Output::Stdout(text) => output_history.push(TerminalOutput::StandardOutput(text.strip_suffix('\n').unwrap_or(&text).to_string())),
+                Output::Stderr(text) => output_history.push(TerminalOutput::ErrorOutput(text.strip_suffix('\n').unwrap_or(&text).to_string())),
+                Output::Clear => output_history.clear(),
+            }
+        }
         Self {
             fs: Rc::clone(&ctx.props().fs),
-            current_directory: "/home".to_string(),
-            command_history: Vec::new(),
+            shell,
             history_index: None,
-            output_history: vec![
-                TerminalOutput::StandardOutput("WasmOS Terminal v0.1.0".to_string()),
-                TerminalOutput::StandardOutput("Type 'help' for available commands.".to_string()),
-            ],
+            search: None,
+            output_history,
             current_input: String::new(),
+            continuation: String::new(),
+            kill_buffer: String::new(),
+            caret: None,
             input_ref: NodeRef::default(),
+            output_ref: NodeRef::default(),
+            measure_ref: NodeRef::default(),
         }
     }
 
@@ -60,47 +114,82 @@ impl Component for Terminal {
                 true
             }
             TerminalMsg::ExecuteCommand => {
-                let command = self.current_input.trim().to_string();
-                if !command.is_empty() {
-                    self.execute_command(&command);
+                let line = self.current_input.clone();
+                let text = format!("{}{}", self.continuation, line);
+                if let Some(incomplete) = parser::incomplete(&text) {
+                    // Read another line. A backslash joins it on, except in
+                    // single quotes, where the newline is part of them.
+                    let escaped = text.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1;
+                    self.output_history.push(TerminalOutput::Command(format!("{}{}", self.prompt(), line), None));
+                    self.continuation = match incomplete {
+                        Incomplete::Backslash | Incomplete::DoubleQuote if escaped => text[..text.len() - 1].to_string(),
+                        _ => format!("{}\n", text),
+                    };
+                    self.current_input = String::new();
+                } else if !text.trim().is_empty() {
+                    // Only the end of the last line goes; whitespace in
+                    // quotes over several lines is part of the command
+                    self.execute_command(text.trim_end_matches(['\r', '\n']), &line);
                     self.current_input = String::new();
-                    ctx.link().send_message(TerminalMsg::ScrollToBottom);
                 }
+                ctx.link().send_message(TerminalMsg::ScrollToBottom);
                 true
             }
             TerminalMsg::KeyDown(event) => {
+                if self.search.is_some() && self.search_key(ctx, &event) {
+                    event.prevent_default();
+                    return true;
+                }
+                if self.edit_key(&event) {
+                    event.prevent_default();
+                    return true;
+                }
                 match event.key().as_str() {
+                    "r" if event.ctrl_key() => {
+                        event.prevent_default();
+                        self.search = Some(ReverseSearch {
+                            query: String::new(),
+                            found: None,
+                            original: self.current_input.clone(),
+                        });
+                        return true;
+                    }
                     "Enter" => {
                         ctx.link().send_message(TerminalMsg::ExecuteCommand);
                     }
                     "ArrowUp" => {
                         event.prevent_default();
                         // Navigate command history (previous)
-                        if !self.command_history.is_empty() {
-                            let index = match self.history_index {
-                                None => self.command_history.len() - 1,
-                                Some(i) if i > 0 => i - 1,
-                                Some(i) => i,
-                            };
-                            self.history_index = Some(index);
-                            self.current_input = self.command_history[index].clone();
+                        let history = self.shell.history();
+                        // Another Terminal may have cleared or trimmed the
+                        // history since, so the index can be past its end
+                        let index = self.history_index
+                            .filter(|i| *i <= history.len())
+                            .unwrap_or(history.len())
+                            .checked_sub(1);
+                        if let Some(i) = index {
+                            self.history_index = Some(i);
+                            self.current_input = history[i].clone();
+                        } else if history.is_empty() {
+                            self.history_index = None;
                         }
                         return true;
                     }
                     "ArrowDown" => {
                         event.prevent_default();
                         // Navigate command history (next)
-                        match self.history_index {
-                            Some(i) if i < self.command_history.len() - 1 => {
-                                let next_index = i + 1;
-                                self.history_index = Some(next_index);
-                                self.current_input = self.command_history[next_index].clone();
-                            }
-                            Some(_) => {
-                                self.history_index = None;
-                                self.current_input = String::new();
+                        let history = self.shell.history();
+                        if let Some(i) = self.history_index {
+                            match history.get(i + 1) {
+                                Some(entry) => {
+                                    self.history_index = Some(i + 1);
+                                    self.current_input = entry.clone();
+                                }
+                                None => {
+                                    self.history_index = None;
+                                    self.current_input = String::new();
+                                }
                             }
-                            None => {}
                         }
                         return true;
                     }
@@ -115,8 +204,9 @@ impl Component for Terminal {
                                 // Command completion
                                 if parts.len() == 1 {
                                     let cmd = parts[0];
-                                    let commands = vec!["help", "cd", "pwd", "ls", "cat", "echo", "clear", "mkdir", "touch", "rm", "history"];
-                                    let matches: Vec<&str> = commands.into_iter()
+                                    let commands = self.shell.commands();
+                                    let commands = commands.borrow();
+                                    let matches: Vec<&str> = commands.names()
                                         .filter(|c| c.starts_with(cmd))
                                         .collect();
                                     
@@ -126,7 +216,7 @@ impl Component for Terminal {
                                         return true;
                                     } else if matches.len() > 1 {
                                         // Multiple matches, show options
-                                        self.output_history.push(TerminalOutput::Command(format!("{} $ {}", self.current_directory, input)));
+                                        self.output_history.push(TerminalOutput::Command(format!("{}{}", self.shell.prompt(), input), None));
                                         self.output_history.push(TerminalOutput::StandardOutput(
                                             matches.join("  ")
                                         ));
@@ -135,9 +225,10 @@ impl Component for Terminal {
                                 }
                                 
                                 // File/directory completion
-                                if parts.len() > 1 || parts[0] == "cd" || parts[0] == "ls" || parts[0] == "cat" || parts[0] == "rm" || parts[0] == "touch" {
+                                let takes_paths = self.shell.commands().borrow().get(parts[0]).is_some_and(|command| command.takes_paths());
+                                if parts.len() > 1 || takes_paths {
                                     let path_part = if parts.len() > 1 { parts[parts.len() - 1] } else { "" };
-                                    let path_to_complete = self.resolve_path(path_part);
+                                    let path_to_complete = self.shell.resolve_path(path_part);
                                     
                                     // Get directory part and file prefix
                                     let (dir_path, file_prefix) = if path_to_complete.ends_with('/') {
@@ -153,11 +244,17 @@ impl Component for Terminal {
                                         }
                                     };
                                     
-                                    // List files in directory
-                                    match self.fs.borrow().list_directory(&dir_path) {
-                                        Ok(files) => {
+                                    // List files in directory; hidden ones only when the
+                                    // prefix asks for them
+                                    let options = ListOptions {
+                                        include_hidden: file_prefix.starts_with('.'),
+                                        ..ListOptions::default()
+                                    };
+                                    let listing = self.fs.borrow().list(&dir_path, &options);
+                                    match listing {
+                                        Ok(listing) => {
                                             // Filter files that match the prefix
-                                            let matches: Vec<FileMetadata> = files.into_iter()
+                                            let matches: Vec<FileMetadata> = listing.entries.into_iter()
                                                 .filter(|f| f.name.starts_with(&file_prefix))
                                                 .collect();
                                             
@@ -192,7 +289,7 @@ impl Component for Terminal {
                                                 return true;
                                             } else if matches.len() > 1 {
                                                 // Multiple matches, show options
-                                                self.output_history.push(TerminalOutput::Command(format!("{} $ {}", self.current_directory, input)));
+                                                self.output_history.push(TerminalOutput::Command(format!("{}{}", self.shell.prompt(), input), None));
                                                 let matches_str = matches.iter()
                                                     .map(|f| {
                                                         match f.file_type {
@@ -223,6 +320,17 @@ impl Component for Terminal {
                 // This happens after rendering
                 false
             }
+            TerminalMsg::OpenLink(uri) => {
+                // Files open in the Text Editor; there is nowhere to open anything else
+                if let Some(path) = uri.strip_prefix("file://") {
+                    let is_file = self.fs.borrow().metadata(path)
+                        .is_ok_and(|metadata| metadata.file_type == FileType::File);
+                    if is_file {
+                        ctx.props().on_open_file.emit((path.to_string(), "text".to_string()));
+                    }
+                }
+                false
+            }
         }
     }
 
@@ -235,15 +343,24 @@ impl Component for Terminal {
         
         html! {
             <div class="terminal" style="height: 100%; overflow: hidden; display: flex; flex-direction: column; background-color: #1e1e1e; color: #f0f0f0; font-family: monospace;">
-                <div class="terminal-output" style="flex-grow: 1; overflow-y: auto; padding: 8px; white-space: pre-wrap;">
+                <span ref={self.measure_ref.clone()} style="position: absolute; visibility: hidden; white-space: pre;">{ "0000000000" }</span>
+                <div class="terminal-output" ref={self.output_ref.clone()} style="flex-grow: 1; overflow-y: auto; padding: 8px; white-space: pre-wrap;">
                     {
                         self.output_history.iter().map(|output| {
                             match output {
-                                TerminalOutput::Command(text) => {
+                                TerminalOutput::Command(text, Some(status)) if *status != EXIT_SUCCESS => {
+                                    html! {
+                                        <div style="color: #f0f0f0; padding: 2px 0 2px 4px; border-left: 3px solid #ff6b6b;">
+                                            { text }
+                                            <span style="color: #ff6b6b; margin-left: 8px;">{ format!("[exit {}]", status) }</span>
+                                        </div>
+                                    }
+                                }
+                                TerminalOutput::Command(text, _) => {
                                     html! { <div style="color: #f0f0f0; padding: 2px 0;">{ text }</div> }
                                 }
                                 TerminalOutput::StandardOutput(text) => {
-                                    html! { <div style="color: #a0a0a0; padding: 2px 0;">{ text }</div> }
+                                    html! { <div style="color: #a0a0a0; padding: 2px 0;">{ self.render_text(ctx, text) }</div> }
                                 }
                                 TerminalOutput::ErrorOutput(text) => {
                                     html! { <div style="color: #ff6b6b; padding: 2px 0;">{ text }</div> }
@@ -253,7 +370,13 @@ impl Component for Terminal {
                     }
                 </div>
                 <div class="terminal-input" style="display: flex; padding: 8px; border-top: 1px solid #333;">
-                    <span>{ format!("{} $ ", self.current_directory) }</span>
+                    <span style="white-space: pre;">{
+                        match &self.search {
+                            Some(search) if search.found.is_none() && !search.query.is_empty() => format!("(failed reverse-i-search)`{}': ", search.query),
+                            Some(search) => format!("(reverse-i-search)`{}': ", search.query),
+                            None => self.prompt(),
+                        }
+                    }</span>
                     <input 
                         type="text"
                         style="flex-grow: 1; background-color: transparent; border: none; color: #f0f0f0; font-family: monospace; outline: none;"
@@ -276,6 +399,13 @@ impl Component for Terminal {
                 let _ = input.focus();
             }
         }
+        if let Some(caret) = self.caret.take() {
+            if let Some(input) = self.input_ref.cast::<HtmlInputElement>() {
+                // The input counts in UTF-16 code units
+                let position = self.current_input[..caret].encode_utf16().count() as u32;
+                let _ = input.set_selection_range(position, position);
+            }
+        }
         
         // Scroll to bottom when new output is added
         if let Some(output_div) = web_sys::window()
@@ -288,183 +418,314 @@ impl Component for Terminal {
     }
 }
 
-impl Terminal {
-    fn execute_command(&mut self, command: &str) {
-        self.output_history.push(TerminalOutput::Command(format!("{} $ {}", self.current_directory, command)));
-        
-        // Save command to history
-        if !command.trim().is_empty() && (!self.command_history.is_empty() && self.command_history.last().unwrap() != command) {
-            self.command_history.push(command.to_string());
-        }
-        
-        if self.command_history.len() > 50 {
-            self.command_history.remove(0);
-        }
-        
-        self.history_index = None;
-        
-        let parts: Vec<&str> = command.trim().split_whitespace().collect();
-        if parts.is_empty() {
-            return;
-        }
+// Where the word the caret is in or after starts, for Alt+B
+fn word_start(text: &str, caret: usize) -> usize {
+    word_start_by(text, caret, |c| !c.is_alphanumeric())
+}
 
-        match parts[0] {
-            "help" => {
-                self.output_history.push(TerminalOutput::StandardOutput(
-                    "Available commands:\n\
-                    help       - Show this help\n\
-                    cd [path]  - Change directory\n\
-                    pwd        - Print working directory\n\
-                    ls         - List directory contents\n\
-                    cat [file] - Display file contents\n\
-                    echo [text]- Display text\n\
-                    clear      - Clear terminal\n\
-                    mkdir [dir]- Create directory\n\
-                    touch [file]- Create empty file\n\
-                    rm [path]  - Remove file or directory\n\
-                    history    - Display command history".to_string()
-                ));
+// Where the word before the caret starts, words being what is between the
+// characters `separator` is true for
+fn word_start_by(text: &str, caret: usize, separator: impl Fn(char) -> bool) -> usize {
+    let before = text[..caret].trim_end_matches(&separator);
+    match before.char_indices().rev().find(|(_, c)| separator(*c)) {
+        Some((i, c)) => i + c.len_utf8(),
+        None => 0,
+    }
+}
+
+// Where the word the caret is in or before ends, for Alt+F
+fn word_end(text: &str, caret: usize) -> usize {
+    let after = &text[caret..];
+    let start = after.find(char::is_alphanumeric).unwrap_or(after.len());
+    let length = after[start..].find(|c: char| !c.is_alphanumeric()).unwrap_or(after.len() - start);
+    caret + start + length
+}
+
+// Take a range out of the text, if there's anything in it, so that killing
+// nothing keeps what was killed before
+fn cut(text: &mut String, range: std::ops::Range<usize>) -> Option<String> {
+    (!range.is_empty()).then(|| text.drain(range).collect())
+}
+
+// The last line of the history before `before` with `query` in it
+fn find_back(history: &[String], query: &str, before: usize) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    history[..before.min(history.len())].iter().rposition(|line| line.contains(query))
+}
+
+impl Terminal {
+    // Output as the escape sequences in it style it
+    fn render_text(&self, ctx: &Context<Self>, text: &str) -> Html {
+        ansi::parse(text).into_iter().map(|span| {
+            let mut style = String::new();
+            if let Some(color) = span.style.foreground {
+                style.push_str(&format!("color: {};", ANSI_COLORS[color as usize]));
             }
-            "cd" => {
-                let target = if parts.len() > 1 { parts[1] } else { "/" };
-                let path = self.resolve_path(target);
-                
-                match self.fs.borrow().list_directory(&path) {
-                    Ok(_) => {
-                        self.current_directory = path;
-                    }
-                    Err(e) => {
-                        self.output_history.push(TerminalOutput::ErrorOutput(format!("cd: {}", e)));
-                    }
-                }
+            if span.style.bold {
+                style.push_str("font-weight: bold;");
             }
-            "pwd" => {
-                self.output_history.push(TerminalOutput::StandardOutput(self.current_directory.clone()));
+            if span.style.underline {
+                style.push_str("text-decoration: underline;");
             }
-            "ls" => {
-                let path = if parts.len() > 1 {
-                    self.resolve_path(parts[1])
-                } else {
-                    self.current_directory.clone()
-                };
-                
-                match self.fs.borrow().list_directory(&path) {
-                    Ok(files) => {
-                        let mut output = String::new();
-                        for file in files {
-                            let type_indicator = match file.file_type {
-                                FileType::Directory => "/",
-                                FileType::File => "",
-                            };
-                            output.push_str(&format!("{}{}\n", file.name, type_indicator));
-                        }
-                        self.output_history.push(TerminalOutput::StandardOutput(output));
-                    }
-                    Err(e) => {
-                        self.output_history.push(TerminalOutput::ErrorOutput(format!("ls: {}", e)));
+
+            match span.link {
+                Some(uri) => {
+                    let onclick = ctx.link().callback(move |e: MouseEvent| {
+                        e.prevent_default();
+                        TerminalMsg::OpenLink(uri.clone())
+                    });
+                    html! {
+                        <a href="#" style={format!("color: inherit; {} text-decoration: underline; cursor: pointer;", style)} {onclick}>{ span.text }</a>
                     }
                 }
+                None if style.is_empty() => html! { { span.text } },
+                None => html! { <span {style}>{ span.text }</span> },
             }
-            "cat" => {
-                if parts.len() < 2 {
-                    self.output_history.push(TerminalOutput::ErrorOutput("cat: missing file operand".to_string()));
-                    return;
-                }
-                
-                let path = self.resolve_path(parts[1]);
-                match self.fs.borrow().read_file(&path) {
-                    Ok(content) => {
-                        self.output_history.push(TerminalOutput::StandardOutput(content));
-                    }
-                    Err(e) => {
-                        self.output_history.push(TerminalOutput::ErrorOutput(format!("cat: {}", e)));
-                    }
+        }).collect::<Html>()
+    }
+
+    // How many characters fit across the output, less its padding
+    fn columns(&self) -> Option<usize> {
+        let output = self.output_ref.cast::<HtmlElement>()?;
+        let measure = self.measure_ref.cast::<HtmlElement>()?;
+        let character = measure.offset_width() as f64 / 10.0;
+        if character <= 0.0 {
+            return None;
+        }
+        let width = (output.client_width() - 16).max(0) as f64;
+        Some(((width / character).floor() as usize).max(1))
+    }
+
+    // Handle a key during a reverse search, saying whether it was used up.
+    // Typing adds to what is searched for and Ctrl+R finds the line before;
+    // Enter runs what was found, Escape or Ctrl+G goes back to what was typed,
+    // and any other key leaves the line found to be edited.
+    fn search_key(&mut self, ctx: &Context<Self>, event: &KeyboardEvent) -> bool {
+        let Some(search) = self.search.as_mut() else {
+            return false;
+        };
+        let history = self.shell.history();
+        let key = event.key();
+        let control = event.ctrl_key() || event.meta_key();
+        match key.as_str() {
+            "r" if control => {
+                // The line before the one found, or the last if it was found
+                let before = search.found.unwrap_or(history.len());
+                if let Some(found) = find_back(&history, &search.query, before) {
+                    search.found = Some(found);
                 }
             }
-            "echo" => {
-                let text = if parts.len() > 1 {
-                    parts[1..].join(" ")
-                } else {
-                    String::new()
-                };
-                self.output_history.push(TerminalOutput::StandardOutput(text));
+            "Backspace" => {
+                search.query.pop();
+                search.found = find_back(&history, &search.query, history.len());
             }
-            "clear" => {
-                self.output_history = Vec::new();
+            "Escape" => self.end_search(true),
+            "g" if control => self.end_search(true),
+            "Enter" => {
+                self.end_search(false);
+                ctx.link().send_message(TerminalMsg::ExecuteCommand);
             }
-            "mkdir" => {
-                if parts.len() < 2 {
-                    self.output_history.push(TerminalOutput::ErrorOutput("mkdir: missing directory operand".to_string()));
-                    return;
-                }
-                
-                let path = self.resolve_path(parts[1]);
-                match self.fs.borrow_mut().create_directory(&path, false) {
-                    Ok(_) => {},
-                    Err(e) => {
-                        self.output_history.push(TerminalOutput::ErrorOutput(format!("mkdir: {}", e)));
-                    }
-                }
+            _ if key.chars().count() == 1 && !control && !event.alt_key() => {
+                search.query.push_str(&key);
+                // The line found may still match; otherwise look further back
+                let from = search.found.map_or(history.len(), |found| found + 1);
+                search.found = find_back(&history, &search.query, from);
             }
-            "touch" => {
-                if parts.len() < 2 {
-                    self.output_history.push(TerminalOutput::ErrorOutput("touch: missing file operand".to_string()));
-                    return;
-                }
-                
-                let path = self.resolve_path(parts[1]);
-                match self.fs.borrow_mut().write_file(&path, "") {
-                    Ok(_) => {},
-                    Err(e) => {
-                        self.output_history.push(TerminalOutput::ErrorOutput(format!("touch: {}", e)));
-                    }
-                }
+            // Modifiers on their own
+            "Shift" | "Control" | "Alt" | "Meta" => {}
+            _ => {
+                self.end_search(false);
+                return false;
             }
-            "rm" => {
-                if parts.len() < 2 {
-                    self.output_history.push(TerminalOutput::ErrorOutput("rm: missing operand".to_string()));
-                    return;
-                }
-                
-                let path = self.resolve_path(parts[1]);
-                let recursive = parts.len() > 2 && parts[2] == "-r";
-                
-                match self.fs.borrow_mut().delete(&path, recursive) {
-                    Ok(_) => {},
-                    Err(e) => {
-                        self.output_history.push(TerminalOutput::ErrorOutput(format!("rm: {}", e)));
-                    }
-                }
+        }
+
+        if let Some(search) = &self.search {
+            self.current_input = match search.found {
+                Some(found) => history[found].clone(),
+                None if search.query.is_empty() => search.original.clone(),
+                None => self.current_input.clone(),
+            };
+        }
+        true
+    }
+
+    // Stop searching, going back to what was typed if `cancel`
+    fn end_search(&mut self, cancel: bool) {
+        if let Some(search) = self.search.take() {
+            if cancel {
+                self.current_input = search.original;
             }
-            "history" => {
-                // Display command history
-                if self.command_history.is_empty() {
-                    self.output_history.push(TerminalOutput::StandardOutput("No command history".to_string()));
-                } else {
-                    let mut history_output = "Command History:".to_string();
-                    for (i, cmd) in self.command_history.iter().enumerate() {
-                        history_output.push_str(&format!("\n{}: {}", i + 1, cmd));
-                    }
-                    self.output_history.push(TerminalOutput::StandardOutput(history_output));
-                }
+        }
+    }
+
+    // The prompt for the line being typed: PS1, or PS2 for the lines after
+    // the first of a command that goes on
+    fn prompt(&self) -> String {
+        if self.continuation.is_empty() {
+            self.shell.prompt()
+        } else {
+            self.shell.continuation_prompt()
+        }
+    }
+
+    // Handle an Emacs-style editing key, saying whether it was one
+    fn edit_key(&mut self, event: &KeyboardEvent) -> bool {
+        let control = event.ctrl_key() && !event.alt_key() && !event.meta_key();
+        // Alt+letter types another character on some keyboards, so go by the key
+        let alt = event.alt_key() && !event.ctrl_key() && !event.meta_key();
+        let caret = self.caret_position();
+        let input = &self.current_input;
+
+        if alt {
+            match event.code().as_str() {
+                "KeyB" => self.caret = Some(word_start(input, caret)),
+                "KeyF" => self.caret = Some(word_end(input, caret)),
+                _ => return false,
             }
-            _ => {
-                self.output_history.push(TerminalOutput::ErrorOutput(format!("Unknown command: {}", parts[0])));
+            return true;
+        }
+        if !control {
+            return false;
+        }
+        match event.key().to_lowercase().as_str() {
+            "a" => self.caret = Some(0),
+            "e" => self.caret = Some(input.len()),
+            "u" => self.kill(0..caret),
+            "k" => self.kill(caret..input.len()),
+            "w" => {
+                // Back to the whitespace before the word, as in bash
+                let start = word_start_by(input, caret, char::is_whitespace);
+                self.kill(start..caret);
             }
+            "y" => {
+                self.current_input.insert_str(caret, &self.kill_buffer);
+                self.caret = Some(caret + self.kill_buffer.len());
+            }
+            "l" => self.output_history.clear(),
+            // With text selected it copies, as it does elsewhere
+            "c" if self.has_selection() => return false,
+            "c" => {
+                // Drop the line, and any it went on from
+                self.output_history.push(TerminalOutput::Command(format!("{}{}^C", self.prompt(), input), None));
+                self.current_input = String::new();
+                self.continuation = String::new();
+                self.history_index = None;
+            }
+            _ => return false,
         }
+        true
     }
 
-    fn resolve_path(&self, path: &str) -> String {
-        if path.starts_with('/') {
-            path.to_string()
-        } else {
-            let current = if self.current_directory.ends_with('/') {
-                self.current_directory.clone()
-            } else {
-                format!("{}/", self.current_directory)
-            };
-            
-            format!("{}{}", current, path)
+    // Cut a range of the input, keeping it for Ctrl+Y
+    fn kill(&mut self, range: std::ops::Range<usize>) {
+        let start = range.start;
+        if let Some(killed) = cut(&mut self.current_input, range) {
+            self.caret = Some(start);
+            self.kill_buffer = killed;
+        }
+    }
+
+    fn has_selection(&self) -> bool {
+        self.input_ref.cast::<HtmlInputElement>()
+            .is_some_and(|input| input.selection_start().ok().flatten() != input.selection_end().ok().flatten())
+    }
+
+    // Where the caret is, as a byte offset into current_input
+    fn caret_position(&self) -> usize {
+        let position = self.input_ref.cast::<HtmlInputElement>()
+            .and_then(|input| input.selection_start().ok().flatten())
+            .map_or(usize::MAX, |position| position as usize);
+        // The input counts in UTF-16 code units
+        let mut units = 0;
+        for (i, c) in self.current_input.char_indices() {
+            if units >= position {
+                return i;
+            }
+            units += c.len_utf16();
+        }
+        self.current_input.len()
+    }
+
+    // Run a command, showing `line` after the prompt as the last line typed
+    fn execute_command(&mut self, command: &str, line: &str) {
+        let mut command_index = Some(self.output_history.len());
+        self.output_history.push(TerminalOutput::Command(format!("{}{}", self.prompt(), line), None));
+        self.continuation = String::new();
+        self.history_index = None;
+
+        if let Some(columns) = self.columns() {
+            self.shell.set_variable("COLUMNS", &columns.to_string());
+        }
+        let outputs = self.shell.run(command);
+        for output in outputs {
+            match output {
+                Output::Stdout(text) => {
+                    let text = text.strip_suffix('\n').unwrap_or(&text);
+                    self.output_history.push(TerminalOutput::StandardOutput(text.to_string()));
+                }
+                Output::Stderr(text) => {
+                    let text = text.strip_suffix('\n').unwrap_or(&text);
+                    self.output_history.push(TerminalOutput::ErrorOutput(text.to_string()));
+                }
+                Output::Clear => {
+                    self.output_history = Vec::new();
+                    command_index = None;
+                }
+            }
+        }
+
+        // Mark the command with how it ended, unless it cleared the terminal
+        if let Some(TerminalOutput::Command(_, status)) = command_index.and_then(|index| self.output_history.get_mut(index)) {
+            *status = Some(self.shell.last_status());
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt_b_and_alt_f_move_by_words() {
+        let text = "cat  some-file.txt|wc";
+        assert_eq!(word_start(text, text.len()), 19);
+        assert_eq!(word_start(text, 19), 15);
+        assert_eq!(word_start(text, 14), 10);
+        assert_eq!(word_start(text, 7), 5);
+        assert_eq!(word_start(text, 5), 0);
+        assert_eq!(word_start(text, 0), 0);
+
+        assert_eq!(word_end(text, 0), 3);
+        assert_eq!(word_end(text, 3), 9);
+        assert_eq!(word_end(text, 9), 14);
+        assert_eq!(word_end(text, 18), 21);
+        assert_eq!(word_end(text, 21), 21);
+        assert_eq!(word_end("é  ü", 0), 2);
+    }
+
+    #[test]
+    fn ctrl_w_kills_back_to_whitespace() {
+        let mut text = "ls -l  some/dir/ ".to_string();
+        let caret = text.len();
+        let start = word_start_by(&text, caret, char::is_whitespace);
+        assert_eq!(start, 7);
+        assert_eq!(cut(&mut text, start..caret).as_deref(), Some("some/dir/ "));
+        assert_eq!(text, "ls -l  ");
+
+        let start = word_start_by(&text, text.len(), char::is_whitespace);
+        assert_eq!(start, 3);
+        assert_eq!(word_start_by("  ", 2, char::is_whitespace), 0);
+    }
+
+    #[test]
+    fn killing_nothing_keeps_the_text() {
+        let mut text = "echo hi".to_string();
+        assert_eq!(cut(&mut text, 0..0), None);
+        assert_eq!(cut(&mut text, 7..7), None);
+        assert_eq!(text, "echo hi");
+        assert_eq!(cut(&mut text, 0..5).as_deref(), Some("echo "));
+        assert_eq!(text, "hi");
+    }
+}
@@ -10,6 +10,7 @@ use crate::components::text_editor::TextEditor;
 use crate::components::clock::Clock;
 use crate::components::image_viewer::ImageViewer;
 use crate::components::file_compressor::FileCompressor;
+use crate::components::settings::Settings;
 
 // Window state
 #[derive(Debug, Clone, PartialEq)]
@@ -28,13 +29,13 @@ pub struct WindowState {
 // Different types of window content
 #[derive(Clone, PartialEq, Debug)]
 pub enum WindowContentType {
-    Empty,
     Terminal,
     FileExplorer,
     TextEditor { file_path: Option<String> },
     Clock,
     ImageViewer { file_path: String },
     FileCompressor,
+    Settings,
 }
 
 // Properties for the Window component
@@ -42,10 +43,18 @@ pub enum WindowContentType {
 pub struct WindowProps {
     pub window: Rc<RefCell<WindowState>>,
     pub fs: Rc<RefCell<FileSystem>>,
+    // Changes when another tab changes the file system, or a snapshot is
+    // restored
+    #[prop_or_default]
+    pub fs_revision: u32,
     pub on_close: Callback<String>,
     pub on_focus: Callback<String>,
     pub on_minimize: Callback<String>,
     pub on_open_file: Callback<(String, String)>,
+    // Called when a window replaces the whole file system, for the others to
+    // show what is there now
+    #[prop_or_default]
+    pub on_fs_replaced: Callback<()>,
 }
 
 // Window component
@@ -180,26 +189,29 @@ impl Window {
         let window = ctx.props().window.borrow();
         let fs = Rc::clone(&ctx.props().fs);
         let on_open_file = ctx.props().on_open_file.clone();
+        let fs_revision = ctx.props().fs_revision;
         
         match &window.content_type {
-            WindowContentType::Empty => html! {},
             WindowContentType::Terminal => {
-                html! { <Terminal fs={fs} /> }
+                html! { <Terminal fs={fs} {fs_revision} on_open_file={on_open_file} /> }
             }
             WindowContentType::FileExplorer => {
-                html! { <FileExplorer fs={fs} on_open_file={on_open_file} /> }
+                html! { <FileExplorer fs={fs} {fs_revision} on_open_file={on_open_file} /> }
             }
             WindowContentType::TextEditor { file_path } => {
                 html! { <TextEditor fs={fs} file_path={file_path.clone()} /> }
             }
             WindowContentType::Clock => {
-                html! { <Clock /> }
+                html! { <Clock time={fs.borrow().time_source()} /> }
             }
             WindowContentType::ImageViewer { file_path } => {
                 html! { <ImageViewer fs={fs} file_path={file_path.clone()} /> }
             }
             WindowContentType::FileCompressor => {
-                html! { <FileCompressor fs={fs} /> }
+                html! { <FileCompressor fs={fs} {fs_revision} /> }
+            }
+            WindowContentType::Settings => {
+                html! { <Settings fs={fs} on_restored={ctx.props().on_fs_replaced.clone()} /> }
             }
         }
     }
@@ -0,0 +1,12 @@
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+
+const COMPRESSION_LEVEL: u8 = 6;
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    compress_to_vec(data, COMPRESSION_LEVEL)
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    decompress_to_vec(data).map_err(|e| format!("Failed to decompress data: {:?}", e.status))
+}
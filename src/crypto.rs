@@ -0,0 +1,184 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+pub const SALT_LEN: usize = 16;
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+// What each subkey of a passphrase key is for, so that none is used for two
+// things
+const ENCRYPTION_LABEL: &[u8] = b"kernelos contents and names";
+const NAME_NONCE_LABEL: &[u8] = b"kernelos name nonces";
+
+// Symmetric keys derived from a passphrase: one to encrypt with, and one to
+// derive the nonces of names from. Deliberately not Debug-printable with its
+// contents so it can't end up in the logs.
+#[derive(Clone, PartialEq)]
+pub struct Key {
+    encryption: [u8; KEY_LEN],
+    name_nonces: [u8; KEY_LEN],
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+pub fn random_bytes(len: usize) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| format!("Failed to generate random bytes: {}", e))?;
+    Ok(bytes)
+}
+
+// The key for a passphrase, with a subkey for each use expanded from what
+// PBKDF2 derives, as HKDF-Expand does
+pub fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, String> {
+    let master = passphrase_key(passphrase, salt);
+    Ok(Key {
+        encryption: expand(&master, ENCRYPTION_LABEL)?,
+        name_nonces: expand(&master, NAME_NONCE_LABEL)?,
+    })
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+// One block of HKDF-Expand: HMAC-SHA256 of the label and a counter of 1
+fn expand(key: &[u8; KEY_LEN], label: &[u8]) -> Result<[u8; KEY_LEN], String> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+        .map_err(|e| format!("Invalid key: {}", e))?;
+    mac.update(label);
+    mac.update(&[1]);
+    Ok(mac.finalize().into_bytes().into())
+}
+
+// Base64 (URL safe, so the result can also be used as a path component)
+pub fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn decode(data: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD.decode(data)
+        .map_err(|e| format!("Invalid encoded data: {}", e))
+}
+
+// SHA-256 of `data`, encoded, to name contents by
+pub fn content_hash(data: &[u8]) -> String {
+    encode(&Sha256::digest(data))
+}
+
+// Encrypt with a random nonce. The result is the encoded nonce followed by
+// the ciphertext.
+pub fn encrypt(key: &Key, plaintext: &[u8]) -> Result<String, String> {
+    let nonce = random_bytes(NONCE_LEN)?;
+    seal(key, &nonce, plaintext)
+}
+
+pub fn decrypt(key: &Key, data: &str) -> Result<Vec<u8>, String> {
+    let bytes = decode(data)?;
+    if bytes.len() < NONCE_LEN {
+        return Err("Encrypted data is truncated".to_string());
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    Aes256Gcm::new_from_slice(&key.encryption)
+        .map_err(|e| format!("Invalid key: {}", e))?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Decryption failed (wrong passphrase or corrupted data)".to_string())
+}
+
+// Deterministic encryption for file names: the nonce is derived from the name
+// itself, so the same name always maps to the same stored name and paths can
+// be looked up without decrypting every entry.
+pub fn encrypt_name(key: &Key, name: &str) -> Result<String, String> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key.name_nonces)
+        .map_err(|e| format!("Invalid key: {}", e))?;
+    mac.update(name.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    seal(key, &digest[..NONCE_LEN], name.as_bytes())
+}
+
+pub fn decrypt_name(key: &Key, data: &str) -> Result<String, String> {
+    let bytes = decrypt(key, data)?;
+    String::from_utf8(bytes).map_err(|_| "Decrypted name is not valid UTF-8".to_string())
+}
+
+fn seal(key: &Key, nonce: &[u8], plaintext: &[u8]) -> Result<String, String> {
+    let ciphertext = Aes256Gcm::new_from_slice(&key.encryption)
+        .map_err(|e| format!("Invalid key: {}", e))?
+        .encrypt(Nonce::from_slice(nonce), plaintext)
+        .map_err(|_| "Encryption failed".to_string())?;
+
+    let mut bytes = nonce.to_vec();
+    bytes.extend_from_slice(&ciphertext);
+    Ok(encode(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: [u8; SALT_LEN] = [7; SALT_LEN];
+
+    #[test]
+    fn contents_round_trip() {
+        let key = derive_key("correct horse", &SALT).unwrap();
+        let encrypted = encrypt(&key, b"some secret contents").unwrap();
+        assert_ne!(decode(&encrypted).unwrap(), b"some secret contents");
+        assert_eq!(decrypt(&key, &encrypted).unwrap(), b"some secret contents");
+    }
+
+    #[test]
+    fn contents_get_a_new_nonce_each_time() {
+        let key = derive_key("correct horse", &SALT).unwrap();
+        assert_ne!(encrypt(&key, b"same").unwrap(), encrypt(&key, b"same").unwrap());
+    }
+
+    #[test]
+    fn names_round_trip_and_are_deterministic() {
+        let key = derive_key("correct horse", &SALT).unwrap();
+        let encrypted = encrypt_name(&key, "notes.txt").unwrap();
+        assert_eq!(encrypted, encrypt_name(&key, "notes.txt").unwrap());
+        assert_ne!(encrypted, encrypt_name(&key, "notes.md").unwrap());
+        assert_eq!(decrypt_name(&key, &encrypted).unwrap(), "notes.txt");
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let key = derive_key("correct horse", &SALT).unwrap();
+        let wrong = derive_key("battery staple", &SALT).unwrap();
+        let encrypted = encrypt(&key, b"some secret contents").unwrap();
+        assert!(decrypt(&wrong, &encrypted).is_err());
+        let name = encrypt_name(&key, "notes.txt").unwrap();
+        assert!(decrypt_name(&wrong, &name).is_err());
+    }
+
+    #[test]
+    fn different_salt_gives_a_different_key() {
+        let key = derive_key("correct horse", &SALT).unwrap();
+        let other = derive_key("correct horse", &[8; SALT_LEN]).unwrap();
+        assert!(decrypt(&other, &encrypt(&key, b"data").unwrap()).is_err());
+    }
+
+    #[test]
+    fn subkeys_are_separate() {
+        let key = derive_key("correct horse", &SALT).unwrap();
+        assert_ne!(key.encryption, key.name_nonces);
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let key = derive_key("correct horse", &SALT).unwrap();
+        assert!(decrypt(&key, &encode(&[1, 2, 3])).is_err());
+    }
+}
@@ -1,7 +1,19 @@
 use serde::{Serialize, Deserialize};
-use web_sys::Storage;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use crate::compression;
+use crate::crypto::{self, Key};
+use crate::storage::SharedStorage;
+use crate::time::SharedTimeSource;
+
+mod fsck;
+mod listing;
+mod snapshot;
+pub use fsck::{FsckIssue, FsckMode, FsckReport, LOST_AND_FOUND};
+pub use listing::{ListOptions, Listing, SortDirection, SortKey};
+pub use snapshot::{SnapshotChange, SnapshotChangeKind, SnapshotInfo};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum FileType {
@@ -9,52 +21,283 @@ pub enum FileType {
     Directory,
 }
 
+// How a file's contents are stored
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum ContentEncoding {
+    #[default]
+    Plain,
+    // Deflate compressed, then base64 encoded unless also encrypted
+    Deflate,
+}
+
+impl ContentEncoding {
+    fn is_plain(&self) -> bool {
+        *self == ContentEncoding::Plain
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FileMetadata {
     pub name: String,
     pub file_type: FileType,
-    pub size: usize,
+    pub size: usize, // Size of the contents as read, however they are stored
     pub created: u64,  // Timestamp
     pub modified: u64, // Timestamp
+    #[serde(default)]
+    pub accessed: u64, // Timestamp
+    #[serde(default, skip_serializing_if = "ContentEncoding::is_plain")]
+    pub encoding: ContentEncoding,
+    // Hash of the blob holding the contents, for contents shared with other
+    // files; None when they're stored under the file's own path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    // Set on directories whose contents are stored encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionInfo>,
+}
+
+impl FileMetadata {
+    // Entries whose name starts with a dot are left out of listings unless
+    // asked for, like on Unix
+    pub fn is_hidden(&self) -> bool {
+        self.name.starts_with('.')
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EncryptionInfo {
+    pub salt: String,
+    // ENCRYPTION_CHECK encrypted with the directory key, to verify passphrases
+    pub check: String,
+    // Whether the names of entries below the directory are encrypted as well
+    pub encrypt_names: bool,
+}
+
+// Key of an encrypted directory that was unlocked during this session
+#[derive(Debug, Clone, PartialEq)]
+struct UnlockedDirectory {
+    key: Key,
+    last_used: Cell<u64>,
+}
+
+const ENCRYPTION_CHECK: &str = "kernelos-encrypted-directory";
+
+// Contents smaller than this are never compressed
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+// What kind of error a file system operation failed with, so that callers
+// don't have to read it from the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    NotFound,
+    NotAFile,
+    NotADirectory,
+    AlreadyExists,
+    NotEmpty,
+    // Inside an encrypted directory that is locked
+    Locked,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FsError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl FsError {
+    fn new(kind: ErrorKind, message: String) -> Self {
+        FsError { kind, message }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// Errors from storage and the like are plain messages
+impl From<String> for FsError {
+    fn from(message: String) -> Self {
+        FsError::new(ErrorKind::Other, message)
+    }
+}
+
+impl From<FsError> for String {
+    fn from(error: FsError) -> Self {
+        error.message
+    }
+}
+
+// Timestamps to set explicitly with `FileSystem::set_times`; `None` leaves the
+// current value alone
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FileTimes {
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
+}
+
+// An entry that was changed both in this tab and in another one since the
+// last time they were in sync. `local` is what this tab had, `remote` what the
+// other tab stored; `None` means the entry was deleted on that side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncConflict {
+    pub path: String,
+    pub local: Option<FileMetadata>,
+    pub remote: Option<FileMetadata>,
+}
+
+// Version of the record kept under the file system's own metadata key
+const FORMAT_VERSION: u32 = 2;
+
+// Where the root entry is tracked among the directories, since it has no
+// parent directory to be stored with: in the file system record itself
+const ROOT_RECORD: &str = "";
+
+// The record of the file system itself. Before version 2 there was no root
+// entry and every entry was kept in `files`, all loaded and saved at once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StoredFileSystem {
+    #[serde(default)]
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root: Option<FileMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    files: Option<HashMap<String, FileMetadata>>,
+}
+
+// Metadata of the entries inside a directory, by name, as stored
+type StoredDirectory = BTreeMap<String, FileMetadata>;
+
+// What storage held for a directory the last time we loaded or saved it
+#[derive(Debug, Clone, Default, PartialEq)]
+struct SyncedDirectory {
+    data: String,
+    entries: HashMap<String, FileMetadata>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FileSystem {
-    // Using a simplified approach where paths are keys
-    files: HashMap<String, FileMetadata>,
+    // Using a simplified approach where paths are keys. Only holds the
+    // entries of directories that have been loaded so far; the others are
+    // read from storage the first time one of their entries is needed.
+    files: RefCell<HashMap<String, FileMetadata>>,
+    loaded: RefCell<HashSet<String>>,
+    // Directories whose entries have changed since the last save
+    dirty: BTreeSet<String>,
+    storage: SharedStorage,
+    // Last state known to be in storage for each loaded directory, used as the
+    // common ancestor when merging changes made by other tabs
+    synced: RefCell<HashMap<String, SyncedDirectory>>,
+    // Keys of unlocked encrypted directories, by stored path. Never persisted.
+    unlocked: HashMap<String, UnlockedDirectory>,
+    time: SharedTimeSource,
+    // Access times recorded by reads, written out with the next save
+    pending_access: RefCell<HashMap<String, u64>>,
+    // When set, changes are only written out by `save` or `save_if_idle`
+    // instead of right away
+    deferred_saves: bool,
+    // Times of the first and the latest change since the last save
+    unsaved_since: Option<u64>,
+    last_change: u64,
 }
 
 impl FileSystem {
     pub fn new() -> Result<Self, String> {
-        // Try to load existing file system from local storage
-        if let Some(storage) = Self::get_storage() {
-            if let Ok(Some(data)) = storage.get_item("wasm_desktop_fs") {
-                if !data.is_empty() {
-                    match serde_json::from_str::<FileSystem>(&data) {
-                        Ok(fs) => return Ok(fs),
-                        Err(_) => {
-                            // If loading fails, create a new file system
-                            log::warn!("Failed to load file system, creating new one");
-                        }
-                    }
+        Self::with_storage(SharedStorage::default(), SharedTimeSource::default())
+    }
+
+    // Like `new`, but taking all timestamps from `time`
+    pub fn with_time_source(time: SharedTimeSource) -> Result<Self, String> {
+        Self::with_storage(SharedStorage::default(), time)
+    }
+
+    // Load the file system kept in `storage`, or create a new one there. Only
+    // the root entry is read up front.
+    pub fn with_storage(storage: SharedStorage, time: SharedTimeSource) -> Result<Self, String> {
+        let mut fs = FileSystem {
+            files: RefCell::new(HashMap::new()),
+            loaded: RefCell::new(HashSet::new()),
+            dirty: BTreeSet::new(),
+            storage,
+            synced: RefCell::new(HashMap::new()),
+            unlocked: HashMap::new(),
+            time,
+            pending_access: RefCell::new(HashMap::new()),
+            deferred_saves: false,
+            unsaved_since: None,
+            last_change: 0,
+        };
+
+        // Try to load existing file system from storage
+        let data = fs.storage.read_metadata()?.unwrap_or_default();
+        let mut stored = None;
+        if !data.is_empty() {
+            match serde_json::from_str::<StoredFileSystem>(&data) {
+                Ok(record) => stored = Some(record),
+                Err(_) => {
+                    // If loading fails, create a new file system
+                    log::warn!("Failed to load file system, creating new one");
                 }
             }
         }
 
-        // Create new file system with root directory
-        let mut fs = FileSystem {
-            files: HashMap::new(),
-        };
+        let mut upgraded = false;
+        match stored {
+            Some(StoredFileSystem { files: Some(files), .. }) => {
+                // Saved by an older version: everything is here, and gets
+                // split up into directories right away
+                fs.files = RefCell::new(files.into_iter()
+                    .map(|(path, metadata)| (path, Self::loaded_entry(metadata)))
+                    .collect());
+                fs.mark_everything_loaded();
+                upgraded = true;
+            }
+            Some(StoredFileSystem { root: Some(root), .. }) => {
+                let root = Self::loaded_entry(root);
+                fs.files.get_mut().insert("/".to_string(), root.clone());
+                fs.synced.get_mut().insert(ROOT_RECORD.to_string(), SyncedDirectory {
+                    data,
+                    entries: HashMap::from([("/".to_string(), root)]),
+                });
+            }
+            _ => {}
+        }
+
+        // Backends that can be changed behind our back have the final say on
+        // which entries exist, so everything has to be compared with them
+        if let Some(entries) = fs.storage.scan()? {
+            if fs.files.get_mut().contains_key("/") {
+                fs.descendants("/")?;
+            }
+            let known = fs.files.take();
+            fs.files = RefCell::new(Self::reconcile(known, entries));
+            fs.mark_everything_loaded();
+        }
+
+        if fs.files.get_mut().contains_key("/") {
+            if upgraded {
+                fs.save()?;
+            }
+            return Ok(fs);
+        }
 
-        // Initialize with root directory
-        let now = js_sys::Date::now() as u64;
-        fs.files.insert("/".to_string(), FileMetadata {
+        // Create new file system with root directory
+        let now = fs.time.now();
+        fs.loaded.get_mut().insert("/".to_string());
+        fs.insert_entry("/".to_string(), FileMetadata {
             name: "/".to_string(),
             file_type: FileType::Directory,
             size: 0,
             created: now,
             modified: now,
-        });
+            accessed: now,
+            encoding: ContentEncoding::Plain,
+            blob: None,
+            encryption: None,
+        })?;
 
         // Create basic directory structure
         fs.create_directory("/home", true)?;
@@ -68,237 +311,1080 @@ impl FileSystem {
         Ok(fs)
     }
 
-    fn get_storage() -> Option<Storage> {
-        web_sys::window()
-            .and_then(|window| window.local_storage().ok())
-            .flatten()
+    // Combine the metadata we saved with the entries found in the backend.
+    // Entries the backend no longer has are dropped, new ones are added.
+    fn reconcile(known: HashMap<String, FileMetadata>, entries: Vec<(String, FileMetadata)>) -> HashMap<String, FileMetadata> {
+        entries.into_iter()
+            .map(|(path, found)| {
+                let metadata = match known.get(&path) {
+                    // Untouched since we saved it: keep what the backend can't
+                    // tell us, like the size of encrypted files
+                    Some(known) if known.file_type == found.file_type && known.modified == found.modified => known.clone(),
+                    // Changed by someone else
+                    Some(known) => FileMetadata {
+                        created: known.created,
+                        encryption: known.encryption.clone(),
+                        ..found
+                    },
+                    None => found,
+                };
+                (path, metadata)
+            })
+            .collect()
     }
 
-    pub fn save(&self) -> Result<(), String> {
-        if let Some(storage) = Self::get_storage() {
-            let serialized = serde_json::to_string(self)
-                .map_err(|e| format!("Failed to serialize file system: {}", e))?;
+    // Treat every entry in `files` as loaded and changed, so that the next save
+    // writes all directories
+    fn mark_everything_loaded(&mut self) {
+        let directories: Vec<String> = self.files.get_mut().iter()
+            .filter(|(_, metadata)| metadata.file_type == FileType::Directory)
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.loaded.get_mut().extend(directories.iter().cloned());
+        self.dirty.extend(directories);
+        self.dirty.insert(ROOT_RECORD.to_string());
+    }
+
+    // Write out everything that changed since the last save
+    pub fn save(&mut self) -> Result<(), String> {
+        for (path, accessed) in self.pending_access.get_mut().drain() {
+            if let Some(metadata) = self.files.get_mut().get_mut(&path) {
+                metadata.accessed = accessed;
+                self.dirty.insert(Self::parent_directory(&path));
+            }
+        }
+
+        // Another tab may have saved since we last looked; fold its changes
+        // in first so that writing ours doesn't discard them
+        let directories: Vec<String> = self.dirty.iter().cloned().collect();
+        let conflicts = self.sync_directories(&directories)?;
+        for conflict in &conflicts {
+            log::warn!("Conflicting change to {} from another tab", conflict.path);
+        }
+
+        let directories: Vec<String> = self.dirty.iter().cloned().collect();
+        for directory in directories {
+            self.write_directory(&directory)?;
+            self.dirty.remove(&directory);
+        }
+
+        self.unsaved_since = None;
+        Ok(())
+    }
+
+    // Only write changes out when `save` or `save_if_idle` is called. Meant for
+    // callers that save periodically, so that bursts of changes are written once.
+    pub fn set_deferred_saves(&mut self, deferred: bool) {
+        self.deferred_saves = deferred;
+    }
+
+    pub fn has_unsaved_changes(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    // Save once nothing has changed for `idle` milliseconds, or once changes
+    // have been waiting for `max_wait` milliseconds. Returns whether it saved.
+    pub fn save_if_idle(&mut self, idle: u64, max_wait: u64) -> Result<bool, String> {
+        let since = match self.unsaved_since {
+            Some(since) if self.has_unsaved_changes() => since,
+            _ => return Ok(false),
+        };
 
-            storage
-                .set_item("wasm_desktop_fs", &serialized)
-                .map_err(|e| format!("Failed to save file system: {:?}", e))?;
+        let now = self.time.now();
+        if now.saturating_sub(self.last_change) < idle && now.saturating_sub(since) < max_wait {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    // Called after every change to the entries
+    fn changed(&mut self) -> Result<(), String> {
+        let now = self.time.now();
+        self.last_change = now;
+        self.unsaved_since.get_or_insert(now);
 
+        if self.deferred_saves {
             Ok(())
         } else {
-            Err("Local storage not available".to_string())
+            self.save()
+        }
+    }
+
+    fn write_directory(&mut self, directory: &str) -> Result<(), String> {
+        let entries = self.local_entries(directory);
+
+        let data = if directory == ROOT_RECORD {
+            let record = StoredFileSystem {
+                version: FORMAT_VERSION,
+                root: entries.get("/").cloned(),
+                files: None,
+            };
+            let data = serde_json::to_string(&record)
+                .map_err(|e| format!("Failed to serialize file system: {}", e))?;
+            self.storage.write_metadata(&data)?;
+            data
+        } else if self.files.get_mut().get(directory).is_some_and(|metadata| metadata.file_type == FileType::Directory) {
+            let stored: StoredDirectory = entries.iter()
+                .map(|(path, metadata)| (Self::entry_name(path), metadata.clone()))
+                .collect();
+            let data = serde_json::to_string(&stored)
+                .map_err(|e| format!("Failed to serialize directory {}: {}", directory, e))?;
+            self.storage.write_directory_metadata(directory, &data)?;
+            data
+        } else {
+            // The directory was deleted
+            self.storage.remove_directory_metadata(directory)?;
+            self.loaded.get_mut().remove(directory);
+            self.synced.get_mut().remove(directory);
+            return Ok(());
+        };
+
+        self.synced.get_mut().insert(directory.to_string(), SyncedDirectory { data, entries });
+        Ok(())
+    }
+
+    // Merge whatever another tab has stored into this file system. Should be
+    // called whenever local storage reports a change to file system metadata.
+    // Returns the entries that were modified on both sides; those are resolved
+    // in favour of the most recent modification.
+    pub fn sync_from_storage(&mut self) -> Result<Vec<SyncConflict>, String> {
+        let mut directories: Vec<String> = self.loaded.get_mut().iter().cloned().collect();
+        directories.push(ROOT_RECORD.to_string());
+        // Parents before their children
+        directories.sort();
+        self.sync_directories(&directories)
+    }
+
+    fn sync_directories(&mut self, directories: &[String]) -> Result<Vec<SyncConflict>, String> {
+        let mut conflicts = Vec::new();
+
+        for directory in directories {
+            // Directories we haven't loaded are read as they are when needed
+            if directory != ROOT_RECORD && !self.loaded.get_mut().contains(directory) {
+                continue;
+            }
+
+            let data = if directory == ROOT_RECORD {
+                self.storage.read_metadata()?
+            } else {
+                self.storage.read_directory_metadata(directory)?
+            }.unwrap_or_default();
+
+            // Nothing has changed since our own last load or save
+            let base = self.synced.get_mut().get(directory).cloned().unwrap_or_default();
+            if data == base.data {
+                continue;
+            }
+
+            let remote = match Self::parse_directory(directory, &data)
+                .map_err(|e| format!("Failed to parse file system from another tab: {}", e))? {
+                Some(remote) => remote,
+                // The file system record is gone, or was written by an older version
+                None => continue,
+            };
+
+            conflicts.extend(self.merge(directory, &remote, &base.entries)?);
+            self.synced.get_mut().insert(directory.clone(), SyncedDirectory { data, entries: remote });
+        }
+
+        self.restore_missing_parents();
+        Ok(conflicts)
+    }
+
+    // Entries of a directory as stored in `data`, by path. None for a file system
+    // record without a root entry.
+    fn parse_directory(directory: &str, data: &str) -> Result<Option<HashMap<String, FileMetadata>>, String> {
+        if directory == ROOT_RECORD {
+            let record = if data.is_empty() {
+                StoredFileSystem::default()
+            } else {
+                serde_json::from_str::<StoredFileSystem>(data).map_err(|e| e.to_string())?
+            };
+            return Ok(record.root.map(|root| HashMap::from([("/".to_string(), Self::loaded_entry(root))])));
+        }
+
+        // A directory without stored metadata has been deleted, or is empty
+        let stored = if data.is_empty() {
+            StoredDirectory::new()
+        } else {
+            serde_json::from_str::<StoredDirectory>(data).map_err(|e| e.to_string())?
+        };
+        Ok(Some(stored.into_iter()
+            .map(|(name, metadata)| (Self::join(directory, &name), Self::loaded_entry(metadata)))
+            .collect()))
+    }
+
+    // Three-way merge of the entries of `directory` stored by another tab into
+    // ours, with `base` as the common ancestor. Only whole entries are merged:
+    // when a file was changed on both sides, the version modified last is kept
+    // and the other one is dropped, so file contents are last-writer-wins.
+    fn merge(&mut self, directory: &str, remote: &HashMap<String, FileMetadata>, base: &HashMap<String, FileMetadata>) -> Result<Vec<SyncConflict>, String> {
+        let mut paths: Vec<String> = self.local_entries(directory).into_keys()
+            .chain(remote.keys().cloned())
+            .chain(base.keys().cloned())
+            .collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut conflicts = Vec::new();
+
+        for path in paths {
+            let local = self.files.get_mut().get(&path).cloned();
+            let theirs = remote.get(&path).cloned();
+            let base = base.get(&path).cloned();
+
+            if local == theirs || theirs == base {
+                // Same on both sides, or only changed here
+                continue;
+            }
+
+            let merged = if local == base {
+                // Only changed in the other tab
+                theirs.clone()
+            } else {
+                conflicts.push(SyncConflict {
+                    path: path.clone(),
+                    local: local.clone(),
+                    remote: theirs.clone(),
+                });
+
+                let merged = match (&local, &theirs) {
+                    (Some(l), Some(r)) => if r.modified > l.modified { theirs.clone() } else { local.clone() },
+                    // Deleted here, changed there: keep their version
+                    (None, Some(_)) => theirs.clone(),
+                    // Changed here, deleted there: the other tab has already removed
+                    // the file contents, so only a directory can be kept
+                    (Some(l), None) => {
+                        let has_contents = matches!(self.read_stored(&path, l), Ok(Some(_)));
+                        if l.file_type == FileType::Directory || has_contents { local.clone() } else { None }
+                    }
+                    (None, None) => None,
+                };
+
+                // Each side stored a blob for its own version and released the
+                // one it replaced; the blob of the version dropped here is now
+                // used by nothing
+                let kept = merged.as_ref().and_then(|metadata| metadata.blob.as_ref());
+                let replaced = base.as_ref().and_then(|metadata| metadata.blob.as_ref());
+                let dropped = [&local, &theirs].into_iter()
+                    .filter_map(|metadata| metadata.as_ref().and_then(|metadata| metadata.blob.as_ref()))
+                    .filter(|hash| Some(*hash) != kept && Some(*hash) != replaced);
+                for hash in dropped {
+                    self.release_blob(hash)?;
+                }
+                merged
+            };
+
+            match merged {
+                Some(metadata) => { self.files.get_mut().insert(path, metadata); }
+                None => { self.files.get_mut().remove(&path); }
+            }
         }
+
+        Ok(conflicts)
     }
 
-    pub fn list_directory(&self, path: &str) -> Result<Vec<FileMetadata>, String> {
+    // A directory removed in one tab may still have children created in the
+    // other; bring the missing parents back rather than orphaning them
+    fn restore_missing_parents(&mut self) {
+        let mut orphans: Vec<String> = self.files.get_mut().keys()
+            .filter(|path| path.as_str() != "/")
+            .cloned()
+            .collect();
+        while let Some(path) = orphans.pop() {
+            let parent_path = Self::parent_directory(&path);
+            if parent_path == ROOT_RECORD || self.files.get_mut().contains_key(&parent_path) {
+                continue;
+            }
+
+            let now = self.time.now();
+            let grandparent = Self::parent_directory(&parent_path);
+            let metadata = self.synced.get_mut().get(&grandparent)
+                .and_then(|synced| synced.entries.get(&parent_path).cloned())
+                .unwrap_or(FileMetadata {
+                    name: Self::entry_name(&parent_path),
+                    file_type: FileType::Directory,
+                    size: 0,
+                    created: now,
+                    modified: now,
+                    accessed: now,
+                    encoding: ContentEncoding::Plain,
+                    blob: None,
+                    encryption: None,
+                });
+            self.files.get_mut().insert(parent_path.clone(), metadata);
+            self.dirty.insert(grandparent);
+            orphans.push(parent_path);
+        }
+    }
+
+    // Read the entries of `directory` from storage, unless that was done already
+    fn ensure_loaded(&self, directory: &str) -> Result<(), String> {
+        if directory == ROOT_RECORD || self.loaded.borrow().contains(directory) {
+            return Ok(());
+        }
+        // Nothing to load for entries that don't exist (yet)
+        if !self.entry(directory)?.is_some_and(|metadata| metadata.file_type == FileType::Directory) {
+            return Ok(());
+        }
+
+        let data = self.storage.read_directory_metadata(directory)?.unwrap_or_default();
+        let entries = Self::parse_directory(directory, &data)
+            .map_err(|e| format!("Failed to load directory {}: {}", directory, e))?
+            .unwrap_or_default();
+
+        let mut files = self.files.borrow_mut();
+        for (path, metadata) in &entries {
+            files.entry(path.clone()).or_insert_with(|| metadata.clone());
+        }
+        self.synced.borrow_mut().insert(directory.to_string(), SyncedDirectory { data, entries });
+        self.loaded.borrow_mut().insert(directory.to_string());
+        Ok(())
+    }
+
+    // The entry at `stored_path`, loading its directory if needed
+    fn entry(&self, stored_path: &str) -> Result<Option<FileMetadata>, String> {
+        self.ensure_loaded(&Self::parent_directory(stored_path))?;
+        Ok(self.files.borrow().get(stored_path).cloned())
+    }
+
+    // The loaded entries directly inside `directory`, by path
+    fn local_entries(&self, directory: &str) -> HashMap<String, FileMetadata> {
+        self.files.borrow().iter()
+            .filter(|(path, _)| path.as_str() != directory && Self::parent_directory(path) == directory)
+            .map(|(path, metadata)| (path.clone(), metadata.clone()))
+            .collect()
+    }
+
+    fn children(&self, directory: &str) -> Result<HashMap<String, FileMetadata>, String> {
+        self.ensure_loaded(directory)?;
+        Ok(self.local_entries(directory))
+    }
+
+    // Paths of everything below `directory`, loading all of it
+    fn descendants(&self, directory: &str) -> Result<Vec<String>, String> {
+        let mut descendants = Vec::new();
+        let mut pending = vec![directory.to_string()];
+        while let Some(directory) = pending.pop() {
+            for (path, metadata) in self.children(&directory)? {
+                if metadata.file_type == FileType::Directory {
+                    pending.push(path.clone());
+                }
+                descendants.push(path);
+            }
+        }
+        Ok(descendants)
+    }
+
+    fn insert_entry(&mut self, stored_path: String, metadata: FileMetadata) -> Result<(), String> {
+        let parent = Self::parent_directory(&stored_path);
+        // Load the rest of the directory first, so it isn't loaded over this later
+        self.ensure_loaded(&parent)?;
+        self.files.get_mut().insert(stored_path, metadata);
+        self.dirty.insert(parent);
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, stored_path: &str) {
+        if let Some(metadata) = self.files.get_mut().remove(stored_path) {
+            self.dirty.insert(Self::parent_directory(stored_path));
+            if metadata.file_type == FileType::Directory {
+                // So that its stored metadata is removed too
+                self.dirty.insert(stored_path.to_string());
+            }
+        }
+    }
+
+    // Entries saved before access times were tracked
+    fn loaded_entry(mut metadata: FileMetadata) -> FileMetadata {
+        if metadata.accessed == 0 {
+            metadata.accessed = metadata.modified;
+        }
+        metadata
+    }
+
+    // The directory whose metadata the entry at `path` is stored with
+    fn parent_directory(path: &str) -> String {
+        if path == "/" {
+            return ROOT_RECORD.to_string();
+        }
+        match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().to_string(),
+            _ => "/".to_string(),
+        }
+    }
+
+    fn entry_name(path: &str) -> String {
+        Path::new(path).file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string())
+    }
+
+    fn join(directory: &str, name: &str) -> String {
+        if directory == "/" { format!("/{}", name) } else { format!("{}/{}", directory, name) }
+    }
+
+    pub fn list_directory(&self, path: &str) -> Result<Vec<FileMetadata>, FsError> {
         // Normalize path
         let path = Self::normalize_path(path);
+        let stored_path = self.storage_path(&path)?;
         
         // Check if path exists and is a directory
-        if let Some(metadata) = self.files.get(&path) {
+        if let Some(metadata) = self.entry(&stored_path)? {
             if !matches!(metadata.file_type, FileType::Directory) {
-                return Err(format!("{} is not a directory", path));
+                return Err(FsError::new(ErrorKind::NotADirectory, format!("{} is not a directory", path)));
             }
         } else {
-            return Err(format!("Directory {} does not exist", path));
+            return Err(FsError::new(ErrorKind::NotFound, format!("Directory {} does not exist", path)));
         }
 
+        // Names below an encrypted directory can only be shown once it's unlocked
+        let name_key = self.name_key(&stored_path)?;
+
         // List all files in this directory
+        let pending_access = self.pending_access.borrow();
         let mut files = Vec::new();
-        let path_prefix = if path.ends_with('/') { path.clone() } else { format!("{}/", path) };
-        
-        for (file_path, metadata) in &self.files {
-            if file_path != &path && file_path.starts_with(&path_prefix) {
-                // Only include direct children, not descendants
-                let remaining = &file_path[path_prefix.len()..];
-                if !remaining.contains('/') {
-                    files.push(metadata.clone());
-                }
+
+        for (file_path, mut metadata) in self.children(&stored_path)? {
+            if let Some(accessed) = pending_access.get(&file_path) {
+                metadata.accessed = *accessed;
+            }
+            if let Some(key) = name_key {
+                metadata.name = crypto::decrypt_name(key, &metadata.name)?;
             }
+            files.push(metadata);
         }
 
         Ok(files)
     }
 
-    pub fn create_directory(&mut self, path: &str, create_parents: bool) -> Result<(), String> {
+    pub fn create_directory(&mut self, path: &str, create_parents: bool) -> Result<(), FsError> {
         let path = Self::normalize_path(path);
+        let stored_path = self.storage_path(&path)?;
         
         // Check if the directory already exists
-        if self.files.contains_key(&path) {
-            return Err(format!("{} already exists", path));
+        if self.entry(&stored_path)?.is_some() {
+            return Err(FsError::new(ErrorKind::AlreadyExists, format!("{} already exists", path)));
         }
 
+        let parent_path = Path::new(&path).parent()
+            .ok_or_else(|| "Invalid path".to_string())?
+            .to_string_lossy()
+            .to_string();
+        let parent_exists = parent_path.is_empty() || parent_path == "/"
+            || self.entry(&self.storage_path(&parent_path)?)?.is_some();
+
         if create_parents {
             // Ensure parent directories exist
-            let parent_path = Path::new(&path).parent()
-                .ok_or_else(|| "Invalid path".to_string())?
-                .to_string_lossy()
-                .to_string();
-            
-            if !parent_path.is_empty() && parent_path != "/" && !self.files.contains_key(&parent_path) {
+            if !parent_exists {
                 self.create_directory(&parent_path, true)?;
             }
-        } else {
+        } else if !parent_exists {
             // Check if parent directory exists
-            let parent_path = Path::new(&path).parent()
-                .ok_or_else(|| "Invalid path".to_string())?
-                .to_string_lossy()
-                .to_string();
-            
-            if !parent_path.is_empty() && parent_path != "/" && !self.files.contains_key(&parent_path) {
-                return Err(format!("Parent directory {} does not exist", parent_path));
-            }
+            return Err(FsError::new(ErrorKind::NotFound, format!("Parent directory {} does not exist", parent_path)));
         }
+            
+        // New entries inside an encrypted directory need it to be unlocked
+        self.key_for(&stored_path)?;
 
         // Create directory
-        let now = js_sys::Date::now() as u64;
-        let name = Path::new(&path).file_name()
+        self.storage.create_directory(&stored_path)?;
+        let now = self.time.now();
+        let name = Path::new(&stored_path).file_name()
             .ok_or_else(|| "Invalid path".to_string())?
             .to_string_lossy()
             .to_string();
         
-        self.files.insert(path, FileMetadata {
+        self.insert_entry(stored_path.clone(), FileMetadata {
             name,
             file_type: FileType::Directory,
             size: 0,
             created: now,
             modified: now,
-        });
+            accessed: now,
+            encoding: ContentEncoding::Plain,
+            blob: None,
+            encryption: None,
+        })?;
+        // Nothing in it to load
+        self.loaded.get_mut().insert(stored_path);
 
-        self.save()?;
+        self.changed()?;
         Ok(())
     }
 
-    pub fn write_file(&mut self, path: &str, contents: &str) -> Result<(), String> {
+    pub fn write_file(&mut self, path: &str, contents: &str) -> Result<(), FsError> {
         let path = Self::normalize_path(path);
-        
-        // Make sure parent directory exists
-        let parent_path = Path::new(&path).parent()
+        let stored_path = self.storage_path(&path)?;
+        let name = self.new_file_name(&path, &stored_path)?;
+
+        // Encrypt before touching anything, so a locked directory leaves no trace
+        let key = self.key_for(&stored_path)?;
+        let encrypted = key.is_some();
+        let (stored_contents, encoding) = self.encode_contents(contents, key)?;
+
+        // Store file contents separately
+        let blob = self.store_contents(&stored_path, &stored_contents, encrypted)?;
+        self.replace_file(stored_path, FileMetadata {
+            name,
+            file_type: FileType::File,
+            size: contents.len(),
+            created: 0,
+            modified: 0,
+            accessed: 0,
+            encoding,
+            blob,
+            encryption: None,
+        })?;
+        Ok(())
+    }
+
+    // Copy a file. Unencrypted contents kept in a blob are shared with the copy
+    // instead of being stored again.
+    pub fn copy_file(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+        let from_path = Self::normalize_path(from);
+        let from_stored_path = self.storage_path(&from_path)?;
+        let source = self.entry(&from_stored_path)?
+            .ok_or_else(|| FsError::new(ErrorKind::NotFound, format!("File {} does not exist", from_path)))?;
+        if source.file_type != FileType::File {
+            return Err(FsError::new(ErrorKind::NotAFile, format!("{} is not a file", from_path)));
+        }
+
+        let path = Self::normalize_path(to);
+        let stored_path = self.storage_path(&path)?;
+        let name = self.new_file_name(&path, &stored_path)?;
+
+        // Only plain contents can be shared, from a plain file to a plain file
+        let hash = match source.blob {
+            Some(hash) if self.key_for(&from_stored_path)?.is_none() && self.key_for(&stored_path)?.is_none() => hash,
+            // The copy needs contents of its own
+            _ => {
+                let contents = self.read_file(&from_path)?;
+                return self.write_file(&path, &contents);
+            }
+        };
+
+        self.retain_blob(&hash, None)?;
+        self.replace_file(stored_path, FileMetadata {
+            name,
+            blob: Some(hash),
+            ..source
+        })?;
+        Ok(())
+    }
+
+    // The stored name of a file to be written at `path`, after making sure its
+    // parent directory exists
+    fn new_file_name(&self, path: &str, stored_path: &str) -> Result<String, FsError> {
+        let parent_path = Path::new(path).parent()
             .ok_or_else(|| "Invalid path".to_string())?
             .to_string_lossy()
             .to_string();
         
-        if !parent_path.is_empty() && parent_path != "/" && !self.files.contains_key(&parent_path) {
-            return Err(format!("Parent directory {} does not exist", parent_path));
+        if !parent_path.is_empty() && parent_path != "/" && self.entry(&self.storage_path(&parent_path)?)?.is_none() {
+            return Err(FsError::new(ErrorKind::NotFound, format!("Parent directory {} does not exist", parent_path)));
         }
 
         // Get filename
-        let name = Path::new(&path).file_name()
-            .ok_or_else(|| "Invalid path".to_string())?
-            .to_string_lossy()
-            .to_string();
-
-        // Create or update file metadata
-        let now = js_sys::Date::now() as u64;
-        let created = if let Some(existing) = self.files.get(&path) {
-            existing.created
-        } else {
-            now
-        };
+        Path::new(stored_path).file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| "Invalid path".to_string().into())
+    }
 
-        self.files.insert(path.clone(), FileMetadata {
-            name,
-            file_type: FileType::File,
-            size: contents.len(),
-            created,
-            modified: now,
-        });
+    // Put `metadata` in place of whatever file is at `stored_path`, once the
+    // new contents are stored. Timestamps are set to now, except for the
+    // creation time of a file being overwritten.
+    fn replace_file(&mut self, stored_path: String, mut metadata: FileMetadata) -> Result<(), String> {
+        let now = self.time.now();
+        let existing = self.entry(&stored_path)?;
+        metadata.created = existing.as_ref().map_or(now, |existing| existing.created);
+        metadata.modified = now;
+        metadata.accessed = now;
 
-        // Store file contents separately
-        if let Some(storage) = Self::get_storage() {
-            let content_key = format!("wasm_desktop_file:{}", path);
-            storage
-                .set_item(&content_key, contents)
-                .map_err(|e| format!("Failed to write file: {:?}", e))?;
-        } else {
-            return Err("Local storage not available".to_string());
+        // Let go of the old contents, unless the new ones were written over them
+        if let Some(existing) = existing.filter(|existing| existing.file_type == FileType::File) {
+            if existing.blob.is_some() || metadata.blob.is_some() {
+                self.discard_contents(&stored_path, &existing)?;
+            }
         }
 
-        self.save()?;
+        self.storage.set_times(&stored_path, &metadata)?;
+        self.insert_entry(stored_path, metadata)?;
+
+        self.changed()?;
         Ok(())
     }
 
-    pub fn read_file(&self, path: &str) -> Result<String, String> {
+    // Store the contents of the file at `stored_path`: in a blob shared with
+    // identical files where the backend supports it, and under the file's own
+    // path otherwise. Encrypted contents are never shared. Returns the blob's hash.
+    fn store_contents(&self, stored_path: &str, stored: &str, encrypted: bool) -> Result<Option<String>, String> {
+        if encrypted || !self.storage.deduplicate_contents() {
+            self.storage.write_contents(stored_path, stored)?;
+            return Ok(None);
+        }
+
+        let hash = crypto::content_hash(stored.as_bytes());
+        self.retain_blob(&hash, Some(stored))?;
+        Ok(Some(hash))
+    }
+
+    // The stored contents of a file, wherever they're kept
+    fn read_stored(&self, stored_path: &str, metadata: &FileMetadata) -> Result<Option<String>, String> {
+        match &metadata.blob {
+            Some(hash) => self.storage.read_blob(hash),
+            None => self.storage.read_contents(stored_path),
+        }
+    }
+
+    // Let go of the contents of a file that is removed or rewritten
+    fn discard_contents(&self, stored_path: &str, metadata: &FileMetadata) -> Result<(), String> {
+        match &metadata.blob {
+            Some(hash) => self.release_blob(hash),
+            None => self.storage.remove_contents(stored_path),
+        }
+    }
+
+    // Add a reference to a blob, storing `data` in it if it is new
+    fn retain_blob(&self, hash: &str, data: Option<&str>) -> Result<(), String> {
+        let references = self.storage.blob_references(hash)?;
+        if references == 0 {
+            match data {
+                Some(data) => self.storage.write_blob(hash, data)?,
+                None if self.storage.read_blob(hash)?.is_none() => return Err(format!("Blob {} is missing", hash)),
+                None => {}
+            }
+        }
+        self.storage.set_blob_references(hash, references + 1)
+    }
+
+    // Remove a reference to a blob, and the blob itself with the last one
+    fn release_blob(&self, hash: &str) -> Result<(), String> {
+        match self.storage.blob_references(hash)? {
+            0 | 1 => self.storage.remove_blob(hash),
+            references => self.storage.set_blob_references(hash, references - 1),
+        }
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<String, FsError> {
         let path = Self::normalize_path(path);
+        let stored_path = self.storage_path(&path)?;
         
         // Check if file exists
-        if let Some(metadata) = self.files.get(&path) {
+        let metadata = if let Some(metadata) = self.entry(&stored_path)? {
             if !matches!(metadata.file_type, FileType::File) {
-                return Err(format!("{} is not a file", path));
+                return Err(FsError::new(ErrorKind::NotAFile, format!("{} is not a file", path)));
             }
+            metadata
         } else {
-            return Err(format!("File {} does not exist", path));
-        }
+            return Err(FsError::new(ErrorKind::NotFound, format!("File {} does not exist", path)));
+        };
+
+        let key = self.key_for(&stored_path)?;
+        self.pending_access.borrow_mut().insert(stored_path.clone(), self.time.now());
 
         // Retrieve file contents
-        if let Some(storage) = Self::get_storage() {
-            let content_key = format!("wasm_desktop_file:{}", path);
-            if let Ok(Some(contents)) = storage.get_item(&content_key) {
-                Ok(contents)
-            } else {
-                Err(format!("Failed to read file {}", path))
+        match self.read_stored(&stored_path, &metadata)? {
+            Some(contents) => Self::decode_contents(&contents, metadata.encoding, key)
+                .map_err(|e| format!("Failed to read file {}: {}", path, e).into()),
+            None => Err(format!("Failed to read file {}", path).into()),
+        }
+    }
+
+    // Turn file contents into what gets stored: compressed if the backend wants
+    // it and it's worth it, then encrypted if a key is given
+    fn encode_contents(&self, contents: &str, key: Option<&Key>) -> Result<(String, ContentEncoding), String> {
+        let mut bytes = contents.as_bytes().to_vec();
+        let mut encoding = ContentEncoding::Plain;
+
+        if self.storage.compress_contents() && contents.len() >= COMPRESSION_THRESHOLD {
+            let compressed = compression::compress(contents.as_bytes());
+            // Compressed data has to be base64 encoded, which adds a third
+            if compressed.len() / 3 * 4 < contents.len() {
+                bytes = compressed;
+                encoding = ContentEncoding::Deflate;
             }
-        } else {
-            Err("Local storage not available".to_string())
         }
+
+        let stored = match (key, encoding) {
+            (Some(key), _) => crypto::encrypt(key, &bytes)?,
+            (None, ContentEncoding::Deflate) => crypto::encode(&bytes),
+            (None, ContentEncoding::Plain) => contents.to_string(),
+        };
+        Ok((stored, encoding))
+    }
+
+    fn decode_contents(stored: &str, encoding: ContentEncoding, key: Option<&Key>) -> Result<String, String> {
+        let bytes = match (key, encoding) {
+            (Some(key), _) => crypto::decrypt(key, stored)?,
+            (None, ContentEncoding::Deflate) => crypto::decode(stored)?,
+            (None, ContentEncoding::Plain) => return Ok(stored.to_string()),
+        };
+        let bytes = match encoding {
+            ContentEncoding::Deflate => compression::decompress(&bytes)?,
+            ContentEncoding::Plain => bytes,
+        };
+        String::from_utf8(bytes).map_err(|_| "contents are not valid UTF-8".to_string())
     }
 
-    pub fn delete(&mut self, path: &str, recursive: bool) -> Result<(), String> {
+    pub fn metadata(&self, path: &str) -> Result<FileMetadata, FsError> {
         let path = Self::normalize_path(path);
+        let stored_path = self.storage_path(&path)?;
+
+        let mut metadata = self.entry(&stored_path)?
+            .ok_or_else(|| FsError::new(ErrorKind::NotFound, format!("{} does not exist", path)))?;
+        if let Some(accessed) = self.pending_access.borrow().get(&stored_path) {
+            metadata.accessed = *accessed;
+        }
+        if let Some(parent) = Path::new(&stored_path).parent() {
+            if let Some(key) = self.name_key(&parent.to_string_lossy())? {
+                metadata.name = crypto::decrypt_name(key, &metadata.name)?;
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    // Create an empty file, or mark an existing entry as accessed and modified now
+    pub fn touch(&mut self, path: &str) -> Result<(), String> {
+        let stored_path = self.storage_path(&Self::normalize_path(path))?;
+        if self.entry(&stored_path)?.is_none() {
+            return Ok(self.write_file(path, "")?);
+        }
+
+        let now = self.time.now();
+        self.set_times(path, FileTimes {
+            created: None,
+            modified: Some(now),
+            accessed: Some(now),
+        })
+    }
+
+    pub fn set_times(&mut self, path: &str, times: FileTimes) -> Result<(), String> {
+        let path = Self::normalize_path(path);
+        let stored_path = self.storage_path(&path)?;
+
+        let mut metadata = self.entry(&stored_path)?
+            .ok_or_else(|| format!("{} does not exist", path))?;
+        if let Some(created) = times.created {
+            metadata.created = created;
+        }
+        if let Some(modified) = times.modified {
+            metadata.modified = modified;
+        }
+        if let Some(accessed) = times.accessed {
+            metadata.accessed = accessed;
+            self.pending_access.get_mut().remove(&stored_path);
+        }
+        self.storage.set_times(&stored_path, &metadata)?;
+        self.insert_entry(stored_path, metadata)?;
+
+        self.changed()?;
+        Ok(())
+    }
+
+    pub fn time_source(&self) -> SharedTimeSource {
+        self.time.clone()
+    }
+
+    pub fn delete(&mut self, path: &str, recursive: bool) -> Result<(), FsError> {
+        let path = Self::normalize_path(path);
+        let stored_path = self.storage_path(&path)?;
         
         // Check if path exists
-        if !self.files.contains_key(&path) {
-            return Err(format!("{} does not exist", path));
+        let metadata = self.entry(&stored_path)?
+            .ok_or_else(|| FsError::new(ErrorKind::NotFound, format!("{} does not exist", path)))?;
+
+        // An encrypted directory can be deleted as a whole while locked, but
+        // not the entries inside it
+        if let Some(parent) = Path::new(&stored_path).parent() {
+            self.key_for(&parent.to_string_lossy())?;
         }
 
-        let is_directory = matches!(self.files.get(&path).unwrap().file_type, FileType::Directory);
+        let is_directory = matches!(metadata.file_type, FileType::Directory);
         
         if is_directory {
+            // Collect paths to delete first to avoid borrowing issues
+            let paths_to_delete = self.descendants(&stored_path)?;
+
             // Check for children
-            let children = self.list_directory(&path)?;
-            if !children.is_empty() && !recursive {
-                return Err(format!("Directory {} is not empty", path));
+            if !paths_to_delete.is_empty() && !recursive {
+                return Err(FsError::new(ErrorKind::NotEmpty, format!("Directory {} is not empty", path)));
             }
 
             if recursive {
-                // Delete all children recursively
-                let path_prefix = if path.ends_with('/') { path.clone() } else { format!("{}/", path) };
-                
-                // Collect paths to delete first to avoid borrowing issues
-                let paths_to_delete: Vec<String> = self.files.keys()
-                    .filter(|k| **k != path && k.starts_with(&path_prefix))
-                    .cloned()
-                    .collect();
-                
-                // Delete files first
-                if let Some(storage) = Self::get_storage() {
-                    for file_path in &paths_to_delete {
-                        if matches!(self.files.get(file_path).unwrap().file_type, FileType::File) {
-                            let content_key = format!("wasm_desktop_file:{}", file_path);
-                            let _ = storage.remove_item(&content_key);
-                        }
+                // Delete files first, then directories from the deepest up
+                let mut directories = Vec::new();
+                for file_path in &paths_to_delete {
+                    let metadata = self.files.get_mut()[file_path].clone();
+                    match metadata.file_type {
+                        FileType::File => { let _ = self.discard_contents(file_path, &metadata); }
+                        FileType::Directory => directories.push(file_path.clone()),
                     }
                 }
+                directories.sort_by_key(|directory| std::cmp::Reverse(directory.matches('/').count()));
+                for directory in &directories {
+                    let _ = self.storage.remove_directory(directory);
+                }
                 
                 // Then remove all entries
                 for file_path in paths_to_delete {
-                    self.files.remove(&file_path);
+                    self.remove_entry(&file_path);
                 }
             }
+
+            self.storage.remove_directory(&stored_path)?;
+            self.unlocked.remove(&stored_path);
         } else {
             // Delete file content
-            if let Some(storage) = Self::get_storage() {
-                let content_key = format!("wasm_desktop_file:{}", path);
-                let _ = storage.remove_item(&content_key);
-            }
+            let _ = self.discard_contents(&stored_path, &metadata);
         }
 
         // Remove the entry itself
-        self.files.remove(&path);
+        self.remove_entry(&stored_path);
         
-        self.save()?;
+        self.changed()?;
+        Ok(())
+    }
+
+    // Mark a directory as encrypted: everything below it is re-stored encrypted
+    // with a key derived from `passphrase`, and the directory stays unlocked for
+    // the rest of the session.
+    pub fn encrypt_directory(&mut self, path: &str, passphrase: &str, encrypt_names: bool) -> Result<(), String> {
+        let path = Self::normalize_path(path);
+        let stored_path = self.storage_path(&path)?;
+
+        match self.entry(&stored_path)? {
+            Some(metadata) if metadata.file_type == FileType::Directory => {}
+            Some(_) => return Err(format!("{} is not a directory", path)),
+            None => return Err(format!("Directory {} does not exist", path)),
+        }
+        if stored_path == "/" {
+            return Err("The root directory cannot be encrypted".to_string());
+        }
+        if passphrase.is_empty() {
+            return Err("Passphrase must not be empty".to_string());
+        }
+        if self.encryption_root(&stored_path)?.is_some() {
+            return Err(format!("{} is already encrypted", path));
+        }
+
+        let path_prefix = format!("{}/", stored_path);
+        let mut descendants = self.descendants(&stored_path)?;
+        descendants.sort();
+        if descendants.iter().any(|k| self.files.get_mut()[k].encryption.is_some()) {
+            return Err(format!("{} contains an encrypted directory", path));
+        }
+
+        let salt = crypto::random_bytes(crypto::SALT_LEN)?;
+        let key = crypto::derive_key(passphrase, &salt)?;
+        let check = crypto::encrypt(&key, ENCRYPTION_CHECK.as_bytes())?;
+
+        // Encrypt everything up front so that a failure leaves the directory untouched
+        let mut converted = Vec::new();
+        for old_path in descendants {
+            let mut metadata = self.files.get_mut()[&old_path].clone();
+            let new_path = if encrypt_names {
+                let names = old_path[path_prefix.len()..]
+                    .split('/')
+                    .map(|name| crypto::encrypt_name(&key, name))
+                    .collect::<Result<Vec<String>, String>>()?;
+                metadata.name = crypto::encrypt_name(&key, &metadata.name)?;
+                format!("{}{}", path_prefix, names.join("/"))
+            } else {
+                old_path.clone()
+            };
+
+            let old_metadata = metadata.clone();
+            let contents = if metadata.file_type == FileType::File {
+                let contents = self.read_stored(&old_path, &metadata)?.unwrap_or_default();
+                let contents = Self::decode_contents(&contents, metadata.encoding, None)?;
+                let (contents, encoding) = self.encode_contents(&contents, Some(&key))?;
+                metadata.encoding = encoding;
+                // Encrypted contents are kept apart from everything else
+                metadata.blob = None;
+                Some(contents)
+            } else {
+                None
+            };
+
+            converted.push((old_path, new_path, old_metadata, metadata, contents));
+        }
+
+        let mut renamed_directories = Vec::new();
+        for (old_path, new_path, old_metadata, metadata, contents) in converted {
+            let renamed = old_path != new_path;
+            if metadata.file_type == FileType::Directory && renamed {
+                self.storage.create_directory(&new_path)?;
+                renamed_directories.push(old_path.clone());
+            }
+            if let Some(contents) = contents {
+                if renamed || old_metadata.blob.is_some() {
+                    let _ = self.discard_contents(&old_path, &old_metadata);
+                }
+                self.storage.write_contents(&new_path, &contents)?;
+                self.storage.set_times(&new_path, &metadata)?;
+            }
+            self.remove_entry(&old_path);
+            self.insert_entry(new_path, metadata)?;
+        }
+        for directory in renamed_directories.iter().rev() {
+            self.storage.remove_directory(directory)?;
+        }
+
+        if let Some(mut metadata) = self.entry(&stored_path)? {
+            metadata.encryption = Some(EncryptionInfo {
+                salt: crypto::encode(&salt),
+                check,
+                encrypt_names,
+            });
+            self.insert_entry(stored_path.clone(), metadata)?;
+        }
+        self.unlocked.insert(stored_path, UnlockedDirectory {
+            key,
+            last_used: Cell::new(self.time.now()),
+        });
+
+        self.changed()?;
+        Ok(())
+    }
+
+    pub fn unlock_directory(&mut self, path: &str, passphrase: &str) -> Result<(), String> {
+        let path = Self::normalize_path(path);
+        let stored_path = self.storage_path(&path)?;
+
+        let info = self.entry(&stored_path)?
+            .and_then(|metadata| metadata.encryption)
+            .ok_or_else(|| format!("{} is not an encrypted directory", path))?;
+
+        let key = crypto::derive_key(passphrase, &crypto::decode(&info.salt)?)?;
+        match crypto::decrypt(&key, &info.check) {
+            Ok(check) if check == ENCRYPTION_CHECK.as_bytes() => {}
+            _ => return Err("Incorrect passphrase".to_string()),
+        }
+
+        self.unlocked.insert(stored_path, UnlockedDirectory {
+            key,
+            last_used: Cell::new(self.time.now()),
+        });
         Ok(())
     }
 
+    pub fn lock_directory(&mut self, path: &str) {
+        let path = Self::normalize_path(path);
+        self.unlocked.remove(&path);
+    }
+
+    pub fn lock_all(&mut self) {
+        self.unlocked.clear();
+    }
+
+    // Lock every directory that hasn't been used for `max_idle` milliseconds and
+    // return their paths
+    pub fn lock_idle(&mut self, max_idle: u64) -> Vec<String> {
+        let now = self.time.now();
+        let idle: Vec<String> = self.unlocked.iter()
+            .filter(|(_, unlocked)| now.saturating_sub(unlocked.last_used.get()) >= max_idle)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in &idle {
+            self.unlocked.remove(path);
+        }
+        idle
+    }
+
+    pub fn is_encrypted(&self, path: &str) -> bool {
+        let path = Self::normalize_path(path);
+        matches!(self.entry(&path), Ok(Some(metadata)) if metadata.encryption.is_some())
+    }
+
+    // The locked encrypted directory that `path` is, or is inside of
+    pub fn locked_directory(&self, path: &str) -> Option<String> {
+        let path = Self::normalize_path(path);
+        // Encrypted directories are never nested, so their stored path is the
+        // same as the plain one
+        Path::new(&path).ancestors()
+            .filter_map(|ancestor| ancestor.to_str())
+            .find(|ancestor| {
+                matches!(self.entry(ancestor), Ok(Some(metadata)) if metadata.encryption.is_some())
+                    && !self.unlocked.contains_key(*ancestor)
+            })
+            .map(|ancestor| ancestor.to_string())
+    }
+
+    // Map a path as the user sees it to the one it is stored under, encrypting
+    // the names below directories with encrypted names
+    fn storage_path(&self, path: &str) -> Result<String, FsError> {
+        let mut stored_path = String::new();
+        let mut key: Option<&Key> = None;
+        let mut locked: Option<String> = None;
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if let Some(directory) = &locked {
+                return Err(FsError::new(ErrorKind::Locked, format!("{} is locked", directory)));
+            }
+
+            stored_path.push('/');
+            match key {
+                Some(key) => stored_path.push_str(&crypto::encrypt_name(key, name)?),
+                None => stored_path.push_str(name),
+            }
+
+            if key.is_none() {
+                let encrypt_names = self.entry(&stored_path)?
+                    .and_then(|metadata| metadata.encryption)
+                    .is_some_and(|info| info.encrypt_names);
+                if encrypt_names {
+                    match self.unlocked.get(&stored_path) {
+                        Some(unlocked) => key = Some(&unlocked.key),
+                        None => locked = Some(stored_path.clone()),
+                    }
+                }
+            }
+        }
+
+        if stored_path.is_empty() {
+            stored_path.push('/');
+        }
+        Ok(stored_path)
+    }
+
+    // The encrypted directory containing `stored_path`, or `stored_path` itself
+    fn encryption_root(&self, stored_path: &str) -> Result<Option<(String, EncryptionInfo)>, String> {
+        for ancestor in Path::new(stored_path).ancestors().filter_map(|ancestor| ancestor.to_str()) {
+            if let Some(info) = self.entry(ancestor)?.and_then(|metadata| metadata.encryption) {
+                return Ok(Some((ancestor.to_string(), info)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Key to encrypt the contents stored at `stored_path` with. Fails if the
+    // enclosing encrypted directory is locked.
+    fn key_for(&self, stored_path: &str) -> Result<Option<&Key>, FsError> {
+        match self.encryption_root(stored_path)? {
+            Some((root, _)) => match self.unlocked.get(&root) {
+                Some(unlocked) => {
+                    unlocked.last_used.set(self.time.now());
+                    Ok(Some(&unlocked.key))
+                }
+                None => Err(FsError::new(ErrorKind::Locked, format!("{} is locked", root))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Key to decrypt the names of entries inside `stored_path` with, if they are
+    // encrypted
+    fn name_key(&self, stored_path: &str) -> Result<Option<&Key>, String> {
+        match self.encryption_root(stored_path)? {
+            Some((_, info)) if info.encrypt_names => Ok(self.key_for(stored_path)?),
+            _ => Ok(None),
+        }
+    }
+
     // Helper method to normalize paths
     fn normalize_path(path: &str) -> String {
         let path = path.trim();
@@ -315,4 +1401,309 @@ impl FileSystem {
             normalized
         }
     }
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, StorageBackend};
+    use crate::time::ManualTime;
+    use std::rc::Rc;
+
+    fn memory_fs() -> FileSystem {
+        FileSystem::with_storage(SharedStorage::new(MemoryStorage::default()), SharedTimeSource::default()).unwrap()
+    }
+
+    fn fs_at(time: &Rc<ManualTime>) -> FileSystem {
+        let source = SharedTimeSource::from_rc(time.clone());
+        FileSystem::with_storage(SharedStorage::new(MemoryStorage::default()), source).unwrap()
+    }
+
+    #[test]
+    fn timestamps_come_from_the_time_source() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let mut fs = fs_at(&time);
+        fs.write_file("/notes.txt", "first").unwrap();
+        let metadata = fs.metadata("/notes.txt").unwrap();
+        assert_eq!((metadata.created, metadata.modified, metadata.accessed), (1_000, 1_000, 1_000));
+
+        time.set(2_000);
+        fs.write_file("/notes.txt", "second").unwrap();
+        let metadata = fs.metadata("/notes.txt").unwrap();
+        assert_eq!((metadata.created, metadata.modified), (1_000, 2_000));
+
+        time.set(3_000);
+        fs.read_file("/notes.txt").unwrap();
+        let metadata = fs.metadata("/notes.txt").unwrap();
+        assert_eq!((metadata.modified, metadata.accessed), (2_000, 3_000));
+
+        time.set(4_000);
+        fs.touch("/notes.txt").unwrap();
+        let metadata = fs.metadata("/notes.txt").unwrap();
+        assert_eq!((metadata.created, metadata.modified, metadata.accessed), (1_000, 4_000, 4_000));
+    }
+
+    #[test]
+    fn set_times_overrides_only_the_given_times() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let mut fs = fs_at(&time);
+        fs.write_file("/notes.txt", "").unwrap();
+        fs.set_times("/notes.txt", FileTimes { created: None, modified: Some(5), accessed: None }).unwrap();
+        let metadata = fs.metadata("/notes.txt").unwrap();
+        assert_eq!((metadata.created, metadata.modified, metadata.accessed), (1_000, 5, 1_000));
+    }
+
+    #[test]
+    fn idle_directories_lock_after_their_time() {
+        let time = Rc::new(ManualTime::new(0));
+        let mut fs = fs_at(&time);
+        fs.create_directory("/secret", false).unwrap();
+        fs.encrypt_directory("/secret", "correct horse", false).unwrap();
+
+        time.set(999);
+        assert!(fs.lock_idle(1_000).is_empty());
+        time.set(1_000);
+        assert_eq!(fs.lock_idle(1_000), vec!["/secret".to_string()]);
+        assert_eq!(fs.locked_directory("/secret").as_deref(), Some("/secret"));
+    }
+
+    #[test]
+    fn concurrent_edits_leave_no_unused_blobs() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let storage = Rc::new(MemoryStorage::default());
+        let open = || {
+            let source = SharedTimeSource::from_rc(time.clone());
+            FileSystem::with_storage(SharedStorage::from_rc(storage.clone()), source).unwrap()
+        };
+        let mut first = open();
+        let mut second = open();
+        first.write_file("/notes.txt", "base").unwrap();
+        second.read_file("/notes.txt").unwrap();
+
+        time.set(2_000);
+        first.write_file("/notes.txt", "first").unwrap();
+        time.set(3_000);
+        // Conflicts with the first tab's change when saving, and wins
+        second.write_file("/notes.txt", "second").unwrap();
+        first.sync_from_storage().unwrap();
+
+        assert_eq!(first.read_file("/notes.txt").unwrap(), "second");
+        let report = open().fsck(FsckMode::Full, false).unwrap();
+        assert_eq!(report.issues, vec![]);
+    }
+
+    #[test]
+    fn large_contents_are_stored_compressed_and_read_back_as_written() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = FileSystem::with_storage(SharedStorage::from_rc(storage.clone()), SharedTimeSource::default()).unwrap();
+        let contents = "All work and no play makes Jack a dull boy.\n".repeat(100);
+        fs.write_file("/large.txt", &contents).unwrap();
+        fs.write_file("/small.txt", "short").unwrap();
+
+        let metadata = fs.metadata("/large.txt").unwrap();
+        assert_eq!((metadata.encoding, metadata.size), (ContentEncoding::Deflate, contents.len()));
+        let stored = storage.read_blob(metadata.blob.as_deref().unwrap()).unwrap().unwrap();
+        assert!(stored.len() < contents.len() / 4);
+        assert_eq!(compression::decompress(&crypto::decode(&stored).unwrap()).unwrap(), contents.as_bytes());
+        assert_eq!(fs.metadata("/small.txt").unwrap().encoding, ContentEncoding::Plain);
+
+        let reopened = FileSystem::with_storage(SharedStorage::from_rc(storage), SharedTimeSource::default()).unwrap();
+        assert_eq!(reopened.read_file("/large.txt").unwrap(), contents);
+        assert_eq!(reopened.read_file("/small.txt").unwrap(), "short");
+    }
+
+    #[test]
+    fn contents_that_barely_shrink_are_stored_as_they_are() {
+        let mut fs = memory_fs();
+        // Pseudo-random printable characters, which deflate can't shrink by
+        // the third that base64 encoding adds back
+        let mut state = 1u32;
+        let contents: String = (0..2_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            char::from(b'!' + (state >> 16) as u8 % 94)
+        }).collect();
+        fs.write_file("/random.txt", &contents).unwrap();
+        assert_eq!(fs.metadata("/random.txt").unwrap().encoding, ContentEncoding::Plain);
+        assert_eq!(fs.read_file("/random.txt").unwrap(), contents);
+    }
+
+    #[test]
+    fn compressed_contents_of_encrypted_files_round_trip() {
+        let mut fs = memory_fs();
+        fs.create_directory("/secret", false).unwrap();
+        fs.encrypt_directory("/secret", "correct horse", false).unwrap();
+        let contents = "line\n".repeat(1_000);
+        fs.write_file("/secret/notes.txt", &contents).unwrap();
+        assert_eq!(fs.metadata("/secret/notes.txt").unwrap().encoding, ContentEncoding::Deflate);
+
+        fs.lock_all();
+        fs.unlock_directory("/secret", "correct horse").unwrap();
+        assert_eq!(fs.read_file("/secret/notes.txt").unwrap(), contents);
+    }
+
+    fn loaded_directories(fs: &FileSystem) -> Vec<String> {
+        let mut loaded: Vec<String> = fs.loaded.borrow().iter().cloned().collect();
+        loaded.sort();
+        loaded
+    }
+
+    #[test]
+    fn directories_are_loaded_when_first_needed() {
+        let storage = Rc::new(MemoryStorage::default());
+        let open = || FileSystem::with_storage(SharedStorage::from_rc(storage.clone()), SharedTimeSource::default()).unwrap();
+        let mut fs = open();
+        fs.create_directory("/a/b", true).unwrap();
+        fs.write_file("/a/b/c.txt", "deep").unwrap();
+        fs.write_file("/home/x.txt", "elsewhere").unwrap();
+
+        let fs = open();
+        assert!(loaded_directories(&fs).is_empty());
+        assert_eq!(fs.read_file("/a/b/c.txt").unwrap(), "deep");
+        assert_eq!(loaded_directories(&fs), ["/", "/a", "/a/b"]);
+        assert!(!fs.files.borrow().contains_key("/home/x.txt"));
+    }
+
+    #[test]
+    fn deferred_changes_are_written_by_save() {
+        let storage = Rc::new(MemoryStorage::default());
+        let open = || FileSystem::with_storage(SharedStorage::from_rc(storage.clone()), SharedTimeSource::default()).unwrap();
+        let mut fs = open();
+        fs.set_deferred_saves(true);
+        fs.write_file("/home/notes.txt", "later").unwrap();
+
+        assert!(fs.has_unsaved_changes());
+        assert_eq!(fs.dirty.iter().collect::<Vec<_>>(), ["/home"]);
+        assert!(open().metadata("/home/notes.txt").is_err());
+
+        fs.save().unwrap();
+        assert!(!fs.has_unsaved_changes());
+        assert_eq!(open().read_file("/home/notes.txt").unwrap(), "later");
+    }
+
+    #[test]
+    fn deferred_changes_are_saved_once_idle_or_after_waiting_too_long() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let mut fs = fs_at(&time);
+        fs.set_deferred_saves(true);
+        assert!(!fs.save_if_idle(500, 2_000).unwrap());
+
+        fs.write_file("/notes.txt", "1").unwrap();
+        time.set(1_499);
+        assert!(!fs.save_if_idle(500, 2_000).unwrap());
+        time.set(1_500);
+        assert!(fs.save_if_idle(500, 2_000).unwrap());
+        assert!(!fs.save_if_idle(500, 2_000).unwrap());
+
+        // Changes keep coming, but not for longer than the maximum wait
+        for now in (2_000..4_000).step_by(400) {
+            time.set(now);
+            fs.write_file("/notes.txt", &now.to_string()).unwrap();
+            assert!(!fs.save_if_idle(500, 2_000).unwrap());
+        }
+        time.set(4_000);
+        assert!(fs.save_if_idle(500, 2_000).unwrap());
+    }
+
+    // Two tabs sharing `storage`, with times from `time`
+    fn tabs(time: &Rc<ManualTime>) -> (FileSystem, FileSystem) {
+        let storage = Rc::new(MemoryStorage::default());
+        let open = || {
+            let source = SharedTimeSource::from_rc(time.clone());
+            FileSystem::with_storage(SharedStorage::from_rc(storage.clone()), source).unwrap()
+        };
+        (open(), open())
+    }
+
+    #[test]
+    fn entries_added_in_both_tabs_are_kept() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let (mut first, mut second) = tabs(&time);
+        first.list_directory("/").unwrap();
+        second.list_directory("/").unwrap();
+
+        first.write_file("/first.txt", "one").unwrap();
+        second.write_file("/second.txt", "two").unwrap();
+        assert_eq!(first.sync_from_storage().unwrap(), vec![]);
+
+        for fs in [&first, &second] {
+            assert_eq!(fs.read_file("/first.txt").unwrap(), "one");
+            assert_eq!(fs.read_file("/second.txt").unwrap(), "two");
+        }
+    }
+
+    #[test]
+    fn a_file_edited_in_one_tab_survives_its_deletion_in_the_other() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let (mut first, mut second) = tabs(&time);
+        first.write_file("/notes.txt", "base").unwrap();
+        second.read_file("/notes.txt").unwrap();
+
+        second.set_deferred_saves(true);
+
+        time.set(2_000);
+        first.delete("/notes.txt", false).unwrap();
+        time.set(3_000);
+        second.write_file("/notes.txt", "edited").unwrap();
+        let conflicts = second.sync_from_storage().unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].path.as_str(), conflicts[0].remote.as_ref()), ("/notes.txt", None));
+        second.save().unwrap();
+        assert_eq!(first.sync_from_storage().unwrap(), vec![]);
+
+        assert_eq!(first.read_file("/notes.txt").unwrap(), "edited");
+        let report = first.fsck(FsckMode::Full, false).unwrap();
+        assert_eq!(report.issues, vec![]);
+    }
+
+    #[test]
+    fn a_file_deleted_after_its_edit_was_synced_stays_deleted() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let (mut first, mut second) = tabs(&time);
+        first.write_file("/notes.txt", "base").unwrap();
+        second.read_file("/notes.txt").unwrap();
+
+        time.set(2_000);
+        first.write_file("/notes.txt", "edited").unwrap();
+        second.sync_from_storage().unwrap();
+        second.delete("/notes.txt", false).unwrap();
+
+        assert_eq!(first.sync_from_storage().unwrap(), vec![]);
+        assert!(first.metadata("/notes.txt").is_err());
+    }
+
+    #[test]
+    fn a_rename_in_one_tab_moves_the_file_in_the_other() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let (mut first, mut second) = tabs(&time);
+        first.create_directory("/docs", false).unwrap();
+        first.write_file("/docs/draft.txt", "text").unwrap();
+        second.read_file("/docs/draft.txt").unwrap();
+
+        // How mv renames: copy, then delete the original
+        time.set(2_000);
+        first.copy_file("/docs/draft.txt", "/docs/final.txt").unwrap();
+        first.delete("/docs/draft.txt", false).unwrap();
+        assert_eq!(second.sync_from_storage().unwrap(), vec![]);
+
+        assert!(second.metadata("/docs/draft.txt").is_err());
+        assert_eq!(second.read_file("/docs/final.txt").unwrap(), "text");
+        let names: Vec<String> = second.list_directory("/docs").unwrap().into_iter().map(|entry| entry.name).collect();
+        assert_eq!(names, ["final.txt"]);
+    }
+
+    #[test]
+    fn encrypted_directory_unlocks_only_with_its_passphrase() {
+        let mut fs = memory_fs();
+        fs.create_directory("/home/user/secret", true).unwrap();
+        fs.write_file("/home/user/secret/notes.txt", "hidden").unwrap();
+        fs.encrypt_directory("/home/user/secret", "correct horse", true).unwrap();
+        fs.lock_all();
+
+        assert_eq!(fs.locked_directory("/home/user/secret/notes.txt").as_deref(), Some("/home/user/secret"));
+        assert!(fs.read_file("/home/user/secret/notes.txt").is_err());
+        assert_eq!(fs.unlock_directory("/home/user/secret", "battery staple"), Err("Incorrect passphrase".to_string()));
+
+        fs.unlock_directory("/home/user/secret", "correct horse").unwrap();
+        assert_eq!(fs.read_file("/home/user/secret/notes.txt").unwrap(), "hidden");
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashSet;
+use std::fmt;
+use crate::compression;
+use crate::crypto;
+use super::{ContentEncoding, FileMetadata, FileSystem, FileType, SyncedDirectory};
+
+// Where repaired contents without an entry end up
+pub const LOST_AND_FOUND: &str = "/lost+found";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsckMode {
+    // Only checks the directories already loaded, against what is stored;
+    // cheap enough for every boot
+    Quick,
+    // Loads the whole file system to also find contents and blobs nothing
+    // uses, and reads every file to check its recorded size
+    Full,
+}
+
+// Paths are the ones entries are stored under, so names below directories with
+// encrypted names show up encrypted
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsckIssue {
+    // An entry whose parent directory doesn't exist
+    MissingParent { path: String, parent: String },
+    // A file entry without stored contents
+    DanglingEntry { path: String },
+    // Stored contents without a file entry
+    OrphanedContents { path: String },
+    SizeMismatch { path: String, recorded: usize, actual: usize },
+    // A blob whose reference count doesn't match the files using it
+    BlobReferences { hash: String, recorded: usize, actual: usize },
+    // A blob no file or snapshot uses any more
+    UnreferencedBlob { hash: String },
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckIssue::MissingParent { path, parent } => write!(f, "{}: parent directory {} is missing", path, parent),
+            FsckIssue::DanglingEntry { path } => write!(f, "{}: contents are missing", path),
+            FsckIssue::OrphanedContents { path } => write!(f, "{}: contents without an entry", path),
+            FsckIssue::SizeMismatch { path, recorded, actual } => {
+                write!(f, "{}: size is recorded as {} but is {}", path, recorded, actual)
+            }
+            FsckIssue::BlobReferences { hash, recorded, actual } => {
+                write!(f, "blob {}: {} references are recorded but there are {}", hash, recorded, actual)
+            }
+            FsckIssue::UnreferencedBlob { hash } => write!(f, "blob {}: not used by any file", hash),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FsckReport {
+    pub issues: Vec<FsckIssue>,
+    // What was done to repair them, if asked to
+    pub repairs: Vec<String>,
+}
+
+impl FileSystem {
+    // Check that the metadata and the stored contents agree, and optionally
+    // repair what doesn't: missing parents are recreated, files in the place of
+    // a parent moved to /lost+found (unless they are locked), entries without
+    // contents removed, contents without an entry moved to /lost+found, blob
+    // reference counts fixed, unused blobs removed and wrong sizes corrected.
+    // Whether contents or blobs are still used can only be told with every
+    // entry loaded, so only a full check looks for those.
+    pub fn fsck(&mut self, mode: FsckMode, repair: bool) -> Result<FsckReport, String> {
+        let mut report = FsckReport::default();
+
+        if mode == FsckMode::Full {
+            self.descendants("/")?;
+            self.load_unreachable_directories()?;
+        }
+
+        self.check_parents(&mut report, repair)?;
+        self.check_entries(&mut report, repair)?;
+        if mode == FsckMode::Full {
+            self.check_orphans(&mut report, repair)?;
+            self.check_blobs(&mut report, repair)?;
+            self.check_sizes(&mut report, repair)?;
+        }
+
+        if repair && !report.repairs.is_empty() {
+            self.changed()?;
+        }
+        Ok(report)
+    }
+
+    // Directory metadata is stored for directories that no entry leads to;
+    // load it anyway so that its entries can be checked
+    fn load_unreachable_directories(&mut self) -> Result<(), String> {
+        let mut directories = self.storage.directory_metadata_paths()?;
+        // Parents before their children
+        directories.sort();
+
+        for directory in directories {
+            if self.loaded.get_mut().contains(&directory) {
+                continue;
+            }
+            if self.entry(&directory)?.is_some_and(|metadata| metadata.file_type == FileType::Directory) {
+                self.descendants(&directory)?;
+                continue;
+            }
+
+            let data = self.storage.read_directory_metadata(&directory)?.unwrap_or_default();
+            let entries = Self::parse_directory(&directory, &data)
+                .map_err(|e| format!("Failed to load directory {}: {}", directory, e))?
+                .unwrap_or_default();
+            let files = self.files.get_mut();
+            for (path, metadata) in &entries {
+                files.entry(path.clone()).or_insert_with(|| metadata.clone());
+            }
+            self.synced.get_mut().insert(directory.clone(), SyncedDirectory { data, entries });
+            self.loaded.get_mut().insert(directory);
+        }
+        Ok(())
+    }
+
+    fn check_parents(&mut self, report: &mut FsckReport, repair: bool) -> Result<(), String> {
+        let mut paths: Vec<String> = self.files.get_mut().keys()
+            .filter(|path| path.as_str() != "/")
+            .cloned()
+            .collect();
+        paths.sort();
+
+        let mut missing = Vec::new();
+        for path in paths {
+            let parent = Self::parent_directory(&path);
+            let parent_exists = self.files.get_mut().get(&parent)
+                .is_some_and(|metadata| metadata.file_type == FileType::Directory);
+            if !parent_exists {
+                report.issues.push(FsckIssue::MissingParent { path, parent: parent.clone() });
+                missing.push(parent);
+            }
+        }
+
+        if !repair {
+            return Ok(());
+        }
+
+        // Bring back the missing directories, and any missing above them
+        missing.sort();
+        missing.dedup();
+        while let Some(directory) = missing.pop() {
+            if directory == "/" {
+                continue;
+            }
+            match self.files.get_mut().get(&directory).cloned() {
+                Some(metadata) if metadata.file_type == FileType::Directory => continue,
+                // A file is in the way; its contents can only be moved if
+                // they can be read
+                Some(metadata) => match self.recover_file(&directory, metadata)? {
+                    Some(target) => report.repairs.push(format!("Moved file {} to {}", directory, target)),
+                    None => continue,
+                },
+                None => {}
+            }
+
+            let now = self.time.now();
+            self.insert_entry(directory.clone(), FileMetadata {
+                name: Self::entry_name(&directory),
+                file_type: FileType::Directory,
+                size: 0,
+                created: now,
+                modified: now,
+                accessed: now,
+                encoding: ContentEncoding::Plain,
+                blob: None,
+                encryption: None,
+            })?;
+            self.loaded.get_mut().insert(directory.clone());
+            report.repairs.push(format!("Recreated directory {}", directory));
+            missing.push(Self::parent_directory(&directory));
+        }
+        Ok(())
+    }
+
+    // File entries whose contents are missing
+    fn check_entries(&mut self, report: &mut FsckReport, repair: bool) -> Result<(), String> {
+        let stored: HashSet<String> = self.storage.content_paths()?.into_iter().collect();
+        let blobs: HashSet<String> = self.storage.blob_hashes()?.into_iter().collect();
+        let mut files = self.file_blobs();
+        files.sort();
+
+        for (path, blob) in &files {
+            let has_contents = match blob {
+                Some(hash) => blobs.contains(hash),
+                None => stored.contains(path),
+            };
+            if !has_contents {
+                report.issues.push(FsckIssue::DanglingEntry { path: path.clone() });
+                if repair {
+                    self.remove_entry(path);
+                    report.repairs.push(format!("Removed entry {}", path));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Stored contents no file entry has
+    fn check_orphans(&mut self, report: &mut FsckReport, repair: bool) -> Result<(), String> {
+        let stored = self.storage.content_paths()?;
+        // Contents under a file's own path belong to files without a blob
+        let files: HashSet<String> = self.file_blobs().into_iter()
+            .filter(|(_, blob)| blob.is_none())
+            .map(|(path, _)| path)
+            .collect();
+        let mut orphans: Vec<String> = stored.into_iter()
+            .filter(|path| !files.contains(path))
+            .collect();
+        orphans.sort();
+
+        for path in orphans {
+            report.issues.push(FsckIssue::OrphanedContents { path: path.clone() });
+            if repair {
+                let target = self.recover_contents(&path)?;
+                report.repairs.push(format!("Moved contents of {} to {}", path, target));
+            }
+        }
+        Ok(())
+    }
+
+    // The path of every file entry loaded, with its blob if it has one
+    fn file_blobs(&mut self) -> Vec<(String, Option<String>)> {
+        self.files.get_mut().iter()
+            .filter(|(_, metadata)| metadata.file_type == FileType::File)
+            .map(|(path, metadata)| (path.clone(), metadata.blob.clone()))
+            .collect()
+    }
+
+    fn check_blobs(&mut self, report: &mut FsckReport, repair: bool) -> Result<(), String> {
+        // Snapshots hold references to the blobs of their files too
+        let mut references = self.snapshot_blob_references()?;
+        for metadata in self.files.get_mut().values() {
+            if let Some(hash) = &metadata.blob {
+                *references.entry(hash.clone()).or_default() += 1;
+            }
+        }
+
+        let mut blobs = self.storage.blob_hashes()?;
+        blobs.sort();
+        for hash in blobs {
+            let recorded = self.storage.blob_references(&hash)?;
+            match references.get(&hash).copied().unwrap_or(0) {
+                0 => {
+                    report.issues.push(FsckIssue::UnreferencedBlob { hash: hash.clone() });
+                    if repair {
+                        self.storage.remove_blob(&hash)?;
+                        report.repairs.push(format!("Removed blob {}", hash));
+                    }
+                }
+                actual if actual != recorded => {
+                    report.issues.push(FsckIssue::BlobReferences { hash: hash.clone(), recorded, actual });
+                    if repair {
+                        self.storage.set_blob_references(&hash, actual)?;
+                        report.repairs.push(format!("Corrected references of blob {}", hash));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check_sizes(&mut self, report: &mut FsckReport, repair: bool) -> Result<(), String> {
+        let mut files: Vec<(String, FileMetadata)> = self.files.get_mut().iter()
+            .filter(|(_, metadata)| metadata.file_type == FileType::File)
+            .map(|(path, metadata)| (path.clone(), metadata.clone()))
+            .collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (path, mut metadata) in files {
+            // Contents of locked directories can't be checked
+            let key = match self.key_for(&path) {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+            let contents = match self.read_stored(&path, &metadata)? {
+                Some(contents) => Self::decode_contents(&contents, metadata.encoding, key),
+                None => continue,
+            };
+            let actual = match contents {
+                Ok(contents) => contents.len(),
+                Err(e) => {
+                    log::warn!("fsck: can't read {}: {}", path, e);
+                    continue;
+                }
+            };
+
+            if actual != metadata.size {
+                report.issues.push(FsckIssue::SizeMismatch { path: path.clone(), recorded: metadata.size, actual });
+                if repair {
+                    metadata.size = actual;
+                    self.insert_entry(path.clone(), metadata)?;
+                    report.repairs.push(format!("Corrected size of {}", path));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Move the file at `path` to /lost+found and return its new path, or None
+    // if it is in a locked directory
+    fn recover_file(&mut self, path: &str, metadata: FileMetadata) -> Result<Option<String>, String> {
+        let Ok(key) = self.key_for(path).map(|key| key.cloned()) else {
+            return Ok(None);
+        };
+        let contents = match self.read_stored(path, &metadata)? {
+            Some(stored) => Self::decode_contents(&stored, metadata.encoding, key.as_ref())?,
+            None => String::new(),
+        };
+
+        let target = self.lost_and_found_path(path)?;
+        self.write_file(&target, &contents)?;
+        self.discard_contents(path, &metadata)?;
+        self.remove_entry(path);
+        Ok(Some(target))
+    }
+
+    // Store the contents kept for `path` as a file in /lost+found instead, and
+    // return that file's path
+    fn recover_contents(&mut self, path: &str) -> Result<String, String> {
+        let stored = self.storage.read_contents(path)?.unwrap_or_default();
+        // Without metadata there's no telling how the contents were stored;
+        // unpack them if they look compressed, and keep them as they are otherwise
+        let contents = crypto::decode(&stored).ok()
+            .and_then(|bytes| compression::decompress(&bytes).ok())
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .unwrap_or(stored);
+
+        let target = self.lost_and_found_path(path)?;
+        self.write_file(&target, &contents)?;
+        self.storage.remove_contents(path)?;
+        Ok(target)
+    }
+
+    // A free path in /lost+found for what was stored at `path`
+    fn lost_and_found_path(&mut self, path: &str) -> Result<String, String> {
+        if self.entry(LOST_AND_FOUND)?.is_none() {
+            self.create_directory(LOST_AND_FOUND, false)?;
+        }
+
+        let name = path.trim_start_matches('/').replace('/', "_");
+        let mut target = Self::join(LOST_AND_FOUND, &name);
+        let mut copy = 1;
+        while self.entry(&target)?.is_some() {
+            target = Self::join(LOST_AND_FOUND, &format!("{}.{}", name, copy));
+            copy += 1;
+        }
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::storage::{MemoryStorage, SharedStorage, StorageBackend};
+    use crate::time::SharedTimeSource;
+
+    fn open(storage: &Rc<MemoryStorage>) -> FileSystem {
+        FileSystem::with_storage(SharedStorage::from_rc(storage.clone()), SharedTimeSource::default()).unwrap()
+    }
+
+    #[test]
+    fn quick_check_only_looks_at_loaded_directories() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        fs.create_directory("/a/b", true).unwrap();
+        fs.write_file("/a/b/notes.txt", "hi").unwrap();
+        fs.save().unwrap();
+
+        let mut fs = open(&storage);
+        fs.fsck(FsckMode::Quick, false).unwrap();
+        assert!(!fs.loaded.get_mut().contains("/a/b"));
+        fs.fsck(FsckMode::Full, false).unwrap();
+        assert!(fs.loaded.get_mut().contains("/a/b"));
+    }
+
+    #[test]
+    fn unused_blobs_are_only_removed_when_asked_to() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        // As another tab leaves it between storing a blob and saving its entry
+        storage.write_blob("unsaved", "contents").unwrap();
+        storage.set_blob_references("unsaved", 1).unwrap();
+
+        let report = fs.fsck(FsckMode::Quick, false).unwrap();
+        assert!(report.issues.is_empty());
+
+        let report = fs.fsck(FsckMode::Full, false).unwrap();
+        assert_eq!(report.issues, vec![FsckIssue::UnreferencedBlob { hash: "unsaved".to_string() }]);
+        assert!(report.repairs.is_empty());
+        assert_eq!(storage.read_blob("unsaved").unwrap().as_deref(), Some("contents"));
+
+        let report = fs.fsck(FsckMode::Full, true).unwrap();
+        assert_eq!(report.repairs.len(), 1);
+        assert_eq!(storage.read_blob("unsaved").unwrap(), None);
+    }
+
+    #[test]
+    fn a_file_in_the_place_of_a_parent_is_moved_to_lost_and_found() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        fs.write_file("/docs", "was a file").unwrap();
+        fs.create_directory("/other", false).unwrap();
+        fs.write_file("/other/inner.txt", "inner").unwrap();
+        // Entries stored below the file, as if it had replaced a directory
+        let entries = storage.read_directory_metadata("/other").unwrap().unwrap();
+        storage.write_directory_metadata("/docs", &entries).unwrap();
+        let hash = fs.metadata("/other/inner.txt").unwrap().blob.unwrap();
+        storage.set_blob_references(&hash, 2).unwrap();
+
+        let mut fs = open(&storage);
+        let report = fs.fsck(FsckMode::Full, false).unwrap();
+        let missing = FsckIssue::MissingParent { path: "/docs/inner.txt".to_string(), parent: "/docs".to_string() };
+        assert_eq!(report.issues, vec![missing]);
+
+        let report = fs.fsck(FsckMode::Full, true).unwrap();
+        assert_eq!(report.repairs, ["Moved file /docs to /lost+found/docs", "Recreated directory /docs"]);
+        assert_eq!(fs.metadata("/docs").unwrap().file_type, FileType::Directory);
+        assert_eq!(fs.read_file("/docs/inner.txt").unwrap(), "inner");
+        assert_eq!(fs.read_file("/lost+found/docs").unwrap(), "was a file");
+
+        let report = open(&storage).fsck(FsckMode::Full, false).unwrap();
+        assert_eq!(report.issues, vec![]);
+    }
+
+    #[test]
+    fn quick_check_finds_missing_contents_of_loaded_files() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        fs.write_file("/notes.txt", "hi").unwrap();
+        let hash = fs.metadata("/notes.txt").unwrap().blob.unwrap();
+        storage.remove_blob(&hash).unwrap();
+
+        let report = fs.fsck(FsckMode::Quick, false).unwrap();
+        assert_eq!(report.issues, vec![FsckIssue::DanglingEntry { path: "/notes.txt".to_string() }]);
+        assert!(fs.metadata("/notes.txt").is_ok());
+    }
+}
@@ -0,0 +1,200 @@
+use std::cmp::Ordering;
+use std::path::Path;
+use super::{FileMetadata, FileSystem, FileType, FsError};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    // Directories, then files by extension
+    Type,
+    Modified,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortDirection {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+impl SortDirection {
+    pub fn reversed(self) -> Self {
+        match self {
+            SortDirection::Ascending => SortDirection::Descending,
+            SortDirection::Descending => SortDirection::Ascending,
+        }
+    }
+}
+
+// Which entries `FileSystem::list` returns, and in what order. Entries that
+// compare equal are ordered by name, so listings are always stable.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListOptions {
+    pub sort_by: SortKey,
+    pub direction: SortDirection,
+    pub directories_first: bool,
+    // Only entries whose name contains this, ignoring case
+    pub filter: Option<String>,
+    pub include_hidden: bool,
+    // The page to return, counted after filtering
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Listing {
+    pub entries: Vec<FileMetadata>,
+    // Number of entries matching the filter, across all pages
+    pub total: usize,
+}
+
+impl FileSystem {
+    pub fn list(&self, path: &str, options: &ListOptions) -> Result<Listing, FsError> {
+        let filter = options.filter.as_ref()
+            .filter(|filter| !filter.is_empty())
+            .map(|filter| filter.to_lowercase());
+
+        let mut entries: Vec<FileMetadata> = self.list_directory(path)?
+            .into_iter()
+            .filter(|entry| options.include_hidden || !entry.is_hidden())
+            .filter(|entry| filter.as_ref().is_none_or(|filter| entry.name.to_lowercase().contains(filter)))
+            .collect();
+
+        entries.sort_by(|a, b| {
+            if options.directories_first && a.file_type != b.file_type {
+                return if a.file_type == FileType::Directory { Ordering::Less } else { Ordering::Greater };
+            }
+            let ordering = compare(a, b, options.sort_by).then_with(|| a.name.cmp(&b.name));
+            match options.direction {
+                SortDirection::Ascending => ordering,
+                SortDirection::Descending => ordering.reverse(),
+            }
+        });
+
+        let total = entries.len();
+        let entries = entries.into_iter()
+            .skip(options.offset)
+            .take(options.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        Ok(Listing { entries, total })
+    }
+}
+
+fn compare(a: &FileMetadata, b: &FileMetadata, key: SortKey) -> Ordering {
+    match key {
+        SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        SortKey::Size => a.size.cmp(&b.size),
+        SortKey::Type => type_rank(a).cmp(&type_rank(b))
+            .then_with(|| extension(a).cmp(&extension(b))),
+        SortKey::Modified => a.modified.cmp(&b.modified),
+    }
+}
+
+fn type_rank(entry: &FileMetadata) -> u8 {
+    match entry.file_type {
+        FileType::Directory => 0,
+        FileType::File => 1,
+    }
+}
+
+fn extension(entry: &FileMetadata) -> String {
+    Path::new(&entry.name).extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::storage::{MemoryStorage, SharedStorage};
+    use crate::time::{ManualTime, SharedTimeSource};
+
+    fn memory_fs() -> FileSystem {
+        FileSystem::with_storage(SharedStorage::new(MemoryStorage::default()), SharedTimeSource::default()).unwrap()
+    }
+
+    fn names(listing: &Listing) -> Vec<&str> {
+        listing.entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    // A directory with files of different sizes, types and times
+    fn sample_fs() -> FileSystem {
+        let time = Rc::new(ManualTime::new(1_000));
+        let mut fs = FileSystem::with_storage(SharedStorage::new(MemoryStorage::default()), SharedTimeSource::from_rc(time.clone())).unwrap();
+        fs.create_directory("/test", false).unwrap();
+        for (name, contents) in [("b.txt", "12345"), ("A.md", "1"), ("c.rs", "123"), ("d.txt", "1")] {
+            time.advance(1_000);
+            fs.write_file(&format!("/test/{}", name), contents).unwrap();
+        }
+        time.advance(1_000);
+        fs.create_directory("/test/zdir", false).unwrap();
+        fs
+    }
+
+    fn listed(fs: &FileSystem, options: ListOptions) -> Vec<String> {
+        fs.list("/test", &options).unwrap().entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn entries_are_sorted_by_the_chosen_key_with_names_breaking_ties() {
+        let fs = sample_fs();
+        assert_eq!(listed(&fs, ListOptions::default()), ["A.md", "b.txt", "c.rs", "d.txt", "zdir"]);
+        let by_size = ListOptions { sort_by: SortKey::Size, ..ListOptions::default() };
+        assert_eq!(listed(&fs, by_size), ["zdir", "A.md", "d.txt", "c.rs", "b.txt"]);
+        let by_type = ListOptions { sort_by: SortKey::Type, ..ListOptions::default() };
+        assert_eq!(listed(&fs, by_type), ["zdir", "A.md", "c.rs", "b.txt", "d.txt"]);
+        let newest_first = ListOptions { sort_by: SortKey::Modified, direction: SortDirection::Descending, ..ListOptions::default() };
+        assert_eq!(listed(&fs, newest_first), ["zdir", "d.txt", "c.rs", "A.md", "b.txt"]);
+    }
+
+    #[test]
+    fn directories_first_holds_in_either_direction() {
+        let fs = sample_fs();
+        let options = ListOptions { directories_first: true, direction: SortDirection::Descending, ..ListOptions::default() };
+        assert_eq!(listed(&fs, options), ["zdir", "d.txt", "c.rs", "b.txt", "A.md"]);
+    }
+
+    #[test]
+    fn filters_ignore_case_and_pages_count_after_filtering() {
+        let fs = sample_fs();
+        let options = ListOptions { filter: Some("TXT".to_string()), ..ListOptions::default() };
+        assert_eq!(listed(&fs, options), ["b.txt", "d.txt"]);
+
+        let options = ListOptions { offset: 1, limit: Some(2), ..ListOptions::default() };
+        let listing = fs.list("/test", &options).unwrap();
+        assert_eq!((names(&listing), listing.total), (vec!["b.txt", "c.rs"], 5));
+        let options = ListOptions { filter: Some("t".to_string()), offset: 2, limit: Some(2), ..ListOptions::default() };
+        let listing = fs.list("/test", &options).unwrap();
+        assert_eq!((names(&listing), listing.total), (vec![], 2));
+    }
+
+    #[test]
+    fn dotfiles_are_only_listed_when_asked_for() {
+        let mut fs = memory_fs();
+        fs.write_file("/home/.profile", "").unwrap();
+        fs.create_directory("/home/.cache", false).unwrap();
+        fs.write_file("/home/notes.txt", "").unwrap();
+
+        let listing = fs.list("/home", &ListOptions::default()).unwrap();
+        assert_eq!((names(&listing), listing.total), (vec!["documents", "notes.txt", "pictures"], 3));
+
+        let options = ListOptions { include_hidden: true, ..ListOptions::default() };
+        let listing = fs.list("/home", &options).unwrap();
+        assert_eq!(names(&listing), [".cache", ".profile", "documents", "notes.txt", "pictures"]);
+    }
+
+    #[test]
+    fn only_names_starting_with_a_dot_are_hidden() {
+        let mut fs = memory_fs();
+        fs.write_file("/home/.hidden", "").unwrap();
+        fs.write_file("/home/not.hidden", "").unwrap();
+        let hidden: Vec<bool> = ["/home/.hidden", "/home/not.hidden", "/home"].iter()
+            .map(|path| fs.metadata(path).unwrap().is_hidden())
+            .collect();
+        assert_eq!(hidden, [true, false, false]);
+    }
+}
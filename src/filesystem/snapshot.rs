@@ -0,0 +1,438 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::crypto;
+use super::{FileMetadata, FileSystem, FileType};
+
+// A snapshot is the metadata of every entry at the time it was taken. Contents
+// are never copied: each file refers to a blob, and the snapshot holds a
+// reference to it just like a file does, so taking one only costs the metadata
+// and contents stay shared until a file is rewritten.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredSnapshot {
+    created: u64,
+    // By stored path, root included
+    entries: BTreeMap<String, FileMetadata>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub created: u64,
+    pub files: usize,
+    pub directories: usize,
+    // Total size of the files, as read
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapshotChangeKind {
+    // Exists now, but not in the snapshot
+    Added,
+    // Exists in the snapshot, but not any more
+    Removed,
+    // Contents or type differ
+    Modified,
+}
+
+// Paths are the ones entries are stored under, so names below directories with
+// encrypted names show up encrypted
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotChange {
+    pub path: String,
+    pub kind: SnapshotChangeKind,
+}
+
+impl fmt::Display for SnapshotChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = match self.kind {
+            SnapshotChangeKind::Added => 'A',
+            SnapshotChangeKind::Removed => 'D',
+            SnapshotChangeKind::Modified => 'M',
+        };
+        write!(f, "{} {}", marker, self.path)
+    }
+}
+
+impl FileSystem {
+    // Record the whole file system under `name`, to restore or compare with later
+    pub fn create_snapshot(&mut self, name: &str) -> Result<SnapshotInfo, String> {
+        Self::check_snapshot_name(name)?;
+        if !self.storage.deduplicate_contents() {
+            return Err("Snapshots aren't supported by this storage".to_string());
+        }
+        if self.storage.read_snapshot(name)?.is_some() {
+            return Err(format!("Snapshot {} already exists", name));
+        }
+
+        self.descendants("/")?;
+        let mut entries: BTreeMap<String, FileMetadata> = self.files.get_mut().iter()
+            .map(|(path, metadata)| (path.clone(), metadata.clone()))
+            .collect();
+        for (path, accessed) in self.pending_access.get_mut().iter() {
+            if let Some(metadata) = entries.get_mut(path) {
+                metadata.accessed = *accessed;
+            }
+        }
+
+        // Contents not in a blob yet (encrypted ones) get one of their own.
+        // Read them first, so that nothing is referenced if one is missing.
+        let mut unshared = HashMap::new();
+        for (path, metadata) in &entries {
+            if metadata.file_type == FileType::File && metadata.blob.is_none() {
+                let stored = self.storage.read_contents(path)?
+                    .ok_or_else(|| format!("Contents of {} are missing; run fsck", path))?;
+                unshared.insert(path.clone(), stored);
+            }
+        }
+        let mut blobs = Vec::new();
+        for (path, metadata) in entries.iter_mut() {
+            if metadata.file_type != FileType::File {
+                continue;
+            }
+            match (&metadata.blob, unshared.get(path)) {
+                (Some(hash), _) => blobs.push((hash.clone(), None)),
+                (None, Some(stored)) => {
+                    let hash = crypto::content_hash(stored.as_bytes());
+                    blobs.push((hash.clone(), Some(stored.as_str())));
+                    metadata.blob = Some(hash);
+                }
+                (None, None) => {}
+            }
+        }
+
+        let snapshot = StoredSnapshot { created: self.time.now(), entries };
+        let data = serde_json::to_string(&snapshot)
+            .map_err(|e| format!("Failed to serialize snapshot {}: {}", name, e))?;
+        self.retain_blobs(&blobs)?;
+        if let Err(e) = self.storage.write_snapshot(name, &data) {
+            self.release_blobs(blobs.iter().map(|(hash, _)| hash));
+            return Err(e);
+        }
+        Ok(Self::snapshot_info(name, &snapshot))
+    }
+
+    // All snapshots, oldest first
+    pub fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>, String> {
+        let mut snapshots = Vec::new();
+        for name in self.storage.snapshot_names()? {
+            let snapshot = self.read_snapshot(&name)?;
+            snapshots.push(Self::snapshot_info(&name, &snapshot));
+        }
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.name.cmp(&b.name)));
+        Ok(snapshots)
+    }
+
+    pub fn delete_snapshot(&mut self, name: &str) -> Result<(), String> {
+        let snapshot = self.read_snapshot(name)?;
+        for hash in Self::snapshot_blobs(&snapshot) {
+            self.release_blob(hash)?;
+        }
+        self.storage.remove_snapshot(name)
+    }
+
+    // Put the whole file system back the way it was when the snapshot was
+    // taken. Everything changed since is lost, and encrypted directories are
+    // locked, as their keys may have changed.
+    pub fn restore_snapshot(&mut self, name: &str) -> Result<(), String> {
+        let snapshot = self.read_snapshot(name)?;
+        let root = snapshot.entries.get("/").cloned()
+            .ok_or_else(|| format!("Snapshot {} has no root directory", name))?;
+
+        // Encrypted contents are kept under their files' own paths, never
+        // shared, so those go back there rather than staying in the blobs the
+        // snapshot gave them. Read them first, so that a missing one changes
+        // nothing.
+        let mut encrypted = HashMap::new();
+        for (path, metadata) in &snapshot.entries {
+            if let (Some(hash), true) = (&metadata.blob, Self::in_encrypted_directory(&snapshot, path)) {
+                let stored = self.storage.read_blob(hash)?
+                    .ok_or_else(|| format!("Blob {} is missing", hash))?;
+                encrypted.insert(path.clone(), stored);
+            }
+        }
+
+        // Take the snapshot's references first, so that contents it shares with
+        // the current files survive them being discarded
+        let blobs: Vec<(String, Option<&str>)> = snapshot.entries.iter()
+            .filter(|(path, metadata)| metadata.file_type == FileType::File && !encrypted.contains_key(*path))
+            .filter_map(|(_, metadata)| metadata.blob.clone())
+            .map(|hash| (hash, None))
+            .collect();
+        self.retain_blobs(&blobs)?;
+
+        let mut current = self.descendants("/")?;
+        let mut directories = Vec::new();
+        for path in &current {
+            let metadata = self.files.get_mut()[path].clone();
+            match metadata.file_type {
+                FileType::File => { let _ = self.discard_contents(path, &metadata); }
+                FileType::Directory => directories.push(path.clone()),
+            }
+        }
+        directories.sort_by_key(|directory| std::cmp::Reverse(directory.matches('/').count()));
+        for directory in &directories {
+            let _ = self.storage.remove_directory(directory);
+        }
+        current.sort();
+        for path in &current {
+            self.remove_entry(path);
+        }
+
+        // Parents sort before their children
+        for (path, mut metadata) in snapshot.entries {
+            if path == "/" {
+                continue;
+            }
+            match metadata.file_type {
+                FileType::Directory => {
+                    self.storage.create_directory(&path)?;
+                    self.insert_entry(path.clone(), metadata)?;
+                    self.loaded.get_mut().insert(path);
+                }
+                FileType::File => {
+                    if let Some(stored) = encrypted.get(&path) {
+                        self.storage.write_contents(&path, stored)?;
+                        metadata.blob = None;
+                    }
+                    self.storage.set_times(&path, &metadata)?;
+                    self.insert_entry(path, metadata)?;
+                }
+            }
+        }
+        self.insert_entry("/".to_string(), root)?;
+
+        self.unlocked.clear();
+        self.pending_access.get_mut().clear();
+        self.changed()
+    }
+
+    // What changed since the snapshot was taken, by path
+    pub fn diff_snapshot(&self, name: &str) -> Result<Vec<SnapshotChange>, String> {
+        let snapshot = self.read_snapshot(name)?;
+        self.descendants("/")?;
+        let current = self.files.borrow().clone();
+
+        let paths: BTreeSet<&String> = snapshot.entries.keys()
+            .chain(current.keys())
+            .filter(|path| path.as_str() != "/")
+            .collect();
+
+        let mut changes = Vec::new();
+        for path in paths {
+            let kind = match (snapshot.entries.get(path), current.get(path)) {
+                (Some(_), None) => SnapshotChangeKind::Removed,
+                (None, Some(_)) => SnapshotChangeKind::Added,
+                (Some(then), Some(now)) if then.file_type != now.file_type => SnapshotChangeKind::Modified,
+                (Some(then), Some(now)) if now.file_type == FileType::File => {
+                    // Contents are the same exactly when they hash the same
+                    let hash = match &now.blob {
+                        Some(hash) => Some(hash.clone()),
+                        None => self.storage.read_contents(path)?
+                            .map(|stored| crypto::content_hash(stored.as_bytes())),
+                    };
+                    if hash == then.blob {
+                        continue;
+                    }
+                    SnapshotChangeKind::Modified
+                }
+                _ => continue,
+            };
+            changes.push(SnapshotChange { path: path.clone(), kind });
+        }
+        Ok(changes)
+    }
+
+    // Blob references held by snapshots, by hash
+    pub(super) fn snapshot_blob_references(&self) -> Result<HashMap<String, usize>, String> {
+        let mut references = HashMap::new();
+        for name in self.storage.snapshot_names()? {
+            let snapshot = self.read_snapshot(&name)?;
+            for hash in Self::snapshot_blobs(&snapshot) {
+                *references.entry(hash.clone()).or_default() += 1;
+            }
+        }
+        Ok(references)
+    }
+
+    // Take a reference to each blob, storing the data given for new ones. If
+    // one can't be taken, the ones taken before it are released again.
+    fn retain_blobs(&self, blobs: &[(String, Option<&str>)]) -> Result<(), String> {
+        for (i, (hash, data)) in blobs.iter().enumerate() {
+            if let Err(e) = self.retain_blob(hash, *data) {
+                self.release_blobs(blobs[..i].iter().map(|(hash, _)| hash));
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // Undo `retain_blobs` after a failure, which is already being reported
+    fn release_blobs<'a>(&self, hashes: impl Iterator<Item = &'a String>) {
+        for hash in hashes {
+            if let Err(e) = self.release_blob(hash) {
+                log::warn!("Failed to release blob {}: {}", hash, e);
+            }
+        }
+    }
+
+    fn read_snapshot(&self, name: &str) -> Result<StoredSnapshot, String> {
+        let data = self.storage.read_snapshot(name)?
+            .ok_or_else(|| format!("Snapshot {} does not exist", name))?;
+        serde_json::from_str(&data).map_err(|e| format!("Failed to load snapshot {}: {}", name, e))
+    }
+
+    fn snapshot_blobs(snapshot: &StoredSnapshot) -> impl Iterator<Item = &String> {
+        snapshot.entries.values()
+            .filter(|metadata| metadata.file_type == FileType::File)
+            .filter_map(|metadata| metadata.blob.as_ref())
+    }
+
+    // Whether an entry of the snapshot is below an encrypted directory
+    fn in_encrypted_directory(snapshot: &StoredSnapshot, path: &str) -> bool {
+        Path::new(path).ancestors().skip(1).any(|ancestor| {
+            snapshot.entries.get(ancestor.to_string_lossy().as_ref())
+                .is_some_and(|metadata| metadata.encryption.is_some())
+        })
+    }
+
+    fn snapshot_info(name: &str, snapshot: &StoredSnapshot) -> SnapshotInfo {
+        let (files, directories): (Vec<&FileMetadata>, Vec<&FileMetadata>) = snapshot.entries.values()
+            .partition(|metadata| metadata.file_type == FileType::File);
+        SnapshotInfo {
+            name: name.to_string(),
+            created: snapshot.created,
+            files: files.len(),
+            directories: directories.len(),
+            size: files.iter().map(|metadata| metadata.size).sum(),
+        }
+    }
+
+    // Snapshot names end up in storage keys and on command lines
+    fn check_snapshot_name(name: &str) -> Result<(), String> {
+        let valid = !name.is_empty() && !name.starts_with('.')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("Invalid snapshot name {:?}: use letters, digits, '-', '_' and '.'", name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::filesystem::FsckMode;
+    use crate::storage::{MemoryStorage, SharedStorage, StorageBackend};
+    use crate::time::SharedTimeSource;
+
+    fn open(storage: &Rc<MemoryStorage>) -> FileSystem {
+        FileSystem::with_storage(SharedStorage::from_rc(storage.clone()), SharedTimeSource::default()).unwrap()
+    }
+
+    fn blob(fs: &FileSystem, path: &str) -> String {
+        fs.metadata(path).unwrap().blob.unwrap()
+    }
+
+    #[test]
+    fn creating_a_snapshot_shares_the_contents_of_files() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        fs.write_file("/home/a.txt", "apple").unwrap();
+        fs.write_file("/home/b.txt", "banana").unwrap();
+
+        let info = fs.create_snapshot("before").unwrap();
+        assert_eq!((info.name.as_str(), info.files, info.size), ("before", 2, 11));
+        // The root, /home and its three subdirectories
+        assert_eq!(info.directories, 5);
+        assert_eq!(storage.blob_references(&blob(&fs, "/home/a.txt")).unwrap(), 2);
+        assert!(fs.create_snapshot("before").is_err());
+        assert!(fs.create_snapshot("../escape").is_err());
+        assert_eq!(fs.list_snapshots().unwrap(), vec![info]);
+    }
+
+    #[test]
+    fn restoring_a_snapshot_brings_back_its_files_and_drops_newer_ones() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        fs.write_file("/home/a.txt", "apple").unwrap();
+        fs.write_file("/home/b.txt", "banana").unwrap();
+        fs.create_snapshot("before").unwrap();
+
+        fs.write_file("/home/a.txt", "apricot").unwrap();
+        fs.delete("/home/b.txt", false).unwrap();
+        fs.create_directory("/home/new", false).unwrap();
+        fs.write_file("/home/new/c.txt", "cherry").unwrap();
+        fs.restore_snapshot("before").unwrap();
+
+        let fs = open(&storage);
+        assert_eq!(fs.read_file("/home/a.txt").unwrap(), "apple");
+        assert_eq!(fs.read_file("/home/b.txt").unwrap(), "banana");
+        assert!(fs.metadata("/home/new").is_err());
+        let report = open(&storage).fsck(FsckMode::Full, false).unwrap();
+        assert_eq!(report.issues, vec![]);
+    }
+
+    #[test]
+    fn a_failed_restore_takes_no_references() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        fs.write_file("/a.txt", "apple").unwrap();
+        fs.write_file("/b.txt", "banana").unwrap();
+        fs.create_snapshot("before").unwrap();
+        let (a, b) = (blob(&fs, "/a.txt"), blob(&fs, "/b.txt"));
+        storage.remove_blob(&b).unwrap();
+
+        assert_eq!(fs.restore_snapshot("before"), Err(format!("Blob {} is missing", b)));
+        assert_eq!(storage.blob_references(&a).unwrap(), 2);
+        assert_eq!(fs.read_file("/a.txt").unwrap(), "apple");
+    }
+
+    #[test]
+    fn restored_encrypted_files_can_be_copied_out() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        fs.create_directory("/home/secret", false).unwrap();
+        fs.encrypt_directory("/home/secret", "correct horse", false).unwrap();
+        fs.write_file("/home/secret/notes.txt", "plaintext").unwrap();
+        fs.create_snapshot("before").unwrap();
+        fs.restore_snapshot("before").unwrap();
+
+        fs.unlock_directory("/home/secret", "correct horse").unwrap();
+        // Kept under the file's own path again, not in the snapshot's blob
+        assert_eq!(fs.metadata("/home/secret/notes.txt").unwrap().blob, None);
+        assert_eq!(fs.diff_snapshot("before").unwrap(), vec![]);
+        fs.copy_file("/home/secret/notes.txt", "/home/out.txt").unwrap();
+        assert_eq!(fs.read_file("/home/out.txt").unwrap(), "plaintext");
+        assert_eq!(fs.read_file("/home/secret/notes.txt").unwrap(), "plaintext");
+        let report = open(&storage).fsck(FsckMode::Full, false).unwrap();
+        assert_eq!(report.issues, vec![]);
+
+        // Deleting the snapshot leaves the restored contents alone
+        fs.delete_snapshot("before").unwrap();
+        assert_eq!(fs.read_file("/home/secret/notes.txt").unwrap(), "plaintext");
+    }
+
+    #[test]
+    fn diff_lists_what_changed_since_the_snapshot() {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut fs = open(&storage);
+        fs.write_file("/home/same.txt", "same").unwrap();
+        fs.write_file("/home/changed.txt", "old").unwrap();
+        fs.write_file("/home/removed.txt", "gone").unwrap();
+        fs.create_snapshot("before").unwrap();
+        assert_eq!(fs.diff_snapshot("before").unwrap(), vec![]);
+
+        fs.write_file("/home/changed.txt", "new").unwrap();
+        fs.delete("/home/removed.txt", false).unwrap();
+        fs.write_file("/home/added.txt", "fresh").unwrap();
+        // Touching a file doesn't change its contents
+        fs.touch("/home/same.txt").unwrap();
+
+        let changes: Vec<String> = fs.diff_snapshot("before").unwrap().iter().map(|change| change.to_string()).collect();
+        assert_eq!(changes, ["A /home/added.txt", "M /home/changed.txt", "D /home/removed.txt"]);
+    }
+}
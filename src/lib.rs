@@ -1,10 +1,14 @@
 mod components;
-mod filesystem;
-use yew::prelude::*;
+pub mod filesystem;
+mod compression;
+mod crypto;
+pub mod shell;
+pub mod storage;
+pub mod time;
 use wasm_bindgen::prelude::*;
 //use yew::prelude::*;
 
-use crate::components::desktop::Desktop;
+use crate::components::Desktop;
 //use crate::filesystem::FileSystem;
 
 #[wasm_bindgen(start)]
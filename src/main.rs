@@ -1,6 +1,124 @@
-fn main() {
-    println!("Starting KernelOS in the browser...");
-    println!("Please open index.html in your browser");
-    println!("This binary doesn't perform any actual functionality by itself.");
-    println!("For development, use 'trunk serve' to start the development server.");
-} 
\ No newline at end of file
+use std::path::Path;
+use std::process::ExitCode;
+use std::rc::Rc;
+use kernelos::filesystem::{FileSystem, FileTimes, FileType, ListOptions};
+use kernelos::storage::{HostDirectory, MemoryStorage, SharedStorage};
+use kernelos::time::SharedTimeSource;
+
+const USAGE: &str = "Usage:
+  kernelos_cli ls <host dir> [path]       List a directory
+  kernelos_cli cat <host dir> <path>      Print a file
+  kernelos_cli seed <host dir> <image>    Write a local storage image of <host dir> to <image>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        [] => {
+            println!("Starting KernelOS in the browser...");
+            println!("Please open index.html in your browser");
+            println!("This binary doesn't perform any actual functionality by itself.");
+            println!("For development, use 'trunk serve' to start the development server.");
+            println!();
+            println!("{}", USAGE);
+            Ok(())
+        }
+        ["ls", root] => list(root, "/"),
+        ["ls", root, path] => list(root, path),
+        ["cat", root, path] => cat(root, path),
+        ["seed", root, image] => seed(root, image),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn open(root: &str) -> Result<FileSystem, String> {
+    let storage = SharedStorage::new(HostDirectory::new(root)?);
+    FileSystem::with_storage(storage, SharedTimeSource::default())
+}
+
+fn list(root: &str, path: &str) -> Result<(), String> {
+    let options = ListOptions { include_hidden: true, ..ListOptions::default() };
+    for file in open(root)?.list(path, &options)?.entries {
+        let type_indicator = match file.file_type {
+            FileType::Directory => "/",
+            FileType::File => "",
+        };
+        println!("{}{}", file.name, type_indicator);
+    }
+    Ok(())
+}
+
+fn cat(root: &str, path: &str) -> Result<(), String> {
+    match open(root)?.read_file(path) {
+        Ok(contents) => print!("{}", contents),
+        Err(_) if !is_text(root, path) => eprintln!("warning: skipping {}, which is not UTF-8 text", path),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+// Whether the host file behind `path` holds UTF-8 text, which is all the file
+// system can hold
+fn is_text(root: &str, path: &str) -> bool {
+    let host_path = Path::new(root).join(path.trim_start_matches('/'));
+    std::fs::read(host_path).map_or(true, |bytes| std::str::from_utf8(&bytes).is_ok())
+}
+
+// Copy a host directory into a fresh file system and write out the local
+// storage items holding it, as a JSON object of key to value
+fn seed(root: &str, image: &str) -> Result<(), String> {
+    let source = open(root)?;
+    let storage = Rc::new(MemoryStorage::default());
+    let mut target = FileSystem::with_storage(SharedStorage::from_rc(storage.clone()), SharedTimeSource::default())?;
+
+    copy_tree(root, &source, &mut target, "/")?;
+
+    let items: serde_json::Map<String, serde_json::Value> = storage.items()
+        .into_iter()
+        .map(|(key, value)| (key, serde_json::Value::String(value)))
+        .collect();
+    let json = serde_json::to_string_pretty(&items)
+        .map_err(|e| format!("Failed to serialize image: {}", e))?;
+
+    std::fs::write(image, json).map_err(|e| format!("Failed to write {}: {}", image, e))
+}
+
+// Files that aren't UTF-8 text are left out, with a warning
+fn copy_tree(root: &str, source: &FileSystem, target: &mut FileSystem, path: &str) -> Result<(), String> {
+    for file in source.list_directory(path)? {
+        let child = if path == "/" { format!("/{}", file.name) } else { format!("{}/{}", path, file.name) };
+
+        match file.file_type {
+            FileType::Directory => {
+                if target.metadata(&child).is_err() {
+                    target.create_directory(&child, false)?;
+                }
+                copy_tree(root, source, target, &child)?;
+            }
+            FileType::File => match source.read_file(&child) {
+                Ok(contents) => target.write_file(&child, &contents)?,
+                Err(_) if !is_text(root, &child) => {
+                    eprintln!("warning: skipping {}, which is not UTF-8 text", child);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            },
+        }
+
+        target.set_times(&child, FileTimes {
+            created: Some(file.created),
+            modified: Some(file.modified),
+            accessed: Some(file.accessed),
+        })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+// ANSI escape sequences: commands write them to colour their output, or to
+// make links of it, when it goes to the terminal, which parses them back into
+// styled spans
+
+const ESCAPE: char = '\x1b';
+
+// Text in the style of SGR parameters like "01;31", then back to normal
+pub fn paint(text: &str, parameters: &str) -> String {
+    format!("{}[{}m{}{}[m", ESCAPE, parameters, text, ESCAPE)
+}
+
+// Text linking to `uri`, as an OSC 8 hyperlink
+pub fn link(uri: &str, text: &str) -> String {
+    format!("{e}]8;;{}{e}\\{}{e}]8;;{e}\\", uri, text, e = ESCAPE)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    pub bold: bool,
+    pub underline: bool,
+    // One of the 16 standard colours: 0 to 7, then their bright versions
+    pub foreground: Option<u8>,
+}
+
+impl Style {
+    // Apply the parameters of an SGR sequence (ESC [ ... m)
+    fn apply(&mut self, parameters: &str) {
+        let mut parameters = parameters.split(';').map(|parameter| parameter.parse::<u8>().unwrap_or(0));
+        while let Some(parameter) = parameters.next() {
+            match parameter {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                4 => self.underline = true,
+                24 => self.underline = false,
+                30..=37 => self.foreground = Some(parameter - 30),
+                90..=97 => self.foreground = Some(parameter - 90 + 8),
+                39 => self.foreground = None,
+                // 256 colours (5;n) and true colour (2;r;g;b), in the standard
+                // colours where they have one
+                38 => match parameters.next() {
+                    Some(5) => self.foreground = parameters.next().filter(|color| *color < 16),
+                    Some(2) => {
+                        parameters.nth(2);
+                    }
+                    _ => {}
+                },
+                48 => match parameters.next() {
+                    Some(5) => {
+                        parameters.next();
+                    }
+                    Some(2) => {
+                        parameters.nth(2);
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+// A piece of text shown the same way throughout
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+    pub link: Option<String>,
+}
+
+// Split text into spans by the escape sequences in it. Sequences other than
+// SGR and OSC 8 are dropped.
+pub fn parse(text: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut current = Span::default();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != ESCAPE {
+            current.text.push(c);
+            continue;
+        }
+
+        let (style, link) = (current.style, current.link.clone());
+        match chars.next() {
+            // Control sequence: parameters, then a final byte from @ to ~
+            Some('[') => {
+                let mut parameters = String::new();
+                let mut last = None;
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        last = Some(c);
+                        break;
+                    }
+                    parameters.push(c);
+                }
+                if last == Some('m') {
+                    let mut style = style;
+                    style.apply(&parameters);
+                    start_span(&mut spans, &mut current, style, link);
+                }
+            }
+            // Operating system command, ended by BEL or ESC \
+            Some(']') => {
+                let mut command = String::new();
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == ESCAPE && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                    command.push(c);
+                }
+                // 8;params;uri, which ends the link when the uri is empty
+                if let Some(rest) = command.strip_prefix("8;") {
+                    let uri = rest.split_once(';').map_or("", |(_, uri)| uri);
+                    let link = Some(uri.to_string()).filter(|uri| !uri.is_empty());
+                    start_span(&mut spans, &mut current, style, link);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !current.text.is_empty() {
+        spans.push(current);
+    }
+    spans
+}
+
+// Go on in a new span with this style and link, if they differ
+fn start_span(spans: &mut Vec<Span>, current: &mut Span, style: Style, link: Option<String>) {
+    if current.style == style && current.link == link {
+        return;
+    }
+    let next = Span { text: String::new(), style, link };
+    let finished = std::mem::replace(current, next);
+    if !finished.text.is_empty() {
+        spans.push(finished);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, style: Style, link: Option<&str>) -> Span {
+        Span { text: text.to_string(), style, link: link.map(str::to_string) }
+    }
+
+    #[test]
+    fn sgr_sequences_style_the_text_after_them() {
+        let bold_red = Style { bold: true, foreground: Some(1), ..Style::default() };
+        assert_eq!(parse(&format!("a{}b", paint("red", "01;31"))), vec![
+            span("a", Style::default(), None),
+            span("red", bold_red, None),
+            span("b", Style::default(), None),
+        ]);
+        let underlined = Style { underline: true, foreground: Some(10), ..Style::default() };
+        assert_eq!(parse("\x1b[4;92mx\x1b[24my\x1b[39mz"), vec![
+            span("x", underlined, None),
+            span("y", Style { foreground: Some(10), ..Style::default() }, None),
+            span("z", Style::default(), None),
+        ]);
+    }
+
+    #[test]
+    fn resets_end_every_style_and_repeated_styles_share_a_span() {
+        assert_eq!(parse("\x1b[1mbold\x1b[0mplain\x1b[mtoo"), vec![
+            span("bold", Style { bold: true, ..Style::default() }, None),
+            span("plaintoo", Style::default(), None),
+        ]);
+        assert_eq!(parse("\x1b[1ma\x1b[1mb"), vec![span("ab", Style { bold: true, ..Style::default() }, None)]);
+    }
+
+    #[test]
+    fn extended_colours_are_kept_only_when_they_are_standard_ones() {
+        assert_eq!(parse("\x1b[38;5;3mx"), vec![span("x", Style { foreground: Some(3), ..Style::default() }, None)]);
+        assert_eq!(parse("\x1b[31;38;5;200mx"), vec![span("x", Style::default(), None)]);
+        // The parameters of true colours and backgrounds aren't read as styles
+        assert_eq!(parse("\x1b[38;2;1;4;1mx"), vec![span("x", Style::default(), None)]);
+        assert_eq!(parse("\x1b[48;5;1;4mx"), vec![span("x", Style { underline: true, ..Style::default() }, None)]);
+    }
+
+    #[test]
+    fn other_and_unterminated_sequences_are_dropped() {
+        assert_eq!(parse("a\x1b[2Kb\x1b]0;title\x07c"), vec![span("abc", Style::default(), None)]);
+        assert_eq!(parse("a\x1b[31"), vec![span("a", Style::default(), None)]);
+        assert_eq!(parse("a\x1b]8;;file:///x"), vec![span("a", Style::default(), None)]);
+        assert_eq!(parse("a\x1b"), vec![span("a", Style::default(), None)]);
+    }
+
+    #[test]
+    fn osc_8_starts_and_ends_links() {
+        assert_eq!(parse(&format!("see {} now", link("file:///home/a", "a"))), vec![
+            span("see ", Style::default(), None),
+            span("a", Style::default(), Some("file:///home/a")),
+            span(" now", Style::default(), None),
+        ]);
+        // Links can end with BEL too, and keep the styles inside them
+        assert_eq!(parse("\x1b]8;id=1;https://example.com\x07\x1b[1mx\x1b]8;;\x07y"), vec![
+            span("x", Style { bold: true, ..Style::default() }, Some("https://example.com")),
+            span("y", Style { bold: true, ..Style::default() }, None),
+        ]);
+    }
+}
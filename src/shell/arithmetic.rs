@@ -0,0 +1,330 @@
+// Arithmetic expansion, $((expression)): integer arithmetic with the C
+// operators POSIX asks for, except those that assign. Variables can be named
+// with or without a $; unset or empty ones count as 0.
+
+// The value of `expression`, looking variables up with `lookup`
+pub(super) fn evaluate(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<i64, String> {
+    let tokens = tokenize(expression, lookup)?;
+    let mut parser = Parser { tokens: &tokens, position: 0 };
+    let value = parser.conditional(true)?;
+    match parser.tokens.get(parser.position) {
+        None => Ok(value),
+        Some(token) => Err(format!("{}: syntax error near `{}'", expression.trim(), token)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Operator(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Operator(operator) => f.write_str(operator),
+        }
+    }
+}
+
+// Longest first, so that e.g. << isn't read as two <
+const OPERATORS: &[&str] = &[
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "?", ":", "(", ")",
+];
+
+// Operators that assign, which expansion can't do
+const ASSIGNMENTS: &[&str] = &["++", "--", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|="];
+
+fn tokenize(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() {
+            let length = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            tokens.push(Token::Number(number(&rest[..length])?));
+            rest = &rest[length..];
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let (name, length) = variable_name(rest)?;
+            tokens.push(Token::Number(variable(&name, lookup)?));
+            rest = &rest[length..];
+        } else if let Some(assignment) = ASSIGNMENTS.iter().find(|operator| rest.starts_with(**operator)) {
+            return Err(format!("{}: assignment is not supported in arithmetic", assignment));
+        } else if rest.starts_with('=') && !rest.starts_with("==") {
+            return Err("=: assignment is not supported in arithmetic".to_string());
+        } else if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(**operator)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            return Err(format!("{}: syntax error: invalid arithmetic operator `{}'", expression.trim(), c));
+        }
+    }
+    Ok(tokens)
+}
+
+// A decimal, octal (0 first) or hexadecimal (0x first) constant
+fn number(text: &str) -> Result<i64, String> {
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if text.len() > 1 && text.starts_with('0') {
+        i64::from_str_radix(&text[1..], 8)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| format!("{}: value too great for base", text))
+}
+
+// The name of the variable at the start of `text`, written as name, $name or
+// ${name}, and how long it is written
+fn variable_name(text: &str) -> Result<(String, usize), String> {
+    if let Some(braced) = text.strip_prefix("${") {
+        let end = braced.find('}').ok_or("unterminated ${")?;
+        return Ok((braced[..end].to_string(), end + 3));
+    }
+    let start = usize::from(text.starts_with('$'));
+    let rest = &text[start..];
+    // Special parameters like $# and $1 are a single character
+    if start == 1 && rest.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '#' | '?')) {
+        return Ok((rest[..1].to_string(), 2));
+    }
+    let length = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+    if length == 0 {
+        return Err("$: syntax error in arithmetic".to_string());
+    }
+    Ok((rest[..length].to_string(), start + length))
+}
+
+fn variable(name: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<i64, String> {
+    let value = lookup(name).unwrap_or_default();
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    match digits.starts_with(|c: char| c.is_ascii_digit()).then(|| number(digits)) {
+        Some(Ok(n)) => Ok(if negative { n.wrapping_neg() } else { n }),
+        _ => Err(format!("{}: {}: not a number", name, value)),
+    }
+}
+
+// Binary operators from the loosest binding to the tightest, above the
+// conditional operator and below the unary ones
+const PRECEDENCE: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// Evaluates while parsing. With `evaluate` false, as in the branch of a
+// conditional or the side of && or || that isn't taken, the value is
+// ignored, so dividing by zero there is no error.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next_if_operator(&mut self, operators: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                self.position += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        match self.next_if_operator(&[operator]) {
+            Some(_) => Ok(()),
+            None => Err(self.unexpected()),
+        }
+    }
+
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            Some(token) => format!("syntax error in arithmetic near `{}'", token),
+            None => "syntax error in arithmetic: operand expected".to_string(),
+        }
+    }
+
+    // condition ? value : value
+    fn conditional(&mut self, evaluate: bool) -> Result<i64, String> {
+        let condition = self.binary(0, evaluate)?;
+        if self.next_if_operator(&["?"]).is_none() {
+            return Ok(condition);
+        }
+        let then = self.conditional(evaluate && condition != 0)?;
+        self.expect(":")?;
+        let otherwise = self.conditional(evaluate && condition == 0)?;
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn binary(&mut self, level: usize, evaluate: bool) -> Result<i64, String> {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.unary(evaluate);
+        };
+        let mut value = self.binary(level + 1, evaluate)?;
+        while let Some(operator) = self.next_if_operator(operators) {
+            // The right side of && and || only counts if the left doesn't decide
+            let evaluate_right = match operator {
+                "&&" => evaluate && value != 0,
+                "||" => evaluate && value == 0,
+                _ => evaluate,
+            };
+            let right = self.binary(level + 1, evaluate_right)?;
+            value = if evaluate_right { apply(operator, value, right)? } else { apply_logical(operator, value) };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self, evaluate: bool) -> Result<i64, String> {
+        if let Some(operator) = self.next_if_operator(&["+", "-", "!", "~"]) {
+            let value = self.unary(evaluate)?;
+            return Ok(match operator {
+                "-" => value.wrapping_neg(),
+                "!" => i64::from(value == 0),
+                "~" => !value,
+                _ => value,
+            });
+        }
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                let n = *n;
+                self.position += 1;
+                Ok(n)
+            }
+            Some(Token::Operator("(")) => {
+                self.position += 1;
+                let value = self.conditional(evaluate)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match operator {
+        "||" => i64::from(left != 0 || right != 0),
+        "&&" => i64::from(left != 0 && right != 0),
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "==" => i64::from(left == right),
+        "!=" => i64::from(left != right),
+        "<" => i64::from(left < right),
+        "<=" => i64::from(left <= right),
+        ">" => i64::from(left > right),
+        ">=" => i64::from(left >= right),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by 0".to_string()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        _ => unreachable!("not a binary operator: {}", operator),
+    })
+}
+
+// The value of && or || whose left side decided it, or of another operator
+// whose value is ignored anyway
+fn apply_logical(operator: &str, left: i64) -> i64 {
+    match operator {
+        "&&" | "||" => i64::from(left != 0),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "i" => Some("41".to_string()),
+            "negative" => Some("-3".to_string()),
+            "empty" => Some(String::new()),
+            "text" => Some("abc".to_string()),
+            "#" => Some("2".to_string()),
+            _ => None,
+        }
+    }
+
+    fn eval(expression: &str) -> Result<i64, String> {
+        evaluate(expression, &lookup)
+    }
+
+    #[test]
+    fn operators_follow_c_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("7 / 2 + 7 % 2"), Ok(4));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("2 < 3 == 1"), Ok(1));
+        assert_eq!(eval("-2 - -3"), Ok(1));
+        assert_eq!(eval("!0 + ~0"), Ok(0));
+        assert_eq!(eval("1 ? 2 : 3"), Ok(2));
+        assert_eq!(eval("0 ? 2 : 0 ? 3 : 4"), Ok(4));
+        assert_eq!(eval("3 && 0 || 5"), Ok(1));
+    }
+
+    #[test]
+    fn constants_can_be_octal_or_hexadecimal() {
+        assert_eq!(eval("010 + 0x10 + 10"), Ok(34));
+        assert!(eval("08").is_err());
+    }
+
+    #[test]
+    fn variables_are_read_with_or_without_a_dollar() {
+        assert_eq!(eval("i + 1"), Ok(42));
+        assert_eq!(eval("$i+1"), Ok(42));
+        assert_eq!(eval("${i}*2"), Ok(82));
+        assert_eq!(eval("negative * $#"), Ok(-6));
+        assert_eq!(eval("unset + empty"), Ok(0));
+        assert!(eval("text + 1").is_err());
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error_only_when_evaluated() {
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("1 % (i - 41)").is_err());
+        assert_eq!(eval("0 && 1 / 0"), Ok(0));
+        assert_eq!(eval("1 || 1 / 0"), Ok(1));
+        assert_eq!(eval("1 ? 2 : 1 / 0"), Ok(2));
+    }
+
+    #[test]
+    fn bad_expressions_are_rejected() {
+        assert!(eval("").is_err());
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("1 ? 2").is_err());
+        assert!(eval("1 @ 2").is_err());
+        assert!(eval("i = 2").is_err());
+        assert!(eval("i++").is_err());
+        assert!(eval("i += 2").is_err());
+    }
+}
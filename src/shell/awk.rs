@@ -0,0 +1,1331 @@
+// A small awk: patterns and actions over the fields of each line, with
+// variables, arithmetic, comparisons, regex matches, print, printf, if and
+// next, but no loops, arrays or user functions
+use std::collections::HashMap;
+use regex::Regex;
+use super::utilities::{inputs, read_input, usage_error, Arguments};
+use super::{Io, Shell, EXIT_SUCCESS, EXIT_USAGE};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    String(String),
+    Regex(String),
+    Name(String),
+    // The name of a built-in function, directly followed by (, or length
+    Function(String),
+    Field,
+    Begin,
+    End,
+    Print,
+    Printf,
+    If,
+    Else,
+    Next,
+    Exit,
+    // Operators and punctuation
+    Symbol(&'static str),
+    Newline,
+}
+
+// Longest first, so that the tokenizer takes the longest one that fits
+const SYMBOLS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "!~", "++", "--", "+=", "-=", "*=", "/=", "%=",
+    "{", "}", "(", ")", ";", ",", "+", "-", "*", "/", "%", "!", "<", ">", "~", "=",
+];
+
+const FUNCTIONS: &[&str] = &["length", "substr", "index", "tolower", "toupper", "sprintf"];
+
+// Records can't have more fields than this, as in mawk, so that $1e9 = 1 or
+// NF = 1e9 fail rather than take all the memory there is
+const MAX_FIELDS: usize = 32767;
+
+// The widest printf conversion, and the most digits it shows, for the same
+// reason
+const MAX_WIDTH: usize = 4096;
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '\n' => {
+                chars.next();
+                tokens.push(Token::Newline);
+            }
+            '#' => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            }
+            // A backslash at the end of a line joins it to the next
+            '\\' => {
+                chars.next();
+                if chars.next() != Some('\n') {
+                    return Err("backslash not last character on line".to_string());
+                }
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        None | Some('\n') => return Err("non-terminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(c) => text.push(c),
+                            None => return Err("non-terminated string".to_string()),
+                        },
+                        Some(c) => text.push(c),
+                    }
+                }
+                tokens.push(Token::String(text));
+            }
+            // A / starts a regex wherever a value can't come before it
+            '/' if !tokens.last().is_some_and(ends_value) => {
+                chars.next();
+                let mut pattern = String::new();
+                loop {
+                    match chars.next() {
+                        None | Some('\n') => return Err("non-terminated regular expression".to_string()),
+                        Some('/') => break,
+                        Some('\\') if chars.peek() == Some(&'/') => {
+                            chars.next();
+                            pattern.push('/');
+                        }
+                        Some('\\') => {
+                            pattern.push('\\');
+                            pattern.extend(chars.next());
+                        }
+                        Some(c) => pattern.push(c),
+                    }
+                }
+                tokens.push(Token::Regex(pattern));
+            }
+            '$' => {
+                chars.next();
+                tokens.push(Token::Field);
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut number = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    number.push(c);
+                }
+                // An exponent, as in 1e9 or 2.5E-3, if digits follow the e
+                let mut lookahead = chars.clone();
+                if lookahead.next_if(|c| matches!(c, 'e' | 'E')).is_some() {
+                    let sign = lookahead.next_if(|c| matches!(c, '+' | '-'));
+                    if lookahead.peek().is_some_and(char::is_ascii_digit) {
+                        number.extend(chars.next());
+                        if sign.is_some() {
+                            number.extend(chars.next());
+                        }
+                        while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                            number.push(c);
+                        }
+                    }
+                }
+                let value = number.parse().map_err(|_| format!("invalid number {}", number))?;
+                tokens.push(Token::Number(value));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                tokens.push(match name.as_str() {
+                    "BEGIN" => Token::Begin,
+                    "END" => Token::End,
+                    "print" => Token::Print,
+                    "printf" => Token::Printf,
+                    "if" => Token::If,
+                    "else" => Token::Else,
+                    "next" => Token::Next,
+                    "exit" => Token::Exit,
+                    name if FUNCTIONS.contains(&name) && (chars.peek() == Some(&'(') || name == "length") => {
+                        Token::Function(name.to_string())
+                    }
+                    _ => Token::Name(name),
+                });
+            }
+            _ => {
+                let rest: String = chars.clone().take(2).collect();
+                let symbol = SYMBOLS.iter()
+                    .find(|symbol| rest.starts_with(*symbol))
+                    .ok_or_else(|| format!("unexpected character '{}'", c))?;
+                for _ in 0..symbol.len() {
+                    chars.next();
+                }
+                tokens.push(Token::Symbol(symbol));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Whether a token can end a value, so that a / after it divides
+fn ends_value(token: &Token) -> bool {
+    matches!(token, Token::Number(_) | Token::String(_) | Token::Name(_) | Token::Function(_) | Token::Symbol(")" | "++" | "--"))
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    Number(f64),
+    String(String),
+    // A regex on its own matches the whole line
+    Regex(Regex),
+    Variable(String),
+    Field(Box<Expression>),
+    Not(Box<Expression>),
+    Negate(Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    Concatenate(Box<Expression>, Box<Expression>),
+    // Against a regex, or a string taken as one
+    Match(bool, Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    // An operator like += (or just =), its target and value
+    Assign(&'static str, Box<Expression>, Box<Expression>),
+    // ++ or --, before or after the target
+    Increment { target: Box<Expression>, by: f64, prefix: bool },
+    Call(String, Vec<Expression>),
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Print(Vec<Expression>),
+    // The format, then the values for it
+    Printf(Vec<Expression>),
+    Expression(Expression),
+    If(Expression, Vec<Statement>, Vec<Statement>),
+    Next,
+    Exit(Option<Expression>),
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Begin,
+    End,
+    Every,
+    Expression(Expression),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    // None prints the line
+    action: Option<Vec<Statement>>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn parse(source: &str) -> Result<Vec<Rule>, String> {
+        let mut parser = Parser { tokens: tokenize(source)?, position: 0 };
+        let mut rules = Vec::new();
+        loop {
+            parser.skip_terminators();
+            if parser.peek().is_none() {
+                return Ok(rules);
+            }
+            rules.push(parser.rule()?);
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        if self.eat(&Token::Symbol(symbol)) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            None => "unexpected end of program".to_string(),
+            Some(Token::Newline) => "unexpected newline".to_string(),
+            Some(token) => format!("syntax error at {}", describe_token(token)),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.eat(&Token::Newline) {}
+    }
+
+    fn skip_terminators(&mut self) {
+        while self.eat(&Token::Newline) || self.eat(&Token::Symbol(";")) {}
+    }
+
+    fn rule(&mut self) -> Result<Rule, String> {
+        let pattern = if self.eat(&Token::Begin) {
+            Pattern::Begin
+        } else if self.eat(&Token::End) {
+            Pattern::End
+        } else if self.peek() == Some(&Token::Symbol("{")) {
+            Pattern::Every
+        } else {
+            Pattern::Expression(self.expression()?)
+        };
+
+        let action = if self.peek() == Some(&Token::Symbol("{")) {
+            Some(self.block()?)
+        } else if matches!(pattern, Pattern::Begin | Pattern::End) {
+            return Err(self.unexpected());
+        } else {
+            None
+        };
+        Ok(Rule { pattern, action })
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, String> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        loop {
+            self.skip_terminators();
+            if self.eat(&Token::Symbol("}")) {
+                return Ok(statements);
+            }
+            statements.push(self.statement()?);
+            // Statements end at ; a newline or the }
+            if !matches!(self.peek(), Some(Token::Newline | Token::Symbol(";" | "}"))) {
+                return Err(self.unexpected());
+            }
+        }
+    }
+
+    // The body of an if or else: a block or a single statement
+    fn body(&mut self) -> Result<Vec<Statement>, String> {
+        self.skip_newlines();
+        if self.peek() == Some(&Token::Symbol("{")) {
+            self.block()
+        } else {
+            Ok(vec![self.statement()?])
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, String> {
+        match self.peek() {
+            Some(Token::Print) => {
+                self.next();
+                Ok(Statement::Print(self.print_arguments("print")?))
+            }
+            Some(Token::Printf) => {
+                self.next();
+                let arguments = self.print_arguments("printf")?;
+                if arguments.is_empty() {
+                    return Err("printf: no format".to_string());
+                }
+                Ok(Statement::Printf(arguments))
+            }
+            Some(Token::If) => {
+                self.next();
+                self.expect("(")?;
+                let condition = self.expression()?;
+                self.expect(")")?;
+                let then = self.body()?;
+
+                // else may come after a ; or newline ending the statement
+                let position = self.position;
+                self.skip_terminators();
+                let otherwise = if self.eat(&Token::Else) {
+                    self.body()?
+                } else {
+                    self.position = position;
+                    Vec::new()
+                };
+                Ok(Statement::If(condition, then, otherwise))
+            }
+            Some(Token::Next) => {
+                self.next();
+                Ok(Statement::Next)
+            }
+            Some(Token::Exit) => {
+                self.next();
+                let status = match self.peek() {
+                    None | Some(Token::Newline | Token::Symbol(";" | "}")) => None,
+                    _ => Some(self.expression()?),
+                };
+                Ok(Statement::Exit(status))
+            }
+            _ => Ok(Statement::Expression(self.expression()?)),
+        }
+    }
+
+    // What print or printf prints, which may be in parentheses
+    fn print_arguments(&mut self, statement: &str) -> Result<Vec<Expression>, String> {
+        let ends = |parser: &Parser| matches!(parser.peek(), None | Some(Token::Newline | Token::Symbol(";" | "}" | ">")));
+        if ends(self) {
+            return Ok(Vec::new());
+        }
+
+        // (a, b) is the whole list, unless more follows it as in ("a") "b"
+        let position = self.position;
+        let mut arguments = None;
+        if self.eat(&Token::Symbol("(")) {
+            arguments = self.expression_list().ok().filter(|_| self.eat(&Token::Symbol(")")) && ends(self));
+        }
+        let arguments = match arguments {
+            Some(arguments) => arguments,
+            None => {
+                self.position = position;
+                self.expression_list()?
+            }
+        };
+        if matches!(self.peek(), Some(Token::Symbol(">"))) {
+            return Err(format!("{} can't write to files", statement));
+        }
+        Ok(arguments)
+    }
+
+    fn expression_list(&mut self) -> Result<Vec<Expression>, String> {
+        let mut expressions = vec![self.expression()?];
+        while self.eat(&Token::Symbol(",")) {
+            self.skip_newlines();
+            expressions.push(self.expression()?);
+        }
+        Ok(expressions)
+    }
+
+    fn expression(&mut self) -> Result<Expression, String> {
+        let target = self.or()?;
+        let operator = match self.peek() {
+            Some(Token::Symbol(operator @ ("=" | "+=" | "-=" | "*=" | "/=" | "%="))) => *operator,
+            _ => return Ok(target),
+        };
+        if !matches!(target, Expression::Variable(_) | Expression::Field(_)) {
+            return Err(format!("can't assign to this with {}", operator));
+        }
+        self.next();
+        self.skip_newlines();
+        let value = self.expression()?;
+        Ok(Expression::Assign(operator, Box::new(target), Box::new(value)))
+    }
+
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut left = self.and()?;
+        while self.eat(&Token::Symbol("||")) {
+            self.skip_newlines();
+            left = Expression::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut left = self.matching()?;
+        while self.eat(&Token::Symbol("&&")) {
+            self.skip_newlines();
+            left = Expression::And(Box::new(left), Box::new(self.matching()?));
+        }
+        Ok(left)
+    }
+
+    fn matching(&mut self) -> Result<Expression, String> {
+        let mut left = self.comparison()?;
+        loop {
+            let matches = if self.eat(&Token::Symbol("~")) {
+                true
+            } else if self.eat(&Token::Symbol("!~")) {
+                false
+            } else {
+                return Ok(left);
+            };
+            left = Expression::Match(matches, Box::new(left), Box::new(self.comparison()?));
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expression, String> {
+        let left = self.concatenation()?;
+        match self.peek() {
+            Some(Token::Symbol(operator @ ("<" | "<=" | "==" | "!=" | ">" | ">="))) => {
+                let operator = *operator;
+                self.next();
+                Ok(Expression::Binary(operator, Box::new(left), Box::new(self.concatenation()?)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    // Values next to each other are joined together
+    fn concatenation(&mut self) -> Result<Expression, String> {
+        let mut left = self.additive()?;
+        while matches!(
+            self.peek(),
+            Some(Token::Number(_) | Token::String(_) | Token::Name(_) | Token::Function(_) | Token::Field | Token::Symbol("(" | "++" | "--"))
+        ) {
+            left = Expression::Concatenate(Box::new(left), Box::new(self.additive()?));
+        }
+        Ok(left)
+    }
+
+    fn additive(&mut self) -> Result<Expression, String> {
+        let mut left = self.multiplicative()?;
+        while let Some(Token::Symbol(operator @ ("+" | "-"))) = self.peek() {
+            let operator = *operator;
+            self.next();
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.multiplicative()?));
+        }
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expression, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Symbol(operator @ ("*" | "/" | "%"))) = self.peek() {
+            let operator = *operator;
+            self.next();
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.eat(&Token::Symbol("!")) {
+            Ok(Expression::Not(Box::new(self.unary()?)))
+        } else if self.eat(&Token::Symbol("-")) {
+            Ok(Expression::Negate(Box::new(self.unary()?)))
+        } else if self.eat(&Token::Symbol("+")) {
+            Ok(Expression::Binary("+", Box::new(Expression::Number(0.0)), Box::new(self.unary()?)))
+        } else {
+            self.increment()
+        }
+    }
+
+    fn increment(&mut self) -> Result<Expression, String> {
+        let prefix = match self.peek() {
+            Some(Token::Symbol("++")) => Some(1.0),
+            Some(Token::Symbol("--")) => Some(-1.0),
+            _ => None,
+        };
+        if let Some(by) = prefix {
+            self.next();
+            let target = self.primary()?;
+            return increment(target, by, true);
+        }
+
+        let value = self.primary()?;
+        let by = match self.peek() {
+            Some(Token::Symbol("++")) => 1.0,
+            Some(Token::Symbol("--")) => -1.0,
+            _ => return Ok(value),
+        };
+        if !matches!(value, Expression::Variable(_) | Expression::Field(_)) {
+            return Ok(value);
+        }
+        self.next();
+        increment(value, by, false)
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expression::Number(number)),
+            Some(Token::String(text)) => Ok(Expression::String(text)),
+            Some(Token::Regex(pattern)) => Regex::new(&pattern)
+                .map(Expression::Regex)
+                .map_err(|_| format!("invalid regular expression /{}/", pattern)),
+            Some(Token::Name(name)) => Ok(Expression::Variable(name)),
+            Some(Token::Field) => {
+                // $ applies to what directly follows it, so $NF-1 is ($NF)-1
+                let index = self.increment_free_primary()?;
+                Ok(Expression::Field(Box::new(index)))
+            }
+            // length on its own is the length of the line
+            Some(Token::Function(name)) if name == "length" && self.peek() != Some(&Token::Symbol("(")) => {
+                Ok(Expression::Call(name, Vec::new()))
+            }
+            Some(Token::Function(name)) => {
+                self.expect("(")?;
+                let mut arguments = Vec::new();
+                if !self.eat(&Token::Symbol(")")) {
+                    loop {
+                        arguments.push(self.expression()?);
+                        if self.eat(&Token::Symbol(")")) {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expression::Call(name, arguments))
+            }
+            Some(Token::Symbol("(")) => {
+                let expression = self.expression()?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected())
+            }
+        }
+    }
+
+    // What $ applies to
+    fn increment_free_primary(&mut self) -> Result<Expression, String> {
+        match self.peek() {
+            Some(Token::Symbol("++" | "--")) => {
+                let by = if self.next() == Some(Token::Symbol("++")) { 1.0 } else { -1.0 };
+                increment(self.primary()?, by, true)
+            }
+            _ => self.primary(),
+        }
+    }
+}
+
+fn increment(target: Expression, by: f64, prefix: bool) -> Result<Expression, String> {
+    match target {
+        Expression::Variable(_) | Expression::Field(_) => Ok(Expression::Increment { target: Box::new(target), by, prefix }),
+        _ => Err("can't increment this".to_string()),
+    }
+}
+
+fn describe_token(token: &Token) -> String {
+    match token {
+        Token::Number(number) => format_number(*number),
+        Token::String(text) => format!("\"{}\"", text),
+        Token::Regex(pattern) => format!("/{}/", pattern),
+        Token::Name(name) | Token::Function(name) => name.clone(),
+        Token::Field => "$".to_string(),
+        Token::Begin => "BEGIN".to_string(),
+        Token::End => "END".to_string(),
+        Token::Print => "print".to_string(),
+        Token::Printf => "printf".to_string(),
+        Token::If => "if".to_string(),
+        Token::Else => "else".to_string(),
+        Token::Next => "next".to_string(),
+        Token::Exit => "exit".to_string(),
+        Token::Symbol(symbol) => symbol.to_string(),
+        Token::Newline => "newline".to_string(),
+    }
+}
+
+// Values are strings or numbers, or strings that came from the input and
+// count as numbers when they look like one
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    String(String),
+    Input(String),
+}
+
+impl Value {
+    fn number(&self) -> f64 {
+        match self {
+            Value::Number(number) => *number,
+            Value::String(text) | Value::Input(text) => leading_number(text),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Value::Number(number) => format_number(*number),
+            Value::String(text) | Value::Input(text) => text.clone(),
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Number(number) => *number != 0.0,
+            Value::String(text) => !text.is_empty(),
+            Value::Input(text) => match text.trim().parse::<f64>() {
+                Ok(number) => number != 0.0,
+                Err(_) => !text.is_empty(),
+            },
+        }
+    }
+
+    // Whether comparisons take this as a number
+    fn is_numeric(&self) -> bool {
+        match self {
+            Value::Number(_) => true,
+            Value::String(_) => false,
+            Value::Input(text) => text.is_empty() || text.trim().parse::<f64>().is_ok(),
+        }
+    }
+}
+
+fn boolean(value: bool) -> Value {
+    Value::Number(if value { 1.0 } else { 0.0 })
+}
+
+// The number a string starts with, or 0
+fn leading_number(text: &str) -> f64 {
+    let text = text.trim_start();
+    let mut end = 0;
+    for (i, c) in text.char_indices() {
+        let part = &text[..i + c.len_utf8()];
+        if part == "-" || part == "+" || part == "." || part.parse::<f64>().is_ok() {
+            end = i + c.len_utf8();
+        } else {
+            break;
+        }
+    }
+    text[..end].parse().unwrap_or(0.0)
+}
+
+// Numbers print as integers when they are one, otherwise like %.6g
+fn format_number(number: f64) -> String {
+    if number.fract() == 0.0 && number.abs() < 1e16 {
+        return format!("{}", number as i64);
+    }
+    if !number.is_finite() {
+        return if number.is_nan() { "nan".to_string() } else if number > 0.0 { "inf".to_string() } else { "-inf".to_string() };
+    }
+    general(number, 6, false)
+}
+
+// A number like %.{precision}e does in C: 1.500000e+02
+fn exponential(number: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, number);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+// A number like %.{precision}g does: as %e or %f, whichever suits its size,
+// without trailing zeros unless `alternate` (the # flag)
+fn general(number: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+    let exponent: i32 = format!("{:.*e}", precision - 1, number)
+        .split_once('e')
+        .map_or(0, |(_, exponent)| exponent.parse().unwrap_or(0));
+    let text = if exponent < -4 || exponent >= precision as i32 {
+        exponential(number, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, number)
+    };
+    if alternate {
+        return text;
+    }
+    let (digits, exponent) = text.split_at(text.find('e').unwrap_or(text.len()));
+    let digits = if digits.contains('.') { digits.trim_end_matches('0').trim_end_matches('.') } else { digits };
+    format!("{}{}", digits, exponent)
+}
+
+// What printf prints: `format` with each % conversion replaced by the next of
+// the `arguments`, as in C
+fn sprintf(format: &str, arguments: &[Value]) -> Result<String, Stop> {
+    let mut arguments = arguments.iter();
+    let mut next_argument = || arguments.next().cloned()
+        .ok_or_else(|| Stop::Error("not enough arguments to satisfy format string".to_string()));
+    let digits = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        let mut number = None;
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            let digit = digit.to_digit(10).unwrap_or_default() as usize;
+            number = Some(number.unwrap_or(0usize).saturating_mul(10).saturating_add(digit));
+        }
+        number
+    };
+
+    let mut output = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            output.push('%');
+            continue;
+        }
+
+        let mut flags = String::new();
+        while let Some(flag) = chars.next_if(|c| "-+ #0".contains(*c)) {
+            flags.push(flag);
+        }
+        // * takes the width or precision from the arguments
+        let width = if chars.next_if_eq(&'*').is_some() {
+            let width = next_argument()?.number() as i64;
+            if width < 0 {
+                flags.push('-');
+            }
+            width.unsigned_abs() as usize
+        } else {
+            digits(&mut chars).unwrap_or(0)
+        };
+        let precision = if chars.next_if_eq(&'.').is_none() {
+            None
+        } else if chars.next_if_eq(&'*').is_some() {
+            Some(next_argument()?.number().max(0.0) as usize)
+        } else {
+            Some(digits(&mut chars).unwrap_or(0))
+        };
+        if width.max(precision.unwrap_or(0)) > MAX_WIDTH {
+            return Err(Stop::Error(format!("program limit exceeded: maximum field width size={}", MAX_WIDTH)));
+        }
+        let conversion = chars.next().ok_or_else(|| Stop::Error("missing conversion at end of format string".to_string()))?;
+        if !"cdiouxXeEfFgGs".contains(conversion) {
+            return Err(Stop::Error(format!("invalid conversion %{} in format string", conversion)));
+        }
+
+        let value = next_argument()?;
+        let number = value.number();
+        let alternate = flags.contains('#');
+        // What goes before any zeros padding the number, and the rest of it
+        let (mut prefix, body) = match conversion {
+            'c' => {
+                let text = match value {
+                    Value::Number(code) => char::from_u32(code as u32).map(String::from).unwrap_or_default(),
+                    _ => value.text().chars().take(1).collect(),
+                };
+                (String::new(), text)
+            }
+            's' => {
+                let text = value.text();
+                (String::new(), match precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                })
+            }
+            _ if !number.is_finite() => (String::new(), format_number(number.abs())),
+            'd' | 'i' => (String::new(), format!("{:0>1$}", number.trunc().abs() as u64, precision.unwrap_or(1))),
+            'o' | 'u' | 'x' | 'X' => {
+                let unsigned = number.trunc() as i64 as u64;
+                let (prefix, digits) = match conversion {
+                    'o' => (if alternate { "0" } else { "" }, format!("{:o}", unsigned)),
+                    'u' => ("", unsigned.to_string()),
+                    'x' => (if alternate && unsigned != 0 { "0x" } else { "" }, format!("{:x}", unsigned)),
+                    _ => (if alternate && unsigned != 0 { "0X" } else { "" }, format!("{:X}", unsigned)),
+                };
+                (prefix.to_string(), format!("{:0>1$}", digits, precision.unwrap_or(1)))
+            }
+            'e' | 'E' => (String::new(), exponential(number.abs(), precision.unwrap_or(6))),
+            'f' | 'F' => (String::new(), format!("{:.*}", precision.unwrap_or(6), number.abs())),
+            _ => (String::new(), general(number.abs(), precision.unwrap_or(6), alternate)),
+        };
+        let body = if conversion.is_ascii_uppercase() { body.to_uppercase() } else { body };
+
+        // Signed conversions show the sign, or what the flags put there instead
+        let numeric = !matches!(conversion, 'c' | 's');
+        if numeric && !matches!(conversion, 'o' | 'u' | 'x' | 'X') {
+            // %d takes the integer part, so -0.5 prints as 0
+            let negative = if matches!(conversion, 'd' | 'i') { number.trunc() < 0.0 } else { number < 0.0 };
+            let sign = if negative {
+                "-"
+            } else if flags.contains('+') {
+                "+"
+            } else if flags.contains(' ') {
+                " "
+            } else {
+                ""
+            };
+            prefix.insert_str(0, sign);
+        }
+
+        let padding = width.saturating_sub(prefix.chars().count() + body.chars().count());
+        // Zeros pad numbers, unless they are left-justified, have a precision
+        // setting their digits or aren't numbers after all
+        let zeros = flags.contains('0') && !flags.contains('-') && numeric && number.is_finite()
+            && !(precision.is_some() && matches!(conversion, 'd' | 'i' | 'o' | 'u' | 'x' | 'X'));
+        if flags.contains('-') {
+            output.push_str(&format!("{}{}{}", prefix, body, " ".repeat(padding)));
+        } else if zeros {
+            output.push_str(&format!("{}{}{}", prefix, "0".repeat(padding), body));
+        } else {
+            output.push_str(&format!("{}{}{}", " ".repeat(padding), prefix, body));
+        }
+    }
+    Ok(output)
+}
+
+// Which rules run: those before the input, for each line, or after it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Begin,
+    Main,
+    End,
+}
+
+// How running a statement ended, if not by reaching its end
+enum Stop {
+    Next,
+    Exit,
+    Error(String),
+}
+
+struct Awk {
+    variables: HashMap<String, Value>,
+    // $0 and then the fields
+    fields: Vec<String>,
+    output: String,
+    exit_status: i32,
+}
+
+impl Awk {
+    fn variable(&self, name: &str) -> Value {
+        match name {
+            "NF" => Value::Number((self.fields.len() - 1) as f64),
+            _ => self.variables.get(name).cloned().unwrap_or(Value::Input(String::new())),
+        }
+    }
+
+    fn separator(&self, name: &str) -> String {
+        self.variable(name).text()
+    }
+
+    // Make `line` the current record, split into fields by FS
+    fn set_record(&mut self, line: &str) {
+        let separator = self.separator("FS");
+        let fields: Vec<String> = match separator.as_str() {
+            " " => line.split_whitespace().map(str::to_string).collect(),
+            _ if line.is_empty() => Vec::new(),
+            separator if separator.chars().count() == 1 => line.split(separator).map(str::to_string).collect(),
+            separator => match Regex::new(separator) {
+                Ok(pattern) => pattern.split(line).map(str::to_string).collect(),
+                Err(_) => line.split(separator).map(str::to_string).collect(),
+            },
+        };
+        self.fields = std::iter::once(line.to_string()).chain(fields).collect();
+    }
+
+    fn field(&self, index: f64) -> Result<Value, Stop> {
+        if index < 0.0 {
+            return Err(Stop::Error(format!("trying to access out of range field {}", index as i64)));
+        }
+        Ok(Value::Input(self.fields.get(index as usize).cloned().unwrap_or_default()))
+    }
+
+    fn set_field(&mut self, index: f64, value: String) -> Result<(), Stop> {
+        if index < 0.0 {
+            return Err(Stop::Error(format!("trying to access out of range field {}", index as i64)));
+        }
+        let index = index as usize;
+        if index == 0 {
+            self.set_record(&value);
+            return Ok(());
+        }
+        if index > MAX_FIELDS {
+            return Err(Stop::Error(format!("program limit exceeded: maximum number of fields size={}", MAX_FIELDS)));
+        }
+        if index >= self.fields.len() {
+            self.fields.resize(index + 1, String::new());
+        }
+        self.fields[index] = value;
+        // Changing a field rebuilds the record with OFS between the fields
+        let separator = self.separator("OFS");
+        self.fields[0] = self.fields[1..].join(&separator);
+        Ok(())
+    }
+
+    fn assign(&mut self, target: &Expression, value: Value) -> Result<(), Stop> {
+        match target {
+            Expression::Variable(name) if name == "NF" => {
+                let count = value.number();
+                if count < 0.0 {
+                    return Err(Stop::Error(format!("NF set to negative value {}", format_number(count))));
+                }
+                if count > MAX_FIELDS as f64 {
+                    return Err(Stop::Error(format!("program limit exceeded: maximum number of fields size={}", MAX_FIELDS)));
+                }
+                self.fields.resize(count as usize + 1, String::new());
+                let separator = self.separator("OFS");
+                self.fields[0] = self.fields[1..].join(&separator);
+                Ok(())
+            }
+            Expression::Variable(name) => {
+                self.variables.insert(name.clone(), value);
+                Ok(())
+            }
+            Expression::Field(index) => {
+                let index = self.evaluate(index)?.number();
+                self.set_field(index, value.text())
+            }
+            _ => Err(Stop::Error("can't assign to this".to_string())),
+        }
+    }
+
+    // Run the actions of the rules for this phase whose patterns select the
+    // current record
+    fn run_rules(&mut self, rules: &[Rule], phase: Phase) -> Result<(), Stop> {
+        for rule in rules {
+            let selected = match (&rule.pattern, phase) {
+                (Pattern::Begin, Phase::Begin) | (Pattern::End, Phase::End) | (Pattern::Every, Phase::Main) => true,
+                (Pattern::Expression(pattern), Phase::Main) => self.evaluate(pattern)?.truthy(),
+                _ => false,
+            };
+            if selected {
+                match &rule.action {
+                    Some(action) => self.run(action)?,
+                    None => self.run(&[Statement::Print(Vec::new())])?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, statements: &[Statement]) -> Result<(), Stop> {
+        for statement in statements {
+            match statement {
+                Statement::Print(arguments) => {
+                    let line = if arguments.is_empty() {
+                        self.fields[0].clone()
+                    } else {
+                        let mut values = Vec::new();
+                        for argument in arguments {
+                            values.push(self.evaluate(argument)?.text());
+                        }
+                        values.join(&self.separator("OFS"))
+                    };
+                    self.output.push_str(&line);
+                    self.output.push_str(&self.separator("ORS"));
+                }
+                Statement::Printf(arguments) => {
+                    let mut values = Vec::new();
+                    for argument in arguments {
+                        values.push(self.evaluate(argument)?);
+                    }
+                    let text = sprintf(&values[0].text(), &values[1..])?;
+                    self.output.push_str(&text);
+                }
+                Statement::Expression(expression) => {
+                    self.evaluate(expression)?;
+                }
+                Statement::If(condition, then, otherwise) => {
+                    if self.evaluate(condition)?.truthy() {
+                        self.run(then)?;
+                    } else {
+                        self.run(otherwise)?;
+                    }
+                }
+                Statement::Next => return Err(Stop::Next),
+                Statement::Exit(status) => {
+                    if let Some(status) = status {
+                        self.exit_status = self.evaluate(status)?.number() as i32;
+                    }
+                    return Err(Stop::Exit);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self, expression: &Expression) -> Result<Value, Stop> {
+        Ok(match expression {
+            Expression::Number(number) => Value::Number(*number),
+            Expression::String(text) => Value::String(text.clone()),
+            Expression::Regex(pattern) => boolean(pattern.is_match(&self.fields[0])),
+            Expression::Variable(name) => self.variable(name),
+            Expression::Field(index) => {
+                let index = self.evaluate(index)?.number();
+                self.field(index)?
+            }
+            Expression::Not(value) => boolean(!self.evaluate(value)?.truthy()),
+            Expression::Negate(value) => Value::Number(-self.evaluate(value)?.number()),
+            Expression::Binary(operator, left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                binary(operator, &left, &right)?
+            }
+            Expression::Concatenate(left, right) => {
+                let left = self.evaluate(left)?.text();
+                Value::String(left + &self.evaluate(right)?.text())
+            }
+            Expression::Match(matches, value, pattern) => {
+                let value = self.evaluate(value)?.text();
+                let pattern = match pattern.as_ref() {
+                    Expression::Regex(pattern) => pattern.clone(),
+                    pattern => {
+                        let pattern = self.evaluate(pattern)?.text();
+                        Regex::new(&pattern).map_err(|_| Stop::Error(format!("invalid regular expression /{}/", pattern)))?
+                    }
+                };
+                boolean(pattern.is_match(&value) == *matches)
+            }
+            Expression::And(left, right) => boolean(self.evaluate(left)?.truthy() && self.evaluate(right)?.truthy()),
+            Expression::Or(left, right) => boolean(self.evaluate(left)?.truthy() || self.evaluate(right)?.truthy()),
+            Expression::Assign(operator, target, value) => {
+                let value = self.evaluate(value)?;
+                let value = match operator.strip_suffix('=').filter(|operator| !operator.is_empty()) {
+                    Some(operator) => binary(operator, &self.evaluate(target)?, &value)?,
+                    None => value,
+                };
+                self.assign(target, value.clone())?;
+                value
+            }
+            Expression::Increment { target, by, prefix } => {
+                let old = self.evaluate(target)?.number();
+                self.assign(target, Value::Number(old + by))?;
+                Value::Number(if *prefix { old + by } else { old })
+            }
+            Expression::Call(name, arguments) => {
+                let mut values = Vec::new();
+                for argument in arguments {
+                    values.push(self.evaluate(argument)?);
+                }
+                call(name, &values, &self.fields[0])?
+            }
+        })
+    }
+}
+
+fn binary(operator: &str, left: &Value, right: &Value) -> Result<Value, Stop> {
+    let (a, b) = (left.number(), right.number());
+    Ok(match operator {
+        "+" => Value::Number(a + b),
+        "-" => Value::Number(a - b),
+        "*" => Value::Number(a * b),
+        "/" | "%" if b == 0.0 => return Err(Stop::Error("division by zero".to_string())),
+        "/" => Value::Number(a / b),
+        "%" => Value::Number(a % b),
+        comparison => {
+            // Numbers compare as numbers, anything else as strings
+            let ordering = if left.is_numeric() && right.is_numeric() {
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            } else {
+                left.text().cmp(&right.text())
+            };
+            boolean(match comparison {
+                "<" => ordering.is_lt(),
+                "<=" => ordering.is_le(),
+                "==" => ordering.is_eq(),
+                "!=" => ordering.is_ne(),
+                ">" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            })
+        }
+    })
+}
+
+fn call(name: &str, arguments: &[Value], record: &str) -> Result<Value, Stop> {
+    let wrong = || Stop::Error(format!("wrong number of arguments to {}", name));
+    let text = |i: usize| arguments.get(i).map(Value::text);
+    Ok(match name {
+        "length" => match arguments {
+            [] => Value::Number(record.chars().count() as f64),
+            [value] => Value::Number(value.text().chars().count() as f64),
+            _ => return Err(wrong()),
+        },
+        "substr" => {
+            let (Some(text), Some(start)) = (text(0), arguments.get(1)) else {
+                return Err(wrong());
+            };
+            // Characters from `start`, counting from 1, for `length` of them
+            let chars: Vec<char> = text.chars().collect();
+            let start = start.number().round();
+            let end = match arguments.get(2) {
+                Some(length) => start + length.number().round(),
+                None => f64::INFINITY,
+            };
+            let (from, to) = (start.max(1.0), end.min(chars.len() as f64 + 1.0));
+            if to <= from {
+                Value::String(String::new())
+            } else {
+                Value::String(chars[from as usize - 1..to as usize - 1].iter().collect())
+            }
+        }
+        "index" => {
+            let (Some(text), Some(part)) = (text(0), text(1)) else {
+                return Err(wrong());
+            };
+            let position = text.find(&part).map_or(0, |offset| text[..offset].chars().count() + 1);
+            Value::Number(position as f64)
+        }
+        "tolower" => Value::String(text(0).ok_or_else(wrong)?.to_lowercase()),
+        "sprintf" => {
+            let (format, values) = arguments.split_first().ok_or_else(wrong)?;
+            Value::String(sprintf(&format.text(), values)?)
+        }
+        _ => Value::String(text(0).ok_or_else(wrong)?.to_uppercase()),
+    })
+}
+
+pub(super) fn awk(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "", "Fv") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "awk", &e),
+    };
+    let Some((source, files)) = arguments.operands.split_first() else {
+        return usage_error(io, "awk", "no program given");
+    };
+    let rules = match Parser::parse(source) {
+        Ok(rules) => rules,
+        Err(e) => {
+            io.eprintln(&format!("awk: {}", e));
+            return EXIT_USAGE;
+        }
+    };
+
+    let mut awk = Awk {
+        variables: HashMap::new(),
+        fields: vec![String::new()],
+        output: String::new(),
+        exit_status: EXIT_SUCCESS,
+    };
+    for (name, value) in [("FS", " "), ("OFS", " "), ("ORS", "\n")] {
+        awk.variables.insert(name.to_string(), Value::String(value.to_string()));
+    }
+    if let Some(separator) = arguments.value('F') {
+        // -Ft is a tab, as in other awks
+        let separator = if separator == "t" { "\t" } else { separator };
+        awk.variables.insert("FS".to_string(), Value::String(separator.replace("\\t", "\t")));
+    }
+    for (option, assignment) in &arguments.options {
+        let Some((name, value)) = assignment.filter(|_| *option == 'v').and_then(|assignment| assignment.split_once('=')) else {
+            continue;
+        };
+        awk.variables.insert(name.to_string(), Value::Input(value.to_string()));
+    }
+
+    let result = run_program(&mut awk, &rules, files, io, shell);
+    io.print(&awk.output);
+    match result {
+        Ok(status) => status,
+        Err(e) => {
+            io.eprintln(&format!("awk: {}", e));
+            EXIT_USAGE
+        }
+    }
+}
+
+// Run the BEGIN rules, the others on every line and then the END rules.
+// Returns the exit status.
+fn run_program(awk: &mut Awk, rules: &[Rule], files: &[&str], io: &mut Io, shell: &Shell) -> Result<i32, String> {
+    let mut status = EXIT_SUCCESS;
+    let mut exited = ended(awk.run_rules(rules, Phase::Begin))?;
+
+    // A program of nothing but BEGIN rules reads no input
+    let reads_input = rules.iter().any(|rule| !matches!(rule.pattern, Pattern::Begin));
+    let mut record = 0;
+    if reads_input && !exited {
+        'files: for operand in inputs(files) {
+            let contents = match read_input(shell, io, operand) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    io.eprintln(&format!("awk: can't open file {}: {}", operand, e));
+                    status = EXIT_USAGE;
+                    continue;
+                }
+            };
+            let name = if operand == "-" { "" } else { operand };
+            awk.variables.insert("FILENAME".to_string(), Value::String(name.to_string()));
+            for (i, line) in contents.lines().enumerate() {
+                record += 1;
+                awk.variables.insert("NR".to_string(), Value::Number(record as f64));
+                awk.variables.insert("FNR".to_string(), Value::Number((i + 1) as f64));
+                awk.set_record(line);
+                exited = ended(awk.run_rules(rules, Phase::Main))?;
+                if exited {
+                    break 'files;
+                }
+            }
+        }
+    }
+
+    // exit before the END rules still runs them
+    ended(awk.run_rules(rules, Phase::End))?;
+    Ok(if awk.exit_status != EXIT_SUCCESS { awk.exit_status } else { status })
+}
+
+// Whether rules that ran ended with exit
+fn ended(result: Result<(), Stop>) -> Result<bool, String> {
+    match result {
+        Ok(()) | Err(Stop::Next) => Ok(false),
+        Err(Stop::Exit) => Ok(true),
+        Err(Stop::Error(e)) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::Shell;
+    use crate::shell::testing::{run, session, stdout};
+
+    // What a BEGIN rule with `action` prints
+    fn begin(shell: &mut Shell, action: &str) -> String {
+        stdout(shell, &format!("awk 'BEGIN {{ {} }}'", action))
+    }
+
+    #[test]
+    fn fields_are_split_on_blanks_or_the_separator() {
+        let mut shell = session();
+        assert_eq!(stdout(&mut shell, "echo '  a  b c ' | awk '{ print $2, NF }'"), "b 3\n");
+        assert_eq!(stdout(&mut shell, "echo a:b::d | awk -F : '{ print $3 \"|\" $4, NF }'"), "|d 4\n");
+        assert_eq!(stdout(&mut shell, "echo a1b22c | awk -F '[0-9]+' '{ print $2 $3 }'"), "bc\n");
+        assert_eq!(stdout(&mut shell, "echo a b c | awk '{ print $NF, $(NF-1), $NF-1 }'"), "c b -1\n");
+    }
+
+    #[test]
+    fn changing_fields_rebuilds_the_record() {
+        let mut shell = session();
+        assert_eq!(stdout(&mut shell, "echo a b c | awk '{ $2 = \"x\"; print }'"), "a x c\n");
+        assert_eq!(stdout(&mut shell, "echo a b | awk -v OFS=- '{ $4 = \"d\"; print; print NF }'"), "a-b--d\n4\n");
+        assert_eq!(stdout(&mut shell, "echo a b c | awk '{ NF = 2; print }'"), "a b\n");
+        assert_eq!(stdout(&mut shell, "echo a b | awk '{ $0 = \"x y z\"; print $3 }'"), "z\n");
+    }
+
+    #[test]
+    fn fields_and_nf_are_limited() {
+        let mut shell = session();
+        let limit = "awk: program limit exceeded: maximum number of fields size=32767\n";
+        assert_eq!(run(&mut shell, "echo a | awk '{ $1e9 = 1 }'"), (String::new(), limit.to_string()));
+        assert_eq!(run(&mut shell, "echo a | awk '{ NF = 1e9 }'"), (String::new(), limit.to_string()));
+        assert_eq!(run(&mut shell, "echo a | awk '{ NF = -1 }'").1, "awk: NF set to negative value -1\n");
+        assert_eq!(run(&mut shell, "echo a | awk '{ print $(-1) }'").1, "awk: trying to access out of range field -1\n");
+        // Reading far past the last field is fine
+        assert_eq!(stdout(&mut shell, "echo a | awk '{ print $1e9 \"|\" NF }'"), "|1\n");
+    }
+
+    #[test]
+    fn patterns_select_lines() {
+        let mut shell = session();
+        stdout(&mut shell, "for line in 'apple 3' 'banana 12' 'cherry 7'; do echo $line; done > fruit");
+        assert_eq!(stdout(&mut shell, "awk '/an/' fruit"), "banana 12\n");
+        assert_eq!(stdout(&mut shell, "awk '$2 > 5 { print $1 }' fruit"), "banana\ncherry\n");
+        assert_eq!(stdout(&mut shell, "awk '$1 !~ /^b/ && NR > 1' fruit"), "cherry 7\n");
+        assert_eq!(stdout(&mut shell, "awk 'NR == 2 { next } { print NR }' fruit"), "1\n3\n");
+        assert_eq!(stdout(&mut shell, "awk '{ total += $2 } END { print total, NR }' fruit"), "22 3\n");
+        assert_eq!(stdout(&mut shell, "awk 'BEGIN { print \"first\" } NR == 1 { exit } END { print \"last\" }' fruit"), "first\nlast\n");
+    }
+
+    #[test]
+    fn printf_formats_strings_and_numbers() {
+        let mut shell = session();
+        assert_eq!(begin(&mut shell, "printf \"%s=%d\\n\", \"x\", 42.9"), "x=42\n");
+        assert_eq!(begin(&mut shell, "printf(\"[%5s|%-5s|%.2s]\", \"ab\", \"ab\", \"abc\")"), "[   ab|ab   |ab]");
+        assert_eq!(begin(&mut shell, "printf \"%05d %+d % d %.3d\", -42, 7, 7, 7"), "-0042 +7  7 007");
+        assert_eq!(begin(&mut shell, "printf \"%x %X %#x %o %#o %c %c\", 255, 255, 255, 8, 8, 65, \"hi\""), "ff FF 0xff 10 010 A h");
+        assert_eq!(begin(&mut shell, "printf \"%f %.2f %8.3f %e %E\", 3.5, 2.345, -1.5, 1234.5, 0.00012"), "3.500000 2.35   -1.500 1.234500e+03 1.200000E-04");
+        assert_eq!(begin(&mut shell, "printf \"%g %g %g %#g %G\", 100000, 1000000, 0.0001, 1.5, 1e-10"), "100000 1e+06 0.0001 1.50000 1E-10");
+        assert_eq!(begin(&mut shell, "printf \"%*d|%-*d|%.*f|100%%\", 4, 1, 3, 2, 1, 2.25"), "   1|2  |2.2|100%");
+        assert_eq!(begin(&mut shell, "x = sprintf(\"%03d-%s\", 7, \"a\"); print x"), "007-a\n");
+    }
+
+    #[test]
+    fn printf_reports_bad_formats() {
+        let mut shell = session();
+        let error = |shell: &mut Shell, action: &str| run(shell, &format!("awk 'BEGIN {{ {} }}'", action)).1;
+        assert_eq!(error(&mut shell, "printf \"%d %d\", 1"), "awk: not enough arguments to satisfy format string\n");
+        assert_eq!(error(&mut shell, "printf \"%y\", 1"), "awk: invalid conversion %y in format string\n");
+        assert_eq!(error(&mut shell, "printf \"%\""), "awk: missing conversion at end of format string\n");
+        assert_eq!(error(&mut shell, "printf \"%1000000d\", 1"), "awk: program limit exceeded: maximum field width size=4096\n");
+        assert_eq!(error(&mut shell, "printf"), "awk: printf: no format\n");
+    }
+
+    #[test]
+    fn numbers_print_like_percent_g() {
+        let mut shell = session();
+        assert_eq!(begin(&mut shell, "print 1/3, 2/2, 1e6 * 10, 123456.7, 0.00001, 1e20"), "0.333333 1 10000000 123457 1e-05 1e+20\n");
+    }
+}
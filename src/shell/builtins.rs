@@ -0,0 +1,1308 @@
+// The commands built into the shell
+use std::rc::Rc;
+use crate::filesystem::{FileMetadata, FileType, FsckMode, ListOptions, SortDirection, SortKey};
+use crate::time::local_time;
+use super::command::{Builtin, CommandRegistry};
+use super::utilities::{usage_error, Arguments};
+use super::{parser, Flow, Io, Shell, EXIT_FAILURE, EXIT_NOT_FOUND, EXIT_SUCCESS, EXIT_USAGE};
+
+pub(super) fn register(registry: &mut CommandRegistry) {
+    let builtins = [
+        Builtin {
+            name: "help",
+            usage: "help [command]",
+            takes_paths: false,
+            help: "Show the commands, or how to use one\n\
+                Without a command, lists every command with a summary, along with the\n\
+                basics of the command language.",
+            run: help,
+        },
+        Builtin {
+            name: "man",
+            usage: "man command",
+            takes_paths: false,
+            help: "Show the manual page of a command",
+            run: man,
+        },
+        Builtin {
+            name: "cd",
+            usage: "cd [dir]",
+            takes_paths: true,
+            help: "Change the working directory\n\
+                Without a directory, goes to $HOME. cd - goes back to the previous\n\
+                directory, $OLDPWD, and prints it.",
+            run: cd,
+        },
+        Builtin {
+            name: "pwd",
+            usage: "pwd",
+            takes_paths: false,
+            help: "Print the working directory",
+            run: pwd,
+        },
+        Builtin {
+            name: "ls",
+            usage: "ls [-laRhtSr1] [path...]",
+            takes_paths: true,
+            help: "List directory contents\n\
+                Names of directories end in /. On the terminal, names are laid out in\n\
+                columns as wide as it is (COLUMNS); elsewhere, one per line.\n\
+                -l      long format: type (d for directories), size, when created and\n\
+                        when last modified, and name\n\
+                -a, -A  include hidden entries, whose names start with a dot\n\
+                -R      list the directories inside too, and theirs\n\
+                -h      sizes like 1.5K and 23M\n\
+                -t      newest first\n\
+                -S      largest first\n\
+                -r      reverse the order\n\
+                -1      one name per line",
+            run: ls,
+        },
+        Builtin {
+            name: "cat",
+            usage: "cat [file...]",
+            takes_paths: true,
+            help: "Print the contents of files\n\
+                Without files, copies the input to the output.",
+            run: cat,
+        },
+        Builtin {
+            name: "echo",
+            usage: "echo [text...]",
+            takes_paths: false,
+            help: "Print text",
+            run: echo,
+        },
+        Builtin {
+            name: "clear",
+            usage: "clear",
+            takes_paths: false,
+            help: "Clear the terminal",
+            run: clear,
+        },
+        Builtin {
+            name: "mkdir",
+            usage: "mkdir dir...",
+            takes_paths: true,
+            help: "Create directories",
+            run: mkdir,
+        },
+        Builtin {
+            name: "touch",
+            usage: "touch file...",
+            takes_paths: true,
+            help: "Create empty files, or update the times of existing ones",
+            run: touch,
+        },
+        Builtin {
+            name: "rm",
+            usage: "rm [-r] path...",
+            takes_paths: true,
+            help: "Remove files or directories\n\
+                -r  remove directories along with everything in them",
+            run: rm,
+        },
+        Builtin {
+            name: "history",
+            usage: "history [-c] [n]",
+            takes_paths: false,
+            help: "Show the command history, or its last n lines\n\
+                The history is kept in ~/.history and shared by every Terminal, up to\n\
+                HISTSIZE lines. In a command line, !! stands for the last line, !n for\n\
+                line n, !-n for the nth last and !text for the last one starting with\n\
+                text. Ctrl+R in the Terminal searches back through it.\n\
+                -c  clear the history",
+            run: history,
+        },
+        Builtin {
+            name: "fsck",
+            usage: "fsck [-f] [-r]",
+            takes_paths: false,
+            help: "Check the file system for problems. Without -f only the\n\
+                directories already loaded are checked.\n\
+                -f  check the whole file system, including contents and blobs no\n\
+                    file uses, and read every file to find damaged contents\n\
+                -r  repair the problems found, except in locked encrypted\n\
+                    directories, which have to be unlocked first",
+            run: fsck,
+        },
+        Builtin {
+            name: "snapshot",
+            usage: "snapshot command [name]",
+            takes_paths: false,
+            help: "Manage file system snapshots\n\
+                create   record the whole file system under a name\n\
+                restore  put the file system back as it was; later changes are lost\n\
+                delete   remove a snapshot\n\
+                diff     list what changed since a snapshot\n\
+                list     list the snapshots",
+            run: snapshot,
+        },
+        Builtin {
+            name: "export",
+            usage: "export [name[=value]...]",
+            takes_paths: false,
+            help: "Export variables to the environment, or list them\n\
+                Exported variables are inherited by scripts.",
+            run: export,
+        },
+        Builtin {
+            name: "unset",
+            usage: "unset name...",
+            takes_paths: false,
+            help: "Remove variables",
+            run: unset,
+        },
+        Builtin {
+            name: "alias",
+            usage: "alias [name[=value]...]",
+            takes_paths: false,
+            help: "Define aliases, or show them\n\
+                A command starting with the name of an alias starts with its value\n\
+                instead, as in alias ll='ls -l'. Aliases apply to what is typed and\n\
+                sourced, like ~/.kshrc, which every Terminal runs when it opens; not to\n\
+                scripts.",
+            run: alias,
+        },
+        Builtin {
+            name: "unalias",
+            usage: "unalias [-a] name...",
+            takes_paths: false,
+            help: "Remove aliases\n\
+                -a  remove them all",
+            run: unalias,
+        },
+        Builtin {
+            name: "env",
+            usage: "env",
+            takes_paths: false,
+            help: "List the exported variables",
+            run: env,
+        },
+        Builtin {
+            name: "set",
+            usage: "set [-- arg...]",
+            takes_paths: false,
+            help: "List all variables, or set the positional parameters\n\
+                set -- arg... makes the arguments $1, $2 and on.",
+            run: set,
+        },
+        Builtin {
+            name: "true",
+            usage: "true",
+            takes_paths: false,
+            help: "Do nothing, successfully",
+            run: succeed,
+        },
+        Builtin {
+            name: ":",
+            usage: ":",
+            takes_paths: false,
+            help: "Do nothing, successfully",
+            run: succeed,
+        },
+        Builtin {
+            name: "false",
+            usage: "false",
+            takes_paths: false,
+            help: "Do nothing, unsuccessfully",
+            run: fail,
+        },
+        Builtin {
+            name: "test",
+            usage: "test expr",
+            takes_paths: false,
+            help: TEST_HELP,
+            run: test,
+        },
+        Builtin {
+            name: "[",
+            usage: "[ expr ]",
+            takes_paths: false,
+            help: TEST_HELP,
+            run: test,
+        },
+        Builtin {
+            name: "sh",
+            usage: "sh [-c commands | file] [arg...]",
+            takes_paths: true,
+            help: "Run a script in a new shell\n\
+                The script inherits the exported variables and the working directory;\n\
+                its arguments are $1 and on. Without a file, the commands are read from\n\
+                the input. An executable path like ./script.sh runs the same way, if\n\
+                its first line is a shebang for a shell like #!/bin/sh.\n\
+                \n\
+                Scripts can use if/then/elif/else/fi, for/in/do/done, while and until,\n\
+                functions defined as name() { ...; }, $# and $@, and { ...; } groups.",
+            run: sh,
+        },
+        Builtin {
+            name: "source",
+            usage: "source file [arg...]",
+            takes_paths: true,
+            help: "Run a script in the current shell\n\
+                Variables and functions it defines stay once it is done.",
+            run: source,
+        },
+        Builtin {
+            name: ".",
+            usage: ". file [arg...]",
+            takes_paths: true,
+            help: "Run a script in the current shell, like source",
+            run: source,
+        },
+        Builtin {
+            name: "exit",
+            usage: "exit [status]",
+            takes_paths: false,
+            help: "Leave the script",
+            run: exit,
+        },
+        Builtin {
+            name: "return",
+            usage: "return [status]",
+            takes_paths: false,
+            help: "Leave the function or sourced script",
+            run: exit,
+        },
+        Builtin {
+            name: "break",
+            usage: "break [n]",
+            takes_paths: false,
+            help: "Leave the loop, or n enclosing loops",
+            run: break_loop,
+        },
+        Builtin {
+            name: "continue",
+            usage: "continue [n]",
+            takes_paths: false,
+            help: "Go on to the next round of the loop, or of the nth enclosing one",
+            run: break_loop,
+        },
+        Builtin {
+            name: "shift",
+            usage: "shift [n]",
+            takes_paths: false,
+            help: "Drop the first positional parameter, or the first n",
+            run: shift,
+        },
+    ];
+    for builtin in builtins {
+        registry.register(Rc::new(builtin));
+    }
+}
+
+const TEST_HELP: &str = "Check files, strings and numbers\n\
+    Succeeds when the expression is true.\n\
+    -e file, -f file, -d file  exists, is a file, is a directory\n\
+    -s file                    is not empty\n\
+    -z text, -n text           is empty, is not empty\n\
+    a = b, a != b              strings are the same, differ\n\
+    a -eq b, -ne, -lt, -le, -gt, -ge  compare numbers\n\
+    ! expr, expr -a expr, expr -o expr, ( expr )";
+
+fn strings(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|part| part.to_string()).collect()
+}
+
+fn help(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    if let Some(name) = parts.get(1) {
+        return match shell.commands.borrow().get(name) {
+            Some(command) => {
+                io.println(&format!("{}: {}", command.name(), command.usage()));
+                io.println(command.help());
+                EXIT_SUCCESS
+            }
+            None => {
+                io.eprintln(&format!("help: no help topics match `{}'", name));
+                EXIT_FAILURE
+            }
+        };
+    }
+
+    let commands = shell.commands.borrow();
+    let width = commands.commands().map(|command| command.usage().len()).max().unwrap_or_default().min(24);
+    io.println("Available commands:");
+    for command in commands.commands() {
+        io.println(&format!("{:<width$} - {}", command.usage(), command.summary(), width = width));
+    }
+    io.println(
+        "\n\
+        Output can be sent to a file with > or >>, or to another command with |;\n\
+        < reads a command's input from a file and 2> sends its errors to one.\n\
+        Commands can be run one after the other with ;, or depending on whether\n\
+        the one before succeeded with && and ||. $? is the last exit status.\n\
+        NAME=value sets a variable, and PS1 sets the prompt.\n\
+        *, ? and [abc] in a path match any characters, any one character and one\n\
+        of a, b or c, and ** any number of directories; quote them to stop that.\n\
+        \n\
+        Scripts run with sh script.sh, or ./script.sh; see man sh.\n\
+        help command or man command tells more about a command."
+    );
+    EXIT_SUCCESS
+}
+
+fn man(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let Some(name) = parts.get(1) else {
+        io.eprintln("What manual page do you want?");
+        return EXIT_FAILURE;
+    };
+    let Some(command) = shell.commands.borrow().get(name) else {
+        io.eprintln(&format!("No manual entry for {}", name));
+        return EXIT_FAILURE;
+    };
+
+    let mut description = command.help().lines().skip(1).peekable();
+    io.println(&format!("NAME\n    {} - {}\n", command.name(), command.summary()));
+    io.println(&format!("SYNOPSIS\n    {}", command.usage()));
+    if description.peek().is_some() {
+        io.println("\nDESCRIPTION");
+        for line in description {
+            io.println(format!("    {}", line).trim_end());
+        }
+    }
+    EXIT_SUCCESS
+}
+
+fn succeed(_parts: &[&str], _io: &mut Io, _shell: &mut Shell) -> i32 {
+    EXIT_SUCCESS
+}
+
+fn fail(_parts: &[&str], _io: &mut Io, _shell: &mut Shell) -> i32 {
+    EXIT_FAILURE
+}
+
+fn cd(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    // Without a directory go home, and with - back to the previous one
+    let (target, print) = match parts.get(1) {
+        Some(&"-") => (shell.directory_variable("OLDPWD"), true),
+        Some(directory) => (Ok(directory.to_string()), false),
+        None => (shell.directory_variable("HOME"), false),
+    };
+    let target = match target {
+        Ok(target) => target,
+        Err(e) => {
+            io.eprintln(&format!("cd: {}", e));
+            return EXIT_FAILURE;
+        }
+    };
+    let path = shell.resolve_path(&target);
+    let path = match path.strip_suffix('/') {
+        Some(stripped) if !stripped.is_empty() => stripped.to_string(),
+        _ => path,
+    };
+
+    let result = shell.fs.borrow().list_directory(&path);
+    match result {
+        Ok(_) => {
+            if print {
+                io.println(&path);
+            }
+            shell.set_current_directory(path);
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            io.eprintln(&format!("cd: {}", e));
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn pwd(_parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    io.println(&shell.current_directory);
+    EXIT_SUCCESS
+}
+
+fn ls(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "laARhtSr1", "") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "ls", &e),
+    };
+    // -t and -S sort newest and largest first; the last one given counts
+    let (sort_by, direction) = match arguments.options.iter().rev().find(|(option, _)| matches!(option, 't' | 'S')) {
+        Some(('t', _)) => (SortKey::Modified, SortDirection::Descending),
+        Some(_) => (SortKey::Size, SortDirection::Descending),
+        None => (SortKey::Name, SortDirection::Ascending),
+    };
+    let list = List {
+        options: ListOptions {
+            sort_by,
+            direction: if arguments.has('r') { direction.reversed() } else { direction },
+            include_hidden: arguments.has('a') || arguments.has('A'),
+            ..ListOptions::default()
+        },
+        long: arguments.has('l'),
+        human_readable: arguments.has('h'),
+        recursive: arguments.has('R'),
+        // Columns only make sense on the terminal, as in other shells
+        width: if io.terminal && !arguments.has('1') { Some(terminal_width(shell)) } else { None },
+    };
+
+    let operands = if arguments.operands.is_empty() { vec!["."] } else { arguments.operands.clone() };
+    let mut status = EXIT_SUCCESS;
+    let mut files = Vec::new();
+    let mut directories = Vec::new();
+    for operand in &operands {
+        match shell.fs.borrow().metadata(&shell.resolve_path(operand)) {
+            Ok(metadata) if metadata.file_type == FileType::Directory => directories.push(*operand),
+            Ok(metadata) => files.push(FileMetadata { name: operand.to_string(), ..metadata }),
+            Err(_) => {
+                io.eprintln(&format!("ls: cannot access '{}': No such file or directory", operand));
+                status = EXIT_USAGE;
+            }
+        }
+    }
+
+    // Files named on the command line come first, then each directory
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    if !files.is_empty() {
+        list.print(io, &files);
+    }
+    let headers = operands.len() > 1 || list.recursive;
+    for (i, directory) in directories.iter().enumerate() {
+        if i > 0 || !files.is_empty() {
+            io.println("");
+        }
+        if list.directory(shell, io, directory, headers).is_err() {
+            status = EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+// How ls lists entries
+struct List {
+    options: ListOptions,
+    long: bool,
+    human_readable: bool,
+    recursive: bool,
+    // Columns to lay names out in, or None for one per line
+    width: Option<usize>,
+}
+
+impl List {
+    // List a directory, and with -R the ones inside it, under a header with
+    // its name if `header`
+    fn directory(&self, shell: &Shell, io: &mut Io, name: &str, header: bool) -> Result<(), ()> {
+        if header {
+            io.println(&format!("{}:", name));
+        }
+        let listing = shell.fs.borrow().list(&shell.resolve_path(name), &self.options);
+        let entries = match listing {
+            Ok(listing) => listing.entries,
+            Err(e) => {
+                io.eprintln(&format!("ls: cannot open directory '{}': {}", name, e));
+                return Err(());
+            }
+        };
+        self.print(io, &entries);
+
+        let mut result = Ok(());
+        if self.recursive {
+            for entry in entries.iter().filter(|entry| entry.file_type == FileType::Directory) {
+                io.println("");
+                let path = if name.ends_with('/') { format!("{}{}", name, entry.name) } else { format!("{}/{}", name, entry.name) };
+                result = result.and(self.directory(shell, io, &path, true));
+            }
+        }
+        result
+    }
+
+    fn print(&self, io: &mut Io, entries: &[FileMetadata]) {
+        let names: Vec<String> = entries.iter()
+            .map(|entry| match entry.file_type {
+                FileType::Directory => format!("{}/", entry.name),
+                FileType::File => entry.name.clone(),
+            })
+            .collect();
+
+        if self.long {
+            let sizes: Vec<String> = entries.iter()
+                .map(|entry| if self.human_readable { human_size(entry.size) } else { entry.size.to_string() })
+                .collect();
+            let size_width = sizes.iter().map(String::len).max().unwrap_or_default();
+            for ((entry, size), name) in entries.iter().zip(&sizes).zip(&names) {
+                let file_type = match entry.file_type {
+                    FileType::Directory => 'd',
+                    FileType::File => '-',
+                };
+                io.println(&format!(
+                    "{} {:>width$} {} {} {}",
+                    file_type,
+                    size,
+                    short_time(entry.created),
+                    short_time(entry.modified),
+                    name,
+                    width = size_width,
+                ));
+            }
+            return;
+        }
+
+        match self.width {
+            Some(width) => io.print(&columns(&names, width)),
+            None => {
+                for name in names {
+                    io.println(&name);
+                }
+            }
+        }
+    }
+}
+
+// Columns of text on the terminal when it hasn't said
+const DEFAULT_COLUMNS: usize = 80;
+
+// The width of the terminal in characters, which it keeps in COLUMNS
+fn terminal_width(shell: &Shell) -> usize {
+    shell.variable("COLUMNS")
+        .and_then(|columns| columns.parse().ok())
+        .filter(|columns| *columns > 0)
+        .unwrap_or(DEFAULT_COLUMNS)
+}
+
+// Names in as many columns as fit in `width`, filled top to bottom
+fn columns(names: &[String], width: usize) -> String {
+    const GAP: usize = 2;
+    let widths: Vec<usize> = names.iter().map(|name| name.chars().count()).collect();
+
+    // The most columns that fit, trying each count from the most possible
+    let layout = (1..=names.len().max(1)).rev().find_map(|columns| {
+        let rows = names.len().div_ceil(columns);
+        let column_widths: Vec<usize> = widths.chunks(rows.max(1))
+            .map(|column| column.iter().max().copied().unwrap_or_default())
+            .collect();
+        let total = column_widths.iter().sum::<usize>() + GAP * column_widths.len().saturating_sub(1);
+        (total <= width || columns == 1).then_some((rows, column_widths))
+    });
+    let Some((rows, column_widths)) = layout else {
+        return String::new();
+    };
+
+    let mut output = String::new();
+    for row in 0..rows {
+        let mut line = String::new();
+        for (column, column_width) in column_widths.iter().enumerate() {
+            let Some(name) = names.get(column * rows + row) else {
+                continue;
+            };
+            if column > 0 {
+                line.push_str(&" ".repeat(GAP));
+            }
+            line.push_str(&format!("{:<width$}", name, width = column_width));
+        }
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+// A size like 1.5K or 23M, in powers of 1024, rounded up as GNU ls does
+fn human_size(size: usize) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    loop {
+        // To a tenth below 10, to a whole number from there
+        let tenths = (value * 10.0).ceil() / 10.0;
+        let rounded = if tenths < 10.0 { tenths } else { value.ceil() };
+        // Rounding up can make it the next unit
+        if rounded >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        } else if rounded < 10.0 {
+            return format!("{:.1}{}", rounded, UNITS[unit]);
+        } else {
+            return format!("{}{}", rounded, UNITS[unit]);
+        }
+    }
+}
+
+// A time to the minute, like 2024-05-01 09:30
+fn short_time(timestamp: u64) -> String {
+    let time = local_time(timestamp).to_string();
+    time.get(..16).unwrap_or(&time).to_string()
+}
+
+fn cat(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    // Without files, copy the input
+    if parts.len() < 2 {
+        let stdin = std::mem::take(&mut io.stdin);
+        io.print(&stdin);
+        return EXIT_SUCCESS;
+    }
+
+    let mut status = EXIT_SUCCESS;
+    for file in &parts[1..] {
+        let path = shell.resolve_path(file);
+        let contents = shell.fs.borrow().read_file(&path);
+        match contents {
+            Ok(content) => {
+                io.print(&content);
+            }
+            Err(e) => {
+                io.eprintln(&format!("cat: {}", e));
+                status = EXIT_FAILURE;
+            }
+        }
+    }
+    status
+}
+
+fn echo(parts: &[&str], io: &mut Io, _shell: &mut Shell) -> i32 {
+    io.println(&parts[1..].join(" "));
+    EXIT_SUCCESS
+}
+
+fn clear(_parts: &[&str], io: &mut Io, _shell: &mut Shell) -> i32 {
+    io.clear();
+    EXIT_SUCCESS
+}
+
+fn export(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let names = parts[1..].iter().filter(|part| **part != "-p");
+    let mut status = EXIT_SUCCESS;
+    for part in names.clone() {
+        let (name, value) = match part.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (*part, None),
+        };
+        if !parser::is_name(name) {
+            io.eprintln(&format!("export: `{}': not a valid identifier", part));
+            status = EXIT_FAILURE;
+            continue;
+        }
+        if let Some(value) = value {
+            shell.set_variable(name, value);
+        }
+        shell.export(name);
+    }
+
+    if names.count() == 0 {
+        for (name, value, exported) in shell.variables() {
+            if exported {
+                io.println(&format!("export {}={}", name, quote(value)));
+            }
+        }
+    }
+    status
+}
+
+fn unset(parts: &[&str], _io: &mut Io, shell: &mut Shell) -> i32 {
+    for name in &parts[1..] {
+        shell.unset_variable(name);
+    }
+    EXIT_SUCCESS
+}
+
+fn alias(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    if parts.len() == 1 {
+        for (name, value) in shell.aliases() {
+            io.println(&format!("alias {}={}", name, quote(value)));
+        }
+        return EXIT_SUCCESS;
+    }
+
+    let mut status = EXIT_SUCCESS;
+    for part in &parts[1..] {
+        match part.split_once('=') {
+            Some((name, value)) => {
+                let valid = !name.is_empty()
+                    && !name.chars().any(|c| c.is_whitespace() || matches!(c, '/' | '$' | '`' | '\'' | '"' | '\\') || "|&;<>()".contains(c));
+                if valid {
+                    shell.set_alias(name, value);
+                } else {
+                    io.eprintln(&format!("alias: `{}': invalid alias name", name));
+                    status = EXIT_FAILURE;
+                }
+            }
+            None => match shell.alias(part) {
+                Some(value) => io.println(&format!("alias {}={}", part, quote(value))),
+                None => {
+                    io.eprintln(&format!("alias: {}: not found", part));
+                    status = EXIT_FAILURE;
+                }
+            },
+        }
+    }
+    status
+}
+
+fn unalias(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    if parts.get(1) == Some(&"-a") {
+        shell.aliases.clear();
+        return EXIT_SUCCESS;
+    }
+    if parts.len() == 1 {
+        return usage_error(io, "unalias", "missing alias name");
+    }
+
+    let mut status = EXIT_SUCCESS;
+    for name in &parts[1..] {
+        if !shell.unset_alias(name) {
+            io.eprintln(&format!("unalias: {}: not found", name));
+            status = EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+fn env(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    if parts.len() > 1 {
+        io.eprintln("env: running commands is not supported; use NAME=value command");
+        return EXIT_USAGE;
+    }
+    for (name, value, exported) in shell.variables() {
+        if exported {
+            io.println(&format!("{}={}", name, value));
+        }
+    }
+    EXIT_SUCCESS
+}
+
+fn set(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    // set -- args replaces the positional parameters
+    if parts.get(1) == Some(&"--") {
+        shell.positional = strings(&parts[2..]);
+        return EXIT_SUCCESS;
+    }
+    if parts.len() > 1 {
+        io.eprintln("set: options are not supported; use set -- [arg...]");
+        return EXIT_USAGE;
+    }
+    for (name, value, _) in shell.variables() {
+        io.println(&format!("{}={}", name, quote(value)));
+    }
+    EXIT_SUCCESS
+}
+
+fn sh(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    match parts.get(1) {
+        // Commands from the input
+        None => {
+            let source = std::mem::take(&mut io.stdin);
+            shell.run_script("sh", &source, &[], io)
+        }
+        Some(&"-c") => match parts.get(2) {
+            Some(source) => {
+                let name = parts.get(3).copied().unwrap_or("sh");
+                let arguments = strings(parts.get(4..).unwrap_or_default());
+                shell.run_script(name, source, &arguments, io)
+            }
+            None => {
+                io.eprintln("sh: -c: option requires an argument");
+                EXIT_USAGE
+            }
+        },
+        Some(file) => match shell.read_script(&shell.resolve_path(file)) {
+            Ok(source) => shell.run_script(file, &source, &strings(&parts[2..]), io),
+            Err(e) => {
+                io.eprintln(&format!("sh: {}: {}", file, e));
+                EXIT_NOT_FOUND
+            }
+        },
+    }
+}
+
+fn source(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    match parts.get(1) {
+        Some(file) => shell.source(file, &strings(&parts[2..]), io),
+        None => {
+            io.eprintln(&format!("{}: filename argument required", parts[0]));
+            EXIT_USAGE
+        }
+    }
+}
+
+fn exit(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let status = match parts.get(1).map(|status| status.parse::<i32>()) {
+        None => shell.last_status,
+        Some(Ok(status)) => status & 0xff,
+        Some(Err(_)) => {
+            io.eprintln(&format!("{}: {}: numeric argument required", parts[0], parts[1]));
+            EXIT_USAGE
+        }
+    };
+    if parts[0] == "return" && shell.function_depth == 0 {
+        io.eprintln("return: can only `return' from a function or sourced script");
+        return EXIT_FAILURE;
+    }
+    shell.flow = Some(if parts[0] == "exit" { Flow::Exit } else { Flow::Return });
+    shell.last_status = status;
+    status
+}
+
+fn break_loop(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let levels = match parts.get(1).map(|levels| levels.parse::<usize>()) {
+        None => 1,
+        Some(Ok(levels)) if levels > 0 => levels,
+        _ => {
+            io.eprintln(&format!("{}: {}: loop count out of range", parts[0], parts[1]));
+            return EXIT_FAILURE;
+        }
+    };
+    if shell.loop_depth == 0 {
+        io.eprintln(&format!("{}: only meaningful in a `for', `while', or `until' loop", parts[0]));
+        return EXIT_SUCCESS;
+    }
+    let levels = levels.min(shell.loop_depth);
+    shell.flow = Some(if parts[0] == "break" { Flow::Break(levels) } else { Flow::Continue(levels) });
+    EXIT_SUCCESS
+}
+
+fn shift(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let count = match parts.get(1).map(|count| count.parse::<usize>()) {
+        None => 1,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            io.eprintln(&format!("shift: {}: numeric argument required", parts[1]));
+            return EXIT_USAGE;
+        }
+    };
+    if count > shell.positional.len() {
+        io.eprintln("shift: shift count out of range");
+        return EXIT_FAILURE;
+    }
+    shell.positional.drain(..count);
+    EXIT_SUCCESS
+}
+
+fn test(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let mut operands = &parts[1..];
+    if parts[0] == "[" {
+        match operands.split_last() {
+            Some((&"]", rest)) => operands = rest,
+            _ => {
+                io.eprintln("[: missing `]'");
+                return EXIT_USAGE;
+            }
+        }
+    }
+    match shell.test(operands) {
+        Ok(true) => EXIT_SUCCESS,
+        Ok(false) => EXIT_FAILURE,
+        Err(e) => {
+            io.eprintln(&format!("{}: {}", parts[0], e));
+            EXIT_USAGE
+        }
+    }
+}
+
+fn mkdir(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    if parts.len() < 2 {
+        io.eprintln("mkdir: missing directory operand");
+        return EXIT_USAGE;
+    }
+
+    let mut status = EXIT_SUCCESS;
+    for directory in &parts[1..] {
+        let path = shell.resolve_path(directory);
+        let result = shell.fs.borrow_mut().create_directory(&path, false);
+        if let Err(e) = result {
+            io.eprintln(&format!("mkdir: {}", e));
+            status = EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+fn touch(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    if parts.len() < 2 {
+        io.eprintln("touch: missing file operand");
+        return EXIT_USAGE;
+    }
+
+    let mut status = EXIT_SUCCESS;
+    for file in &parts[1..] {
+        let path = shell.resolve_path(file);
+        let result = shell.fs.borrow_mut().touch(&path);
+        if let Err(e) = result {
+            io.eprintln(&format!("touch: {}", e));
+            status = EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+fn rm(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    // -r may come before or after the paths
+    let recursive = parts[1..].iter().any(|part| matches!(*part, "-r" | "-R"));
+    let paths: Vec<&str> = parts[1..].iter().copied().filter(|part| !matches!(*part, "-r" | "-R")).collect();
+    if paths.is_empty() {
+        io.eprintln("rm: missing operand");
+        return EXIT_USAGE;
+    }
+
+    let mut status = EXIT_SUCCESS;
+    for path in paths {
+        let path = shell.resolve_path(path);
+        let result = shell.fs.borrow_mut().delete(&path, recursive);
+        if let Err(e) = result {
+            io.eprintln(&format!("rm: {}", e));
+            status = EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+fn history(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "c", "") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "history", &e),
+    };
+    if arguments.has('c') {
+        shell.set_history(Vec::new());
+        return EXIT_SUCCESS;
+    }
+    let count = match arguments.operands.as_slice() {
+        [] => None,
+        [count] => match count.parse::<usize>() {
+            Ok(count) => Some(count),
+            Err(_) => return usage_error(io, "history", &format!("{}: numeric argument required", count)),
+        },
+        _ => return usage_error(io, "history", "too many arguments"),
+    };
+
+    // Numbered as !n refers to them
+    let history = shell.history();
+    let skip = count.map_or(0, |count| history.len().saturating_sub(count));
+    for (i, line) in history.iter().enumerate().skip(skip) {
+        io.println(&format!("{:>5}  {}", i + 1, line));
+    }
+    EXIT_SUCCESS
+}
+
+fn fsck(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let mode = if parts.contains(&"-f") { FsckMode::Full } else { FsckMode::Quick };
+    let repair = parts.contains(&"-r");
+
+    let result = shell.fs.borrow_mut().fsck(mode, repair);
+    match result {
+        Ok(report) => {
+            for issue in &report.issues {
+                io.println(&issue.to_string());
+            }
+            for repair in &report.repairs {
+                io.println(repair);
+            }
+            if report.issues.is_empty() {
+                io.println("No problems found");
+                EXIT_SUCCESS
+            } else if repair {
+                io.println(&format!("{} problem(s) found and repaired", report.issues.len()));
+                EXIT_SUCCESS
+            } else {
+                let command = if mode == FsckMode::Full { "fsck -f -r" } else { "fsck -r" };
+                io.println(&format!("{} problem(s) found; run '{}' to repair", report.issues.len(), command));
+                EXIT_FAILURE
+            }
+        }
+        Err(e) => {
+            io.eprintln(&format!("fsck: {}", e));
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn snapshot(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let result = match (parts.get(1).copied(), parts.get(2).copied()) {
+        (Some("create"), Some(name)) => shell.fs.borrow_mut().create_snapshot(name)
+            .map(|info| format!("Created snapshot {} ({} files, {} bytes)", info.name, info.files, info.size)),
+        (Some("list"), None) => shell.fs.borrow().list_snapshots()
+            .map(|snapshots| if snapshots.is_empty() {
+                "No snapshots".to_string()
+            } else {
+                snapshots.iter()
+                    .map(|info| format!("{}  {}  {} files, {} bytes",
+                        info.name, local_time(info.created), info.files, info.size))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }),
+        (Some("restore"), Some(name)) => shell.fs.borrow_mut().restore_snapshot(name)
+            .map(|_| format!("Restored snapshot {}", name)),
+        (Some("delete"), Some(name)) => shell.fs.borrow_mut().delete_snapshot(name)
+            .map(|_| format!("Deleted snapshot {}", name)),
+        (Some("diff"), Some(name)) => shell.fs.borrow().diff_snapshot(name)
+            .map(|changes| if changes.is_empty() {
+                format!("No changes since snapshot {}", name)
+            } else {
+                changes.iter()
+                    .map(|change| change.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }),
+        _ => Err("usage: snapshot create|restore|delete|diff <name>, or snapshot list".to_string()),
+    };
+    let status = match result {
+        Ok(output) => {
+            io.println(&output);
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            io.eprintln(&format!("snapshot: {}", e));
+            EXIT_FAILURE
+        }
+    };
+
+    // The working directory may be gone after a restore
+    if shell.fs.borrow().list_directory(&shell.current_directory).is_err() {
+        shell.set_current_directory("/".to_string());
+    }
+    status
+}
+
+impl Shell {
+    fn directory_variable(&self, name: &str) -> Result<String, String> {
+        match self.variable(name) {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ => Err(format!("{} not set", name)),
+        }
+    }
+
+    // Evaluate the expression of `test`, as in POSIX: -o binds looser than -a,
+    // which binds looser than !
+    fn test(&self, operands: &[&str]) -> Result<bool, String> {
+        if operands.is_empty() {
+            return Ok(false);
+        }
+        let mut position = 0;
+        let result = self.test_or(operands, &mut position)?;
+        match operands.get(position) {
+            None => Ok(result),
+            Some(operand) => Err(format!("{}: unexpected operator", operand)),
+        }
+    }
+
+    fn test_or(&self, operands: &[&str], position: &mut usize) -> Result<bool, String> {
+        let mut result = self.test_and(operands, position)?;
+        while operands.get(*position) == Some(&"-o") {
+            *position += 1;
+            result |= self.test_and(operands, position)?;
+        }
+        Ok(result)
+    }
+
+    fn test_and(&self, operands: &[&str], position: &mut usize) -> Result<bool, String> {
+        let mut result = self.test_not(operands, position)?;
+        while operands.get(*position) == Some(&"-a") {
+            *position += 1;
+            result &= self.test_not(operands, position)?;
+        }
+        Ok(result)
+    }
+
+    fn test_not(&self, operands: &[&str], position: &mut usize) -> Result<bool, String> {
+        // A lone ! is just a non-empty string
+        if operands.get(*position) == Some(&"!") && *position + 1 < operands.len() {
+            *position += 1;
+            return Ok(!self.test_not(operands, position)?);
+        }
+        self.test_primary(operands, position)
+    }
+
+    fn test_primary(&self, operands: &[&str], position: &mut usize) -> Result<bool, String> {
+        let operand = *operands.get(*position).ok_or("argument expected")?;
+
+        if operand == "(" {
+            *position += 1;
+            let result = self.test_or(operands, position)?;
+            if operands.get(*position) != Some(&")") {
+                return Err("missing `)'".to_string());
+            }
+            *position += 1;
+            return Ok(result);
+        }
+
+        // Binary operators
+        if let (Some(&operator), Some(&right)) = (operands.get(*position + 1), operands.get(*position + 2)) {
+            let result = match operator {
+                "=" | "==" => Some(operand == right),
+                "!=" => Some(operand != right),
+                "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                    let left: i64 = operand.parse().map_err(|_| format!("{}: integer expression expected", operand))?;
+                    let right: i64 = right.parse().map_err(|_| format!("{}: integer expression expected", right))?;
+                    Some(match operator {
+                        "-eq" => left == right,
+                        "-ne" => left != right,
+                        "-lt" => left < right,
+                        "-le" => left <= right,
+                        "-gt" => left > right,
+                        _ => left >= right,
+                    })
+                }
+                _ => None,
+            };
+            if let Some(result) = result {
+                *position += 3;
+                return Ok(result);
+            }
+        }
+
+        // Unary operators
+        if let Some(&argument) = operands.get(*position + 1) {
+            let result = match operand {
+                "-n" => Some(!argument.is_empty()),
+                "-z" => Some(argument.is_empty()),
+                "-e" | "-f" | "-d" | "-s" | "-r" | "-w" => {
+                    let metadata = self.fs.borrow().metadata(&self.resolve_path(argument)).ok();
+                    Some(metadata.is_some_and(|metadata| match operand {
+                        "-f" => metadata.file_type == FileType::File,
+                        "-d" => metadata.file_type == FileType::Directory,
+                        "-s" => metadata.size > 0,
+                        _ => true,
+                    }))
+                }
+                _ => None,
+            };
+            if let Some(result) = result {
+                *position += 2;
+                return Ok(result);
+            }
+        }
+
+        // Anything else is a string, true when not empty
+        *position += 1;
+        Ok(!operand.is_empty())
+    }
+}
+
+// `value` quoted so that the shell reads it back as it is
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty() && value.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | ':' | ',' | '+' | '@' | '%'));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::testing::{run, session, stdout};
+
+    #[test]
+    fn restoring_a_snapshot_without_the_working_directory_moves_to_the_root() {
+        let mut shell = session();
+        stdout(&mut shell, "snapshot create before");
+        stdout(&mut shell, "mkdir /home/work && cd /home/work");
+        assert_eq!(stdout(&mut shell, "snapshot restore before"), "Restored snapshot before\n");
+        assert_eq!(shell.current_directory(), "/");
+        assert_eq!(stdout(&mut shell, "echo $PWD $OLDPWD"), "/ /home/work\n");
+    }
+
+    #[test]
+    fn only_exported_variables_are_in_the_environment() {
+        let mut shell = session();
+        stdout(&mut shell, "LOCAL=1; export SHARED=2");
+        let environment = stdout(&mut shell, "env");
+        assert!(environment.lines().any(|line| line == "SHARED=2"));
+        assert!(environment.lines().any(|line| line == "HOME=/home"));
+        assert!(!environment.contains("LOCAL="));
+
+        stdout(&mut shell, "export LOCAL");
+        assert!(stdout(&mut shell, "env").lines().any(|line| line == "LOCAL=1"));
+        stdout(&mut shell, "unset LOCAL");
+        assert!(!stdout(&mut shell, "env").contains("LOCAL="));
+    }
+
+    #[test]
+    fn export_rejects_invalid_names_and_lists_with_quotes() {
+        let mut shell = session();
+        assert_eq!(stdout(&mut shell, "export 1X=2; echo $?"), "1\n");
+        stdout(&mut shell, "export GREETING='hello world'");
+        assert!(stdout(&mut shell, "export -p").lines().any(|line| line == "export GREETING='hello world'"));
+    }
+
+    #[test]
+    fn exported_variables_are_inherited_by_scripts() {
+        let mut shell = session();
+        stdout(&mut shell, "export NAME=outer");
+        assert_eq!(stdout(&mut shell, "sh -c 'echo $NAME'"), "outer\n");
+    }
+
+    #[test]
+    fn alias_lists_and_shows_aliases_quoted() {
+        let mut shell = session();
+        assert_eq!(run(&mut shell, "alias ll='ls -l' la=ls l=\"echo 'hi'\""), (String::new(), String::new()));
+        assert_eq!(stdout(&mut shell, "alias"), "alias l='echo '\\''hi'\\'''\nalias la=ls\nalias ll='ls -l'\n");
+        assert_eq!(stdout(&mut shell, "alias ll la"), "alias ll='ls -l'\nalias la=ls\n");
+        assert_eq!(stdout(&mut shell, "l"), "hi\n");
+        assert_eq!(run(&mut shell, "alias ll nope; echo $?"), ("alias ll='ls -l'\n1\n".to_string(), "alias: nope: not found\n".to_string()));
+        assert_eq!(run(&mut shell, "alias a/b=ls; echo $?"), ("1\n".to_string(), "alias: `a/b': invalid alias name\n".to_string()));
+    }
+
+    #[test]
+    fn unalias_removes_one_alias_or_all_of_them() {
+        let mut shell = session();
+        stdout(&mut shell, "alias ll='ls -l' la=ls");
+        assert_eq!(run(&mut shell, "unalias ll; echo $?"), ("0\n".to_string(), String::new()));
+        assert_eq!(stdout(&mut shell, "alias"), "alias la=ls\n");
+        assert_eq!(run(&mut shell, "unalias ll la; echo $?"), ("1\n".to_string(), "unalias: ll: not found\n".to_string()));
+        assert_eq!(stdout(&mut shell, "alias"), "");
+
+        stdout(&mut shell, "alias ll='ls -l' la=ls");
+        assert_eq!(run(&mut shell, "unalias -a; echo $?"), ("0\n".to_string(), String::new()));
+        assert_eq!(stdout(&mut shell, "alias"), "");
+        assert_eq!(run(&mut shell, "unalias; echo $?").0, "2\n");
+    }
+
+    #[test]
+    fn cd_dash_goes_back_to_the_previous_directory() {
+        let mut shell = session();
+        stdout(&mut shell, "mkdir /home/work; cd /home/work");
+        assert_eq!(stdout(&mut shell, "echo $PWD $OLDPWD"), "/home/work /home\n");
+        assert_eq!(stdout(&mut shell, "cd -"), "/home\n");
+        assert_eq!(stdout(&mut shell, "cd -; pwd"), "/home/work\n/home/work\n");
+
+        let mut fresh = session();
+        assert_eq!(stdout(&mut fresh, "unset OLDPWD; cd -; echo $?"), "1\n");
+    }
+
+    #[test]
+    fn human_sizes_round_up_like_gnu_ls() {
+        assert_eq!(human_size(0), "0");
+        assert_eq!(human_size(1023), "1023");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(1025), "1.1K");
+        assert_eq!(human_size(1536), "1.5K");
+        assert_eq!(human_size(10 * 1024 - 1), "10K");
+        assert_eq!(human_size(10 * 1024 + 1), "11K");
+        assert_eq!(human_size(1024 * 1024 - 1), "1.0M");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0G");
+        assert_eq!(human_size(usize::MAX), "16384P");
+    }
+
+    #[test]
+    fn names_fill_columns_top_to_bottom() {
+        let names: Vec<String> = ["a", "bb", "ccc", "dddd", "e"].iter().map(|name| name.to_string()).collect();
+        // One row takes 1 + 2 + 3 + 4 + 1, and 2 between each
+        assert_eq!(columns(&names, 19), "a  bb  ccc  dddd  e\n");
+        assert_eq!(columns(&names, 18), "a   ccc   e\nbb  dddd\n");
+        assert_eq!(columns(&names, 10), "a    dddd\nbb   e\nccc\n");
+        // Names too wide for two columns get a line each
+        assert_eq!(columns(&names, 8), "a\nbb\nccc\ndddd\ne\n");
+        assert_eq!(columns(&names, 1), "a\nbb\nccc\ndddd\ne\n");
+        assert_eq!(columns(&[], 80), "");
+    }
+
+    #[test]
+    fn ls_lists_in_columns_or_long_with_human_sizes() {
+        let mut shell = session();
+        stdout(&mut shell, "mkdir /home/list; cd /home/list; touch a b; mkdir c");
+        // Columns are for the terminal, and as wide as COLUMNS
+        assert_eq!(stdout(&mut shell, "ls"), "a\nb\nc/\n");
+        shell.set_terminal(true);
+        assert_eq!(stdout(&mut shell, "ls"), "a  b  c/\n");
+        assert_eq!(stdout(&mut shell, "COLUMNS=5; ls"), "a  c/\nb\n");
+        assert_eq!(stdout(&mut shell, "COLUMNS=80; ls -1"), "a\nb\nc/\n");
+        stdout(&mut shell, "for i in 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20; do echo 0123456789012345678901234567890123456789012345678 >> a; done");
+        assert!(stdout(&mut shell, "ls -lh a").contains(" 1000 "));
+        stdout(&mut shell, "cat a a > b");
+        assert!(stdout(&mut shell, "ls -lh b").contains(" 2.0K "));
+    }
+}
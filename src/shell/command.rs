@@ -0,0 +1,143 @@
+// Commands by name: what the shell runs when a command isn't a function. The
+// built-ins register here, and so can apps and plugins, so that help, man and
+// tab completion know about every command without a list of their own.
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use super::{Io, Shell};
+
+pub trait Command {
+    fn name(&self) -> &str;
+
+    // How it is called, like "cat [file...]"
+    fn usage(&self) -> &str;
+
+    // What it does: a one line summary, then any details, for help and man
+    fn help(&self) -> &str;
+
+    // Run it, returning its exit status. `args` starts with the name it was
+    // called by, and `shell` is the session it runs in.
+    fn run(&self, args: &[String], io: &mut Io, shell: &mut Shell) -> i32;
+
+    // The first line of the help
+    fn summary(&self) -> &str {
+        self.help().lines().next().unwrap_or_default()
+    }
+
+    // Whether its arguments name files, for tab completion to complete paths
+    fn takes_paths(&self) -> bool {
+        false
+    }
+}
+
+// A command implemented by a plain function, as the built-ins are
+pub struct Builtin {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub takes_paths: bool,
+    pub help: &'static str,
+    pub run: fn(&[&str], &mut Io, &mut Shell) -> i32,
+}
+
+impl Command for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn usage(&self) -> &str {
+        self.usage
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn takes_paths(&self) -> bool {
+        self.takes_paths
+    }
+
+    fn run(&self, args: &[String], io: &mut Io, shell: &mut Shell) -> i32 {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        (self.run)(&args, io, shell)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Rc<dyn Command>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A registry holding the shell's built-in commands
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        super::builtins::register(&mut registry);
+        super::utilities::register(&mut registry);
+        super::text::register(&mut registry);
+        registry
+    }
+
+    // Add a command, replacing any other of the same name
+    pub fn register(&mut self, command: Rc<dyn Command>) {
+        self.commands.insert(command.name().to_string(), command);
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Option<Rc<dyn Command>> {
+        self.commands.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<dyn Command>> {
+        self.commands.get(name).cloned()
+    }
+
+    // All commands, by name
+    pub fn commands(&self) -> impl Iterator<Item = &Rc<dyn Command>> {
+        self.commands.values()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.commands.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Plugin;
+
+    impl Command for Plugin {
+        fn name(&self) -> &str {
+            "plugin"
+        }
+
+        fn usage(&self) -> &str {
+            "plugin file"
+        }
+
+        fn help(&self) -> &str {
+            "A command from outside the shell"
+        }
+
+        fn run(&self, _: &[String], _: &mut Io, _: &mut Shell) -> i32 {
+            0
+        }
+    }
+
+    #[test]
+    fn only_commands_that_say_so_take_paths() {
+        let mut registry = CommandRegistry::with_builtins();
+        let takes_paths = |registry: &CommandRegistry, name| registry.get(name).unwrap().takes_paths();
+        assert!(takes_paths(&registry, "cat"));
+        assert!(takes_paths(&registry, "cd"));
+        assert!(takes_paths(&registry, "mv"));
+        assert!(!takes_paths(&registry, "echo"));
+        assert!(!takes_paths(&registry, "help"));
+        // Whatever the usage says
+        registry.register(Rc::new(Plugin));
+        assert!(!takes_paths(&registry, "plugin"));
+    }
+}
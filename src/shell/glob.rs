@@ -0,0 +1,315 @@
+// Pathname expansion: words with *, ? or [...] in them stand for the paths
+// they match, and a ** component for any number of directories. Characters
+// that were quoted reach here escaped with \, so they only match themselves.
+use crate::filesystem::FileType;
+use super::Shell;
+
+// Escape the characters of `text` that are special in patterns
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// The text a pattern stands for when it's taken as is
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+// Whether a pattern has anything in it that matches more than itself
+pub fn is_pattern(pattern: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '*' | '?' => return true,
+            '[' if bracket(&chars, i).is_some() => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+// Whether `name` matches the pattern, which can't have a / in it
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    let Some((&first, rest)) = pattern.split_first() else {
+        return name.is_empty();
+    };
+    match first {
+        // Any number of characters: try each, fewest first
+        '*' => (0..=name.len()).any(|skip| matches_from(rest, &name[skip..])),
+        '?' => !name.is_empty() && matches_from(rest, &name[1..]),
+        '[' => match bracket(pattern, 0) {
+            Some(end) => {
+                !name.is_empty()
+                    && bracket_matches(&pattern[1..end], name[0])
+                    && matches_from(&pattern[end + 1..], &name[1..])
+            }
+            None => name.first() == Some(&'[') && matches_from(rest, &name[1..]),
+        },
+        '\\' => match rest.split_first() {
+            Some((&escaped, rest)) => name.first() == Some(&escaped) && matches_from(rest, &name[1..]),
+            None => name == ['\\'],
+        },
+        c => name.first() == Some(&c) && matches_from(rest, &name[1..]),
+    }
+}
+
+// Where the bracket expression starting at `start` ends: the index of its
+// ], if it has one. A ] right after the [ (or [! or [^) is part of the set.
+fn bracket(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if matches!(chars.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    if chars.get(i) == Some(&']') {
+        i += 1;
+    }
+    while i < chars.len() {
+        match chars[i] {
+            ']' => return Some(i),
+            '\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+// Whether `c` is in the set of a bracket expression, given without the [ ]:
+// characters and ranges like a-z, all but them after a ! or ^
+fn bracket_matches(set: &[char], c: char) -> bool {
+    let (negated, mut set) = match set.split_first() {
+        Some(('!' | '^', rest)) => (true, rest),
+        _ => (false, set),
+    };
+
+    let mut found = false;
+    while let Some((&first, rest)) = set.split_first() {
+        let (low, rest) = match (first, rest) {
+            ('\\', [escaped, rest @ ..]) => (*escaped, rest),
+            (first, rest) => (first, rest),
+        };
+        match rest {
+            ['-', high, rest @ ..] => {
+                found |= (low..=*high).contains(&c);
+                set = rest;
+            }
+            rest => {
+                found |= low == c;
+                set = rest;
+            }
+        }
+    }
+    found != negated
+}
+
+// The paths a pattern matches, in order, written the way the pattern is:
+// relative when it is, and ending in / when it does, which only matches
+// directories. Hidden entries only match a component that starts with a dot.
+// A word that isn't a pattern, or matches nothing, stands for itself.
+pub(super) fn expand(shell: &Shell, pattern: &str) -> Vec<String> {
+    if !is_pattern(pattern) {
+        return vec![unescape(pattern)];
+    }
+
+    let directories_only = pattern.ends_with('/');
+    let components: Vec<&str> = pattern.split('/').filter(|component| !component.is_empty()).collect();
+    let mut paths = vec![if pattern.starts_with('/') { "/".to_string() } else { String::new() }];
+
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        // Only the last component can be a file, unless the pattern ends in /
+        let must_be_directory = !last || directories_only;
+        let mut next = Vec::new();
+        for path in &paths {
+            if *component == "**" {
+                // Any number of directories; at the end, everything below
+                if !last {
+                    next.push(path.clone());
+                }
+                descendants(shell, path, !must_be_directory, &mut next);
+            } else if is_pattern(component) {
+                let hidden = component.starts_with('.');
+                for (name, file_type) in entries(shell, path) {
+                    if (hidden || !name.starts_with('.'))
+                        && (!must_be_directory || file_type == FileType::Directory)
+                        && matches(component, &name)
+                    {
+                        next.push(join(path, &name));
+                    }
+                }
+            } else {
+                // Literal components have to exist as well
+                let joined = join(path, &unescape(component));
+                let exists = shell.fs.borrow().metadata(&shell.resolve_path(&joined))
+                    .is_ok_and(|metadata| !must_be_directory || metadata.file_type == FileType::Directory);
+                if exists {
+                    next.push(joined);
+                }
+            }
+        }
+        paths = next;
+    }
+
+    let mut matches: Vec<String> = paths.into_iter()
+        .map(|path| if directories_only { format!("{}/", path) } else { path })
+        .collect();
+    if matches.is_empty() {
+        return vec![unescape(pattern)];
+    }
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+// The names and types of what is in the directory at `path`, as written in
+// a pattern, or nothing if it can't be listed
+fn entries(shell: &Shell, path: &str) -> Vec<(String, FileType)> {
+    let directory = if path.is_empty() { "." } else { path };
+    shell.fs.borrow().list_directory(&shell.resolve_path(directory))
+        .map(|entries| entries.into_iter().map(|entry| (entry.name, entry.file_type)).collect())
+        .unwrap_or_default()
+}
+
+// Add everything below `path` that isn't hidden to `paths`: every
+// directory, and files too if `files`
+fn descendants(shell: &Shell, path: &str, files: bool, paths: &mut Vec<String>) {
+    for (name, file_type) in entries(shell, path) {
+        if name.starts_with('.') {
+            continue;
+        }
+        let joined = join(path, &name);
+        match file_type {
+            FileType::Directory => {
+                paths.push(joined.clone());
+                descendants(shell, &joined, files, paths);
+            }
+            FileType::File if files => paths.push(joined),
+            FileType::File => {}
+        }
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else if path.ends_with('/') {
+        format!("{}{}", path, name)
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::filesystem::FileSystem;
+
+    // A shell in /home/glob, holding a.txt, b.txt, c.md, .hidden.txt, and
+    // src/main.rs and src/lib/mod.rs
+    fn session() -> Shell {
+        let mut fs = FileSystem::new().unwrap();
+        for directory in ["/home/glob", "/home/glob/src", "/home/glob/src/lib"] {
+            fs.create_directory(directory, false).unwrap();
+        }
+        for file in ["a.txt", "b.txt", "c.md", ".hidden.txt", "src/main.rs", "src/lib/mod.rs"] {
+            fs.write_file(&format!("/home/glob/{}", file), "").unwrap();
+        }
+        let mut shell = Shell::new(Rc::new(RefCell::new(fs)));
+        shell.run("cd /home/glob");
+        shell
+    }
+
+    #[test]
+    fn stars_and_question_marks_match_any_characters() {
+        assert!(matches("*", "anything"));
+        assert!(matches("*", ""));
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+        assert!(matches("?.rs", "a.rs"));
+        assert!(!matches("?.rs", "ab.rs"));
+        assert!(matches("résumé.*", "résumé.pdf"));
+    }
+
+    #[test]
+    fn brackets_match_sets_and_ranges() {
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[abc].txt", "d.txt"));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(matches("[!0-9]*", "x1"));
+        assert!(!matches("[^0-9]*", "1x"));
+        // A ] first is part of the set, and an unclosed [ is just a [
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a", "[a"));
+        assert!(!is_pattern("[a"));
+        assert!(matches("[\\]]", "]"));
+    }
+
+    #[test]
+    fn escaped_characters_only_match_themselves() {
+        assert_eq!(escape("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+        assert_eq!(unescape(&escape("a*b?[c]\\")), "a*b?[c]\\");
+        assert!(matches(&escape("*.txt"), "*.txt"));
+        assert!(!matches(&escape("*.txt"), "a.txt"));
+        assert!(!is_pattern(&escape("*?[a]")));
+        assert!(is_pattern("\\**"));
+    }
+
+    #[test]
+    fn patterns_expand_to_sorted_paths() {
+        let shell = session();
+        assert_eq!(expand(&shell, "*.txt"), ["a.txt", "b.txt"]);
+        assert_eq!(expand(&shell, "[ac].*"), ["a.txt", "c.md"]);
+        assert_eq!(expand(&shell, "/home/glob/*.md"), ["/home/glob/c.md"]);
+        assert_eq!(expand(&shell, "src/*"), ["src/lib", "src/main.rs"]);
+        assert_eq!(expand(&shell, "*/"), ["src/"]);
+    }
+
+    #[test]
+    fn hidden_files_only_match_patterns_starting_with_a_dot() {
+        let shell = session();
+        assert!(!expand(&shell, "*").contains(&".hidden.txt".to_string()));
+        assert_eq!(expand(&shell, ".*.txt"), [".hidden.txt"]);
+    }
+
+    #[test]
+    fn double_stars_match_any_number_of_directories() {
+        let shell = session();
+        assert_eq!(expand(&shell, "**/*.rs"), ["src/lib/mod.rs", "src/main.rs"]);
+        assert_eq!(expand(&shell, "src/**"), ["src/lib", "src/lib/mod.rs", "src/main.rs"]);
+    }
+
+    #[test]
+    fn words_that_match_nothing_stand_for_themselves() {
+        let shell = session();
+        assert_eq!(expand(&shell, "*.zip"), ["*.zip"]);
+        assert_eq!(expand(&shell, "missing/*"), ["missing/*"]);
+        assert_eq!(expand(&shell, "\\*.txt"), ["*.txt"]);
+        assert_eq!(expand(&shell, "a.txt"), ["a.txt"]);
+    }
+}
@@ -0,0 +1,165 @@
+// The history file, and history expansion: before a line is parsed, !!
+// stands for the last command line, !n for line n of the history, !-n for the
+// nth last and !text for the last one starting with text. As in bash, nothing
+// in single quotes or after a backslash is expanded, and neither is a ! before
+// a space, = or (.
+
+// The entries of a history file: one a line, except that a line ending in an
+// odd number of backslashes goes on with the next, without the last one
+pub(super) fn read(contents: &str) -> Vec<String> {
+    let mut history = Vec::new();
+    let mut entry = String::new();
+    let mut continued = false;
+    for line in contents.lines() {
+        continued = line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1;
+        if continued {
+            entry.push_str(&line[..line.len() - 1]);
+            entry.push('\n');
+        } else {
+            entry.push_str(line);
+            history.push(std::mem::take(&mut entry));
+        }
+    }
+    // A file cut off after a backslash
+    if continued {
+        entry.pop();
+        history.push(entry);
+    }
+    history
+}
+
+// A history file holding these entries, the lines of an entry of several,
+// like one with a quoted newline, each ended by a backslash but the last
+pub(super) fn write(history: &[String]) -> String {
+    history.iter().map(|entry| format!("{}\n", entry.replace('\n', "\\\n"))).collect()
+}
+
+// The line with its history references replaced, or None if it has none
+pub(super) fn expand(line: &str, history: &[String]) -> Result<Option<String>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut expanded = String::with_capacity(line.len());
+    let mut changed = false;
+    let mut single_quoted = false;
+    let mut double_quoted = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' if !double_quoted => single_quoted = !single_quoted,
+            '"' if !single_quoted => double_quoted = !double_quoted,
+            '\\' if !single_quoted => {
+                expanded.push(c);
+                expanded.extend(chars.get(i + 1));
+                i += 2;
+                continue;
+            }
+            '!' if !single_quoted => {
+                if let Some((event, length)) = event(&chars[i + 1..]) {
+                    let text: String = chars[i..i + 1 + length].iter().collect();
+                    let found = match event {
+                        Event::Number(n) => n.checked_sub(1).and_then(|index| history.get(index)),
+                        Event::Back(n) => history.len().checked_sub(n).and_then(|index| history.get(index)),
+                        Event::Prefix(prefix) => history.iter().rev().find(|entry| entry.starts_with(&prefix)),
+                    };
+                    let Some(found) = found else {
+                        return Err(format!("{}: event not found", text));
+                    };
+                    expanded.push_str(found);
+                    changed = true;
+                    i += 1 + length;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        expanded.push(c);
+        i += 1;
+    }
+    Ok(changed.then_some(expanded))
+}
+
+// Which line a reference is to
+enum Event {
+    // From the start, counting from 1
+    Number(usize),
+    // From the end: 1 is the last line
+    Back(usize),
+    Prefix(String),
+}
+
+// The event after a !, and how many characters it takes, if it is one
+fn event(rest: &[char]) -> Option<(Event, usize)> {
+    let digits = |chars: &[char]| chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    match rest.first()? {
+        '!' => Some((Event::Back(1), 1)),
+        c if c.is_ascii_digit() => {
+            let length = digits(rest);
+            let number = rest[..length].iter().collect::<String>().parse().ok()?;
+            Some((Event::Number(number), length))
+        }
+        '-' => {
+            let length = digits(&rest[1..]);
+            if length == 0 {
+                return None;
+            }
+            let number = rest[1..1 + length].iter().collect::<String>().parse().ok()?;
+            Some((Event::Back(number), 1 + length))
+        }
+        c if c.is_whitespace() || matches!(c, '=' | '(' | '"' | '\'' | ';' | '&' | '|' | ')') => None,
+        _ => {
+            let length = rest.iter()
+                .take_while(|c| !c.is_whitespace() && !matches!(c, ';' | '&' | '|' | '(' | ')' | '<' | '>' | '"' | '\''))
+                .count();
+            Some((Event::Prefix(rest[..length].iter().collect()), length))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn entries_of_several_lines_are_written_and_read_back() {
+        let history = entries(&["ls", "echo 'a\nb'", "echo a\\\\"]);
+        let contents = write(&history);
+        assert_eq!(contents, "ls\necho 'a\\\nb'\necho a\\\\\n");
+        assert_eq!(read(&contents), history);
+
+        // Cut off after a backslash
+        assert_eq!(read("ls\necho 'a\\"), entries(&["ls", "echo 'a"]));
+    }
+
+    #[test]
+    fn events_are_replaced_by_history_lines() {
+        let history = entries(&["ls -l", "echo one", "cat file"]);
+        let expand = |line| expand(line, &history);
+        assert_eq!(expand("!!"), Ok(Some("cat file".to_string())));
+        assert_eq!(expand("!1 /"), Ok(Some("ls -l /".to_string())));
+        assert_eq!(expand("!-2|wc"), Ok(Some("echo one|wc".to_string())));
+        assert_eq!(expand("sudo !ec"), Ok(Some("sudo echo one".to_string())));
+        assert_eq!(expand("echo \"!!\""), Ok(Some("echo \"cat file\"".to_string())));
+    }
+
+    #[test]
+    fn quoted_and_lone_exclamation_marks_are_left_alone() {
+        let history = entries(&["ls"]);
+        assert_eq!(expand("echo '!!' \\!! ! != !(", &history), Ok(None));
+        assert_eq!(expand("echo hi!", &history), Ok(None));
+    }
+
+    #[test]
+    fn missing_events_are_errors() {
+        let history = entries(&["ls"]);
+        assert_eq!(expand("!2", &history), Err("!2: event not found".to_string()));
+        assert_eq!(expand("!-2", &history), Err("!-2: event not found".to_string()));
+        assert_eq!(expand("!0", &history), Err("!0: event not found".to_string()));
+        assert_eq!(expand("!cd", &history), Err("!cd: event not found".to_string()));
+        assert_eq!(expand("!!", &[]), Err("!!: event not found".to_string()));
+    }
+}
@@ -0,0 +1,991 @@
+// The Terminal's command language, kept apart from the components so that it
+// can be used and tested without a browser
+pub mod ansi;
+pub mod parser;
+mod arithmetic;
+mod awk;
+mod builtins;
+mod command;
+mod glob;
+mod history;
+mod script;
+mod text;
+mod utilities;
+#[cfg(test)]
+mod testing;
+
+pub use command::{Builtin, Command, CommandRegistry};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::filesystem::{ErrorKind, FileSystem, FsError};
+use parser::{AndOr, Connector, ForLoop, IfClause, List, Pipeline, Redirect, RedirectKind, SimpleCommand, WhileLoop, Word};
+
+// Where `~` leads, and where shells start
+pub const HOME_DIRECTORY: &str = "/home";
+
+// The variables every session starts with, and whether they are exported
+const DEFAULT_VARIABLES: &[(&str, &str, bool)] = &[
+    ("HOME", HOME_DIRECTORY, true),
+    ("PATH", "/bin", true),
+    ("USER", "user", true),
+    ("PS1", "\\w $ ", false),
+    // Shown for each line more a command needs, as after an open quote
+    ("PS2", "> ", false),
+    ("HISTSIZE", "1000", false),
+];
+
+// Where the history is kept in the home directory, so that every Terminal
+// shares it and it outlives them
+const HISTORY_FILE: &str = ".history";
+
+// Run in the home directory when a Terminal starts
+const STARTUP_FILE: &str = ".kshrc";
+
+// Most command lines kept in the history when HISTSIZE isn't a number
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+// Exit statuses with a meaning of their own
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+// Wrong arguments or syntax
+pub const EXIT_USAGE: i32 = 2;
+// Found, but can't be run
+pub const EXIT_CANNOT_RUN: i32 = 126;
+pub const EXIT_NOT_FOUND: i32 = 127;
+
+// How deep functions, sourced files and scripts may call each other
+const MAX_NESTING: usize = 100;
+
+// Most times a while or until loop may go round, so that a script that never
+// stops can't hang the page
+const MAX_LOOP_ITERATIONS: usize = 100_000;
+
+// What running a command line produced for the terminal, in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Stdout(String),
+    Stderr(String),
+    // Everything shown so far should be cleared
+    Clear,
+}
+
+// The streams of a running command. Its input is available all at once, and
+// what it writes is collected for the shell to send wherever it goes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Io {
+    pub stdin: String,
+    // Whether stdout goes straight to a terminal that shows ANSI escape
+    // sequences as colours and links, rather than to a file or a pipe
+    pub terminal: bool,
+    output: Vec<Output>,
+}
+
+impl Io {
+    pub fn new(stdin: String) -> Self {
+        Self { stdin, terminal: false, output: Vec::new() }
+    }
+
+    pub fn print(&mut self, text: &str) {
+        push_output(&mut self.output, Output::Stdout(text.to_string()));
+    }
+
+    pub fn println(&mut self, text: &str) {
+        self.print(&format!("{}\n", text));
+    }
+
+    pub fn eprintln(&mut self, text: &str) {
+        push_output(&mut self.output, Output::Stderr(format!("{}\n", text)));
+    }
+
+    pub fn clear(&mut self) {
+        self.output.push(Output::Clear);
+    }
+}
+
+// Append to `outputs`, joining text with what was last written to the same stream
+fn push_output(outputs: &mut Vec<Output>, output: Output) {
+    match (outputs.last_mut(), output) {
+        (Some(Output::Stdout(last)), Output::Stdout(text)) | (Some(Output::Stderr(last)), Output::Stderr(text)) => {
+            last.push_str(&text);
+        }
+        (_, output) => outputs.push(output),
+    }
+}
+
+// A command that leaves the commands around it: they stop running until
+// whatever it is meant for is reached
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flow {
+    // Out of this many enclosing loops
+    Break(usize),
+    // On to the next round of the loop this many levels up
+    Continue(usize),
+    // Out of the function or sourced file
+    Return,
+    // Out of the script or session
+    Exit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Variable {
+    value: String,
+    // Exported variables make up the environment, which scripts inherit
+    exported: bool,
+}
+
+// A shell session: runs command lines against the file system and keeps the
+// state they share, like the working directory and variables
+pub struct Shell {
+    fs: Rc<RefCell<FileSystem>>,
+    current_directory: String,
+    variables: BTreeMap<String, Variable>,
+    functions: HashMap<String, Rc<parser::Command>>,
+    // Replaced at the start of commands typed or sourced, but not in scripts
+    aliases: BTreeMap<String, String>,
+    // Shared with the sessions of scripts
+    commands: Rc<RefCell<CommandRegistry>>,
+    // $0, and $1 and on
+    script_name: String,
+    positional: Vec<String>,
+    history: RefCell<History>,
+    // Exit status of the last pipeline, as $?
+    last_status: i32,
+    flow: Option<Flow>,
+    // Loops, and functions or sourced files, being run
+    loop_depth: usize,
+    function_depth: usize,
+    // Calls of functions, sourced files and scripts that led here
+    nesting: usize,
+    // Whether what is being run writes to the terminal, see `Io::terminal`
+    terminal: bool,
+    // $$, different for every session, as a process ID is
+    process_id: u32,
+}
+
+// The history as last read from the file or written to it, and which
+// version of the file that was: its path, when it was modified and its size
+#[derive(Default)]
+struct History {
+    lines: Vec<String>,
+    version: Option<(String, u64, usize)>,
+}
+
+// The process ID of the next session
+static NEXT_PROCESS_ID: AtomicU32 = AtomicU32::new(1000);
+
+impl Shell {
+    // A session with the built-in commands
+    pub fn new(fs: Rc<RefCell<FileSystem>>) -> Self {
+        Self::with_commands(fs, Rc::new(RefCell::new(CommandRegistry::with_builtins())))
+    }
+
+    // A session running the commands of `commands`, which may be shared
+    pub fn with_commands(fs: Rc<RefCell<FileSystem>>, commands: Rc<RefCell<CommandRegistry>>) -> Self {
+        let mut shell = Self {
+            fs,
+            current_directory: HOME_DIRECTORY.to_string(),
+            variables: BTreeMap::new(),
+            functions: HashMap::new(),
+            aliases: BTreeMap::new(),
+            commands,
+            script_name: "sh".to_string(),
+            positional: Vec::new(),
+            history: RefCell::default(),
+            last_status: EXIT_SUCCESS,
+            flow: None,
+            loop_depth: 0,
+            function_depth: 0,
+            nesting: 0,
+            terminal: false,
+            process_id: NEXT_PROCESS_ID.fetch_add(1, Ordering::Relaxed),
+        };
+        for (name, value, exported) in DEFAULT_VARIABLES {
+            shell.set_variable(name, value);
+            if *exported {
+                shell.export(name);
+            }
+        }
+        shell.set_variable("PWD", HOME_DIRECTORY);
+        shell.export("PWD");
+        shell
+    }
+
+    // A new session for running a script: same working directory, with the
+    // exported variables and nothing else
+    pub fn child(&self) -> Shell {
+        Self {
+            fs: Rc::clone(&self.fs),
+            current_directory: self.current_directory.clone(),
+            variables: self.variables.iter()
+                .filter(|(_, variable)| variable.exported)
+                .map(|(name, variable)| (name.clone(), variable.clone()))
+                .collect(),
+            functions: HashMap::new(),
+            aliases: BTreeMap::new(),
+            commands: Rc::clone(&self.commands),
+            script_name: self.script_name.clone(),
+            positional: Vec::new(),
+            history: RefCell::default(),
+            last_status: EXIT_SUCCESS,
+            flow: None,
+            loop_depth: 0,
+            function_depth: 0,
+            nesting: self.nesting + 1,
+            terminal: self.terminal,
+            // A script runs as a process of its own
+            process_id: NEXT_PROCESS_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    // Say whether the output of `run` is shown on a terminal that understands
+    // ANSI escape sequences, so commands may colour what they write there
+    pub fn set_terminal(&mut self, terminal: bool) {
+        self.terminal = terminal;
+    }
+
+    pub fn commands(&self) -> Rc<RefCell<CommandRegistry>> {
+        Rc::clone(&self.commands)
+    }
+
+    pub fn current_directory(&self) -> &str {
+        &self.current_directory
+    }
+
+    // Change the working directory, keeping PWD and OLDPWD up to date
+    fn set_current_directory(&mut self, path: String) {
+        let previous = std::mem::replace(&mut self.current_directory, path);
+        self.set_variable("OLDPWD", &previous);
+        let current = self.current_directory.clone();
+        self.set_variable("PWD", &current);
+    }
+
+    // Value of a shell variable, for parameter expansion
+    pub fn variable(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "$" => Some(self.process_id.to_string()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            "0" => Some(self.script_name.clone()),
+            _ => match name.parse::<usize>() {
+                Ok(index) => index.checked_sub(1).and_then(|index| self.positional.get(index)).cloned(),
+                Err(_) => self.variables.get(name).map(|variable| variable.value.clone()),
+            },
+        }
+    }
+
+    // Set a variable, keeping whether it is exported
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        self.variables.entry(name.to_string())
+            .and_modify(|variable| variable.value = value.to_string())
+            .or_insert_with(|| Variable { value: value.to_string(), exported: false });
+    }
+
+    pub fn unset_variable(&mut self, name: &str) {
+        self.variables.remove(name);
+    }
+
+    // Mark a variable as part of the environment, creating it empty if needed
+    pub fn export(&mut self, name: &str) {
+        self.variables.entry(name.to_string())
+            .or_insert_with(|| Variable { value: String::new(), exported: true })
+            .exported = true;
+    }
+
+    // All variables by name, as (name, value, exported)
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &str, bool)> {
+        self.variables.iter()
+            .map(|(name, variable)| (name.as_str(), variable.value.as_str(), variable.exported))
+    }
+
+    // The prompt, from PS1. Like bash, \w is the working directory with the
+    // home directory as ~, \W its last component, \u the user, \h the host
+    // and \$ a $.
+    pub fn prompt(&self) -> String {
+        let ps1 = self.variable("PS1").unwrap_or_default();
+        let home = self.variable("HOME").unwrap_or_default();
+        let directory = match self.current_directory.strip_prefix(&home) {
+            Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => format!("~{}", rest),
+            _ => self.current_directory.clone(),
+        };
+
+        let mut prompt = String::new();
+        let mut chars = ps1.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                prompt.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('w') => prompt.push_str(&directory),
+                Some('W') => prompt.push_str(match self.current_directory.rsplit('/').next() {
+                    Some("") | None => "/",
+                    Some(name) => name,
+                }),
+                Some('u') => prompt.push_str(&self.variable("USER").unwrap_or_default()),
+                Some('h') => prompt.push_str("kernelos"),
+                Some('$') => prompt.push('$'),
+                Some('n') => prompt.push('\n'),
+                Some('\\') | None => prompt.push('\\'),
+                Some(other) => {
+                    prompt.push('\\');
+                    prompt.push(other);
+                }
+            }
+        }
+        prompt
+    }
+
+    // The prompt for each line more a command needs, from PS2
+    pub fn continuation_prompt(&self) -> String {
+        self.variable("PS2").unwrap_or_default()
+    }
+
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    // Command lines run so far, oldest first, the last HISTSIZE. They come
+    // from the history file, which other sessions add to as well, or from
+    // this session alone when it can't be read, like when the home directory
+    // is locked.
+    pub fn history(&self) -> Vec<String> {
+        self.load_history();
+        let history = &self.history.borrow().lines;
+        history[history.len().saturating_sub(self.history_size())..].to_vec()
+    }
+
+    // Read the history file again if it changed since it was last read or
+    // written, so that lines other sessions added show up
+    fn load_history(&self) {
+        let path = self.history_path();
+        let version = match self.fs.borrow().metadata(&path) {
+            Ok(metadata) => Some((path.clone(), metadata.modified, metadata.size)),
+            Err(e) if e.kind == ErrorKind::NotFound => {
+                *self.history.borrow_mut() = History::default();
+                return;
+            }
+            Err(_) => None,
+        };
+        let mut history = self.history.borrow_mut();
+        if version.is_some() && history.version == version {
+            return;
+        }
+        match self.fs.borrow().read_file(&path) {
+            Ok(contents) => *history = History { lines: history::read(&contents), version },
+            Err(_) => history.version = None,
+        }
+    }
+
+    fn history_size(&self) -> usize {
+        self.variable("HISTSIZE")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_SIZE)
+    }
+
+    fn history_path(&self) -> String {
+        self.home_path(HISTORY_FILE)
+    }
+
+    // The path of a file in the home directory
+    fn home_path(&self, name: &str) -> String {
+        let home = self.variable("HOME").filter(|home| !home.is_empty()).unwrap_or(HOME_DIRECTORY.to_string());
+        self.resolve_path(&format!("{}/{}", home.trim_end_matches('/'), name))
+    }
+
+    // Source ~/.kshrc, if there is one, as an interactive shell does when it
+    // starts, so that the aliases, variables and prompt it sets are there
+    // from the start. Returns what it wrote.
+    pub fn run_startup_file(&mut self) -> Vec<Output> {
+        let path = self.home_path(STARTUP_FILE);
+        if self.fs.borrow().metadata(&path).is_err() {
+            return Vec::new();
+        }
+        let mut io = Io::new(String::new());
+        io.terminal = self.terminal;
+        self.source(&path, &[], &mut io);
+        self.flow = None;
+        io.output
+    }
+
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(String::as_str)
+    }
+
+    // All aliases by name, as (name, value)
+    pub fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn set_alias(&mut self, name: &str, value: &str) {
+        self.aliases.insert(name.to_string(), value.to_string());
+    }
+
+    // Remove an alias, saying whether there was one
+    pub fn unset_alias(&mut self, name: &str) -> bool {
+        self.aliases.remove(name).is_some()
+    }
+
+    // Add a line to the history, unless it's blank or the same as the last
+    fn remember(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        self.load_history();
+        let mut history = self.history.borrow().lines.clone();
+        if history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        history.push(line.to_string());
+        self.set_history(history);
+    }
+
+    // Keep the last HISTSIZE lines of `history`, and write them to the file
+    fn set_history(&mut self, mut history: Vec<String>) {
+        history.drain(..history.len().saturating_sub(self.history_size()));
+
+        let path = self.history_path();
+        let mut fs = self.fs.borrow_mut();
+        // Failing to save it is no reason to stop the command
+        let version = fs.write_file(&path, &history::write(&history))
+            .and_then(|_| fs.metadata(&path))
+            .ok()
+            .map(|metadata| (path.clone(), metadata.modified, metadata.size));
+        *self.history.borrow_mut() = History { lines: history, version };
+    }
+
+    // Run a command line, returning what it has for the terminal. Its exit
+    // status is `last_status` afterwards.
+    pub fn run(&mut self, line: &str) -> Vec<Output> {
+        let mut output = Vec::new();
+
+        // History references are replaced first, and the line shown as run
+        let line = match history::expand(line, &self.history()) {
+            Ok(Some(expanded)) => {
+                output.push(Output::Stdout(format!("{}\n", expanded)));
+                expanded
+            }
+            Ok(None) => line.to_string(),
+            Err(e) => {
+                output.push(Output::Stderr(format!("sh: {}\n", e)));
+                self.last_status = EXIT_FAILURE;
+                return output;
+            }
+        };
+        let line = line.as_str();
+        self.remember(line);
+
+        match parser::parse_with_aliases(line, &self.aliases) {
+            Ok(list) => self.run_list(&list, &mut output),
+            Err(e) => {
+                output.push(Output::Stderr(format!("sh: {}\n", e)));
+                self.last_status = EXIT_USAGE;
+            }
+        }
+        // There's nothing to leave at the top, not even with exit
+        self.flow = None;
+        output
+    }
+
+    fn run_list(&mut self, list: &List, output: &mut Vec<Output>) {
+        for and_or in &list.items {
+            self.run_and_or(and_or, output);
+            if self.flow.is_some() {
+                break;
+            }
+        }
+    }
+
+    fn run_and_or(&mut self, and_or: &AndOr, output: &mut Vec<Output>) {
+        self.last_status = self.run_pipeline(&and_or.first, output);
+        for (connector, pipeline) in &and_or.rest {
+            if self.flow.is_some() {
+                break;
+            }
+            let run = match connector {
+                Connector::And => self.last_status == EXIT_SUCCESS,
+                Connector::Or => self.last_status != EXIT_SUCCESS,
+            };
+            if run {
+                self.last_status = self.run_pipeline(pipeline, output);
+            }
+        }
+    }
+
+    // The status of a pipeline is that of its last command
+    fn run_pipeline(&mut self, pipeline: &Pipeline, output: &mut Vec<Output>) -> i32 {
+        let mut stdin = String::new();
+        let mut status = EXIT_SUCCESS;
+        let last = pipeline.commands.len().saturating_sub(1);
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            let piped = i < last;
+            let input = std::mem::take(&mut stdin);
+            (stdin, status) = match command {
+                parser::Command::Simple(command) => self.run_simple_command(command, input, piped, output),
+                command => {
+                    // Nothing run inside writes to the terminal when the output is piped
+                    let terminal = self.terminal;
+                    self.terminal = terminal && !piped;
+                    let result = self.run_compound_command(command, input, piped, output);
+                    self.terminal = terminal;
+                    result
+                }
+            };
+            if self.flow.is_some() {
+                break;
+            }
+        }
+
+        match (pipeline.negated, status) {
+            (false, status) => status,
+            (true, EXIT_SUCCESS) => EXIT_FAILURE,
+            (true, _) => EXIT_SUCCESS,
+        }
+    }
+
+    // Run anything but a simple command. Returns what it wrote to stdout if
+    // that goes to the next command, and its exit status.
+    fn run_compound_command(&mut self, command: &parser::Command, stdin: String, piped: bool, output: &mut Vec<Output>) -> (String, i32) {
+        let mut inner = Vec::new();
+        let status = match command {
+            parser::Command::Simple(command) => return self.run_simple_command(command, stdin, piped, output),
+            parser::Command::Redirected(command, redirects) => {
+                return self.run_redirected(redirects, stdin, piped, output, |shell, io| {
+                    let mut inner = Vec::new();
+                    let (_, status) = shell.run_compound_command(command, String::new(), false, &mut inner);
+                    for chunk in inner {
+                        push_output(&mut io.output, chunk);
+                    }
+                    status
+                });
+            }
+            parser::Command::Group(list) => {
+                self.run_list(list, &mut inner);
+                self.last_status
+            }
+            parser::Command::If(clause) => self.run_if(clause, &mut inner),
+            parser::Command::For(for_loop) => self.run_for(for_loop, &mut inner),
+            parser::Command::While(while_loop) => self.run_while(while_loop, &mut inner),
+            parser::Command::Function(definition) => {
+                self.functions.insert(definition.name.clone(), Rc::clone(&definition.body));
+                EXIT_SUCCESS
+            }
+        };
+
+        let mut stdout = String::new();
+        for chunk in inner {
+            match chunk {
+                Output::Stdout(text) if piped => stdout.push_str(&text),
+                chunk => push_output(output, chunk),
+            }
+        }
+        (stdout, status)
+    }
+
+    fn run_if(&mut self, clause: &IfClause, output: &mut Vec<Output>) -> i32 {
+        for (condition, branch) in &clause.branches {
+            self.run_list(condition, output);
+            if self.flow.is_some() {
+                return self.last_status;
+            }
+            if self.last_status == EXIT_SUCCESS {
+                self.run_list(branch, output);
+                return self.last_status;
+            }
+        }
+        match &clause.otherwise {
+            Some(otherwise) => {
+                self.run_list(otherwise, output);
+                self.last_status
+            }
+            None => EXIT_SUCCESS,
+        }
+    }
+
+    fn run_for(&mut self, for_loop: &ForLoop, output: &mut Vec<Output>) -> i32 {
+        let values = match &for_loop.words {
+            Some(words) => match self.expand_words(words) {
+                Ok(values) => values,
+                Err(e) => {
+                    output.push(Output::Stderr(format!("sh: {}\n", e)));
+                    return EXIT_FAILURE;
+                }
+            },
+            None => self.positional.clone(),
+        };
+
+        let mut status = EXIT_SUCCESS;
+        self.loop_depth += 1;
+        for value in values {
+            self.set_variable(&for_loop.variable, &value);
+            self.run_list(&for_loop.body, output);
+            status = self.last_status;
+            if !self.continue_loop() {
+                break;
+            }
+        }
+        self.loop_depth -= 1;
+        status
+    }
+
+    fn run_while(&mut self, while_loop: &WhileLoop, output: &mut Vec<Output>) -> i32 {
+        let mut status = EXIT_SUCCESS;
+        self.loop_depth += 1;
+        for _ in 0..MAX_LOOP_ITERATIONS {
+            self.run_list(&while_loop.condition, output);
+            if self.flow.is_some() {
+                if self.continue_loop() {
+                    continue;
+                }
+                break;
+            }
+            if (self.last_status == EXIT_SUCCESS) == while_loop.until {
+                self.loop_depth -= 1;
+                return status;
+            }
+
+            self.run_list(&while_loop.body, output);
+            status = self.last_status;
+            if !self.continue_loop() {
+                self.loop_depth -= 1;
+                return status;
+            }
+        }
+        self.loop_depth -= 1;
+
+        if self.flow.is_none() {
+            let name = if while_loop.until { "until" } else { "while" };
+            push_output(output, Output::Stderr(format!("sh: {}: stopped after {} iterations\n", name, MAX_LOOP_ITERATIONS)));
+            return EXIT_FAILURE;
+        }
+        status
+    }
+
+    // After the body of a loop ran: whether the loop goes on, dealing with a
+    // break or continue meant for it
+    fn continue_loop(&mut self) -> bool {
+        match self.flow {
+            None => true,
+            Some(Flow::Break(1)) => {
+                self.flow = None;
+                false
+            }
+            Some(Flow::Continue(1)) => {
+                self.flow = None;
+                true
+            }
+            Some(Flow::Break(levels)) => {
+                self.flow = Some(Flow::Break(levels - 1));
+                false
+            }
+            Some(Flow::Continue(levels)) => {
+                self.flow = Some(Flow::Continue(levels - 1));
+                false
+            }
+            Some(Flow::Return | Flow::Exit) => false,
+        }
+    }
+
+    // Run one command of a pipeline, with its redirections. Returns what it
+    // wrote to stdout if that goes to the next command, and its exit status.
+    fn run_simple_command(&mut self, command: &SimpleCommand, stdin: String, piped: bool, output: &mut Vec<Output>) -> (String, i32) {
+        self.run_redirected(&command.redirects, stdin, piped, output, |shell, io| shell.run_words(&command.words, io))
+    }
+
+    // Run `run` with the streams `redirects` give it, then send what it wrote
+    // where it goes. Returns what it wrote to stdout if that goes to the next
+    // command, and its exit status.
+    fn run_redirected(
+        &mut self,
+        redirects: &[Redirect],
+        stdin: String,
+        piped: bool,
+        output: &mut Vec<Output>,
+        run: impl FnOnce(&mut Self, &mut Io) -> i32,
+    ) -> (String, i32) {
+        let mut io = Io::new(stdin);
+        let mut stdout_file = None;
+        let mut stderr_file = None;
+
+        for redirect in redirects {
+            let target = match self.expand_words(std::slice::from_ref(&redirect.target)).as_deref() {
+                Ok([target]) => target.clone(),
+                Ok(_) => {
+                    output.push(Output::Stderr("sh: ambiguous redirect\n".to_string()));
+                    return (String::new(), EXIT_FAILURE);
+                }
+                Err(e) => {
+                    output.push(Output::Stderr(format!("sh: {}\n", e)));
+                    return (String::new(), EXIT_FAILURE);
+                }
+            };
+            let path = self.resolve_path(&target);
+
+            let result = match (redirect.fd, redirect.kind) {
+                (0, RedirectKind::Read) => self.fs.borrow().read_file(&path).map(|contents| io.stdin = contents),
+                // Truncated before the command runs, as in sh
+                (1 | 2, RedirectKind::Write) => self.fs.borrow_mut().write_file(&path, ""),
+                (1 | 2, RedirectKind::Append) => Ok(()),
+                (fd, _) => Err(format!("{}: bad file descriptor", fd).into()),
+            };
+            if let Err(e) = result {
+                output.push(Output::Stderr(format!("sh: {}: {}\n", target, e)));
+                return (String::new(), EXIT_FAILURE);
+            }
+
+            match redirect.fd {
+                1 => stdout_file = Some((path, redirect.kind)),
+                2 => stderr_file = Some((path, redirect.kind)),
+                _ => {}
+            }
+        }
+
+        io.terminal = self.terminal && !piped && stdout_file.is_none();
+        let terminal = self.terminal;
+        self.terminal = io.terminal;
+        let mut status = run(self, &mut io);
+        self.terminal = terminal;
+
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        for chunk in io.output {
+            match chunk {
+                Output::Stdout(text) if stdout_file.is_some() || piped => stdout.push_str(&text),
+                Output::Stderr(text) if stderr_file.is_some() => stderr.push_str(&text),
+                chunk => push_output(output, chunk),
+            }
+        }
+
+        for (file, text) in [(stdout_file.as_ref(), &mut stdout), (stderr_file.as_ref(), &mut stderr)] {
+            if let Some((path, kind)) = file {
+                if let Err(e) = self.write_redirected(path, *kind, text) {
+                    output.push(Output::Stderr(format!("sh: {}\n", e)));
+                    status = EXIT_FAILURE;
+                }
+                text.clear();
+            }
+        }
+        (stdout, status)
+    }
+
+    // Expand the words of a simple command and run it
+    fn run_words(&mut self, words: &[Word], io: &mut Io) -> i32 {
+        // Leading NAME=value words assign variables: for good when there is
+        // no command, otherwise only while it runs, exported
+        let assignments: Vec<(String, Word)> = words.iter()
+            .map_while(Word::assignment)
+            .collect();
+        let expanded = assignments.iter()
+            .map(|(name, value)| Ok((name.clone(), value.expand_unsplit(&|name| self.variable(name))?)))
+            .collect::<Result<Vec<(String, String)>, String>>()
+            .and_then(|assignments| Ok((self.expand_words(&words[assignments.len()..])?, assignments)));
+        let (args, assignments) = match expanded {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                io.eprintln(&format!("sh: {}", e));
+                return EXIT_FAILURE;
+            }
+        };
+
+        if args.is_empty() {
+            for (name, value) in &assignments {
+                self.set_variable(name, value);
+            }
+            EXIT_SUCCESS
+        } else {
+            let saved: Vec<(String, Option<Variable>)> = assignments.iter()
+                .map(|(name, _)| (name.clone(), self.variables.get(name).cloned()))
+                .collect();
+            for (name, value) in &assignments {
+                self.set_variable(name, value);
+                self.export(name);
+            }
+            let status = self.execute(&args, io);
+            for (name, variable) in saved.into_iter().rev() {
+                match variable {
+                    Some(variable) => self.variables.insert(name, variable),
+                    None => self.variables.remove(&name),
+                };
+            }
+            status
+        }
+    }
+
+    fn write_redirected(&mut self, path: &str, kind: RedirectKind, text: &str) -> Result<(), FsError> {
+        let existing = match kind {
+            RedirectKind::Append => match self.fs.borrow().read_file(path) {
+                Ok(contents) => Some(contents),
+                Err(e) if e.kind == ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            },
+            _ => Some(String::new()),
+        };
+        // Already created empty by the redirection, or appended to
+        if text.is_empty() && existing.is_some() {
+            return Ok(());
+        }
+        let contents = format!("{}{}", existing.unwrap_or_default(), text);
+        self.fs.borrow_mut().write_file(path, &contents)
+    }
+
+    // The arguments words stand for, with patterns replaced by the paths they
+    // match
+    fn expand_words(&self, words: &[Word]) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+        for word in words {
+            for pattern in word.expand_patterns(&|name| self.variable(name), &self.positional)? {
+                args.extend(glob::expand(self, &pattern));
+            }
+        }
+        Ok(args)
+    }
+
+    // The absolute path `path` stands for, with . and .. resolved. A trailing
+    // / is kept, and so is the one of the working directory for "".
+    pub fn resolve_path(&self, path: &str) -> String {
+        let absolute = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.current_directory, path)
+        };
+
+        let mut components: Vec<&str> = Vec::new();
+        for component in absolute.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop();
+                }
+                component => components.push(component),
+            }
+        }
+
+        let mut resolved = format!("/{}", components.join("/"));
+        if absolute.ends_with('/') && !resolved.ends_with('/') {
+            resolved.push('/');
+        }
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{run, session, stdout};
+
+    #[test]
+    fn output_redirections_write_and_append_to_files() {
+        let mut shell = session();
+        assert_eq!(run(&mut shell, "echo one > list.txt"), (String::new(), String::new()));
+        stdout(&mut shell, "echo two >> list.txt");
+        assert_eq!(stdout(&mut shell, "cat list.txt"), "one\ntwo\n");
+        stdout(&mut shell, "echo three > list.txt");
+        assert_eq!(shell.fs.borrow().read_file("/home/list.txt").unwrap(), "three\n");
+    }
+
+    #[test]
+    fn input_and_error_redirections_use_files() {
+        let mut shell = session();
+        stdout(&mut shell, "echo b > list.txt; echo a >> list.txt");
+        assert_eq!(stdout(&mut shell, "sort < list.txt"), "a\nb\n");
+
+        assert_eq!(run(&mut shell, "cat missing.txt 2> errors.txt"), (String::new(), String::new()));
+        assert_eq!(stdout(&mut shell, "echo $?"), "1\n");
+        assert!(stdout(&mut shell, "cat errors.txt").starts_with("cat: File "));
+
+        let (_, stderr) = run(&mut shell, "sort < missing.txt");
+        assert!(stderr.starts_with("sh: missing.txt: "));
+        assert_eq!(stdout(&mut shell, "echo $?"), "1\n");
+    }
+
+    #[test]
+    fn pipes_pass_stdout_to_the_next_command() {
+        let mut shell = session();
+        stdout(&mut shell, "echo cherry > fruit; echo apple >> fruit; echo banana >> fruit");
+        assert_eq!(stdout(&mut shell, "cat fruit | sort | head -n 2"), "apple\nbanana\n");
+        assert_eq!(stdout(&mut shell, "cat fruit | sort -r | head -n 1 > last; cat last"), "cherry\n");
+        // Errors aren't piped
+        assert_eq!(run(&mut shell, "cat missing | wc -l").0, "0\n");
+    }
+
+    #[test]
+    fn and_or_lists_run_on_the_previous_status() {
+        let mut shell = session();
+        assert_eq!(stdout(&mut shell, "true && echo yes || echo no"), "yes\n");
+        assert_eq!(stdout(&mut shell, "false && echo yes || echo no"), "no\n");
+        assert_eq!(stdout(&mut shell, "false || false && echo never; echo after"), "after\n");
+        assert_eq!(stdout(&mut shell, "test 2 -gt 1 && [ -d /home ] && echo both"), "both\n");
+    }
+
+    #[test]
+    fn the_exit_status_is_kept_in_question_mark() {
+        let mut shell = session();
+        assert_eq!(stdout(&mut shell, "true; echo $?"), "0\n");
+        assert_eq!(stdout(&mut shell, "false; echo $?"), "1\n");
+        assert_eq!(stdout(&mut shell, "! false; echo $?"), "0\n");
+        assert_eq!(stdout(&mut shell, "no-such-command; echo $?"), "127\n");
+        assert_eq!(stdout(&mut shell, "[ 1 -eq; echo $?"), "2\n");
+        shell.run("false");
+        assert_eq!(shell.last_status, EXIT_FAILURE);
+    }
+
+    #[test]
+    fn new_sessions_pick_up_what_the_startup_file_sets() {
+        let fs = Rc::new(RefCell::new(FileSystem::new().unwrap()));
+        let mut shell = Shell::new(fs.clone());
+        assert!(shell.run_startup_file().is_empty());
+
+        fs.borrow_mut().write_file("/home/.kshrc", "alias ll='ls -a'\nexport EDITOR=vi\nPS1='\\w\\$ '\nno-such-command\n").unwrap();
+        let mut shell = Shell::new(fs);
+        let errors: Vec<_> = shell.run_startup_file().into_iter()
+            .filter_map(|output| match output {
+                Output::Stderr(text) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(errors, ["sh: no-such-command: command not found\n"]);
+        assert_eq!(shell.alias("ll"), Some("ls -a"));
+        assert_eq!(stdout(&mut shell, "mkdir a; cd a; touch .hidden; ll"), ".hidden\n");
+        assert_eq!(stdout(&mut shell, "env | grep EDITOR"), "EDITOR=vi\n");
+        assert_eq!(shell.prompt(), "~/a$ ");
+        // What it ran isn't in the history
+        assert!(shell.history().iter().all(|line| !line.contains("alias")));
+    }
+
+    #[test]
+    fn history_is_shared_through_the_file() {
+        let fs = Rc::new(RefCell::new(FileSystem::new().unwrap()));
+        let mut first = Shell::new(fs.clone());
+        let mut second = Shell::new(fs.clone());
+        run(&mut first, "echo one");
+        run(&mut second, "echo two");
+        run(&mut second, "echo two");
+        run(&mut first, "   ");
+        assert_eq!(first.history(), ["echo one", "echo two"]);
+        assert_eq!(fs.borrow().read_file("/home/.history").unwrap(), "echo one\necho two\n");
+
+        // A line cut off after a backslash isn't joined to the next
+        fs.borrow_mut().write_file("/home/.history", "echo one\\\n").unwrap();
+        run(&mut first, "echo three");
+        assert_eq!(second.history(), ["echo one", "echo three"]);
+        assert_eq!(fs.borrow().read_file("/home/.history").unwrap(), "echo one\necho three\n");
+
+        run(&mut first, "history -c");
+        assert!(second.history().is_empty());
+    }
+
+    #[test]
+    fn history_keeps_the_last_histsize_lines() {
+        let mut shell = session();
+        run(&mut shell, "HISTSIZE=2");
+        for n in 1..=5 {
+            run(&mut shell, &format!("echo {}", n));
+        }
+        assert_eq!(shell.history(), ["echo 4", "echo 5"]);
+        assert_eq!(stdout(&mut shell, "!-2"), "echo 4\n4\n");
+        assert_eq!(run(&mut shell, "!9").1, "sh: !9: event not found\n");
+    }
+}
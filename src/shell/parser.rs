@@ -0,0 +1,1060 @@
+// Parsing of command lines and scripts, following the POSIX shell rules for
+// quoting, escapes, parameters, comments, pipes, redirections, lists and
+// compound commands
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
+use super::{arithmetic, glob};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    // Text as written, outside of any quotes
+    Literal(String),
+    // Text to be taken as is: quoted or escaped
+    Quoted(String),
+    // $NAME or ${NAME}; `quoted` when inside double quotes, which keeps its
+    // value from being split into several words
+    Variable { name: String, quoted: bool },
+    // $((expression)), as written between the parentheses
+    Arithmetic(String),
+    // A ~ at the start of a word, for the home directory
+    Tilde,
+}
+
+// A word of a command line, made of the pieces it is written as
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    // <
+    Read,
+    // >
+    Write,
+    // >>
+    Append,
+}
+
+// Redirection of one of a command's streams to or from a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    // 0 for stdin, 1 for stdout, 2 for stderr
+    pub fd: u32,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    // In the order they were written
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    // { list; }
+    Group(List),
+    If(IfClause),
+    For(ForLoop),
+    While(WhileLoop),
+    // name() command
+    Function(FunctionDefinition),
+    // A compound command followed by redirections, like done > file
+    Redirected(Box<Command>, Vec<Redirect>),
+}
+
+// if list; then list; [elif list; then list;]... [else list;] fi
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IfClause {
+    // Each condition with what to run when it succeeds, in order
+    pub branches: Vec<(List, List)>,
+    pub otherwise: Option<List>,
+}
+
+// for name [in word...]; do list; done
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ForLoop {
+    pub variable: String,
+    // Without `in`, the loop goes over the positional parameters
+    pub words: Option<Vec<Word>>,
+    pub body: List,
+}
+
+// while list; do list; done, or the same with until
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WhileLoop {
+    pub condition: List,
+    pub body: List,
+    // Loop as long as the condition fails, rather than succeeds
+    pub until: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    // Shared with the shell, which keeps it once defined
+    pub body: Rc<Command>,
+}
+
+// Commands joined by |, each reading what the one before it wrote
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    // Preceded by !, which inverts the exit status
+    pub negated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    // &&: run the next pipeline only if this one succeeded
+    And,
+    // ||: run the next pipeline only if this one failed
+    Or,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+// What a command line or script holds: commands to run one after the other,
+// as separated by ; or newlines
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(Word),
+    Pipe,
+    And,
+    Or,
+    Semicolon,
+    Newline,
+    // The file descriptor, if one was written before the operator
+    Redirect(Option<u32>, RedirectKind),
+}
+
+// Parse a command line or a script. The list is empty if it holds no command,
+// e.g. only a comment.
+pub fn parse(input: &str) -> Result<List, String> {
+    parse_with_aliases(input, &BTreeMap::new())
+}
+
+// Parse, replacing aliases at the start of commands with their values
+pub fn parse_with_aliases(input: &str, aliases: &BTreeMap<String, String>) -> Result<List, String> {
+    let tokens = Lexer::new(input).tokens()?;
+    let mut parser = Parser { tokens: tokens.into_iter().peekable(), aliases, expansions: 0 };
+    let list = parser.compound_list(&[])?;
+    match parser.tokens.peek() {
+        None => Ok(list),
+        token => Err(unexpected(token)),
+    }
+}
+
+// Errors for input that stops inside a quoted string or right after a
+// backslash, which another line can finish
+const UNTERMINATED_SINGLE_QUOTE: &str = "unterminated single quote";
+const UNTERMINATED_DOUBLE_QUOTE: &str = "unterminated double quote";
+const TRAILING_BACKSLASH: &str = "unexpected end of line after \\";
+
+// Where input that another line can finish stops
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Incomplete {
+    SingleQuote,
+    DoubleQuote,
+    Backslash,
+}
+
+// Whether the input stops inside quotes or after a backslash, so that a
+// terminal should read another line to add to it
+pub fn incomplete(input: &str) -> Option<Incomplete> {
+    match Lexer::new(input).tokens() {
+        Err(e) if e == UNTERMINATED_SINGLE_QUOTE => Some(Incomplete::SingleQuote),
+        Err(e) if e == UNTERMINATED_DOUBLE_QUOTE => Some(Incomplete::DoubleQuote),
+        Err(e) if e == TRAILING_BACKSLASH => Some(Incomplete::Backslash),
+        _ => None,
+    }
+}
+
+// Words only reserved at the start of a command
+const RESERVED_WORDS: &[&str] = &["if", "then", "elif", "else", "fi", "for", "in", "do", "done", "while", "until", "{", "}", "!", "function"];
+
+// Most aliases replaced in one parse, so that aliases naming each other in a
+// circle can't go on forever
+const MAX_ALIAS_EXPANSIONS: usize = 1000;
+
+struct Parser<'a> {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+    aliases: &'a BTreeMap<String, String>,
+    expansions: usize,
+}
+
+impl Parser<'_> {
+    // Commands separated by ; or newlines, up to one of the reserved words in
+    // `terminators` or the end of the input
+    fn compound_list(&mut self, terminators: &[&str]) -> Result<List, String> {
+        let mut list = List::default();
+        loop {
+            self.newlines();
+            match self.tokens.peek() {
+                None => return Ok(list),
+                Some(token) if terminators.iter().any(|terminator| is_reserved(token, terminator)) => return Ok(list),
+                _ => {}
+            }
+
+            list.items.push(self.and_or()?);
+            match self.tokens.peek() {
+                Some(Token::Semicolon | Token::Newline) => {
+                    self.tokens.next();
+                }
+                None => {}
+                Some(token) if terminators.iter().any(|terminator| is_reserved(token, terminator)) => {}
+                token => return Err(unexpected(token)),
+            }
+        }
+    }
+
+    // A list ended by `terminator`, which must not be empty
+    fn body(&mut self, terminator: &str) -> Result<List, String> {
+        self.terminated_list(&[terminator]).map(|(list, _)| list)
+    }
+
+    // A non-empty list ended by one of `terminators`, and the one that ended it
+    fn terminated_list(&mut self, terminators: &[&str]) -> Result<(List, String), String> {
+        let list = self.compound_list(terminators)?;
+        let terminator = match self.tokens.peek() {
+            Some(token) => terminators.iter().find(|terminator| is_reserved(token, terminator)),
+            None => return Err("syntax error: unexpected end of file".to_string()),
+        };
+        match terminator {
+            Some(terminator) if !list.items.is_empty() => {
+                self.tokens.next();
+                Ok((list, terminator.to_string()))
+            }
+            _ => Err(unexpected(self.tokens.peek())),
+        }
+    }
+
+    fn newlines(&mut self) {
+        while self.tokens.next_if_eq(&Token::Newline).is_some() {}
+    }
+
+    fn reserved(&mut self, word: &str) -> Result<(), String> {
+        match self.tokens.next() {
+            Some(token) if is_reserved(&token, word) => Ok(()),
+            token => Err(unexpected(token.as_ref())),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.tokens.next() {
+            Some(Token::Word(word)) => match word.literal() {
+                Some(name) if is_name(name) => Ok(name.to_string()),
+                _ => Err(format!("syntax error: `{}' is not a valid name", word.literal().unwrap_or("word"))),
+            },
+            token => Err(unexpected(token.as_ref())),
+        }
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, String> {
+        let mut and_or = AndOr { first: self.pipeline()?, rest: Vec::new() };
+        loop {
+            let connector = match self.tokens.peek() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                _ => return Ok(and_or),
+            };
+            self.tokens.next();
+            self.newlines();
+            and_or.rest.push((connector, self.pipeline()?));
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, String> {
+        let negated = self.tokens.next_if(|token| is_reserved(token, "!")).is_some();
+        let mut pipeline = Pipeline { commands: vec![self.command()?], negated };
+        while self.tokens.next_if_eq(&Token::Pipe).is_some() {
+            self.newlines();
+            pipeline.commands.push(self.command()?);
+        }
+        Ok(pipeline)
+    }
+
+    fn command(&mut self) -> Result<Command, String> {
+        let command = self.unredirected_command()?;
+        if matches!(command, Command::Simple(_) | Command::Function(_)) {
+            return Ok(command);
+        }
+
+        let mut redirects = Vec::new();
+        while let Some(&Token::Redirect(fd, kind)) = self.tokens.peek() {
+            self.tokens.next();
+            redirects.push(self.redirect(fd, kind)?);
+        }
+        if redirects.is_empty() {
+            Ok(command)
+        } else {
+            Ok(Command::Redirected(Box::new(command), redirects))
+        }
+    }
+
+    fn unredirected_command(&mut self) -> Result<Command, String> {
+        self.expand_alias()?;
+        let keyword = match self.tokens.peek() {
+            Some(Token::Word(word)) => word.literal().map(str::to_string),
+            _ => None,
+        };
+        match keyword.as_deref() {
+            Some("if") => self.if_clause(),
+            Some("for") => self.for_loop(),
+            Some("while" | "until") => self.while_loop(),
+            Some("{") => {
+                self.tokens.next();
+                Ok(Command::Group(self.body("}")?))
+            }
+            Some("function") => {
+                self.tokens.next();
+                let name = self.name()?;
+                self.tokens.next_if(|token| matches!(token, Token::Word(word) if word.literal() == Some("()")));
+                self.function_body(name)
+            }
+            // name() or name ()
+            Some(word) if word.ends_with("()") && is_name(&word[..word.len() - 2]) => {
+                self.tokens.next();
+                self.function_body(word[..word.len() - 2].to_string())
+            }
+            Some(word) if is_name(word) && self.next_is_parentheses() => {
+                self.tokens.next();
+                self.tokens.next();
+                self.function_body(word.to_string())
+            }
+            Some(word) if RESERVED_WORDS.contains(&word) => Err(format!("syntax error near unexpected token `{}'", word)),
+            _ => self.simple_command().map(Command::Simple),
+        }
+    }
+
+    // Replace an alias at the start of a command with the tokens of its value,
+    // then the first of those too if it is another alias, but not one already
+    // replaced here, so that an alias like ls='ls -a' uses the command
+    fn expand_alias(&mut self) -> Result<(), String> {
+        let mut expanded: Vec<String> = Vec::new();
+        loop {
+            let (name, value) = match self.tokens.peek() {
+                Some(Token::Word(word)) => match word.literal() {
+                    Some(name) if !RESERVED_WORDS.contains(&name) && !expanded.iter().any(|expanded| expanded == name) => {
+                        match self.aliases.get(name) {
+                            Some(value) => (name.to_string(), value),
+                            None => return Ok(()),
+                        }
+                    }
+                    _ => return Ok(()),
+                },
+                _ => return Ok(()),
+            };
+
+            self.expansions += 1;
+            if self.expansions > MAX_ALIAS_EXPANSIONS {
+                return Err(format!("{}: too many alias expansions", name));
+            }
+            let mut tokens = Lexer::new(value).tokens()?;
+            self.tokens.next();
+            tokens.extend(self.tokens.by_ref());
+            self.tokens = tokens.into_iter().peekable();
+            expanded.push(name);
+        }
+    }
+
+    fn next_is_parentheses(&self) -> bool {
+        let mut tokens = self.tokens.clone();
+        tokens.next();
+        matches!(tokens.next(), Some(Token::Word(word)) if word.literal() == Some("()"))
+    }
+
+    fn function_body(&mut self, name: String) -> Result<Command, String> {
+        self.newlines();
+        let body = self.command()?;
+        if matches!(body, Command::Simple(_) | Command::Function(_)) {
+            return Err(format!("syntax error: the body of function {} must be a compound command", name));
+        }
+        Ok(Command::Function(FunctionDefinition { name, body: Rc::new(body) }))
+    }
+
+    fn if_clause(&mut self) -> Result<Command, String> {
+        self.reserved("if")?;
+        let mut clause = IfClause::default();
+        loop {
+            let condition = self.body("then")?;
+            let (branch, terminator) = self.terminated_list(&["elif", "else", "fi"])?;
+            clause.branches.push((condition, branch));
+            match terminator.as_str() {
+                "elif" => continue,
+                "else" => {
+                    clause.otherwise = Some(self.body("fi")?);
+                    break;
+                }
+                _ => break,
+            }
+        }
+        Ok(Command::If(clause))
+    }
+
+    fn for_loop(&mut self) -> Result<Command, String> {
+        self.reserved("for")?;
+        let variable = self.name()?;
+        self.newlines();
+
+        let mut words = None;
+        if self.tokens.next_if(|token| is_reserved(token, "in")).is_some() {
+            let mut list = Vec::new();
+            while let Some(Token::Word(_)) = self.tokens.peek() {
+                if let Some(Token::Word(word)) = self.tokens.next() {
+                    list.push(word);
+                }
+            }
+            words = Some(list);
+            match self.tokens.next() {
+                Some(Token::Semicolon | Token::Newline) => {}
+                token => return Err(unexpected(token.as_ref())),
+            }
+        } else {
+            self.tokens.next_if_eq(&Token::Semicolon);
+        }
+        self.newlines();
+
+        self.reserved("do")?;
+        let body = self.body("done")?;
+        Ok(Command::For(ForLoop { variable, words, body }))
+    }
+
+    fn while_loop(&mut self) -> Result<Command, String> {
+        let until = self.tokens.next().is_some_and(|token| is_reserved(&token, "until"));
+        let condition = self.body("do")?;
+        let body = self.body("done")?;
+        Ok(Command::While(WhileLoop { condition, body, until }))
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, String> {
+        let mut command = SimpleCommand::default();
+        loop {
+            match self.tokens.peek() {
+                Some(Token::Word(_)) => {
+                    if let Some(Token::Word(word)) = self.tokens.next() {
+                        command.words.push(word);
+                    }
+                }
+                Some(&Token::Redirect(fd, kind)) => {
+                    self.tokens.next();
+                    command.redirects.push(self.redirect(fd, kind)?);
+                }
+                _ => break,
+            }
+        }
+
+        if command.words.is_empty() && command.redirects.is_empty() {
+            return Err(unexpected(self.tokens.peek()));
+        }
+        Ok(command)
+    }
+}
+
+impl Parser<'_> {
+    // The rest of a redirection, after its operator
+    fn redirect(&mut self, fd: Option<u32>, kind: RedirectKind) -> Result<Redirect, String> {
+        let target = match self.tokens.next() {
+            Some(Token::Word(word)) => word,
+            token => return Err(unexpected(token.as_ref())),
+        };
+        let fd = fd.unwrap_or(if kind == RedirectKind::Read { 0 } else { 1 });
+        Ok(Redirect { fd, kind, target })
+    }
+}
+
+// Whether `token` is the reserved word `word`, which is never quoted
+fn is_reserved(token: &Token, word: &str) -> bool {
+    matches!(token, Token::Word(token) if token.literal() == Some(word))
+}
+
+fn unexpected(token: Option<&Token>) -> String {
+    let token = match token {
+        None | Some(Token::Newline) => "newline",
+        Some(Token::Word(word)) => return format!("syntax error near unexpected token `{}'", word.literal().unwrap_or("word")),
+        Some(Token::Pipe) => "|",
+        Some(Token::And) => "&&",
+        Some(Token::Or) => "||",
+        Some(Token::Semicolon) => ";",
+        Some(Token::Redirect(_, RedirectKind::Read)) => "<",
+        Some(Token::Redirect(_, RedirectKind::Write)) => ">",
+        Some(Token::Redirect(_, RedirectKind::Append)) => ">>",
+    };
+    format!("syntax error near unexpected token `{}'", token)
+}
+
+impl Word {
+    // The arguments this word stands for once parameters are replaced by the
+    // values `lookup` gives them. Unquoted values are split at whitespace, and
+    // a word that ends up empty without being quoted disappears, like in sh.
+    // "$@" stands for the `positional` parameters as separate arguments.
+    // Fails on arithmetic that can't be evaluated.
+    pub fn expand(&self, lookup: &dyn Fn(&str) -> Option<String>, positional: &[String]) -> Result<Vec<String>, String> {
+        self.expand_with(lookup, positional, &str::to_string)
+    }
+
+    // Like `expand`, but into glob patterns, in which what was quoted is
+    // escaped so that it only matches itself
+    pub fn expand_patterns(&self, lookup: &dyn Fn(&str) -> Option<String>, positional: &[String]) -> Result<Vec<String>, String> {
+        self.expand_with(lookup, positional, &glob::escape)
+    }
+
+    // Expand the word, passing text that was quoted through `quote`
+    fn expand_with(&self, lookup: &dyn Fn(&str) -> Option<String>, positional: &[String], quote: &dyn Fn(&str) -> String) -> Result<Vec<String>, String> {
+        let mut fields = Vec::new();
+        let mut current = String::new();
+        // Whether `current` is a field even if empty
+        let mut has_field = false;
+
+        for part in &self.parts {
+            match part {
+                WordPart::Literal(text) => {
+                    current.push_str(text);
+                    has_field = true;
+                }
+                WordPart::Quoted(text) => {
+                    current.push_str(&quote(text));
+                    has_field = true;
+                }
+                WordPart::Tilde => {
+                    current.push_str(&quote(&lookup("HOME").unwrap_or_else(|| "~".to_string())));
+                    has_field = true;
+                }
+                // A number is never split
+                WordPart::Arithmetic(expression) => {
+                    current.push_str(&arithmetic::evaluate(expression, lookup)?.to_string());
+                    has_field = true;
+                }
+                WordPart::Variable { name, quoted: true } if name == "@" => {
+                    for (i, parameter) in positional.iter().enumerate() {
+                        if i > 0 {
+                            fields.push(std::mem::take(&mut current));
+                        }
+                        current.push_str(&quote(parameter));
+                        has_field = true;
+                    }
+                }
+                WordPart::Variable { name, quoted: true } => {
+                    current.push_str(&quote(&lookup(name).unwrap_or_default()));
+                    has_field = true;
+                }
+                WordPart::Variable { name, quoted: false } => {
+                    let value = lookup(name).unwrap_or_default();
+                    if value.starts_with(char::is_whitespace) && has_field {
+                        fields.push(std::mem::take(&mut current));
+                        has_field = false;
+                    }
+                    for (i, piece) in value.split_whitespace().enumerate() {
+                        if i > 0 {
+                            fields.push(std::mem::take(&mut current));
+                        }
+                        current.push_str(piece);
+                        has_field = true;
+                    }
+                    if value.ends_with(char::is_whitespace) && has_field {
+                        fields.push(std::mem::take(&mut current));
+                        has_field = false;
+                    }
+                }
+            }
+        }
+
+        if has_field {
+            fields.push(current);
+        }
+        Ok(fields)
+    }
+
+    // Like `expand`, but as a single string without any splitting, the way
+    // the value of an assignment is expanded
+    pub fn expand_unsplit(&self, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+        self.parts.iter()
+            .map(|part| Ok(match part {
+                WordPart::Literal(text) | WordPart::Quoted(text) => text.clone(),
+                WordPart::Tilde => lookup("HOME").unwrap_or_else(|| "~".to_string()),
+                WordPart::Variable { name, .. } => lookup(name).unwrap_or_default(),
+                WordPart::Arithmetic(expression) => arithmetic::evaluate(expression, lookup)?.to_string(),
+            }))
+            .collect()
+    }
+
+    // The name and value of a word like NAME=value, which assigns a variable
+    // rather than being an argument
+    pub fn assignment(&self) -> Option<(String, Word)> {
+        let Some(WordPart::Literal(first)) = self.parts.first() else {
+            return None;
+        };
+        let (name, rest) = first.split_once('=')?;
+        if !is_name(name) {
+            return None;
+        }
+
+        let mut value = Word::default();
+        if !rest.is_empty() {
+            value.parts.push(WordPart::Literal(rest.to_string()));
+        }
+        value.parts.extend(self.parts[1..].iter().cloned());
+        Some((name.to_string(), value))
+    }
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+// What a $ starts
+enum Parameter {
+    Variable(String),
+    // The expression of $((expression))
+    Arithmetic(String),
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { chars: input.chars().peekable() }
+    }
+
+    fn tokens(&mut self) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        loop {
+            while self.chars.next_if(|c| c.is_whitespace() && *c != '\n').is_some() {}
+            let token = match self.chars.peek() {
+                None => break,
+                // Comments only start at the beginning of a word, and go on to
+                // the end of the line
+                Some('#') => {
+                    while self.chars.next_if(|c| *c != '\n').is_some() {}
+                    continue;
+                }
+                Some('\n') => {
+                    self.chars.next();
+                    Token::Newline
+                }
+                Some('|') => {
+                    self.chars.next();
+                    if self.chars.next_if_eq(&'|').is_some() { Token::Or } else { Token::Pipe }
+                }
+                Some('&') => {
+                    self.chars.next();
+                    if self.chars.next_if_eq(&'&').is_none() {
+                        return Err("running commands in the background is not supported".to_string());
+                    }
+                    Token::And
+                }
+                Some(';') => {
+                    self.chars.next();
+                    Token::Semicolon
+                }
+                Some('<' | '>') => self.redirect(None),
+                Some(_) => {
+                    let word = self.word()?;
+                    // Digits right before a redirection pick the stream, as in 2>
+                    match word.parts.as_slice() {
+                        [WordPart::Literal(digits)] if matches!(self.chars.peek(), Some('<' | '>')) => {
+                            match digits.parse() {
+                                Ok(fd) => self.redirect(Some(fd)),
+                                Err(_) => Token::Word(word),
+                            }
+                        }
+                        _ => Token::Word(word),
+                    }
+                }
+            };
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    fn redirect(&mut self, fd: Option<u32>) -> Token {
+        let kind = match self.chars.next() {
+            Some('<') => RedirectKind::Read,
+            _ if self.chars.next_if_eq(&'>').is_some() => RedirectKind::Append,
+            _ => RedirectKind::Write,
+        };
+        Token::Redirect(fd, kind)
+    }
+
+    fn word(&mut self) -> Result<Word, String> {
+        let mut word = Word::default();
+
+        if self.chars.peek() == Some(&'~') {
+            let mut rest = self.chars.clone();
+            rest.next();
+            if rest.peek().is_none_or(|c| *c == '/' || c.is_whitespace() || is_operator(*c)) {
+                self.chars.next();
+                word.parts.push(WordPart::Tilde);
+            }
+        }
+
+        while let Some(&c) = self.chars.peek() {
+            if c.is_whitespace() || is_operator(c) {
+                break;
+            }
+            self.chars.next();
+            match c {
+                '\'' => {
+                    let mut text = String::new();
+                    loop {
+                        match self.chars.next() {
+                            Some('\'') => break,
+                            Some(c) => text.push(c),
+                            None => return Err(UNTERMINATED_SINGLE_QUOTE.to_string()),
+                        }
+                    }
+                    word.push_quoted(&text);
+                }
+                '"' => self.double_quoted(&mut word)?,
+                '\\' => match self.chars.next() {
+                    // An escaped newline joins the lines
+                    Some('\n') => {}
+                    Some(c) => word.push_quoted(&c.to_string()),
+                    None => return Err(TRAILING_BACKSLASH.to_string()),
+                },
+                '$' => match self.parameter()? {
+                    Some(Parameter::Variable(name)) => word.parts.push(WordPart::Variable { name, quoted: false }),
+                    Some(Parameter::Arithmetic(expression)) => word.parts.push(WordPart::Arithmetic(expression)),
+                    None => word.push_literal('$'),
+                },
+                c => word.push_literal(c),
+            }
+        }
+        Ok(word)
+    }
+
+    // The rest of a double quoted string, up to and including the closing quote
+    fn double_quoted(&mut self, word: &mut Word) -> Result<(), String> {
+        // An empty string is still a word
+        word.push_quoted("");
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(()),
+                // Backslashes only escape what is special inside double quotes
+                Some('\\') => match self.chars.next_if(|c| matches!(c, '$' | '`' | '"' | '\\' | '\n')) {
+                    Some('\n') => {}
+                    Some(c) => word.push_quoted(&c.to_string()),
+                    None => word.push_quoted("\\"),
+                },
+                Some('$') => match self.parameter()? {
+                    Some(Parameter::Variable(name)) => word.parts.push(WordPart::Variable { name, quoted: true }),
+                    Some(Parameter::Arithmetic(expression)) => word.parts.push(WordPart::Arithmetic(expression)),
+                    None => word.push_quoted("$"),
+                },
+                Some(c) => word.push_quoted(&c.to_string()),
+                None => return Err(UNTERMINATED_DOUBLE_QUOTE.to_string()),
+            }
+        }
+    }
+
+    // What follows a $, if it is a parameter or arithmetic
+    fn parameter(&mut self) -> Result<Option<Parameter>, String> {
+        // Commands can't run in the background, so there is never a last one
+        if self.chars.peek() == Some(&'!') {
+            return Err("$!: there are no background jobs".to_string());
+        }
+        if self.chars.peek() == Some(&'(') {
+            let mut rest = self.chars.clone();
+            rest.next();
+            if rest.peek() != Some(&'(') {
+                return Err("$(: command substitution is not supported".to_string());
+            }
+            self.chars.next();
+            self.chars.next();
+            return self.arithmetic().map(|expression| Some(Parameter::Arithmetic(expression)));
+        }
+        Ok(self.parameter_name()?.map(Parameter::Variable))
+    }
+
+    // The expression of $((expression)), after the opening parentheses and up
+    // to and including the closing ones
+    fn arithmetic(&mut self) -> Result<String, String> {
+        let mut expression = String::new();
+        let mut depth = 0;
+        loop {
+            let c = self.chars.next().ok_or("unterminated $((")?;
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    if self.chars.next_if_eq(&')').is_none() {
+                        return Err(format!("$(({}): missing `)'", expression));
+                    }
+                    return Ok(expression);
+                }
+                ')' => depth -= 1,
+                _ => {}
+            }
+            expression.push(c);
+        }
+    }
+
+    // The name of the parameter after a $, if there is one
+    fn parameter_name(&mut self) -> Result<Option<String>, String> {
+        if self.chars.next_if_eq(&'{').is_some() {
+            let mut name = String::new();
+            loop {
+                match self.chars.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => return Err("unterminated ${".to_string()),
+                }
+            }
+            if name == "!" {
+                return Err("${!}: there are no background jobs".to_string());
+            }
+            let positional = !name.is_empty() && name.chars().all(|c| c.is_ascii_digit());
+            if !is_name(&name) && !is_special_parameter(&name) && !positional {
+                return Err(format!("${{{}}}: bad substitution", name));
+            }
+            return Ok(Some(name));
+        }
+
+        // Special parameters are a single character, like $? or $1
+        if let Some(c) = self.chars.next_if(|c| is_special_parameter(&c.to_string())) {
+            return Ok(Some(c.to_string()));
+        }
+
+        let mut name = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            name.push(c);
+        }
+        Ok(if name.is_empty() { None } else { Some(name) })
+    }
+}
+
+impl Word {
+    // The word as written, if it is plain text without quotes or parameters
+    pub fn literal(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(text)] => Some(text),
+            _ => None,
+        }
+    }
+
+    fn push_literal(&mut self, c: char) {
+        match self.parts.last_mut() {
+            Some(WordPart::Literal(text)) => text.push(c),
+            _ => self.parts.push(WordPart::Literal(c.to_string())),
+        }
+    }
+
+    fn push_quoted(&mut self, s: &str) {
+        match self.parts.last_mut() {
+            Some(WordPart::Quoted(text)) => text.push_str(s),
+            _ => self.parts.push(WordPart::Quoted(s.to_string())),
+        }
+    }
+}
+
+// Characters that end a word unless quoted
+fn is_operator(c: char) -> bool {
+    matches!(c, '|' | '&' | ';' | '<' | '>')
+}
+
+// Whether `name` can name a variable
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_special_parameter(name: &str) -> bool {
+    matches!(name, "?" | "#" | "@" | "*" | "$" | "!" | "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The only simple command of a line
+    fn simple(input: &str) -> SimpleCommand {
+        let list = parse(input).unwrap();
+        match list.items.as_slice() {
+            [AndOr { first: Pipeline { commands, negated: false }, rest }] if rest.is_empty() => match commands.as_slice() {
+                [Command::Simple(command)] => command.clone(),
+                other => panic!("not a simple command: {:?}", other),
+            },
+            other => panic!("not a single command: {:?}", other),
+        }
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/user".to_string()),
+            "NAME" => Some("world".to_string()),
+            "SPACED" => Some(" a  b ".to_string()),
+            "?" => Some("0".to_string()),
+            _ => None,
+        }
+    }
+
+    // The arguments of the only command of a line, once expanded
+    fn arguments(input: &str) -> Vec<String> {
+        simple(input).words.iter().flat_map(|word| word.expand(&lookup, &[]).unwrap()).collect()
+    }
+
+    #[test]
+    fn words_are_split_at_whitespace() {
+        assert_eq!(arguments("echo  a\tb   c"), ["echo", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn quotes_keep_words_together() {
+        assert_eq!(arguments("echo 'a  b' \"c  d\" e' 'f"), ["echo", "a  b", "c  d", "e f"]);
+        assert_eq!(arguments("echo '' \"\""), ["echo", "", ""]);
+    }
+
+    #[test]
+    fn single_quotes_keep_everything_as_written() {
+        assert_eq!(arguments(r#"echo '$NAME \n "x"'"#), ["echo", r#"$NAME \n "x""#]);
+    }
+
+    #[test]
+    fn backslashes_escape_the_next_character() {
+        assert_eq!(arguments(r"echo a\ b \$NAME \\ \'"), ["echo", "a b", "$NAME", "\\", "'"]);
+        assert_eq!(arguments("echo a\\\nb"), ["echo", "ab"]);
+    }
+
+    #[test]
+    fn backslashes_in_double_quotes_only_escape_special_characters() {
+        assert_eq!(arguments(r#"echo "\$NAME \" \\ \n""#), ["echo", r#"$NAME " \ \n"#]);
+    }
+
+    #[test]
+    fn variables_are_replaced() {
+        assert_eq!(arguments("echo $NAME ${NAME}s \"hello $NAME\" $?"), ["echo", "world", "worlds", "hello world", "0"]);
+        assert_eq!(arguments("echo $UNSET \"$UNSET\" x$"), ["echo", "", "x$"]);
+    }
+
+    #[test]
+    fn unquoted_variables_are_split() {
+        assert_eq!(arguments("echo $SPACED"), ["echo", "a", "b"]);
+        assert_eq!(arguments("echo \"$SPACED\""), ["echo", " a  b "]);
+        assert_eq!(arguments("echo x${SPACED}y"), ["echo", "x", "a", "b", "y"]);
+    }
+
+    #[test]
+    fn arithmetic_is_evaluated() {
+        assert_eq!(arguments("echo $((1 + 2 * 3)) x$(( (1+1) * 2 ))y \"$(($? - 1))\""), ["echo", "7", "x4y", "-1"]);
+        assert!(parse("echo $((1 + 2)").is_err());
+        assert!(parse("echo $((1 + 2").is_err());
+        assert!(simple("echo $((1 / 0))").words[1].expand(&lookup, &[]).is_err());
+    }
+
+    #[test]
+    fn background_jobs_and_command_substitution_are_rejected() {
+        assert!(parse("echo $!").is_err());
+        assert!(parse("echo \"${!}\"").is_err());
+        assert!(parse("echo $(ls)").is_err());
+        assert_eq!(arguments("echo '$!' \\$!"), ["echo", "$!", "$!"]);
+    }
+
+    #[test]
+    fn bad_substitutions_are_rejected() {
+        assert!(parse("echo ${NA ME}").is_err());
+        assert!(parse("echo ${NAME").is_err());
+    }
+
+    #[test]
+    fn tilde_stands_for_home_at_the_start_of_a_word() {
+        assert_eq!(arguments("cd ~ ~/docs a~ '~'"), ["cd", "/home/user", "/home/user/docs", "a~", "~"]);
+    }
+
+    #[test]
+    fn comments_are_left_out() {
+        assert_eq!(arguments("echo a # b c"), ["echo", "a"]);
+        assert_eq!(arguments("echo a#b"), ["echo", "a#b"]);
+        assert!(parse("# only a comment").unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn pipes_join_commands() {
+        let list = parse("ls | grep a | wc -l").unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].first.commands.len(), 3);
+        assert!(parse("ls |").is_err());
+        assert!(parse("| ls").is_err());
+    }
+
+    #[test]
+    fn and_or_lists_keep_their_connectors() {
+        let list = parse("true && echo yes || echo no; echo done").unwrap();
+        assert_eq!(list.items.len(), 2);
+        let connectors: Vec<Connector> = list.items[0].rest.iter().map(|(connector, _)| *connector).collect();
+        assert_eq!(connectors, [Connector::And, Connector::Or]);
+        assert!(parse("true &&").is_err());
+        assert!(parse("true & false").is_err());
+    }
+
+    #[test]
+    fn redirections_are_taken_out_of_the_words() {
+        let command = simple("sort < in.txt > out.txt 2>> errors.txt");
+        assert_eq!(command.words.len(), 1);
+        let redirects: Vec<(u32, RedirectKind, String)> = command.redirects.iter()
+            .map(|redirect| (redirect.fd, redirect.kind, redirect.target.expand_unsplit(&lookup).unwrap()))
+            .collect();
+        assert_eq!(redirects, [
+            (0, RedirectKind::Read, "in.txt".to_string()),
+            (1, RedirectKind::Write, "out.txt".to_string()),
+            (2, RedirectKind::Append, "errors.txt".to_string()),
+        ]);
+        assert!(parse("echo >").is_err());
+    }
+
+    #[test]
+    fn quoted_digits_are_not_a_stream() {
+        let command = simple("echo '2'>out.txt");
+        assert_eq!(command.words.len(), 2);
+        assert_eq!(command.redirects[0].fd, 1);
+    }
+
+    #[test]
+    fn assignments_are_recognized() {
+        let command = simple("NAME=a'b c' echo");
+        let (name, value) = command.words[0].assignment().unwrap();
+        assert_eq!((name.as_str(), value.expand_unsplit(&lookup).unwrap().as_str()), ("NAME", "ab c"));
+        assert!(simple("echo =x").words[1].assignment().is_none());
+    }
+
+    #[test]
+    fn unfinished_input_is_recognized() {
+        assert_eq!(incomplete("echo 'abc"), Some(Incomplete::SingleQuote));
+        assert_eq!(incomplete("echo \"abc"), Some(Incomplete::DoubleQuote));
+        assert_eq!(incomplete("echo abc\\"), Some(Incomplete::Backslash));
+        assert_eq!(incomplete("echo 'abc'"), None);
+    }
+
+    #[test]
+    fn aliases_are_replaced_at_the_start_of_commands() {
+        let aliases = BTreeMap::from([("ll".to_string(), "ls -l".to_string())]);
+        let list = parse_with_aliases("ll ll | ll", &aliases).unwrap();
+        let words: Vec<Vec<String>> = list.items[0].first.commands.iter()
+            .map(|command| match command {
+                Command::Simple(command) => command.words.iter().flat_map(|word| word.expand(&lookup, &[]).unwrap()).collect(),
+                other => panic!("not a simple command: {:?}", other),
+            })
+            .collect();
+        assert_eq!(words, [vec!["ls", "-l", "ll"], vec!["ls", "-l"]]);
+    }
+
+    #[test]
+    fn compound_commands_parse() {
+        let list = parse("if true; then echo a; elif false; then echo b; else echo c; fi").unwrap();
+        assert!(matches!(&list.items[0].first.commands[0], Command::If(clause) if clause.branches.len() == 2 && clause.otherwise.is_some()));
+        let list = parse("for i in 1 2 3; do echo $i; done > out.txt").unwrap();
+        assert!(matches!(&list.items[0].first.commands[0], Command::Redirected(command, _) if matches!(**command, Command::For(_))));
+        assert!(parse("if true; then echo a").is_err());
+    }
+}
@@ -0,0 +1,298 @@
+// Scripts and functions: running the commands they hold in a session of their
+// own, or in the current one for `source`
+use std::rc::Rc;
+use super::parser::{self, Command};
+use crate::filesystem::FileType;
+use super::{push_output, Flow, Io, Shell, EXIT_CANNOT_RUN, EXIT_FAILURE, EXIT_NOT_FOUND, EXIT_SUCCESS, EXIT_USAGE, MAX_NESTING};
+
+// Interpreters a shebang line may name for a script this shell runs
+const SHELLS: &[&str] = &["sh", "bash", "ksh", "dash"];
+
+impl Shell {
+    // Run a command by name: a function, a registered command, or a script
+    pub(super) fn execute(&mut self, args: &[String], io: &mut Io) -> i32 {
+        match self.functions.get(&args[0]) {
+            Some(body) => {
+                let body = Rc::clone(body);
+                self.call_function(&args[0], &body, &args[1..], io)
+            }
+            None => {
+                let command = self.commands.borrow().get(&args[0]);
+                match command {
+                    Some(command) => command.run(args, io, self),
+                    None => self.run_external(args, io),
+                }
+            }
+        }
+    }
+
+    fn call_function(&mut self, name: &str, body: &Command, args: &[String], io: &mut Io) -> i32 {
+        if self.nesting >= MAX_NESTING {
+            io.eprintln(&format!("sh: {}: maximum nesting level exceeded", name));
+            return EXIT_FAILURE;
+        }
+
+        let positional = std::mem::replace(&mut self.positional, args.to_vec());
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        self.nesting += 1;
+        self.function_depth += 1;
+
+        let mut output = Vec::new();
+        let (_, status) = self.run_compound_command(body, String::new(), false, &mut output);
+        let status = match self.flow {
+            Some(Flow::Return) => {
+                self.flow = None;
+                self.last_status
+            }
+            _ => status,
+        };
+
+        self.function_depth -= 1;
+        self.nesting -= 1;
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        for chunk in output {
+            push_output(&mut io.output, chunk);
+        }
+        status
+    }
+
+    // A command that isn't built in: run the script it names, found through
+    // PATH unless it is a path itself
+    pub(super) fn run_external(&mut self, args: &[String], io: &mut Io) -> i32 {
+        let name = &args[0];
+        let path = if name.contains('/') {
+            Some(self.resolve_path(name))
+        } else {
+            self.find_in_path(name)
+        };
+        let path = match path {
+            Some(path) => path,
+            None => {
+                io.eprintln(&format!("sh: {}: command not found", name));
+                return EXIT_NOT_FOUND;
+            }
+        };
+
+        let contents = match self.read_script(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                io.eprintln(&format!("sh: {}: {}", name, e));
+                return if self.fs.borrow().metadata(&path).is_ok() { EXIT_CANNOT_RUN } else { EXIT_NOT_FOUND };
+            }
+        };
+
+        // Scripts without a shebang are run by the shell too, as sh does
+        if let Some(shebang) = contents.lines().next().and_then(|line| line.strip_prefix("#!")) {
+            let mut words = shebang.split_whitespace();
+            let mut interpreter = words.next().unwrap_or_default();
+            if interpreter.rsplit('/').next() == Some("env") {
+                interpreter = words.next().unwrap_or_default();
+            }
+            if !SHELLS.contains(&interpreter.rsplit('/').next().unwrap_or_default()) {
+                io.eprintln(&format!("sh: {}: {}: bad interpreter", name, shebang.trim()));
+                return EXIT_CANNOT_RUN;
+            }
+        }
+
+        self.run_script(name, &contents, &args[1..], io)
+    }
+
+    fn find_in_path(&self, name: &str) -> Option<String> {
+        let search_path = self.variable("PATH").unwrap_or_default();
+        search_path.split(':')
+            .map(|directory| {
+                let directory = if directory.is_empty() { "." } else { directory };
+                self.resolve_path(&format!("{}/{}", directory, name))
+            })
+            .find(|path| self.read_script(path).is_ok())
+    }
+
+    pub(super) fn read_script(&self, path: &str) -> Result<String, String> {
+        let fs = self.fs.borrow();
+        if fs.metadata(path)?.file_type == FileType::Directory {
+            return Err("Is a directory".to_string());
+        }
+        Ok(fs.read_file(path)?)
+    }
+
+    // Run a script in a new session, which inherits the environment and the
+    // working directory. `name` is its $0 and `args` its positional parameters.
+    pub(super) fn run_script(&mut self, name: &str, source: &str, args: &[String], io: &mut Io) -> i32 {
+        let list = match parser::parse(source) {
+            Ok(list) => list,
+            Err(e) => {
+                io.eprintln(&format!("{}: {}", name, e));
+                return EXIT_USAGE;
+            }
+        };
+        if self.nesting >= MAX_NESTING {
+            io.eprintln(&format!("sh: {}: maximum nesting level exceeded", name));
+            return EXIT_FAILURE;
+        }
+
+        let mut script = self.child();
+        script.script_name = name.to_string();
+        script.positional = args.to_vec();
+
+        let mut output = Vec::new();
+        script.run_list(&list, &mut output);
+        for chunk in output {
+            push_output(&mut io.output, chunk);
+        }
+        script.last_status
+    }
+
+    // Run the commands of a file in this session, as `source` and `.` do, so
+    // that the variables and functions it defines stay
+    pub(super) fn source(&mut self, path: &str, args: &[String], io: &mut Io) -> i32 {
+        let contents = self.read_script(&self.resolve_path(path));
+        let list = match contents.and_then(|contents| parser::parse_with_aliases(&contents, &self.aliases)) {
+            Ok(list) => list,
+            Err(e) => {
+                io.eprintln(&format!("source: {}: {}", path, e));
+                return EXIT_FAILURE;
+            }
+        };
+        if self.nesting >= MAX_NESTING {
+            io.eprintln(&format!("source: {}: maximum nesting level exceeded", path));
+            return EXIT_FAILURE;
+        }
+
+        // Arguments replace the positional parameters while it runs
+        let positional = (!args.is_empty()).then(|| std::mem::replace(&mut self.positional, args.to_vec()));
+        self.nesting += 1;
+        self.function_depth += 1;
+
+        let mut output = Vec::new();
+        self.last_status = EXIT_SUCCESS;
+        self.run_list(&list, &mut output);
+        if self.flow == Some(Flow::Return) {
+            self.flow = None;
+        }
+
+        self.function_depth -= 1;
+        self.nesting -= 1;
+        if let Some(positional) = positional {
+            self.positional = positional;
+        }
+        for chunk in output {
+            push_output(&mut io.output, chunk);
+        }
+        self.last_status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::testing::{run, session, stdout};
+
+    // Write a file for the shell to run
+    fn write(shell: &Shell, path: &str, contents: &str) {
+        shell.fs.borrow_mut().write_file(path, contents).unwrap();
+    }
+
+    #[test]
+    fn if_runs_the_first_branch_whose_condition_succeeds() {
+        let mut shell = session();
+        let line = "for n in 1 2 3; do if [ $n = 1 ]; then echo one; elif [ $n = 2 ]; then echo two; else echo many; fi; done";
+        assert_eq!(stdout(&mut shell, line), "one\ntwo\nmany\n");
+        // Without a branch taken, the status is 0
+        assert_eq!(stdout(&mut shell, "if false; then echo no; fi; echo $?"), "0\n");
+        assert_eq!(stdout(&mut shell, "if false; then :; else false; fi; echo $?"), "1\n");
+    }
+
+    #[test]
+    fn while_and_until_loop_on_their_conditions() {
+        let mut shell = session();
+        let line = "n=0\nwhile [ $n != 3 ]; do\n  n=$((n + 1))\n  echo $n\ndone";
+        assert_eq!(stdout(&mut shell, line), "1\n2\n3\n");
+        assert_eq!(stdout(&mut shell, "until [ $n = 0 ]; do n=$((n - 1)); echo $n; done"), "2\n1\n0\n");
+        assert_eq!(run(&mut shell, "while true; do :; done; echo $?"), (
+            "1\n".to_string(),
+            "sh: while: stopped after 100000 iterations\n".to_string(),
+        ));
+    }
+
+    #[test]
+    fn break_and_continue_leave_as_many_loops_as_asked() {
+        let mut shell = session();
+        let line = "for a in 1 2; do for b in x y z; do [ $b = y ] && continue; echo $a$b; done; done";
+        assert_eq!(stdout(&mut shell, line), "1x\n1z\n2x\n2z\n");
+        let line = "for a in 1 2; do for b in x y; do [ $b = y ] && continue 2; echo $a$b; done; echo never; done";
+        assert_eq!(stdout(&mut shell, line), "1x\n2x\n");
+        let line = "for a in 1 2; do while true; do echo $a; break 2; done; done; echo out";
+        assert_eq!(stdout(&mut shell, line), "1\nout\n");
+        // More levels than there are loops leaves them all
+        assert_eq!(stdout(&mut shell, "for a in 1 2; do echo $a; break 5; done"), "1\n");
+        assert_eq!(run(&mut shell, "break 0").1, "break: 0: loop count out of range\n");
+        assert_eq!(run(&mut shell, "continue").1, "continue: only meaningful in a `for', `while', or `until' loop\n");
+    }
+
+    #[test]
+    fn functions_take_positional_parameters_and_return_a_status() {
+        let mut shell = session();
+        stdout(&mut shell, "greet() { echo \"hello $1, $# of you: $@\"; return 3; echo never; }");
+        assert_eq!(stdout(&mut shell, "greet ann bob; echo $?"), "hello ann, 2 of you: ann bob\n3\n");
+        // The caller's parameters are back afterwards
+        stdout(&mut shell, "set -- outer");
+        assert_eq!(stdout(&mut shell, "greet inner > /dev/null; echo $1"), "outer\n");
+        assert_eq!(run(&mut shell, "return").1, "return: can only `return' from a function or sourced script\n");
+    }
+
+    #[test]
+    fn shift_and_set_change_the_positional_parameters() {
+        let mut shell = session();
+        assert_eq!(stdout(&mut shell, "set -- a b c; echo $# $1"), "3 a\n");
+        assert_eq!(stdout(&mut shell, "shift; echo $# $1"), "2 b\n");
+        assert_eq!(stdout(&mut shell, "shift 2; echo $# $@."), "0 .\n");
+        assert_eq!(run(&mut shell, "shift; echo $?"), ("1\n".to_string(), "shift: shift count out of range\n".to_string()));
+        assert_eq!(run(&mut shell, "shift x").1, "shift: x: numeric argument required\n");
+        assert_eq!(run(&mut shell, "set -e").1, "set: options are not supported; use set -- [arg...]\n");
+    }
+
+    #[test]
+    fn sourced_files_run_in_the_session_and_scripts_in_their_own() {
+        let mut shell = session();
+        write(&shell, "/home/vars.sh", "name=sourced\nshow() { echo \"$name $1\"; }\n");
+        assert_eq!(stdout(&mut shell, "source vars.sh; show it"), "sourced it\n");
+        assert_eq!(stdout(&mut shell, "name=changed; . vars.sh arg; echo $name"), "sourced\n");
+
+        write(&shell, "/home/script.sh", "name=script\necho \"$0: $name $1\"\nexit 4\n");
+        assert_eq!(stdout(&mut shell, "sh script.sh x; echo $? $name"), "script.sh: script x\n4 sourced\n");
+        assert_eq!(run(&mut shell, "source missing.sh; echo $?").0, "1\n");
+        assert_eq!(run(&mut shell, "source").1, "source: filename argument required\n");
+    }
+
+    #[test]
+    fn scripts_are_found_through_path_and_need_a_shell_in_their_shebang() {
+        let mut shell = session();
+        stdout(&mut shell, "mkdir /home/bin");
+        write(&shell, "/home/bin/hello", "#!/bin/sh\necho hello $1\n");
+        write(&shell, "/home/bin/env-hello", "#!/usr/bin/env bash\necho env\n");
+        write(&shell, "/home/bin/python", "#!/usr/bin/python3\nprint('no')\n");
+        write(&shell, "/home/plain", "echo plain\n");
+
+        assert_eq!(run(&mut shell, "hello; echo $?"), ("127\n".to_string(), "sh: hello: command not found\n".to_string()));
+        stdout(&mut shell, "PATH=/bin:/home/bin");
+        assert_eq!(stdout(&mut shell, "hello world"), "hello world\n");
+        assert_eq!(stdout(&mut shell, "env-hello"), "env\n");
+        assert_eq!(run(&mut shell, "python; echo $?"), ("126\n".to_string(), "sh: python: /usr/bin/python3: bad interpreter\n".to_string()));
+        // Paths aren't looked up, and scripts without a shebang are run too
+        assert_eq!(stdout(&mut shell, "./plain; /home/plain"), "plain\nplain\n");
+        assert_eq!(run(&mut shell, "./bin; echo $?"), ("126\n".to_string(), "sh: ./bin: Is a directory\n".to_string()));
+    }
+
+    #[test]
+    fn nesting_too_deep_is_an_error() {
+        let mut shell = session();
+        let (output, errors) = run(&mut shell, "down() { down; }; down; echo $?");
+        assert_eq!(output, "1\n");
+        assert_eq!(errors, "sh: down: maximum nesting level exceeded\n");
+
+        write(&shell, "/home/again.sh", "source again.sh\n");
+        let (_, errors) = run(&mut shell, "source again.sh");
+        assert_eq!(errors, "source: again.sh: maximum nesting level exceeded\n");
+    }
+}
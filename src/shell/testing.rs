@@ -0,0 +1,42 @@
+// What the tests of the shell and its commands share: sessions on a file
+// system in memory, and running lines in them
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::filesystem::FileSystem;
+use crate::storage::{MemoryStorage, SharedStorage};
+use crate::time::{ManualTime, SharedTimeSource};
+use super::{Output, Shell};
+
+pub(super) fn session() -> Shell {
+    Shell::new(Rc::new(RefCell::new(FileSystem::new().unwrap())))
+}
+
+// A session on a file system whose clock only moves when told to
+pub(super) fn timed_session() -> (Shell, Rc<ManualTime>) {
+    let time = Rc::new(ManualTime::new(1_000));
+    let storage = SharedStorage::new(MemoryStorage::default());
+    let fs = FileSystem::with_storage(storage, SharedTimeSource::from_rc(time.clone())).unwrap();
+    (Shell::new(Rc::new(RefCell::new(fs))), time)
+}
+
+// What a line wrote to stdout and to stderr
+pub(super) fn run(shell: &mut Shell, line: &str) -> (String, String) {
+    let (mut stdout, mut stderr) = (String::new(), String::new());
+    for output in shell.run(line) {
+        match output {
+            Output::Stdout(text) => stdout.push_str(&text),
+            Output::Stderr(text) => stderr.push_str(&text),
+            Output::Clear => {}
+        }
+    }
+    (stdout, stderr)
+}
+
+pub(super) fn stdout(shell: &mut Shell, line: &str) -> String {
+    run(shell, line).0
+}
+
+pub(super) fn stderr(shell: &mut Shell, line: &str) -> String {
+    run(shell, line).1
+}
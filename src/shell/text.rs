@@ -0,0 +1,930 @@
+// Utilities that pick text apart with regular expressions. Patterns use the
+// syntax of the regex crate, which is close to POSIX extended expressions.
+use std::rc::Rc;
+use regex::{Regex, RegexBuilder};
+use crate::filesystem::FileType;
+use super::ansi;
+use super::command::{Builtin, CommandRegistry};
+use super::utilities::{describe, input_name, inputs, join, read_input, usage_error, Arguments};
+use super::{Io, Shell, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+
+// How grep colours its output on the terminal, as GNU grep does by default
+const MATCH_COLOR: &str = "01;31";
+const FILE_NAME_COLOR: &str = "35";
+const LINE_NUMBER_COLOR: &str = "32";
+const SEPARATOR_COLOR: &str = "36";
+
+pub(super) fn register(registry: &mut CommandRegistry) {
+    let utilities = [
+        Builtin {
+            name: "grep",
+            usage: "grep [-i] [-v] [-n] [-r] [-l] [-c] pattern [file...]",
+            takes_paths: true,
+            help: "Print lines that match a regular expression\n\
+                Without files, reads the input; with -r, searches the working directory.\n\
+                On the terminal, matches are highlighted and file names can be clicked\n\
+                to open them in the Text Editor.\n\
+                -i          ignore case\n\
+                -v          print the lines that don't match\n\
+                -n          number the lines\n\
+                -r, -R      search directories and everything in them\n\
+                -l          only print the names of files with matches\n\
+                -c          only print how many lines match in each file\n\
+                -H, -h      always or never print file names with lines\n\
+                -F          take the pattern as plain text\n\
+                -E          use extended expressions, which is all grep has\n\
+                -e pattern  the pattern, even if it starts with -; may be repeated",
+            run: grep,
+        },
+        Builtin {
+            name: "sed",
+            usage: "sed [-n] [-i] [-e script]... [script] [file...]",
+            takes_paths: true,
+            help: "Edit lines as a script says\n\
+                Without files, reads the input. A script is commands separated by ; or\n\
+                newlines, each run on the lines its address selects, or on every line:\n\
+                N          line N\n\
+                $          the last line\n\
+                /regex/    lines that match, or with /regex/I ignoring case\n\
+                A,B        lines from one that A selects to one that B does\n\
+                A!         lines that A doesn't select\n\
+                The commands are:\n\
+                s/regex/replacement/flags  replace the first match; & in the replacement\n\
+                           is the match and \\1 to \\9 its groups. The flags are g (every\n\
+                           match), N (the Nth), p (print if replaced) and i (ignore case).\n\
+                d          delete the line\n\
+                p          print the line\n\
+                =          print the line number\n\
+                q          print the line and stop\n\
+                -n         only print what p says to\n\
+                -i         edit the files in place\n\
+                -E, -r     use extended expressions, which is all sed has\n\
+                -e script  a script to run; may be repeated",
+            run: sed,
+        },
+        Builtin {
+            name: "awk",
+            usage: "awk [-F sep] [-v name=value]... program [file...]",
+            takes_paths: true,
+            help: "Run a program over the fields of each line\n\
+                Without files, reads the input. A program is rules like pattern { action },\n\
+                where either part may be left out: a rule without a pattern runs on every\n\
+                line, and one without an action prints the lines its pattern selects.\n\
+                BEGIN and END rules run before and after the input.\n\
+                $0 is the line and $1, $2 and on its fields, split at blanks or at FS.\n\
+                NR and NF are the line and field numbers, and OFS joins what print prints.\n\
+                Patterns and actions use numbers, \"strings\", variables, + - * / %,\n\
+                comparisons, ~ and !~ to match a /regex/, && || !, = += -= ++ --, the\n\
+                functions length, substr, index, tolower, toupper and sprintf, and the\n\
+                statements print, printf, if/else, next and exit. There are no loops or\n\
+                arrays, and records have at most 32767 fields.\n\
+                -F sep         split fields at sep, a character or a regex\n\
+                -v name=value  set a variable before the program runs",
+            run: super::awk::awk,
+        },
+    ];
+    for utility in utilities {
+        registry.register(Rc::new(utility));
+    }
+}
+
+struct Grep {
+    pattern: Regex,
+    invert: bool,
+    line_numbers: bool,
+    files_with_matches: bool,
+    count: bool,
+    file_names: bool,
+    // Whether to colour the output and link file names
+    terminal: bool,
+}
+
+fn grep(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "ivnrRlcHhFE", "e") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "grep", &e),
+    };
+
+    // Without -e, the first operand is the pattern
+    let mut operands = arguments.operands.clone();
+    let mut patterns: Vec<&str> = arguments.options.iter()
+        .filter(|(option, _)| *option == 'e')
+        .filter_map(|(_, pattern)| *pattern)
+        .collect();
+    if patterns.is_empty() {
+        if operands.is_empty() {
+            return usage_error(io, "grep", "no pattern given");
+        }
+        patterns.push(operands.remove(0));
+    }
+
+    let patterns: Vec<String> = patterns.iter()
+        .map(|pattern| if arguments.has('F') { regex::escape(pattern) } else { pattern.to_string() })
+        .map(|pattern| format!("(?:{})", pattern))
+        .collect();
+    let pattern = match RegexBuilder::new(&patterns.join("|")).case_insensitive(arguments.has('i')).build() {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            io.eprintln(&format!("grep: {}", regex_error(&e)));
+            return EXIT_USAGE;
+        }
+    };
+
+    // grep -r searches the working directory, naming files relative to it
+    let recursive = arguments.has('r') || arguments.has('R');
+    let working_directory = recursive && operands.is_empty();
+    if working_directory {
+        operands.push(".");
+    }
+    let operands = inputs(&operands);
+    let file_names = match arguments.options.iter().rev().find(|(option, _)| matches!(option, 'H' | 'h')) {
+        Some(('H', _)) => true,
+        Some(_) => false,
+        None => recursive || operands.len() > 1,
+    };
+    let grep = Grep {
+        pattern,
+        invert: arguments.has('v'),
+        line_numbers: arguments.has('n'),
+        files_with_matches: arguments.has('l'),
+        count: arguments.has('c'),
+        file_names,
+        terminal: io.terminal,
+    };
+
+    let mut matched = false;
+    let mut failed = false;
+    for operand in operands {
+        let path = shell.resolve_path(operand);
+        let is_directory = operand != "-" && shell.fs.borrow().metadata(&path)
+            .is_ok_and(|metadata| metadata.file_type == FileType::Directory);
+        if is_directory && recursive {
+            let name = if working_directory { "" } else { operand };
+            grep.search_directory(shell, io, name, &path, &mut matched, &mut failed);
+            continue;
+        }
+        match read_input(shell, io, operand) {
+            Ok(contents) => matched |= grep.search(io, input_name(operand), &path, operand != "-", &contents),
+            Err(e) => {
+                io.eprintln(&format!("grep: {}: {}", operand, e));
+                failed = true;
+            }
+        }
+    }
+
+    // As in GNU grep, an error counts for more than a match
+    if failed {
+        EXIT_USAGE
+    } else if matched {
+        EXIT_SUCCESS
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+impl Grep {
+    // Search every file under a directory, in order of name. `name` is how
+    // it was given, empty for the working directory, and `path` where it is.
+    fn search_directory(&self, shell: &Shell, io: &mut Io, name: &str, path: &str, matched: &mut bool, failed: &mut bool) {
+        let mut entries = match shell.fs.borrow().list_directory(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                io.eprintln(&format!("grep: {}: {}", name, describe(&e)));
+                *failed = true;
+                return;
+            }
+        };
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        for entry in entries {
+            let entry_name = if name.is_empty() { entry.name.clone() } else { join(name, &entry.name) };
+            let entry_path = join(path, &entry.name);
+            match entry.file_type {
+                FileType::Directory => self.search_directory(shell, io, &entry_name, &entry_path, matched, failed),
+                FileType::File => match shell.fs.borrow().read_file(&entry_path) {
+                    Ok(contents) => *matched |= self.search(io, &entry_name, &entry_path, true, &contents),
+                    Err(e) => {
+                        io.eprintln(&format!("grep: {}: {}", entry_name, describe(&e)));
+                        *failed = true;
+                    }
+                },
+            }
+        }
+    }
+
+    // Print what grep has to say about the contents of one input, and return
+    // whether any line was selected. Only files (not the input) have a path
+    // to link to.
+    fn search(&self, io: &mut Io, name: &str, path: &str, is_file: bool, contents: &str) -> bool {
+        let link = if is_file { Some(path) } else { None };
+        let selected = contents.lines()
+            .enumerate()
+            .filter(|(_, line)| self.pattern.is_match(line) != self.invert);
+
+        if self.files_with_matches {
+            let found = selected.count() > 0;
+            if found {
+                io.println(&self.file_name(name, link));
+            }
+            return found;
+        }
+        if self.count {
+            let count = selected.count();
+            let prefix = if self.file_names { format!("{}{}", self.file_name(name, link), self.separator()) } else { String::new() };
+            io.println(&format!("{}{}", prefix, count));
+            return count > 0;
+        }
+
+        let mut found = false;
+        for (number, line) in selected {
+            found = true;
+            let mut prefix = String::new();
+            if self.file_names {
+                prefix.push_str(&self.file_name(name, None));
+                prefix.push_str(&self.separator());
+            }
+            if self.line_numbers {
+                prefix.push_str(&self.paint(&(number + 1).to_string(), LINE_NUMBER_COLOR));
+                prefix.push_str(&self.separator());
+            }
+            // The file name and line number open the file
+            if let Some(path) = link.filter(|_| self.terminal && !prefix.is_empty()) {
+                prefix = ansi::link(&file_uri(path), &prefix);
+            }
+            io.println(&format!("{}{}", prefix, self.highlight(line)));
+        }
+        found
+    }
+
+    // The name of an input, linking to the file at `link` if there is one
+    fn file_name(&self, name: &str, link: Option<&str>) -> String {
+        let name = self.paint(name, FILE_NAME_COLOR);
+        match link.filter(|_| self.terminal) {
+            Some(path) => ansi::link(&file_uri(path), &name),
+            None => name,
+        }
+    }
+
+    fn separator(&self) -> String {
+        self.paint(":", SEPARATOR_COLOR)
+    }
+
+    fn paint(&self, text: &str, color: &str) -> String {
+        if self.terminal { ansi::paint(text, color) } else { text.to_string() }
+    }
+
+    // A selected line with what matched in it highlighted. Lines selected by
+    // -v have nothing to highlight.
+    fn highlight(&self, line: &str) -> String {
+        if !self.terminal || self.invert {
+            return line.to_string();
+        }
+        let mut highlighted = String::new();
+        let mut end = 0;
+        for found in self.pattern.find_iter(line).filter(|found| !found.is_empty()) {
+            highlighted.push_str(&line[end..found.start()]);
+            highlighted.push_str(&ansi::paint(found.as_str(), MATCH_COLOR));
+            end = found.end();
+        }
+        highlighted.push_str(&line[end..]);
+        highlighted
+    }
+}
+
+// The gist of a regex error, which otherwise spans several lines to show
+// where in the pattern it is
+fn regex_error(error: &regex::Error) -> String {
+    let message = error.to_string();
+    let last = message.lines().last().unwrap_or_default();
+    last.strip_prefix("error: ").unwrap_or(last).to_string()
+}
+
+// Where a file links to, which the terminal opens in the Text Editor
+fn file_uri(path: &str) -> String {
+    format!("file://{}", path)
+}
+
+// A line, or lines, that sed commands apply to
+enum Address {
+    Line(usize),
+    // $
+    Last,
+    Pattern(Regex),
+}
+
+// What follows the address in the replacement of s: text, or a group of
+// the match, with 0 for all of it
+enum Replacement {
+    Text(String),
+    Group(usize),
+}
+
+enum SedAction {
+    Substitute {
+        pattern: Regex,
+        replacement: Vec<Replacement>,
+        // Which match to replace, counting from 1; with `global`, the first
+        occurrence: usize,
+        global: bool,
+        print: bool,
+    },
+    Delete,
+    Print,
+    Quit,
+    LineNumber,
+}
+
+struct SedCommand {
+    // One address, or the first and last of a range
+    start: Option<Address>,
+    end: Option<Address>,
+    // !, which applies the command to the lines the addresses don't select
+    negated: bool,
+    action: SedAction,
+}
+
+// Reads one -e expression of a sed script
+struct SedParser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    case_insensitive: bool,
+}
+
+impl SedParser<'_> {
+    fn parse(script: &str) -> Result<Vec<SedCommand>, String> {
+        let mut parser = SedParser { chars: script.char_indices().peekable(), case_insensitive: false };
+        let mut commands = Vec::new();
+        loop {
+            parser.skip(|c| c.is_whitespace() || c == ';');
+            if parser.chars.peek().is_none() {
+                return Ok(commands);
+            }
+            commands.push(parser.command().map_err(|e| format!("char {}: {}", parser.position(script), e))?);
+        }
+    }
+
+    // Characters read so far, which GNU sed reports errors at
+    fn position(&mut self, script: &str) -> usize {
+        let offset = self.chars.peek().map_or(script.len(), |(i, _)| *i);
+        script[..offset].chars().count()
+    }
+
+    fn skip(&mut self, skip: impl Fn(char) -> bool) {
+        while self.chars.next_if(|(_, c)| skip(*c)).is_some() {}
+    }
+
+    fn command(&mut self) -> Result<SedCommand, String> {
+        let start = self.address()?;
+        let end = match (&start, self.chars.next_if(|(_, c)| *c == ',')) {
+            (Some(_), Some(_)) => Some(self.address()?.ok_or("unexpected `,'")?),
+            (None, Some(_)) => return Err("unexpected `,'".to_string()),
+            (_, None) => None,
+        };
+        self.skip(char::is_whitespace);
+        let negated = self.chars.next_if(|(_, c)| *c == '!').is_some();
+        self.skip(char::is_whitespace);
+
+        let action = match self.chars.next().map(|(_, c)| c) {
+            None => return Err("missing command".to_string()),
+            Some('s') => self.substitute()?,
+            Some('d') => SedAction::Delete,
+            Some('p') => SedAction::Print,
+            Some('q') => SedAction::Quit,
+            Some('=') => SedAction::LineNumber,
+            Some(c) => return Err(format!("unknown command: `{}'", c)),
+        };
+
+        // A command ends at ; or the end of the line
+        self.skip(|c| c == ' ' || c == '\t');
+        match self.chars.next() {
+            None | Some((_, ';' | '\n')) => Ok(SedCommand { start, end, negated, action }),
+            Some((_, c)) => Err(format!("extra characters after command: `{}'", c)),
+        }
+    }
+
+    fn address(&mut self) -> Result<Option<Address>, String> {
+        match self.chars.peek().map(|(_, c)| *c) {
+            Some('$') => {
+                self.chars.next();
+                Ok(Some(Address::Last))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some((_, digit)) = self.chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    number.push(digit);
+                }
+                match number.parse() {
+                    Ok(0) | Err(_) => Err("invalid usage of line address 0".to_string()),
+                    Ok(line) => Ok(Some(Address::Line(line))),
+                }
+            }
+            Some('/') => {
+                self.chars.next();
+                let pattern = self.delimited('/').ok_or("unterminated address regex")?;
+                let case_insensitive = self.chars.next_if(|(_, c)| *c == 'I').is_some();
+                Ok(Some(Address::Pattern(self.regex(&pattern, case_insensitive)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Text up to an unescaped `delimiter`, with the backslash taken off
+    // escaped delimiters and newlines made of \n
+    fn delimited(&mut self, delimiter: char) -> Option<String> {
+        let mut text = String::new();
+        while let Some((_, c)) = self.chars.next() {
+            match c {
+                c if c == delimiter => return Some(text),
+                '\\' => match self.chars.next()?.1 {
+                    c if c == delimiter => text.push(c),
+                    'n' => text.push('\n'),
+                    c => {
+                        text.push('\\');
+                        text.push(c);
+                    }
+                },
+                c => text.push(c),
+            }
+        }
+        None
+    }
+
+    fn regex(&self, pattern: &str, case_insensitive: bool) -> Result<Regex, String> {
+        if pattern.is_empty() {
+            return Err("no previous regular expression".to_string());
+        }
+        RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive || self.case_insensitive)
+            .build()
+            .map_err(|e| regex_error(&e))
+    }
+
+    // s/pattern/replacement/flags, after the s
+    fn substitute(&mut self) -> Result<SedAction, String> {
+        let unterminated = || "unterminated `s' command".to_string();
+        let delimiter = match self.chars.next() {
+            Some((_, c)) if c != '\n' && c != '\\' => c,
+            _ => return Err(unterminated()),
+        };
+        let pattern = self.delimited(delimiter).ok_or_else(unterminated)?;
+        let replacement = self.delimited(delimiter).ok_or_else(unterminated)?;
+
+        let (mut occurrence, mut global, mut print, mut case_insensitive) = (None, false, false, false);
+        while let Some((_, flag)) = self.chars.next_if(|(_, c)| !matches!(c, ';' | '\n' | ' ' | '\t' | '}')) {
+            // Each flag may be given once, as in GNU sed
+            let repeated = match flag {
+                'g' => std::mem::replace(&mut global, true),
+                'p' => std::mem::replace(&mut print, true),
+                'i' | 'I' => {
+                    case_insensitive = true;
+                    false
+                }
+                c if c.is_ascii_digit() => {
+                    let mut number = c.to_string();
+                    while let Some((_, digit)) = self.chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                        number.push(digit);
+                    }
+                    let number = number.parse().ok().filter(|n| *n > 0)
+                        .ok_or("number option to `s' command may not be zero")?;
+                    occurrence.replace(number).is_some()
+                }
+                _ => return Err("unknown option to `s'".to_string()),
+            };
+            if repeated {
+                let option = if flag.is_ascii_digit() { "number".to_string() } else { format!("`{}'", flag) };
+                return Err(format!("multiple {} options to `s' command", option));
+            }
+        }
+        let occurrence = occurrence.unwrap_or(1);
+
+        let pattern = self.regex(&pattern, case_insensitive)?;
+        let replacement = parse_replacement(&replacement);
+        if let Some(group) = replacement.iter().find_map(|piece| match piece {
+            Replacement::Group(group) if *group >= pattern.captures_len() => Some(*group),
+            _ => None,
+        }) {
+            return Err(format!("invalid reference \\{} on `s' command's RHS", group));
+        }
+        Ok(SedAction::Substitute { pattern, replacement, occurrence, global, print })
+    }
+}
+
+// The replacement of s: & is the match, \1 to \9 its groups
+fn parse_replacement(text: &str) -> Vec<Replacement> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let group = match c {
+            '&' => Some(0),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => digit.to_digit(10).map(|digit| digit as usize),
+                Some(c) => {
+                    literal.push(c);
+                    None
+                }
+                None => None,
+            },
+            c => {
+                literal.push(c);
+                None
+            }
+        };
+        if let Some(group) = group {
+            if !literal.is_empty() {
+                pieces.push(Replacement::Text(std::mem::take(&mut literal)));
+            }
+            pieces.push(Replacement::Group(group));
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(Replacement::Text(literal));
+    }
+    pieces
+}
+
+struct Sed {
+    commands: Vec<SedCommand>,
+    quiet: bool,
+}
+
+impl Sed {
+    // Run the script over the lines of `contents`, returning what it prints
+    // and whether it quit
+    fn run(&self, contents: &str) -> (String, bool) {
+        let mut output = String::new();
+        // Whether each range has started and not yet ended
+        let mut in_range = vec![false; self.commands.len()];
+        let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+
+        for (i, line) in lines.iter().enumerate() {
+            let number = i + 1;
+            let last = number == lines.len();
+            let (text, newline) = match line.strip_suffix('\n') {
+                Some(text) => (text, "\n"),
+                None => (*line, ""),
+            };
+            let mut space = text.to_string();
+            let mut deleted = false;
+            let mut quit = false;
+
+            for (command, in_range) in self.commands.iter().zip(in_range.iter_mut()) {
+                if command.selects(number, last, &space, in_range) == command.negated {
+                    continue;
+                }
+                match &command.action {
+                    SedAction::Substitute { pattern, replacement, occurrence, global, print } => {
+                        if let Some(replaced) = substitute(&space, pattern, replacement, *occurrence, *global) {
+                            space = replaced;
+                            if *print {
+                                output.push_str(&format!("{}{}", space, newline));
+                            }
+                        }
+                    }
+                    SedAction::Delete => {
+                        deleted = true;
+                        break;
+                    }
+                    SedAction::Print => output.push_str(&format!("{}{}", space, newline)),
+                    SedAction::LineNumber => output.push_str(&format!("{}\n", number)),
+                    SedAction::Quit => {
+                        quit = true;
+                        break;
+                    }
+                }
+            }
+
+            if !deleted && !self.quiet {
+                output.push_str(&format!("{}{}", space, newline));
+            }
+            if quit {
+                return (output, true);
+            }
+        }
+        (output, false)
+    }
+}
+
+impl SedCommand {
+    // Whether the addresses select this line, keeping track of the range
+    fn selects(&self, number: usize, last: bool, line: &str, in_range: &mut bool) -> bool {
+        let matches = |address: &Address| match address {
+            Address::Line(line_number) => *line_number == number,
+            Address::Last => last,
+            Address::Pattern(pattern) => pattern.is_match(line),
+        };
+        let (start, end) = match (&self.start, &self.end) {
+            (None, _) => return true,
+            (Some(start), None) => return matches(start),
+            (Some(start), Some(end)) => (start, end),
+        };
+
+        if *in_range {
+            // A line number already passed ends the range at once
+            let ended = match end {
+                Address::Line(line_number) => number >= *line_number,
+                end => matches(end),
+            };
+            *in_range = !ended;
+            return true;
+        }
+        if matches(start) {
+            // The end is only looked for from the next line, unless it's a
+            // line number that isn't after this one
+            *in_range = match end {
+                Address::Line(line_number) => *line_number > number,
+                Address::Last => !last,
+                Address::Pattern(_) => true,
+            };
+            return true;
+        }
+        false
+    }
+}
+
+// `line` with the chosen matches of `pattern` replaced, or None if nothing was
+fn substitute(line: &str, pattern: &Regex, replacement: &[Replacement], occurrence: usize, global: bool) -> Option<String> {
+    let mut result = String::new();
+    let mut end = 0;
+    let mut replaced = false;
+    for (i, captures) in pattern.captures_iter(line).enumerate() {
+        if i + 1 < occurrence {
+            continue;
+        }
+        let found = captures.get(0)?;
+        result.push_str(&line[end..found.start()]);
+        for piece in replacement {
+            match piece {
+                Replacement::Text(text) => result.push_str(text),
+                Replacement::Group(group) => result.push_str(captures.get(*group).map_or("", |group| group.as_str())),
+            }
+        }
+        end = found.end();
+        replaced = true;
+        if !global {
+            break;
+        }
+    }
+    if !replaced {
+        return None;
+    }
+    result.push_str(&line[end..]);
+    Some(result)
+}
+
+fn sed(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "niErs", "e") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "sed", &e),
+    };
+
+    // Without -e, the first operand is the script
+    let mut operands = arguments.operands.clone();
+    let mut expressions: Vec<&str> = arguments.options.iter()
+        .filter(|(option, _)| *option == 'e')
+        .filter_map(|(_, expression)| *expression)
+        .collect();
+    if expressions.is_empty() {
+        if operands.is_empty() {
+            return usage_error(io, "sed", "no script specified");
+        }
+        expressions.push(operands.remove(0));
+    }
+
+    let mut commands = Vec::new();
+    for (i, expression) in expressions.iter().enumerate() {
+        match SedParser::parse(expression) {
+            Ok(parsed) => commands.extend(parsed),
+            Err(e) => {
+                io.eprintln(&format!("sed: -e expression #{}, {}", i + 1, e));
+                return EXIT_FAILURE;
+            }
+        }
+    }
+    let sed = Sed { commands, quiet: arguments.has('n') };
+
+    let in_place = arguments.has('i');
+    if in_place && operands.is_empty() {
+        io.eprintln("sed: no input files");
+        return EXIT_FAILURE;
+    }
+
+    // Files are edited one by one in place; otherwise they run together, so
+    // that line numbers go on from one to the next and $ is the last line of
+    // the last file
+    let mut status = EXIT_SUCCESS;
+    let mut stream = String::new();
+    for operand in inputs(&operands) {
+        let contents = match read_input(shell, io, operand) {
+            Ok(contents) => contents,
+            Err(e) => {
+                io.eprintln(&format!("sed: can't read {}: {}", operand, e));
+                status = EXIT_USAGE;
+                continue;
+            }
+        };
+        if !in_place {
+            if !stream.is_empty() && !stream.ends_with('\n') {
+                stream.push('\n');
+            }
+            stream.push_str(&contents);
+            continue;
+        }
+
+        let (edited, _) = sed.run(&contents);
+        let path = shell.resolve_path(operand);
+        let result = shell.fs.borrow_mut().write_file(&path, &edited);
+        if let Err(e) = result {
+            io.eprintln(&format!("sed: couldn't edit {}: {}", operand, describe(&e)));
+            status = EXIT_FAILURE;
+        }
+    }
+    if !in_place {
+        io.print(&sed.run(&stream).0);
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::Shell;
+    use crate::shell::testing::{run, session, stdout};
+
+    // A session in /home/fruit, which holds a, b and basket/c
+    fn fruit() -> Shell {
+        let mut shell = session();
+        stdout(&mut shell, "mkdir fruit fruit/basket; cd fruit; echo apple > a; echo Banana >> a; echo cherry >> a");
+        stdout(&mut shell, "echo banana split > b; echo apple pie > basket/c");
+        shell
+    }
+
+    #[test]
+    fn grep_selects_lines_as_its_options_say() {
+        let mut shell = fruit();
+        assert_eq!(stdout(&mut shell, "grep an a"), "Banana\n");
+        assert_eq!(stdout(&mut shell, "grep -i b a"), "Banana\n");
+        assert_eq!(stdout(&mut shell, "grep -v an a"), "apple\ncherry\n");
+        assert_eq!(stdout(&mut shell, "grep -n e a"), "1:apple\n3:cherry\n");
+        assert_eq!(stdout(&mut shell, "grep -e apple -e cherry a"), "apple\ncherry\n");
+        assert_eq!(stdout(&mut shell, "grep -e -x a; echo $?"), "1\n");
+        assert_eq!(stdout(&mut shell, "echo abc | grep -F a.c; echo a.c | grep -F a.c"), "a.c\n");
+        assert_eq!(stdout(&mut shell, "echo abc | grep a.c"), "abc\n");
+        assert_eq!(stdout(&mut shell, "cat a | grep -c a"), "2\n");
+    }
+
+    #[test]
+    fn grep_names_files_when_there_are_several() {
+        let mut shell = fruit();
+        assert_eq!(stdout(&mut shell, "grep an a b"), "a:Banana\nb:banana split\n");
+        assert_eq!(stdout(&mut shell, "grep -h an a b"), "Banana\nbanana split\n");
+        assert_eq!(stdout(&mut shell, "grep -H an a"), "a:Banana\n");
+        assert_eq!(stdout(&mut shell, "grep -l an a b basket/c"), "a\nb\n");
+        assert_eq!(stdout(&mut shell, "grep -c an a b basket/c"), "a:1\nb:1\nbasket/c:0\n");
+        assert_eq!(stdout(&mut shell, "echo banana | grep -H an - b"), "standard input:banana\nb:banana split\n");
+    }
+
+    #[test]
+    fn grep_r_names_files_relative_to_where_it_searched() {
+        let mut shell = fruit();
+        assert_eq!(stdout(&mut shell, "grep -r apple"), "a:apple\nbasket/c:apple pie\n");
+        assert_eq!(stdout(&mut shell, "grep -r apple basket"), "basket/c:apple pie\n");
+        assert_eq!(stdout(&mut shell, "cd ..; grep -rl apple fruit; cd fruit"), "fruit/a\nfruit/basket/c\n");
+        assert_eq!(stdout(&mut shell, "grep -rh pie ."), "apple pie\n");
+        // Without -r, a directory can't be read
+        assert_eq!(run(&mut shell, "grep apple basket; echo $?"), ("2\n".to_string(), "grep: basket: Is a directory\n".to_string()));
+    }
+
+    #[test]
+    fn grep_exits_with_whether_it_matched_or_failed() {
+        let mut shell = fruit();
+        assert_eq!(stdout(&mut shell, "grep apple a > found; echo $?"), "0\n");
+        assert_eq!(stdout(&mut shell, "grep kiwi a; echo $?"), "1\n");
+        assert_eq!(run(&mut shell, "grep apple missing a; echo $?"), ("a:apple\n2\n".to_string(), "grep: missing: No such file or directory\n".to_string()));
+        assert_eq!(run(&mut shell, "grep '(' a; echo $?").0, "2\n");
+        assert_eq!(run(&mut shell, "grep; echo $?").0, "2\n");
+    }
+
+    #[test]
+    fn grep_highlights_matches_and_links_files_on_the_terminal() {
+        let mut shell = fruit();
+        shell.set_terminal(true);
+        let (bold_red, magenta, green, cyan, normal) = ("\x1b[01;31m", "\x1b[35m", "\x1b[32m", "\x1b[36m", "\x1b[m");
+        let link = |uri: &str, text: &str| format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", uri, text);
+
+        assert_eq!(stdout(&mut shell, "grep an a"), format!("B{bold_red}an{normal}{bold_red}an{normal}a\n"));
+        let prefix = format!("{magenta}b{normal}{cyan}:{normal}{green}1{normal}{cyan}:{normal}");
+        assert_eq!(
+            stdout(&mut shell, "grep -Hn split b"),
+            format!("{}banana {bold_red}split{normal}\n", link("file:///home/fruit/b", &prefix)),
+        );
+        assert_eq!(stdout(&mut shell, "grep -l pie basket/c"), format!("{}\n", link("file:///home/fruit/basket/c", &format!("{magenta}basket/c{normal}"))));
+        // Lines -v selects have nothing to highlight, and the input has no file to link to
+        assert_eq!(stdout(&mut shell, "grep -v an a"), "apple\ncherry\n");
+        assert_eq!(stdout(&mut shell, "echo kiwi | grep -H i"), format!("{magenta}standard input{normal}{cyan}:{normal}k{bold_red}i{normal}w{bold_red}i{normal}\n"));
+        // Nor is anything coloured when the output is piped
+        assert_eq!(stdout(&mut shell, "grep -n an a | cat"), "2:Banana\n");
+    }
+
+    #[test]
+    fn sed_replaces_the_first_match_or_the_ones_the_flags_say() {
+        let mut shell = session();
+        assert_eq!(stdout(&mut shell, "echo aaa | sed s/a/b/"), "baa\n");
+        assert_eq!(stdout(&mut shell, "echo aaa | sed s/a/b/g"), "bbb\n");
+        assert_eq!(stdout(&mut shell, "echo aaa | sed s/a/b/2"), "aba\n");
+        assert_eq!(stdout(&mut shell, "echo aaa | sed s/a/b/2g"), "abb\n");
+        assert_eq!(stdout(&mut shell, "echo AaA | sed s/a/b/Ig"), "bbb\n");
+        assert_eq!(stdout(&mut shell, "echo AaA | sed s/a/b/i"), "baA\n");
+    }
+
+    #[test]
+    fn sed_prints_replaced_lines_with_p() {
+        let mut shell = session();
+        stdout(&mut shell, "echo one > lines; echo two >> lines");
+        assert_eq!(stdout(&mut shell, "sed -n s/o/0/p lines"), "0ne\ntw0\n");
+        assert_eq!(stdout(&mut shell, "sed -n s/n/N/gp lines"), "oNe\n");
+        assert_eq!(stdout(&mut shell, "sed s/t/T/p lines"), "one\nTwo\nTwo\n");
+    }
+
+    #[test]
+    fn sed_replacements_refer_to_the_match_and_its_groups() {
+        let mut shell = session();
+        assert_eq!(stdout(&mut shell, "echo 'John Smith' | sed 's/(\\w+) (\\w+)/\\2, \\1/'"), "Smith, John\n");
+        assert_eq!(stdout(&mut shell, "echo abc | sed 's/b/[&]/'"), "a[b]c\n");
+        assert_eq!(stdout(&mut shell, "echo a/b | sed 's|/|\\\\|'"), "a\\b\n");
+    }
+
+    #[test]
+    fn sed_rejects_unknown_and_repeated_flags() {
+        let mut shell = session();
+        for (script, error) in [
+            ("s/a/b/x", "char 7: unknown option to `s'"),
+            ("s/a/b/w", "char 7: unknown option to `s'"),
+            ("s/a/b/gg", "char 8: multiple `g' options to `s' command"),
+            ("s/a/b/pgp", "char 9: multiple `p' options to `s' command"),
+            ("s/a/b/1g2", "char 9: multiple number options to `s' command"),
+            ("s/a/b/0", "char 7: number option to `s' command may not be zero"),
+            ("s/a/b/g x", "char 9: extra characters after command: `x'"),
+        ] {
+            let (output, errors) = run(&mut shell, &format!("echo a | sed '{}'", script));
+            assert_eq!(output, "", "{}", script);
+            assert_eq!(errors, format!("sed: -e expression #1, {}\n", error), "{}", script);
+        }
+        assert_eq!(stdout(&mut shell, "echo a | sed 's/a/b/x'; echo $?"), "1\n");
+    }
+
+    // A session with the file nums holding the lines 1 to 6
+    fn numbers() -> Shell {
+        let mut shell = session();
+        stdout(&mut shell, "for n in 1 2 3 4 5 6; do echo $n; done > nums");
+        shell
+    }
+
+    // What sed with `arguments` prints of nums, on one line
+    fn sed(shell: &mut Shell, arguments: &str) -> String {
+        stdout(shell, &format!("sed {} nums", arguments)).replace('\n', " ")
+    }
+
+    #[test]
+    fn sed_addresses_select_lines_and_ranges() {
+        let mut shell = numbers();
+        assert_eq!(sed(&mut shell, "-n 2p"), "2 ");
+        assert_eq!(sed(&mut shell, "-n '$p'"), "6 ");
+        assert_eq!(sed(&mut shell, "-n /[35]/p"), "3 5 ");
+        assert_eq!(sed(&mut shell, "-n 2,4p"), "2 3 4 ");
+        assert_eq!(sed(&mut shell, "-n '4,$p'"), "4 5 6 ");
+        assert_eq!(sed(&mut shell, "-n /2/,/4/p"), "2 3 4 ");
+        // An end already passed selects only the start, and an end pattern is
+        // only looked for from the line after it
+        assert_eq!(sed(&mut shell, "-n 3,1p"), "3 ");
+        assert_eq!(sed(&mut shell, "-n /3/,/[0-9]/p"), "3 4 ");
+        // Ranges start again after they end
+        assert_eq!(sed(&mut shell, "-n '/[15]/,/[26]/p'"), "1 2 5 6 ");
+    }
+
+    #[test]
+    fn sed_negates_deletes_quits_and_numbers_lines() {
+        let mut shell = numbers();
+        assert_eq!(sed(&mut shell, "-n '2,5!p'"), "1 6 ");
+        assert_eq!(sed(&mut shell, "2,5d"), "1 6 ");
+        assert_eq!(sed(&mut shell, "'/[246]/!d'"), "2 4 6 ");
+        assert_eq!(sed(&mut shell, "3q"), "1 2 3 ");
+        assert_eq!(sed(&mut shell, "-n '$='"), "6 ");
+        assert_eq!(sed(&mut shell, "-n '/4/=;/4/p'"), "4 4 ");
+        assert_eq!(sed(&mut shell, "-e 1d -e 's/2/two/' -e 3q"), "two 3 ");
+    }
+
+    #[test]
+    fn sed_runs_files_together_unless_editing_them_in_place() {
+        let mut shell = session();
+        stdout(&mut shell, "echo a > one; echo b >> one; echo c > two");
+        // Line numbers go on from one file to the next, and $ is the last of all
+        assert_eq!(stdout(&mut shell, "sed -n '2,$p' one two"), "b\nc\n");
+        assert_eq!(stdout(&mut shell, "sed 1d one - < two"), "b\nc\n");
+        assert_eq!(stdout(&mut shell, "sed 2q one two"), "a\nb\n");
+
+        // With -i, each file is edited on its own
+        assert_eq!(run(&mut shell, "sed -i '$s/$/!/' one two"), (String::new(), String::new()));
+        assert_eq!(stdout(&mut shell, "cat one two"), "a\nb!\nc!\n");
+        assert_eq!(run(&mut shell, "sed -i 1d missing one; echo $?"), ("2\n".to_string(), "sed: can't read missing: No such file or directory\n".to_string()));
+        assert_eq!(stdout(&mut shell, "cat one"), "b!\n");
+        assert_eq!(run(&mut shell, "sed -i 1d"), (String::new(), "sed: no input files\n".to_string()));
+    }
+}
@@ -0,0 +1,1172 @@
+// File utilities modelled on their GNU counterparts: same flags where they
+// make sense here, and the same error messages
+use std::cmp::Ordering;
+use std::rc::Rc;
+use crate::filesystem::{ErrorKind, FileMetadata, FileTimes, FileType, FsError, ListOptions};
+use crate::time::local_time;
+use super::command::{Builtin, CommandRegistry};
+use super::{Io, Shell, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+
+pub(super) fn register(registry: &mut CommandRegistry) {
+    let utilities = [
+        Builtin {
+            name: "cp",
+            usage: "cp [-r] [-n] [-p] [-v] source... dest",
+            takes_paths: true,
+            help: "Copy files and directories\n\
+                With several sources, dest must be a directory to copy them into.\n\
+                -r, -R  copy directories and everything in them\n\
+                -n      don't overwrite existing files\n\
+                -p      keep the times of the originals\n\
+                -v      tell what is being done",
+            run: cp,
+        },
+        Builtin {
+            name: "mv",
+            usage: "mv [-n] [-v] source... dest",
+            takes_paths: true,
+            help: "Move or rename files and directories\n\
+                With several sources, dest must be a directory to move them into.\n\
+                -n  don't overwrite existing files\n\
+                -v  tell what is being done",
+            run: mv,
+        },
+        Builtin {
+            name: "head",
+            usage: "head [-n lines] [-c bytes] [-q] [-v] [file...]",
+            takes_paths: true,
+            help: "Print the first lines of files\n\
+                Without files, reads the input.\n\
+                -n N   the first N lines, 10 by default; -n -N all but the last N\n\
+                -c N   the first N bytes; -c -N all but the last N\n\
+                -q     never print headers with file names\n\
+                -v     always print them",
+            run: head,
+        },
+        Builtin {
+            name: "tail",
+            usage: "tail [-n lines] [-c bytes] [-q] [-v] [file...]",
+            takes_paths: true,
+            help: "Print the last lines of files\n\
+                Without files, reads the input.\n\
+                -n N   the last N lines, 10 by default; -n +N from line N on\n\
+                -c N   the last N bytes; -c +N from byte N on\n\
+                -q     never print headers with file names\n\
+                -v     always print them",
+            run: tail,
+        },
+        Builtin {
+            name: "wc",
+            usage: "wc [-l] [-w] [-c] [-m] [file...]",
+            takes_paths: true,
+            help: "Count lines, words and bytes\n\
+                Without files, reads the input. With several, prints a total too.\n\
+                -l  lines\n\
+                -w  words\n\
+                -c  bytes\n\
+                -m  characters",
+            run: wc,
+        },
+        Builtin {
+            name: "sort",
+            usage: "sort [-r] [-n] [-f] [-u] [-t sep] [-k field[,field]] [file...]",
+            takes_paths: true,
+            help: "Sort lines\n\
+                Without files, reads the input.\n\
+                -r        reverse the order\n\
+                -n        compare numbers rather than text\n\
+                -f        ignore case\n\
+                -u        output only the first of lines that compare equal\n\
+                -t sep    fields are separated by sep rather than blanks\n\
+                -k N[,M]  compare fields N to M (or the end) rather than whole lines",
+            run: sort,
+        },
+        Builtin {
+            name: "uniq",
+            usage: "uniq [-c] [-d] [-u] [-i] [input [output]]",
+            takes_paths: true,
+            help: "Drop repeated lines\n\
+                Only lines next to each other are compared, so sort first to drop all\n\
+                duplicates. Without input, reads the input.\n\
+                -c  prefix lines with how many times they occur\n\
+                -d  only print repeated lines\n\
+                -u  only print lines that are not repeated\n\
+                -i  ignore case",
+            run: uniq,
+        },
+        Builtin {
+            name: "tree",
+            usage: "tree [-a] [-d] [-L level] [dir...]",
+            takes_paths: true,
+            help: "Show the contents of directories as a tree\n\
+                -a        include hidden entries\n\
+                -d        only show directories\n\
+                -L level  descend at most level directories deep",
+            run: tree,
+        },
+        Builtin {
+            name: "stat",
+            usage: "stat file...",
+            takes_paths: true,
+            help: "Show the details of files: size, type and times",
+            run: stat,
+        },
+        Builtin {
+            name: "ln",
+            usage: "ln [-f] [-v] target [link | target... dir]",
+            takes_paths: true,
+            help: "Make hard links to files\n\
+                The link is a second name for the contents of the target, which are\n\
+                stored once. Writing to either name gives it contents of its own, and\n\
+                there are no symbolic links.\n\
+                -f  replace existing files\n\
+                -v  tell what is being done",
+            run: ln,
+        },
+    ];
+    for utility in utilities {
+        registry.register(Rc::new(utility));
+    }
+}
+
+// A command line split GNU style into options and operands: flags can be
+// grouped (-rf), a value follows its option (-n 5 or -n5), and -- ends the
+// options
+pub(super) struct Arguments<'a> {
+    pub(super) options: Vec<(char, Option<&'a str>)>,
+    pub(super) operands: Vec<&'a str>,
+}
+
+impl<'a> Arguments<'a> {
+    // `flags` are the options without a value and `valued` the ones with one
+    pub(super) fn parse(parts: &[&'a str], flags: &str, valued: &str) -> Result<Self, String> {
+        let mut arguments = Arguments { options: Vec::new(), operands: Vec::new() };
+        let mut parts = parts[1..].iter();
+        while let Some(part) = parts.next() {
+            if *part == "--" {
+                arguments.operands.extend(parts);
+                break;
+            }
+            let Some(options) = part.strip_prefix('-').filter(|options| !options.is_empty()) else {
+                arguments.operands.push(part);
+                continue;
+            };
+
+            for (i, option) in options.char_indices() {
+                if flags.contains(option) {
+                    arguments.options.push((option, None));
+                } else if valued.contains(option) {
+                    let rest = &options[i + option.len_utf8()..];
+                    let value = if rest.is_empty() {
+                        *parts.next().ok_or_else(|| format!("option requires an argument -- '{}'", option))?
+                    } else {
+                        rest
+                    };
+                    arguments.options.push((option, Some(value)));
+                    break;
+                } else {
+                    return Err(format!("invalid option -- '{}'", option));
+                }
+            }
+        }
+        Ok(arguments)
+    }
+
+    pub(super) fn has(&self, option: char) -> bool {
+        self.options.iter().any(|(flag, _)| *flag == option)
+    }
+
+    // The value given last to `option`
+    pub(super) fn value(&self, option: char) -> Option<&'a str> {
+        self.options.iter().rev()
+            .find(|(flag, _)| *flag == option)
+            .and_then(|(_, value)| *value)
+    }
+}
+
+pub(super) fn usage_error(io: &mut Io, command: &str, message: &str) -> i32 {
+    io.eprintln(&format!("{}: {}", command, message));
+    io.eprintln(&format!("Try 'help {}' for more information.", command));
+    EXIT_USAGE
+}
+
+// A file system error the way GNU tools describe errors
+pub(super) fn describe(error: &FsError) -> String {
+    let description = match error.kind {
+        ErrorKind::NotFound => "No such file or directory",
+        ErrorKind::NotAFile => "Is a directory",
+        ErrorKind::NotADirectory => "Not a directory",
+        ErrorKind::AlreadyExists => "File exists",
+        ErrorKind::NotEmpty => "Directory not empty",
+        ErrorKind::Locked => "Permission denied",
+        ErrorKind::Other => &error.message,
+    };
+    description.to_string()
+}
+
+pub(super) fn join(directory: &str, name: &str) -> String {
+    if directory.ends_with('/') {
+        format!("{}{}", directory, name)
+    } else {
+        format!("{}/{}", directory, name)
+    }
+}
+
+// The last component of a path, ignoring a trailing /
+pub(super) fn base_name(path: &str) -> &str {
+    let path = path.trim_end_matches('/');
+    path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("/")
+}
+
+// The contents of an operand: a file, or the input for - (once)
+pub(super) fn read_input(shell: &Shell, io: &mut Io, operand: &str) -> Result<String, String> {
+    if operand == "-" {
+        return Ok(std::mem::take(&mut io.stdin));
+    }
+    let path = shell.resolve_path(operand);
+    let fs = shell.fs.borrow();
+    match fs.metadata(&path) {
+        Ok(metadata) if metadata.file_type == FileType::Directory => Err("Is a directory".to_string()),
+        Ok(_) => fs.read_file(&path).map_err(|e| describe(&e)),
+        Err(e) => Err(describe(&e)),
+    }
+}
+
+// The operands to read, with - for the input when there are none
+pub(super) fn inputs<'a>(operands: &[&'a str]) -> Vec<&'a str> {
+    if operands.is_empty() {
+        vec!["-"]
+    } else {
+        operands.to_vec()
+    }
+}
+
+// How a file operand is named in headers and totals
+pub(super) fn input_name(operand: &str) -> &str {
+    if operand == "-" { "standard input" } else { operand }
+}
+
+fn cp(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "rRnpvf", "") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "cp", &e),
+    };
+    let transfer = Transfer {
+        command: "cp",
+        recursive: arguments.has('r') || arguments.has('R'),
+        no_clobber: arguments.has('n'),
+        preserve: arguments.has('p'),
+        verbose: arguments.has('v'),
+        remove_source: false,
+    };
+    transfer.run(&arguments.operands, io, shell)
+}
+
+fn mv(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "nvf", "") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "mv", &e),
+    };
+    let transfer = Transfer {
+        command: "mv",
+        recursive: true,
+        no_clobber: arguments.has('n'),
+        preserve: true,
+        verbose: arguments.has('v'),
+        remove_source: true,
+    };
+    transfer.run(&arguments.operands, io, shell)
+}
+
+// What cp and mv have in common: a move is a copy that keeps the times, with
+// the source removed afterwards. The copies share their contents with the
+// originals, so nothing is stored twice.
+struct Transfer {
+    command: &'static str,
+    recursive: bool,
+    no_clobber: bool,
+    preserve: bool,
+    verbose: bool,
+    remove_source: bool,
+}
+
+impl Transfer {
+    fn run(&self, operands: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+        let (target, sources) = match operands.split_last() {
+            None => return usage_error(io, self.command, "missing file operand"),
+            Some((target, [])) => {
+                let message = format!("missing destination file operand after '{}'", target);
+                return usage_error(io, self.command, &message);
+            }
+            Some((target, sources)) => (*target, sources),
+        };
+
+        let target_path = shell.resolve_path(target);
+        let target_is_directory = shell.fs.borrow().metadata(&target_path)
+            .is_ok_and(|metadata| metadata.file_type == FileType::Directory);
+        if sources.len() > 1 && !target_is_directory {
+            io.eprintln(&format!("{}: target '{}' is not a directory", self.command, target));
+            return EXIT_FAILURE;
+        }
+
+        let mut status = EXIT_SUCCESS;
+        for source in sources {
+            let source_path = shell.resolve_path(source);
+            let (destination, destination_path) = if target_is_directory {
+                (join(target, base_name(source)), join(&target_path, base_name(&source_path)))
+            } else {
+                (target.to_string(), target_path.clone())
+            };
+
+            let result = self.transfer(shell, io, (source, &source_path), (&destination, &destination_path));
+            match result {
+                Ok(true) => {}
+                // What failed below it has been reported
+                Ok(false) => status = EXIT_FAILURE,
+                Err(e) => {
+                    io.eprintln(&format!("{}: {}", self.command, e));
+                    status = EXIT_FAILURE;
+                }
+            }
+        }
+        status
+    }
+
+    // Copy or move one entry. Paths come as (what to show, absolute path).
+    // Entries of a directory that fail are reported and the rest carry on, as
+    // in GNU cp; it returns whether they all made it, and a directory is only
+    // removed after a move if they did.
+    fn transfer(&self, shell: &mut Shell, io: &mut Io, source: (&str, &str), destination: (&str, &str)) -> Result<bool, String> {
+        let (source_name, source_path) = source;
+        let (destination_name, destination_path) = destination;
+        let metadata = shell.fs.borrow().metadata(source_path)
+            .map_err(|e| format!("cannot stat '{}': {}", source_name, describe(&e)))?;
+        let existing = shell.fs.borrow().metadata(destination_path).ok();
+
+        if source_path.trim_end_matches('/') == destination_path.trim_end_matches('/') {
+            return Err(format!("'{}' and '{}' are the same file", source_name, destination_name));
+        }
+        if existing.is_some() && self.no_clobber {
+            return Ok(true);
+        }
+
+        let mut complete = true;
+        match metadata.file_type {
+            FileType::Directory => {
+                if !self.recursive {
+                    return Err(format!("-r not specified; omitting directory '{}'", source_name));
+                }
+                if destination_path.starts_with(&join(source_path, "")) {
+                    return Err(if self.remove_source {
+                        format!("cannot move '{}' to a subdirectory of itself, '{}'", source_name, destination_name)
+                    } else {
+                        format!("cannot copy a directory, '{}', into itself, '{}'", source_name, destination_name)
+                    });
+                }
+                match &existing {
+                    Some(existing) if existing.file_type == FileType::File => {
+                        return Err(format!("cannot overwrite non-directory '{}' with directory '{}'", destination_name, source_name));
+                    }
+                    Some(_) if self.remove_source && !shell.fs.borrow().list_directory(destination_path)?.is_empty() => {
+                        return Err(format!("cannot move '{}' to '{}': Directory not empty", source_name, destination_name));
+                    }
+                    Some(_) => {}
+                    None => shell.fs.borrow_mut().create_directory(destination_path, false)
+                        .map_err(|e| format!("cannot create directory '{}': {}", destination_name, describe(&e)))?,
+                }
+                self.report(io, source_name, destination_name);
+
+                let entries = shell.fs.borrow().list_directory(source_path)
+                    .map_err(|e| format!("cannot access '{}': {}", source_name, describe(&e)))?;
+                for entry in entries {
+                    let result = self.transfer(
+                        shell,
+                        io,
+                        (&join(source_name, &entry.name), &join(source_path, &entry.name)),
+                        (&join(destination_name, &entry.name), &join(destination_path, &entry.name)),
+                    );
+                    match result {
+                        Ok(entry_complete) => complete &= entry_complete,
+                        Err(e) => {
+                            io.eprintln(&format!("{}: {}", self.command, e));
+                            complete = false;
+                        }
+                    }
+                }
+            }
+            FileType::File => {
+                if existing.as_ref().is_some_and(|existing| existing.file_type == FileType::Directory) {
+                    return Err(format!("cannot overwrite directory '{}' with non-directory", destination_name));
+                }
+                shell.fs.borrow_mut().copy_file(source_path, destination_path)
+                    .map_err(|e| format!("cannot create regular file '{}': {}", destination_name, describe(&e)))?;
+                self.report(io, source_name, destination_name);
+            }
+        }
+
+        if self.preserve {
+            keep_times(shell, destination_path, &metadata)?;
+        }
+        if self.remove_source && complete {
+            shell.fs.borrow_mut().delete(source_path, true)
+                .map_err(|e| format!("cannot remove '{}': {}", source_name, describe(&e)))?;
+        }
+        Ok(complete)
+    }
+
+    fn report(&self, io: &mut Io, source: &str, destination: &str) {
+        if self.verbose {
+            let verb = if self.remove_source { "renamed " } else { "" };
+            io.println(&format!("{}'{}' -> '{}'", verb, source, destination));
+        }
+    }
+}
+
+fn keep_times(shell: &mut Shell, path: &str, metadata: &FileMetadata) -> Result<(), String> {
+    shell.fs.borrow_mut().set_times(path, FileTimes {
+        created: Some(metadata.created),
+        modified: Some(metadata.modified),
+        accessed: Some(metadata.accessed),
+    })
+}
+
+// How much of its input head or tail prints: lines or bytes, counted from the
+// start (+N for tail, -N for head) or from the end
+#[derive(Clone, Copy)]
+struct Amount {
+    bytes: bool,
+    count: usize,
+    from_start: bool,
+}
+
+// The amount from -n or -c, or the default of 10 lines. `opposite` is the sign
+// that counts from the other end than usual: - for head, + for tail.
+fn amount(command: &str, arguments: &Arguments, opposite: char) -> Result<Amount, String> {
+    let (bytes, value) = match arguments.options.iter().rev().find(|(option, _)| matches!(option, 'n' | 'c')) {
+        Some(('c', Some(value))) => (true, *value),
+        Some((_, Some(value))) => (false, *value),
+        _ => return Ok(Amount { bytes: false, count: 10, from_start: command == "head" }),
+    };
+
+    let (flipped, digits) = match value.strip_prefix(opposite) {
+        Some(digits) => (true, digits),
+        None => (false, value.trim_start_matches(['+', '-'])),
+    };
+    let count = digits.parse().map_err(|_| {
+        let what = if bytes { "bytes" } else { "lines" };
+        format!("invalid number of {}: '{}'", what, value)
+    })?;
+    Ok(Amount { bytes, count, from_start: (command == "head") != flipped })
+}
+
+// Numbers written like -5, which head and tail take as -n 5, unless they are
+// the value of -n or -c
+fn expand_count(parts: &[&str]) -> Vec<String> {
+    parts.iter().enumerate()
+        .flat_map(|(i, part)| {
+            let is_value = i > 0 && matches!(parts[i - 1], "-n" | "-c");
+            let digits = part.strip_prefix('-')
+                .filter(|digits| i > 0 && !is_value && !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()));
+            match digits {
+                Some(digits) => vec!["-n".to_string(), digits.to_string()],
+                None => vec![part.to_string()],
+            }
+        })
+        .collect()
+}
+
+fn head(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    head_or_tail("head", parts, io, shell)
+}
+
+fn tail(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    head_or_tail("tail", parts, io, shell)
+}
+
+fn head_or_tail(command: &str, parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let expanded = expand_count(parts);
+    let expanded: Vec<&str> = expanded.iter().map(String::as_str).collect();
+    let arguments = match Arguments::parse(&expanded, "qv", "nc") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, command, &e),
+    };
+    let opposite = if command == "head" { '-' } else { '+' };
+    let amount = match amount(command, &arguments, opposite) {
+        Ok(amount) => amount,
+        Err(e) => {
+            io.eprintln(&format!("{}: {}", command, e));
+            return EXIT_FAILURE;
+        }
+    };
+
+    let inputs = inputs(&arguments.operands);
+    let headers = !arguments.has('q') && (arguments.has('v') || inputs.len() > 1);
+    let mut status = EXIT_SUCCESS;
+    let mut first = true;
+    for operand in inputs {
+        let contents = match read_input(shell, io, operand) {
+            Ok(contents) => contents,
+            Err(e) => {
+                io.eprintln(&format!("{}: cannot open '{}' for reading: {}", command, operand, e));
+                status = EXIT_FAILURE;
+                continue;
+            }
+        };
+        if headers {
+            let separator = if first { "" } else { "\n" };
+            io.println(&format!("{}==> {} <==", separator, input_name(operand)));
+        }
+        first = false;
+        io.print(select(&contents, amount, command == "head"));
+    }
+    status
+}
+
+// The part of `contents` that head (or tail) prints
+fn select(contents: &str, amount: Amount, head: bool) -> &str {
+    // Where the pieces start, and the end
+    let boundaries: Vec<usize> = if amount.bytes {
+        (0..=contents.len()).filter(|i| contents.is_char_boundary(*i)).collect()
+    } else {
+        std::iter::once(0)
+            .chain(contents.match_indices('\n').map(|(i, _)| i + 1).filter(|i| *i < contents.len()))
+            .chain(std::iter::once(contents.len()))
+            .collect()
+    };
+    let pieces = boundaries.len() - 1;
+
+    // The number of pieces to skip before printing, and of pieces to print
+    let (start, end) = match (head, amount.from_start) {
+        // head -n N
+        (true, true) => (0, amount.count.min(pieces)),
+        // head -n -N
+        (true, false) => (0, pieces.saturating_sub(amount.count)),
+        // tail -n +N, which counts from 1
+        (false, true) => (amount.count.saturating_sub(1).min(pieces), pieces),
+        // tail -n N
+        (false, false) => (pieces.saturating_sub(amount.count), pieces),
+    };
+    &contents[boundaries[start]..boundaries[end.max(start)]]
+}
+
+fn wc(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "lwcm", "") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "wc", &e),
+    };
+    // The counts to show, in the order GNU shows them
+    let selected: Vec<char> = ['l', 'w', 'm', 'c'].into_iter().filter(|option| arguments.has(*option)).collect();
+    let selected = if selected.is_empty() { vec!['l', 'w', 'c'] } else { selected };
+
+    let mut status = EXIT_SUCCESS;
+    let mut rows: Vec<(Vec<usize>, &str)> = Vec::new();
+    let operands = inputs(&arguments.operands);
+    for operand in &operands {
+        match read_input(shell, io, operand) {
+            Ok(contents) => {
+                let counts = selected.iter()
+                    .map(|option| match option {
+                        'l' => contents.matches('\n').count(),
+                        'w' => contents.split_whitespace().count(),
+                        'm' => contents.chars().count(),
+                        _ => contents.len(),
+                    })
+                    .collect();
+                rows.push((counts, if arguments.operands.is_empty() { "" } else { operand }));
+            }
+            Err(e) => {
+                io.eprintln(&format!("wc: {}: {}", operand, e));
+                status = EXIT_FAILURE;
+            }
+        }
+    }
+    if operands.len() > 1 {
+        let totals = (0..selected.len()).map(|i| rows.iter().map(|(counts, _)| counts[i]).sum()).collect();
+        rows.push((totals, "total"));
+    }
+
+    // Columns are as wide as the largest number, unless there's only one
+    let largest = rows.iter().flat_map(|(counts, _)| counts.iter()).max().copied().unwrap_or_default();
+    let width = if rows.len() == 1 && selected.len() == 1 { 1 } else { largest.to_string().len() };
+    for (counts, name) in rows {
+        let mut line = counts.iter()
+            .map(|count| format!("{:>width$}", count, width = width))
+            .collect::<Vec<String>>()
+            .join(" ");
+        if !name.is_empty() {
+            line = format!("{} {}", line, name);
+        }
+        io.println(&line);
+    }
+    status
+}
+
+// Read every operand, or the input, as lines
+fn read_lines(command: &str, operands: &[&str], io: &mut Io, shell: &Shell) -> Result<Vec<String>, i32> {
+    let mut lines = Vec::new();
+    for operand in inputs(operands) {
+        match read_input(shell, io, operand) {
+            Ok(contents) => lines.extend(contents.lines().map(str::to_string)),
+            Err(e) => {
+                io.eprintln(&format!("{}: {}: {}", command, operand, e));
+                return Err(EXIT_FAILURE);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+fn sort(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "rnfub", "kt") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "sort", &e),
+    };
+    let separator = match arguments.value('t').map(|separator| separator.chars().collect::<Vec<char>>()) {
+        None => None,
+        Some(separator) if separator.len() == 1 => Some(separator[0]),
+        Some(_) => {
+            io.eprintln("sort: multi-character tab");
+            return EXIT_USAGE;
+        }
+    };
+    let fields = match arguments.value('k').map(parse_key) {
+        None => None,
+        Some(Ok(fields)) => Some(fields),
+        Some(Err(e)) => {
+            io.eprintln(&format!("sort: {}", e));
+            return EXIT_USAGE;
+        }
+    };
+
+    let mut lines = match read_lines("sort", &arguments.operands, io, shell) {
+        Ok(lines) => lines,
+        Err(status) => return status,
+    };
+
+    let numeric = arguments.has('n');
+    let fold_case = arguments.has('f');
+    let key = |line: &str| -> String {
+        let key = match fields {
+            Some((first, last)) => {
+                let fields: Vec<&str> = match separator {
+                    Some(separator) => line.split(separator).collect(),
+                    None => line.split_whitespace().collect(),
+                };
+                let last = last.unwrap_or(fields.len()).min(fields.len());
+                fields.get(first - 1..last.max(first - 1)).unwrap_or_default().join(" ")
+            }
+            None => line.to_string(),
+        };
+        if fold_case { key.to_lowercase() } else { key }
+    };
+    let compare = |a: &str, b: &str| -> Ordering {
+        let (a, b) = (key(a), key(b));
+        if numeric {
+            leading_number(&a).partial_cmp(&leading_number(&b)).unwrap_or(Ordering::Equal)
+        } else {
+            a.cmp(&b)
+        }
+    };
+
+    // Lines with equal keys keep to their order by the whole line, like GNU
+    lines.sort_by(|a, b| compare(a, b).then_with(|| if fields.is_some() || numeric || fold_case { a.cmp(b) } else { Ordering::Equal }));
+    if arguments.has('r') {
+        lines.reverse();
+    }
+    if arguments.has('u') {
+        lines.dedup_by(|a, b| compare(a, b) == Ordering::Equal);
+    }
+    for line in lines {
+        io.println(&line);
+    }
+    EXIT_SUCCESS
+}
+
+// A -k key: the first field and maybe the last, counted from 1
+fn parse_key(key: &str) -> Result<(usize, Option<usize>), String> {
+    let field = |text: &str| match text.split('.').next().unwrap_or_default().parse::<usize>() {
+        Ok(0) => Err(format!("field number is zero: invalid field specification '{}'", key)),
+        Ok(field) => Ok(field),
+        Err(_) => Err(format!("invalid number at field start: invalid count at start of '{}'", text)),
+    };
+    match key.split_once(',') {
+        Some((first, last)) => Ok((field(first)?, Some(field(last)?))),
+        None => Ok((field(key)?, None)),
+    }
+}
+
+// The number a line starts with, as sort -n sees it; 0 if there isn't one
+fn leading_number(text: &str) -> f64 {
+    let text = text.trim_start();
+    let end = text.char_indices()
+        .find(|(i, c)| !(c.is_ascii_digit() || *c == '.' || (*i == 0 && *c == '-')))
+        .map_or(text.len(), |(i, _)| i);
+    text[..end].parse().unwrap_or(0.0)
+}
+
+fn uniq(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "cdui", "") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "uniq", &e),
+    };
+    if arguments.operands.len() > 2 {
+        return usage_error(io, "uniq", &format!("extra operand '{}'", arguments.operands[2]));
+    }
+
+    let lines = match read_lines("uniq", &arguments.operands[..arguments.operands.len().min(1)], io, shell) {
+        Ok(lines) => lines,
+        Err(status) => return status,
+    };
+
+    let same = |a: &str, b: &str| if arguments.has('i') { a.to_lowercase() == b.to_lowercase() } else { a == b };
+    let mut groups: Vec<(&str, usize)> = Vec::new();
+    for line in &lines {
+        match groups.last_mut() {
+            Some((first, count)) if same(first, line) => *count += 1,
+            _ => groups.push((line, 1)),
+        }
+    }
+
+    let mut output = String::new();
+    for (line, count) in groups {
+        if (arguments.has('d') && count == 1) || (arguments.has('u') && count > 1) {
+            continue;
+        }
+        if arguments.has('c') {
+            output.push_str(&format!("{:>7} ", count));
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    match arguments.operands.get(1) {
+        Some(file) => {
+            let path = shell.resolve_path(file);
+            let result = shell.fs.borrow_mut().write_file(&path, &output);
+            if let Err(e) = result {
+                io.eprintln(&format!("uniq: {}: {}", file, describe(&e)));
+                return EXIT_FAILURE;
+            }
+        }
+        None => io.print(&output),
+    }
+    EXIT_SUCCESS
+}
+
+fn tree(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "ad", "L") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "tree", &e),
+    };
+    let depth = match arguments.value('L').map(str::parse::<usize>) {
+        None => None,
+        Some(Ok(depth)) if depth > 0 => Some(depth),
+        Some(_) => {
+            io.eprintln("tree: Invalid level, must be greater than 0.");
+            return EXIT_USAGE;
+        }
+    };
+    let tree = Tree {
+        include_hidden: arguments.has('a'),
+        directories_only: arguments.has('d'),
+        depth,
+    };
+
+    let operands = if arguments.operands.is_empty() { vec!["."] } else { arguments.operands.clone() };
+    let mut counts = (0, 0);
+    let mut status = EXIT_SUCCESS;
+    for operand in operands {
+        let path = shell.resolve_path(operand);
+        match shell.fs.borrow().metadata(&path) {
+            Ok(metadata) if metadata.file_type == FileType::Directory => {
+                io.println(operand);
+                tree.print(shell, io, &path, "", 1, &mut counts);
+            }
+            _ => {
+                io.println(&format!("{}  [error opening dir]", operand));
+                status = EXIT_FAILURE;
+            }
+        }
+    }
+
+    let (directories, files) = counts;
+    let plural = |count: usize, one: &str, many: &str| format!("{} {}", count, if count == 1 { one } else { many });
+    if tree.directories_only {
+        io.println(&format!("\n{}", plural(directories, "directory", "directories")));
+    } else {
+        io.println(&format!("\n{}, {}", plural(directories, "directory", "directories"), plural(files, "file", "files")));
+    }
+    status
+}
+
+struct Tree {
+    include_hidden: bool,
+    directories_only: bool,
+    depth: Option<usize>,
+}
+
+impl Tree {
+    // Print what is in the directory at `path`, each line after `prefix`,
+    // counting (directories, files)
+    fn print(&self, shell: &Shell, io: &mut Io, path: &str, prefix: &str, level: usize, counts: &mut (usize, usize)) {
+        if self.depth.is_some_and(|depth| level > depth) {
+            return;
+        }
+        let options = ListOptions {
+            include_hidden: self.include_hidden,
+            ..ListOptions::default()
+        };
+        let entries = match shell.fs.borrow().list(path, &options) {
+            Ok(listing) => listing.entries,
+            Err(e) => {
+                io.println(&format!("{}[{}]", prefix, describe(&e)));
+                return;
+            }
+        };
+        let entries: Vec<FileMetadata> = entries.into_iter()
+            .filter(|entry| !self.directories_only || entry.file_type == FileType::Directory)
+            .collect();
+
+        let last = entries.len().saturating_sub(1);
+        for (i, entry) in entries.iter().enumerate() {
+            let (branch, indent) = if i == last { ("└── ", "    ") } else { ("├── ", "│   ") };
+            io.println(&format!("{}{}{}", prefix, branch, entry.name));
+            match entry.file_type {
+                FileType::Directory => {
+                    counts.0 += 1;
+                    self.print(shell, io, &join(path, &entry.name), &format!("{}{}", prefix, indent), level + 1, counts);
+                }
+                FileType::File => counts.1 += 1,
+            }
+        }
+    }
+}
+
+fn stat(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "", "") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "stat", &e),
+    };
+    if arguments.operands.is_empty() {
+        return usage_error(io, "stat", "missing operand");
+    }
+
+    let mut status = EXIT_SUCCESS;
+    for operand in &arguments.operands {
+        let path = shell.resolve_path(operand);
+        let metadata = match shell.fs.borrow().metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                io.eprintln(&format!("stat: cannot statx '{}': {}", operand, describe(&e)));
+                status = EXIT_FAILURE;
+                continue;
+            }
+        };
+
+        let file_type = match metadata.file_type {
+            FileType::Directory => "directory",
+            FileType::File if metadata.size == 0 => "regular empty file",
+            FileType::File => "regular file",
+        };
+        io.println(&format!("  File: {}", operand));
+        io.println(&format!("  Size: {:<15} {}", metadata.size, file_type));
+        if shell.fs.borrow().is_encrypted(&path) {
+            io.println("Encrypted: yes");
+        }
+        io.println(&format!("Access: {}", local_time(metadata.accessed)));
+        io.println(&format!("Modify: {}", local_time(metadata.modified)));
+        io.println(&format!(" Birth: {}", local_time(metadata.created)));
+    }
+    status
+}
+
+fn ln(parts: &[&str], io: &mut Io, shell: &mut Shell) -> i32 {
+    let arguments = match Arguments::parse(parts, "fv", "") {
+        Ok(arguments) => arguments,
+        Err(e) => return usage_error(io, "ln", &e),
+    };
+    let is_directory = |operand: &str| shell.fs.borrow().metadata(&shell.resolve_path(operand))
+        .is_ok_and(|metadata| metadata.file_type == FileType::Directory);
+    // (target, link) pairs; links into a directory keep the names of their targets
+    let links: Vec<(&str, String)> = match arguments.operands.as_slice() {
+        [] => return usage_error(io, "ln", "missing file operand"),
+        [target] => vec![(*target, base_name(target).to_string())],
+        [target, link] if !is_directory(link) => vec![(*target, link.to_string())],
+        [targets @ .., directory] => {
+            if !is_directory(directory) {
+                io.eprintln(&format!("ln: target '{}' is not a directory", directory));
+                return EXIT_FAILURE;
+            }
+            targets.iter().map(|target| (*target, join(directory, base_name(target)))).collect()
+        }
+    };
+
+    let mut status = EXIT_SUCCESS;
+    for (target, link) in links {
+        match link_file(shell, target, &link, arguments.has('f')) {
+            Ok(()) if arguments.has('v') => io.println(&format!("'{}' => '{}'", link, target)),
+            Ok(()) => {}
+            Err(e) => {
+                io.eprintln(&format!("ln: {}", e));
+                status = EXIT_FAILURE;
+            }
+        }
+    }
+    status
+}
+
+// Make `link` a hard link to the file `target`: a second entry for the same
+// contents, which are kept once
+fn link_file(shell: &mut Shell, target: &str, link: &str, force: bool) -> Result<(), String> {
+    let target_path = shell.resolve_path(target);
+    let link_path = shell.resolve_path(link);
+    let metadata = shell.fs.borrow().metadata(&target_path)
+        .map_err(|e| format!("failed to access '{}': {}", target, describe(&e)))?;
+    if metadata.file_type == FileType::Directory {
+        return Err(format!("{}: hard link not allowed for directory", target));
+    }
+
+    let existing = shell.fs.borrow().metadata(&link_path).ok();
+    if let Some(existing) = existing {
+        if target_path == link_path {
+            return Err(format!("'{}' and '{}' are the same file", target, link));
+        }
+        if !force || existing.file_type == FileType::Directory {
+            return Err(format!("failed to create hard link '{}': File exists", link));
+        }
+    }
+    shell.fs.borrow_mut().copy_file(&target_path, &link_path)
+        .map_err(|e| format!("failed to create hard link '{}': {}", link, describe(&e)))?;
+    // Both names are the same file, times and all
+    keep_times(shell, &link_path, &metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::Shell;
+    use crate::shell::testing::{run, stderr, stdout, timed_session};
+
+    fn blob(shell: &Shell, path: &str) -> Option<String> {
+        shell.fs.borrow().metadata(path).unwrap().blob
+    }
+
+    #[test]
+    fn cp_copies_files_sharing_their_contents() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "echo hello > a.txt; cp a.txt b.txt");
+        assert_eq!(stdout(&mut shell, "cat b.txt"), "hello\n");
+        assert!(blob(&shell, "/home/a.txt").is_some());
+        assert_eq!(blob(&shell, "/home/a.txt"), blob(&shell, "/home/b.txt"));
+
+        // Writing to the copy leaves the original alone
+        stdout(&mut shell, "echo changed > b.txt");
+        assert_eq!(stdout(&mut shell, "cat a.txt"), "hello\n");
+    }
+
+    #[test]
+    fn cp_copies_directories_only_with_r() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "mkdir src src/inner; echo x > src/inner/file");
+        assert_eq!(stderr(&mut shell, "cp src dest"), "cp: -r not specified; omitting directory 'src'\n");
+        assert_eq!(stdout(&mut shell, "cp -rv src dest"), "'src' -> 'dest'\n'src/inner' -> 'dest/inner'\n'src/inner/file' -> 'dest/inner/file'\n");
+        assert_eq!(stdout(&mut shell, "cat dest/inner/file"), "x\n");
+        assert_eq!(stderr(&mut shell, "cp -r src src/inner"), "cp: cannot copy a directory, 'src', into itself, 'src/inner/src'\n");
+    }
+
+    #[test]
+    fn cp_copies_several_sources_into_a_directory() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "echo 1 > one; echo 2 > two; mkdir both");
+        assert_eq!(run(&mut shell, "cp one two both"), (String::new(), String::new()));
+        assert_eq!(stdout(&mut shell, "cat both/one both/two"), "1\n2\n");
+        assert_eq!(stderr(&mut shell, "cp one two three"), "cp: target 'three' is not a directory\n");
+        assert_eq!(stderr(&mut shell, "cp missing both"), "cp: cannot stat 'missing': No such file or directory\n");
+    }
+
+    #[test]
+    fn cp_keeps_existing_files_with_n_and_times_with_p() {
+        let (mut shell, time) = timed_session();
+        stdout(&mut shell, "echo old > a; echo new > b");
+        stdout(&mut shell, "cp -n b a");
+        assert_eq!(stdout(&mut shell, "cat a"), "old\n");
+
+        let modified = shell.fs.borrow().metadata("/home/a").unwrap().modified;
+        time.advance(60_000);
+        stdout(&mut shell, "cp -p a kept; cp a fresh");
+        assert_eq!(shell.fs.borrow().metadata("/home/kept").unwrap().modified, modified);
+        assert_eq!(shell.fs.borrow().metadata("/home/fresh").unwrap().modified, modified + 60_000);
+    }
+
+    #[test]
+    fn mv_renames_and_moves_into_directories() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "echo x > a; mkdir dir");
+        assert_eq!(stdout(&mut shell, "mv -v a b"), "renamed 'a' -> 'b'\n");
+        assert_eq!(stderr(&mut shell, "cat a"), "cat: File /home/a does not exist\n");
+        stdout(&mut shell, "mv b dir");
+        assert_eq!(stdout(&mut shell, "cat dir/b"), "x\n");
+        assert_eq!(stderr(&mut shell, "mv dir dir/sub"), "mv: cannot move 'dir' to a subdirectory of itself, 'dir/sub'\n");
+    }
+
+    #[test]
+    fn cp_r_reports_each_entry_that_fails_and_copies_the_rest() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "mkdir src dst dst/src dst/src/b; echo a > src/a; echo b > src/b; echo c > src/c");
+        let (output, errors) = run(&mut shell, "cp -r src dst; echo $?");
+        assert_eq!(output, "1\n");
+        assert_eq!(errors, "cp: cannot overwrite directory 'dst/src/b' with non-directory\n");
+        assert_eq!(stdout(&mut shell, "cat dst/src/a dst/src/c"), "a\nc\n");
+    }
+
+    #[test]
+    fn mv_only_removes_directories_whose_entries_all_moved() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "mkdir src src/secret dst; echo a > src/a; echo s > src/secret/s");
+        {
+            let mut fs = shell.fs.borrow_mut();
+            fs.encrypt_directory("/home/src/secret", "correct horse", false).unwrap();
+            fs.lock_all();
+        }
+        let (output, errors) = run(&mut shell, "mv src dst; echo $?");
+        assert_eq!(output, "1\n");
+        assert_eq!(errors, "mv: cannot create regular file 'dst/src/secret/s': Permission denied\n");
+        assert_eq!(stdout(&mut shell, "cat dst/src/a"), "a\n");
+        // What didn't move is still there, and so are the directories above it
+        assert_eq!(stdout(&mut shell, "ls src"), "secret/\n");
+        assert_eq!(stderr(&mut shell, "cat src/a"), "cat: File /home/src/a does not exist\n");
+
+        shell.fs.borrow_mut().unlock_directory("/home/src/secret", "correct horse").unwrap();
+        assert_eq!(run(&mut shell, "mv src/secret dst/src"), (String::new(), String::new()));
+        assert_eq!(stdout(&mut shell, "cat dst/src/secret/s; ls src"), "s\n");
+    }
+
+    #[test]
+    fn head_and_tail_print_lines_and_bytes() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "for i in 1 2 3 4 5; do echo $i; done > n");
+        assert_eq!(stdout(&mut shell, "head -n 2 n"), "1\n2\n");
+        assert_eq!(stdout(&mut shell, "head -2 n"), "1\n2\n");
+        assert_eq!(stdout(&mut shell, "head -n -3 n"), "1\n2\n");
+        assert_eq!(stdout(&mut shell, "head -c 3 n"), "1\n2");
+        assert_eq!(stdout(&mut shell, "tail -n 2 n"), "4\n5\n");
+        assert_eq!(stdout(&mut shell, "tail -n +4 n"), "4\n5\n");
+        assert_eq!(stdout(&mut shell, "tail -c 2 n"), "5\n");
+        assert_eq!(stdout(&mut shell, "cat n | tail -1"), "5\n");
+    }
+
+    #[test]
+    fn head_and_tail_print_headers_for_several_files() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "echo a > one; echo b > two");
+        assert_eq!(stdout(&mut shell, "head one two"), "==> one <==\na\n\n==> two <==\nb\n");
+        assert_eq!(stdout(&mut shell, "tail -q one two"), "a\nb\n");
+        assert_eq!(stdout(&mut shell, "head -v one"), "==> one <==\na\n");
+        assert_eq!(stderr(&mut shell, "head missing"), "head: cannot open 'missing' for reading: No such file or directory\n");
+        assert_eq!(stderr(&mut shell, "head -n x one"), "head: invalid number of lines: 'x'\n");
+    }
+
+    #[test]
+    fn wc_counts_lines_words_and_bytes() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "echo one two > a; echo three > b");
+        assert_eq!(stdout(&mut shell, "wc a"), "1 2 8 a\n");
+        assert_eq!(stdout(&mut shell, "wc -l a b"), "1 a\n1 b\n2 total\n");
+        assert_eq!(stdout(&mut shell, "cat a b | wc -w"), "3\n");
+        assert_eq!(stdout(&mut shell, "echo héllo | wc -m -c"), "6 7\n");
+        assert_eq!(stderr(&mut shell, "wc .."), "wc: ..: Is a directory\n");
+    }
+
+    #[test]
+    fn sort_orders_lines() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "for line in 10 9 b B 9; do echo $line; done > lines");
+        assert_eq!(stdout(&mut shell, "sort lines"), "10\n9\n9\nB\nb\n");
+        assert_eq!(stdout(&mut shell, "sort -n lines | head -n 3"), "B\nb\n9\n");
+        assert_eq!(stdout(&mut shell, "sort -u lines"), "10\n9\nB\nb\n");
+        assert_eq!(stdout(&mut shell, "sort -r lines | head -n 1"), "b\n");
+        assert_eq!(stdout(&mut shell, "sort -f -u lines | tail -n 1"), "B\n");
+    }
+
+    #[test]
+    fn sort_compares_fields() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "for line in bob:30 ann:4 cid:200; do echo $line; done > ages");
+        assert_eq!(stdout(&mut shell, "sort -t : -k 2 -n ages"), "ann:4\nbob:30\ncid:200\n");
+        assert_eq!(stdout(&mut shell, "sort -t : -k 2 ages"), "cid:200\nbob:30\nann:4\n");
+        assert_eq!(stderr(&mut shell, "sort -k 0 ages"), "sort: field number is zero: invalid field specification '0'\n");
+        assert_eq!(stderr(&mut shell, "sort -k x ages"), "sort: invalid number at field start: invalid count at start of 'x'\n");
+    }
+
+    #[test]
+    fn uniq_drops_and_counts_repeated_lines() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "for line in a a b A a; do echo $line; done > letters");
+        assert_eq!(stdout(&mut shell, "uniq letters"), "a\nb\nA\na\n");
+        assert_eq!(stdout(&mut shell, "uniq -c letters"), "      2 a\n      1 b\n      1 A\n      1 a\n");
+        assert_eq!(stdout(&mut shell, "uniq -d letters"), "a\n");
+        assert_eq!(stdout(&mut shell, "uniq -u letters"), "b\nA\na\n");
+        assert_eq!(stdout(&mut shell, "uniq -i -c letters | tail -n 1"), "      2 A\n");
+        stdout(&mut shell, "uniq letters out");
+        assert_eq!(stdout(&mut shell, "cat out"), "a\nb\nA\na\n");
+    }
+
+    #[test]
+    fn tree_draws_directories() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "mkdir top top/a top/a/deep; touch top/a/file top/b top/.hidden");
+        assert_eq!(stdout(&mut shell, "tree top"), "top\n├── a\n│   ├── deep\n│   └── file\n└── b\n\n2 directories, 2 files\n");
+        assert_eq!(stdout(&mut shell, "tree -L 1 top"), "top\n├── a\n└── b\n\n1 directory, 1 file\n");
+        assert_eq!(stdout(&mut shell, "tree -d top"), "top\n└── a\n    └── deep\n\n2 directories\n");
+        assert!(stdout(&mut shell, "tree -a top").contains("├── .hidden\n"));
+        assert_eq!(run(&mut shell, "tree -L 0 top").1, "tree: Invalid level, must be greater than 0.\n");
+    }
+
+    #[test]
+    fn stat_shows_sizes_types_and_times() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "echo hi > file; touch empty");
+        assert_eq!(
+            stdout(&mut shell, "stat file"),
+            "  File: file\n  Size: 3               regular file\n\
+            Access: 1970-01-01 00:00:01\nModify: 1970-01-01 00:00:01\n Birth: 1970-01-01 00:00:01\n",
+        );
+        assert!(stdout(&mut shell, "stat empty").contains("regular empty file"));
+        assert!(stdout(&mut shell, "stat /home").contains("directory"));
+        assert_eq!(stderr(&mut shell, "stat missing"), "stat: cannot statx 'missing': No such file or directory\n");
+    }
+
+    #[test]
+    fn ln_makes_a_second_name_for_the_same_contents() {
+        let (mut shell, time) = timed_session();
+        stdout(&mut shell, "echo shared > target");
+        time.advance(60_000);
+        assert_eq!(stdout(&mut shell, "ln -v target link"), "'link' => 'target'\n");
+        assert_eq!(stdout(&mut shell, "cat link"), "shared\n");
+        assert_eq!(blob(&shell, "/home/link"), blob(&shell, "/home/target"));
+        let fs = shell.fs.borrow();
+        assert_eq!(fs.metadata("/home/link").unwrap().modified, fs.metadata("/home/target").unwrap().modified);
+    }
+
+    #[test]
+    fn ln_links_into_directories_and_replaces_only_with_f() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "echo 1 > one; echo 2 > two; mkdir dir");
+        stdout(&mut shell, "ln one two dir");
+        assert_eq!(stdout(&mut shell, "cat dir/one dir/two"), "1\n2\n");
+
+        assert_eq!(stderr(&mut shell, "ln one two"), "ln: failed to create hard link 'two': File exists\n");
+        stdout(&mut shell, "ln -f one two");
+        assert_eq!(stdout(&mut shell, "cat two"), "1\n");
+    }
+
+    #[test]
+    fn ln_reports_what_it_cannot_link() {
+        let (mut shell, _) = timed_session();
+        stdout(&mut shell, "mkdir dir; touch file");
+        assert_eq!(stderr(&mut shell, "ln missing link"), "ln: failed to access 'missing': No such file or directory\n");
+        assert_eq!(stderr(&mut shell, "ln dir link"), "ln: dir: hard link not allowed for directory\n");
+        assert_eq!(stderr(&mut shell, "ln -f file file"), "ln: 'file' and 'file' are the same file\n");
+        assert_eq!(stderr(&mut shell, "ln file file dir/x"), "ln: target 'dir/x' is not a directory\n");
+        assert!(stderr(&mut shell, "ln -s file link").starts_with("ln: invalid option -- 's'\n"));
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::filesystem::{ContentEncoding, FileMetadata, FileType};
+use super::StorageBackend;
+
+// File inside the root that keeps what the host can't store itself, such as
+// encryption settings. Hidden from the file system.
+pub const METADATA_FILE: &str = ".kernelos_fs.json";
+// Same for the metadata of every directory, as a JSON object keyed by path
+pub const DIRECTORIES_FILE: &str = ".kernelos_dirs.json";
+
+// Maps the file system onto a directory of the host: "/home/notes.txt" is
+// stored as "<root>/home/notes.txt", and entries created or changed by other
+// programs are picked up when the file system is loaded.
+#[derive(Debug, Clone)]
+pub struct HostDirectory {
+    root: PathBuf,
+}
+
+impl HostDirectory {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Result<Self, String> {
+        let root = root.into();
+        if !root.is_dir() {
+            return Err(format!("{} is not a directory", root.display()));
+        }
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn host_path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    fn scan_directory(&self, host_dir: &Path, path: &str, entries: &mut Vec<(String, FileMetadata)>) -> Result<(), String> {
+        let read_dir = fs::read_dir(host_dir)
+            .map_err(|e| format!("Failed to read {}: {}", host_dir.display(), e))?;
+
+        for entry in read_dir {
+            let entry = entry.map_err(|e| format!("Failed to read {}: {}", host_dir.display(), e))?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if path == "/" && (name == METADATA_FILE || name == DIRECTORIES_FILE) {
+                continue;
+            }
+
+            let child_path = if path == "/" { format!("/{}", name) } else { format!("{}/{}", path, name) };
+            // Symlinks are left out rather than followed, since one pointing
+            // at a directory above it would be scanned forever
+            let host_metadata = match fs::symlink_metadata(entry.path()) {
+                Ok(metadata) if !metadata.file_type().is_symlink() => metadata,
+                _ => continue,
+            };
+
+            let metadata = to_file_metadata(name, &host_metadata);
+            entries.push((child_path.clone(), metadata));
+
+            if host_metadata.is_dir() {
+                self.scan_directory(&entry.path(), &child_path, entries)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_directories(&self) -> Result<BTreeMap<String, String>, String> {
+        match fs::read_to_string(self.root.join(DIRECTORIES_FILE)) {
+            Ok(data) => serde_json::from_str(&data)
+                .map_err(|e| format!("Failed to load directories: {}", e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(format!("Failed to load directories: {}", e)),
+        }
+    }
+
+    fn write_directories(&self, directories: &BTreeMap<String, String>) -> Result<(), String> {
+        let data = serde_json::to_string(directories)
+            .map_err(|e| format!("Failed to serialize directories: {}", e))?;
+        fs::write(self.root.join(DIRECTORIES_FILE), data)
+            .map_err(|e| format!("Failed to save directories: {}", e))
+    }
+}
+
+impl StorageBackend for HostDirectory {
+    fn read_metadata(&self) -> Result<Option<String>, String> {
+        match fs::read_to_string(self.root.join(METADATA_FILE)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to load file system: {}", e)),
+        }
+    }
+
+    fn write_metadata(&self, data: &str) -> Result<(), String> {
+        fs::write(self.root.join(METADATA_FILE), data)
+            .map_err(|e| format!("Failed to save file system: {}", e))
+    }
+
+    fn read_directory_metadata(&self, path: &str) -> Result<Option<String>, String> {
+        Ok(self.read_directories()?.remove(path))
+    }
+
+    fn write_directory_metadata(&self, path: &str, data: &str) -> Result<(), String> {
+        let mut directories = self.read_directories()?;
+        directories.insert(path.to_string(), data.to_string());
+        self.write_directories(&directories)
+    }
+
+    fn remove_directory_metadata(&self, path: &str) -> Result<(), String> {
+        let mut directories = self.read_directories()?;
+        if directories.remove(path).is_some() {
+            self.write_directories(&directories)?;
+        }
+        Ok(())
+    }
+
+    fn content_paths(&self) -> Result<Vec<String>, String> {
+        let entries = self.scan()?.unwrap_or_default();
+        Ok(entries.into_iter()
+            .filter(|(_, metadata)| metadata.file_type == FileType::File)
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn directory_metadata_paths(&self) -> Result<Vec<String>, String> {
+        Ok(self.read_directories()?.into_keys().collect())
+    }
+
+    fn read_contents(&self, path: &str) -> Result<Option<String>, String> {
+        match fs::read_to_string(self.host_path(path)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read file {}: {}", path, e)),
+        }
+    }
+
+    fn write_contents(&self, path: &str, contents: &str) -> Result<(), String> {
+        fs::write(self.host_path(path), contents)
+            .map_err(|e| format!("Failed to write file: {}", e))
+    }
+
+    fn remove_contents(&self, path: &str) -> Result<(), String> {
+        match fs::remove_file(self.host_path(path)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(format!("Failed to delete file {}: {}", path, e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn create_directory(&self, path: &str) -> Result<(), String> {
+        fs::create_dir_all(self.host_path(path))
+            .map_err(|e| format!("Failed to create directory {}: {}", path, e))
+    }
+
+    fn remove_directory(&self, path: &str) -> Result<(), String> {
+        match fs::remove_dir(self.host_path(path)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(format!("Failed to delete directory {}: {}", path, e)),
+            _ => Ok(()),
+        }
+    }
+
+    // Creation times can't be changed on most hosts, so only the modification
+    // and access times are applied
+    fn set_times(&self, path: &str, metadata: &FileMetadata) -> Result<(), String> {
+        let times = fs::FileTimes::new()
+            .set_modified(UNIX_EPOCH + Duration::from_millis(metadata.modified))
+            .set_accessed(UNIX_EPOCH + Duration::from_millis(metadata.accessed));
+
+        fs::File::open(self.host_path(path))
+            .and_then(|file| file.set_times(times))
+            .map_err(|e| format!("Failed to set times of {}: {}", path, e))
+    }
+
+    fn scan(&self) -> Result<Option<Vec<(String, FileMetadata)>>, String> {
+        let root_metadata = fs::metadata(&self.root)
+            .map_err(|e| format!("Failed to read {}: {}", self.root.display(), e))?;
+
+        let mut entries = vec![("/".to_string(), to_file_metadata("/".to_string(), &root_metadata))];
+        self.scan_directory(&self.root, "/", &mut entries)?;
+        Ok(Some(entries))
+    }
+}
+
+fn to_file_metadata(name: String, metadata: &fs::Metadata) -> FileMetadata {
+    let millis = |time: std::io::Result<SystemTime>| {
+        time.ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|elapsed| elapsed.as_millis() as u64)
+    };
+    let modified = millis(metadata.modified()).unwrap_or(0);
+
+    FileMetadata {
+        name,
+        file_type: if metadata.is_dir() { FileType::Directory } else { FileType::File },
+        size: if metadata.is_dir() { 0 } else { metadata.len() as usize },
+        created: millis(metadata.created()).unwrap_or(modified),
+        modified,
+        accessed: millis(metadata.accessed()).unwrap_or(modified),
+        encoding: ContentEncoding::Plain,
+        blob: None,
+        encryption: None,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::filesystem::FileSystem;
+    use crate::storage::SharedStorage;
+    use crate::time::SharedTimeSource;
+
+    #[test]
+    fn symlinks_are_not_followed() {
+        let root = std::env::temp_dir().join(format!("kernelos-host-directory-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/notes.txt"), "hi").unwrap();
+        // A loop back to the root, and a link to nowhere
+        std::os::unix::fs::symlink("..", root.join("sub/up")).unwrap();
+        std::os::unix::fs::symlink("/nonexistent", root.join("dangling")).unwrap();
+
+        let storage = SharedStorage::new(HostDirectory::new(root.to_str().unwrap()).unwrap());
+        let fs = FileSystem::with_storage(storage, SharedTimeSource::default()).unwrap();
+        let names: Vec<String> = fs.list_directory("/sub").unwrap().into_iter().map(|file| file.name).collect();
+        assert_eq!(names, vec!["notes.txt".to_string()]);
+        assert!(fs.metadata("/dangling").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
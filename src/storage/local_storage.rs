@@ -0,0 +1,206 @@
+use web_sys::Storage;
+use super::StorageBackend;
+
+// Local storage key holding the serialized record of the file system itself
+pub const FS_STORAGE_KEY: &str = "wasm_desktop_fs";
+
+const DIRECTORY_KEY_PREFIX: &str = "wasm_desktop_dir:";
+const CONTENT_KEY_PREFIX: &str = "wasm_desktop_file:";
+const BLOB_KEY_PREFIX: &str = "wasm_desktop_blob:";
+const BLOB_REFERENCES_KEY_PREFIX: &str = "wasm_desktop_blob_refs:";
+const SNAPSHOT_KEY_PREFIX: &str = "wasm_desktop_snapshot:";
+
+// Local storage key holding the metadata of the entries inside the directory at `path`
+pub fn directory_key(path: &str) -> String {
+    format!("{}{}", DIRECTORY_KEY_PREFIX, path)
+}
+
+// Local storage key holding the contents shared by all files with the given hash
+pub fn blob_key(hash: &str) -> String {
+    format!("{}{}", BLOB_KEY_PREFIX, hash)
+}
+
+// Local storage key holding the number of files sharing a blob
+pub fn blob_references_key(hash: &str) -> String {
+    format!("{}{}", BLOB_REFERENCES_KEY_PREFIX, hash)
+}
+
+// Local storage key holding the snapshot with the given name
+pub fn snapshot_key(name: &str) -> String {
+    format!("{}{}", SNAPSHOT_KEY_PREFIX, name)
+}
+
+// Whether a local storage key holds file system metadata, as opposed to file contents
+pub fn is_metadata_key(key: &str) -> bool {
+    key == FS_STORAGE_KEY || key.starts_with(DIRECTORY_KEY_PREFIX)
+}
+
+// The paths of all keys starting with `prefix`
+pub(super) fn paths_with_prefix<'a>(keys: impl Iterator<Item = &'a str>, prefix: &str) -> Vec<String> {
+    keys.filter_map(|key| key.strip_prefix(prefix))
+        .map(|path| path.to_string())
+        .collect()
+}
+
+// Local storage key holding the contents of the file at `path`
+pub fn content_key(path: &str) -> String {
+    format!("{}{}", CONTENT_KEY_PREFIX, path)
+}
+
+// The browser's local storage: the file system record under FS_STORAGE_KEY,
+// every directory's metadata under its directory key, and file contents under
+// a blob key shared by identical files (or, if encrypted, the file's own
+// content key), and snapshots under their snapshot key
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalStorage;
+
+impl LocalStorage {
+    fn storage() -> Result<Storage, String> {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok())
+            .flatten()
+            .ok_or_else(|| "Local storage not available".to_string())
+    }
+
+    fn keys() -> Result<Vec<String>, String> {
+        let storage = Self::storage()?;
+        let length = storage.length()
+            .map_err(|e| format!("Failed to read local storage: {:?}", e))?;
+        let mut keys = Vec::new();
+        for i in 0..length {
+            if let Ok(Some(key)) = storage.key(i) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    // Local storage quotas are small
+    fn compress_contents(&self) -> bool {
+        true
+    }
+
+    fn deduplicate_contents(&self) -> bool {
+        true
+    }
+
+    fn read_blob(&self, hash: &str) -> Result<Option<String>, String> {
+        Self::storage()?
+            .get_item(&blob_key(hash))
+            .map_err(|e| format!("Failed to read blob {}: {:?}", hash, e))
+    }
+
+    fn write_blob(&self, hash: &str, data: &str) -> Result<(), String> {
+        Self::storage()?
+            .set_item(&blob_key(hash), data)
+            .map_err(|e| format!("Failed to write blob {}: {:?}", hash, e))
+    }
+
+    fn remove_blob(&self, hash: &str) -> Result<(), String> {
+        let storage = Self::storage()?;
+        storage.remove_item(&blob_key(hash))
+            .and_then(|_| storage.remove_item(&blob_references_key(hash)))
+            .map_err(|e| format!("Failed to delete blob {}: {:?}", hash, e))
+    }
+
+    fn blob_references(&self, hash: &str) -> Result<usize, String> {
+        let references = Self::storage()?
+            .get_item(&blob_references_key(hash))
+            .map_err(|e| format!("Failed to read blob {}: {:?}", hash, e))?;
+        Ok(references.and_then(|references| references.parse().ok()).unwrap_or(0))
+    }
+
+    fn set_blob_references(&self, hash: &str, references: usize) -> Result<(), String> {
+        Self::storage()?
+            .set_item(&blob_references_key(hash), &references.to_string())
+            .map_err(|e| format!("Failed to write blob {}: {:?}", hash, e))
+    }
+
+    fn blob_hashes(&self) -> Result<Vec<String>, String> {
+        let keys = Self::keys()?;
+        Ok(paths_with_prefix(keys.iter().map(String::as_str), BLOB_KEY_PREFIX))
+    }
+
+    fn read_snapshot(&self, name: &str) -> Result<Option<String>, String> {
+        Self::storage()?
+            .get_item(&snapshot_key(name))
+            .map_err(|e| format!("Failed to read snapshot {}: {:?}", name, e))
+    }
+
+    fn write_snapshot(&self, name: &str, data: &str) -> Result<(), String> {
+        Self::storage()?
+            .set_item(&snapshot_key(name), data)
+            .map_err(|e| format!("Failed to write snapshot {}: {:?}", name, e))
+    }
+
+    fn remove_snapshot(&self, name: &str) -> Result<(), String> {
+        Self::storage()?
+            .remove_item(&snapshot_key(name))
+            .map_err(|e| format!("Failed to delete snapshot {}: {:?}", name, e))
+    }
+
+    fn snapshot_names(&self) -> Result<Vec<String>, String> {
+        let keys = Self::keys()?;
+        Ok(paths_with_prefix(keys.iter().map(String::as_str), SNAPSHOT_KEY_PREFIX))
+    }
+
+    fn read_metadata(&self) -> Result<Option<String>, String> {
+        Self::storage()?
+            .get_item(FS_STORAGE_KEY)
+            .map_err(|e| format!("Failed to load file system: {:?}", e))
+    }
+
+    fn write_metadata(&self, data: &str) -> Result<(), String> {
+        Self::storage()?
+            .set_item(FS_STORAGE_KEY, data)
+            .map_err(|e| format!("Failed to save file system: {:?}", e))
+    }
+
+    fn read_directory_metadata(&self, path: &str) -> Result<Option<String>, String> {
+        Self::storage()?
+            .get_item(&directory_key(path))
+            .map_err(|e| format!("Failed to load directory {}: {:?}", path, e))
+    }
+
+    fn content_paths(&self) -> Result<Vec<String>, String> {
+        let keys = Self::keys()?;
+        Ok(paths_with_prefix(keys.iter().map(String::as_str), CONTENT_KEY_PREFIX))
+    }
+
+    fn directory_metadata_paths(&self) -> Result<Vec<String>, String> {
+        let keys = Self::keys()?;
+        Ok(paths_with_prefix(keys.iter().map(String::as_str), DIRECTORY_KEY_PREFIX))
+    }
+
+    fn write_directory_metadata(&self, path: &str, data: &str) -> Result<(), String> {
+        Self::storage()?
+            .set_item(&directory_key(path), data)
+            .map_err(|e| format!("Failed to save directory {}: {:?}", path, e))
+    }
+
+    fn remove_directory_metadata(&self, path: &str) -> Result<(), String> {
+        Self::storage()?
+            .remove_item(&directory_key(path))
+            .map_err(|e| format!("Failed to delete directory {}: {:?}", path, e))
+    }
+
+    fn read_contents(&self, path: &str) -> Result<Option<String>, String> {
+        Self::storage()?
+            .get_item(&content_key(path))
+            .map_err(|e| format!("Failed to read file {}: {:?}", path, e))
+    }
+
+    fn write_contents(&self, path: &str, contents: &str) -> Result<(), String> {
+        Self::storage()?
+            .set_item(&content_key(path), contents)
+            .map_err(|e| format!("Failed to write file: {:?}", e))
+    }
+
+    fn remove_contents(&self, path: &str) -> Result<(), String> {
+        Self::storage()?
+            .remove_item(&content_key(path))
+            .map_err(|e| format!("Failed to delete file {}: {:?}", path, e))
+    }
+}
@@ -0,0 +1,127 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use super::local_storage::{blob_key, blob_references_key, content_key, directory_key, paths_with_prefix, snapshot_key, FS_STORAGE_KEY};
+use super::StorageBackend;
+
+// Keeps everything in memory, laid out under the same keys as LocalStorage so
+// that its items can be loaded into a browser as they are
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    items: RefCell<BTreeMap<String, String>>,
+}
+
+impl MemoryStorage {
+    // All stored items, as (local storage key, value)
+    pub fn items(&self) -> Vec<(String, String)> {
+        self.items.borrow()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    // Local storage quotas are small
+    fn compress_contents(&self) -> bool {
+        true
+    }
+
+    fn deduplicate_contents(&self) -> bool {
+        true
+    }
+
+    fn read_blob(&self, hash: &str) -> Result<Option<String>, String> {
+        Ok(self.items.borrow().get(&blob_key(hash)).cloned())
+    }
+
+    fn write_blob(&self, hash: &str, data: &str) -> Result<(), String> {
+        self.items.borrow_mut().insert(blob_key(hash), data.to_string());
+        Ok(())
+    }
+
+    fn remove_blob(&self, hash: &str) -> Result<(), String> {
+        let mut items = self.items.borrow_mut();
+        items.remove(&blob_key(hash));
+        items.remove(&blob_references_key(hash));
+        Ok(())
+    }
+
+    fn blob_references(&self, hash: &str) -> Result<usize, String> {
+        Ok(self.items.borrow()
+            .get(&blob_references_key(hash))
+            .and_then(|references| references.parse().ok())
+            .unwrap_or(0))
+    }
+
+    fn set_blob_references(&self, hash: &str, references: usize) -> Result<(), String> {
+        self.items.borrow_mut().insert(blob_references_key(hash), references.to_string());
+        Ok(())
+    }
+
+    fn blob_hashes(&self) -> Result<Vec<String>, String> {
+        Ok(paths_with_prefix(self.items.borrow().keys().map(String::as_str), &blob_key("")))
+    }
+
+    fn read_snapshot(&self, name: &str) -> Result<Option<String>, String> {
+        Ok(self.items.borrow().get(&snapshot_key(name)).cloned())
+    }
+
+    fn write_snapshot(&self, name: &str, data: &str) -> Result<(), String> {
+        self.items.borrow_mut().insert(snapshot_key(name), data.to_string());
+        Ok(())
+    }
+
+    fn remove_snapshot(&self, name: &str) -> Result<(), String> {
+        self.items.borrow_mut().remove(&snapshot_key(name));
+        Ok(())
+    }
+
+    fn snapshot_names(&self) -> Result<Vec<String>, String> {
+        Ok(paths_with_prefix(self.items.borrow().keys().map(String::as_str), &snapshot_key("")))
+    }
+
+    fn read_metadata(&self) -> Result<Option<String>, String> {
+        Ok(self.items.borrow().get(FS_STORAGE_KEY).cloned())
+    }
+
+    fn write_metadata(&self, data: &str) -> Result<(), String> {
+        self.items.borrow_mut().insert(FS_STORAGE_KEY.to_string(), data.to_string());
+        Ok(())
+    }
+
+    fn read_directory_metadata(&self, path: &str) -> Result<Option<String>, String> {
+        Ok(self.items.borrow().get(&directory_key(path)).cloned())
+    }
+
+    fn write_directory_metadata(&self, path: &str, data: &str) -> Result<(), String> {
+        self.items.borrow_mut().insert(directory_key(path), data.to_string());
+        Ok(())
+    }
+
+    fn remove_directory_metadata(&self, path: &str) -> Result<(), String> {
+        self.items.borrow_mut().remove(&directory_key(path));
+        Ok(())
+    }
+
+    fn content_paths(&self) -> Result<Vec<String>, String> {
+        Ok(paths_with_prefix(self.items.borrow().keys().map(String::as_str), &content_key("")))
+    }
+
+    fn directory_metadata_paths(&self) -> Result<Vec<String>, String> {
+        Ok(paths_with_prefix(self.items.borrow().keys().map(String::as_str), &directory_key("")))
+    }
+
+    fn read_contents(&self, path: &str) -> Result<Option<String>, String> {
+        Ok(self.items.borrow().get(&content_key(path)).cloned())
+    }
+
+    fn write_contents(&self, path: &str, contents: &str) -> Result<(), String> {
+        self.items.borrow_mut().insert(content_key(path), contents.to_string());
+        Ok(())
+    }
+
+    fn remove_contents(&self, path: &str) -> Result<(), String> {
+        self.items.borrow_mut().remove(&content_key(path));
+        Ok(())
+    }
+}
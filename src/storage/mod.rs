@@ -0,0 +1,164 @@
+// Backends the file system keeps its metadata and file contents in
+pub mod local_storage;
+pub mod memory;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod host_directory;
+
+use std::fmt;
+use std::rc::Rc;
+use crate::filesystem::FileMetadata;
+
+pub use local_storage::LocalStorage;
+pub use memory::MemoryStorage;
+#[cfg(not(target_arch = "wasm32"))]
+pub use host_directory::HostDirectory;
+
+// Where a `FileSystem` persists itself. Paths are the absolute paths entries
+// are stored under, e.g. "/home/documents/notes.txt".
+pub trait StorageBackend {
+    // Serialized record of the file system itself (the root entry), as last
+    // written. Older versions kept the metadata of every entry in here.
+    fn read_metadata(&self) -> Result<Option<String>, String>;
+    fn write_metadata(&self, data: &str) -> Result<(), String>;
+
+    // Serialized metadata of the entries directly inside `path`, so that
+    // directories can be loaded and saved one at a time
+    fn read_directory_metadata(&self, path: &str) -> Result<Option<String>, String>;
+    fn write_directory_metadata(&self, path: &str, data: &str) -> Result<(), String>;
+    fn remove_directory_metadata(&self, path: &str) -> Result<(), String>;
+
+    // Paths of everything stored, whether or not the metadata knows about it;
+    // used to check the file system's integrity
+    fn content_paths(&self) -> Result<Vec<String>, String>;
+    fn directory_metadata_paths(&self) -> Result<Vec<String>, String>;
+
+    fn read_contents(&self, path: &str) -> Result<Option<String>, String>;
+    fn write_contents(&self, path: &str, contents: &str) -> Result<(), String>;
+    fn remove_contents(&self, path: &str) -> Result<(), String>;
+
+    // Whether file contents should be stored once per distinct content, as
+    // blobs named by their hash and shared by every file with that content.
+    // Backends that don't can leave the blob methods alone.
+    fn deduplicate_contents(&self) -> bool {
+        false
+    }
+
+    fn read_blob(&self, _hash: &str) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    fn write_blob(&self, _hash: &str, _data: &str) -> Result<(), String> {
+        Err("This storage doesn't keep blobs".to_string())
+    }
+
+    // Removes the blob along with its reference count
+    fn remove_blob(&self, _hash: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    // Number of files sharing a blob
+    fn blob_references(&self, _hash: &str) -> Result<usize, String> {
+        Ok(0)
+    }
+
+    fn set_blob_references(&self, _hash: &str, _references: usize) -> Result<(), String> {
+        Err("This storage doesn't keep blobs".to_string())
+    }
+
+    fn blob_hashes(&self) -> Result<Vec<String>, String> {
+        Ok(Vec::new())
+    }
+
+    // Serialized snapshots of the file system, by name. Only backends that
+    // keep blobs can hold them, since snapshots share contents through blobs.
+    fn read_snapshot(&self, _name: &str) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    fn write_snapshot(&self, _name: &str, _data: &str) -> Result<(), String> {
+        Err("This storage doesn't keep snapshots".to_string())
+    }
+
+    fn remove_snapshot(&self, _name: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn snapshot_names(&self) -> Result<Vec<String>, String> {
+        Ok(Vec::new())
+    }
+
+    // Whether large file contents should be compressed before being stored.
+    // Backends whose files are meant to be read by other programs keep them as is.
+    fn compress_contents(&self) -> bool {
+        false
+    }
+
+    // Only backends that mirror a real directory tree need to do anything for
+    // directories and timestamps
+    fn create_directory(&self, _path: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn remove_directory(&self, _path: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn set_times(&self, _path: &str, _metadata: &FileMetadata) -> Result<(), String> {
+        Ok(())
+    }
+
+    // The entries that actually exist in the backend, for backends that can
+    // be changed behind the file system's back. None if the stored metadata
+    // is the only record.
+    fn scan(&self) -> Result<Option<Vec<(String, FileMetadata)>>, String> {
+        Ok(None)
+    }
+}
+
+// Cheaply clonable handle to a storage backend. Defaults to the browser's
+// local storage on wasm and to an in-memory store everywhere else.
+#[derive(Clone)]
+pub struct SharedStorage(Rc<dyn StorageBackend>);
+
+impl SharedStorage {
+    pub fn new<T: StorageBackend + 'static>(backend: T) -> Self {
+        Self(Rc::new(backend))
+    }
+
+    // Share an existing backend, e.g. a MemoryStorage the caller wants to inspect
+    pub fn from_rc(backend: Rc<dyn StorageBackend>) -> Self {
+        Self(backend)
+    }
+}
+
+impl std::ops::Deref for SharedStorage {
+    type Target = dyn StorageBackend;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl Default for SharedStorage {
+    #[cfg(target_arch = "wasm32")]
+    fn default() -> Self {
+        Self::new(LocalStorage)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn default() -> Self {
+        Self::new(MemoryStorage::default())
+    }
+}
+
+impl PartialEq for SharedStorage {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for SharedStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedStorage(..)")
+    }
+}
@@ -0,0 +1,188 @@
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+
+// Source of the current time, in milliseconds since the Unix epoch. The file
+// system and the clock widgets take one of these instead of asking the browser
+// directly, so that timestamps can be controlled (e.g. in tests).
+pub trait TimeSource {
+    fn now(&self) -> u64;
+}
+
+// The real time: `Date.now()` in the browser, the system clock elsewhere
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemTime;
+
+impl TimeSource for SystemTime {
+    #[cfg(target_arch = "wasm32")]
+    fn now(&self) -> u64 {
+        js_sys::Date::now() as u64
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+// A time that only changes when told to
+#[derive(Debug, Default)]
+pub struct ManualTime {
+    now: Cell<u64>,
+}
+
+impl ManualTime {
+    pub fn new(now: u64) -> Self {
+        Self { now: Cell::new(now) }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.now.set(self.now.get() + millis);
+    }
+}
+
+impl TimeSource for ManualTime {
+    fn now(&self) -> u64 {
+        self.now.get()
+    }
+}
+
+// Cheaply clonable handle to a time source, usable as a component property.
+// Defaults to the system time.
+#[derive(Clone)]
+pub struct SharedTimeSource(Rc<dyn TimeSource>);
+
+impl SharedTimeSource {
+    pub fn new<T: TimeSource + 'static>(source: T) -> Self {
+        Self(Rc::new(source))
+    }
+
+    // Share an existing source, e.g. a ManualTime the caller keeps advancing
+    pub fn from_rc(source: Rc<dyn TimeSource>) -> Self {
+        Self(source)
+    }
+
+    pub fn now(&self) -> u64 {
+        self.0.now()
+    }
+}
+
+impl Default for SharedTimeSource {
+    fn default() -> Self {
+        Self::new(SystemTime)
+    }
+}
+
+impl PartialEq for SharedTimeSource {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for SharedTimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SharedTimeSource({})", self.now())
+    }
+}
+
+// A timestamp broken down into calendar fields
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    pub year: i32,
+    pub month: u32,   // 0 = January
+    pub day: u32,     // Day of the month, starting at 1
+    pub weekday: u32, // 0 = Sunday
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+}
+
+// As "2024-01-31 13:45:00"
+impl fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month + 1, self.day, self.hours, self.minutes, self.seconds)
+    }
+}
+
+// Break a timestamp down in the browser's time zone (UTC outside the browser)
+#[cfg(target_arch = "wasm32")]
+pub fn local_time(timestamp: u64) -> LocalTime {
+    let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(timestamp as f64));
+    LocalTime {
+        year: date.get_full_year() as i32,
+        month: date.get_month(),
+        day: date.get_date(),
+        weekday: date.get_day(),
+        hours: date.get_hours(),
+        minutes: date.get_minutes(),
+        seconds: date.get_seconds(),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn local_time(timestamp: u64) -> LocalTime {
+    let seconds_total = timestamp / 1000;
+    let days = (seconds_total / 86_400) as i64;
+    let seconds_of_day = (seconds_total % 86_400) as u32;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 2 } else { mp - 10 } as u32;
+    let year = (year_of_era + era * 400) as i32 + if month <= 1 { 1 } else { 0 };
+
+    LocalTime {
+        year,
+        month,
+        day,
+        weekday: (days + 4).rem_euclid(7) as u32, // 1970-01-01 was a Thursday
+        hours: seconds_of_day / 3600,
+        minutes: seconds_of_day / 60 % 60,
+        seconds: seconds_of_day % 60,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_time_only_moves_when_told() {
+        let time = Rc::new(ManualTime::new(1_000));
+        let shared = SharedTimeSource::from_rc(time.clone());
+        assert_eq!(shared.now(), 1_000);
+        time.advance(500);
+        assert_eq!(shared.now(), 1_500);
+        time.set(42);
+        assert_eq!(shared.now(), 42);
+    }
+
+    #[test]
+    fn shared_sources_are_equal_only_to_clones() {
+        let shared = SharedTimeSource::new(ManualTime::new(0));
+        assert_eq!(shared, shared.clone());
+        assert_ne!(shared, SharedTimeSource::new(ManualTime::new(0)));
+    }
+
+    #[test]
+    fn local_time_breaks_down_utc_timestamps() {
+        let epoch = LocalTime { year: 1970, month: 0, day: 1, weekday: 4, hours: 0, minutes: 0, seconds: 0 };
+        assert_eq!(local_time(0), epoch);
+        // 2024-02-29 13:45:30 UTC, a Thursday
+        let leap_day = LocalTime { year: 2024, month: 1, day: 29, weekday: 4, hours: 13, minutes: 45, seconds: 30 };
+        assert_eq!(local_time(1_709_214_330_000), leap_day);
+    }
+}